@@ -0,0 +1,51 @@
+use std::net::SocketAddr;
+
+use utils::config::{self, ConfigError, Settings};
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub server: SocketAddr,
+    pub token: String,
+    pub timeout: u64, //seconds, applies to connecting and to every reply
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            server: SocketAddr::from(([127, 0, 0, 1], 7879)),
+            token: String::new(),
+            timeout: 10,
+        }
+    }
+}
+
+impl Settings for AdminConfig {
+    const ENV_PREFIX: &'static str = "CHAT_ADMIN";
+    const DEFAULT_FILE: &'static str = "admin.toml";
+    const KEYS: &'static [(&'static str, &'static str, &'static str)] = &[
+        ("server", "ADDR", "Admin address of the server (default 127.0.0.1:7879)"),
+        ("token", "TOKEN", "Admin token, prefer the environment variable over this flag"),
+        ("timeout", "SECS", "Connect and reply timeout (default 10)"),
+    ];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "server" => self.server = config::parse_value(key, value)?,
+            "token" => self.token = value.to_string(),
+            "timeout" => self.timeout = config::parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownArg(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.token.is_empty() {
+            return Err(ConfigError::Invalid { key: "token".to_string(), reason: "required, set CHAT_ADMIN_TOKEN or --token".to_string() });
+        }
+        if self.timeout == 0 {
+            return Err(ConfigError::Invalid { key: "timeout".to_string(), reason: "must be at least 1".to_string() });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+extern crate netutils;
+extern crate utils;
+
+mod config;
+use config::AdminConfig;
+
+use std::io::{self, BufRead, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use netutils::message_stream::{self, MsgError};
+use netutils::messages;
+
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+//sends one request and waits for the matching OnResult
+fn request<T: serde::Serialize>(stream: &mut TcpStream, code: u32, msg: &T) -> Result<(bool, String), MsgError> {
+    let msg_encoded = message_stream::serialize_data(code, msg)?;
+    stream.write_all(msg_encoded.as_slice()).map_err(MsgError::Write)?;
+
+    let msginfo = message_stream::read_msginfo(stream, MAX_FRAME_LEN)?;
+    if msginfo.code != messages::admin::Message::OnResult as u32 {
+        return Err(MsgError::LogicError { msg: format!("unexpected reply code {}", msginfo.code) });
+    }
+    let result = msginfo.decode_data::<messages::admin::MsgResult>()?;
+    Ok((result.ok, result.output.to_string()))
+}
+
+fn print_output(ok: bool, output: &str) {
+    let output = output.trim_end();
+    if output.is_empty() {
+        return;
+    }
+    if ok {
+        println!("{}", output);
+    }
+    else {
+        eprintln!("{}", output);
+    }
+}
+
+fn main() {
+    let (config, operands) = match utils::config::load_with_operands::<AdminConfig, _>(std::env::args().skip(1)) {
+        Ok(Some(loaded)) => loaded,
+        Ok(None) => {
+            print!("{}", utils::config::usage::<AdminConfig>("chat-admin"));
+            println!("\nCommands are given after the options, e.g. chat-admin kick alice spamming,");
+            println!("without one they are read from stdin, one per line. Run chat-admin help for the list.");
+            return;
+        },
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        },
+    };
+
+    let timeout = Duration::from_secs(config.timeout);
+    let mut stream = match TcpStream::connect_timeout(&config.server, timeout) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Failed to connect to {}: {}", config.server, e);
+            std::process::exit(1);
+        },
+    };
+    if let Err(e) = stream.set_read_timeout(Some(timeout)).and_then(|_| stream.set_write_timeout(Some(timeout))) {
+        eprintln!("Failed to set socket timeouts: {}", e);
+        std::process::exit(1);
+    }
+
+    let auth = messages::admin::MsgAuth {
+        token: config.token.as_str(),
+    };
+    match request(&mut stream, messages::admin::Message::OnAuth as u32, &auth) {
+        Ok((true, _)) => {},
+        Ok((false, output)) => {
+            eprintln!("{}", output);
+            std::process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("Authentication failed: {}", e);
+            std::process::exit(1);
+        },
+    }
+
+    let lines: Box<dyn Iterator<Item = io::Result<String>>> = if operands.is_empty() {
+        Box::new(io::stdin().lock().lines())
+    }
+    else {
+        Box::new(std::iter::once(Ok(operands.join(" "))))
+    };
+
+    let mut failed = false;
+    for line in lines {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to read command: {}", e);
+                std::process::exit(1);
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let msg = messages::admin::MsgCommand {
+            line: line.trim(),
+        };
+        match request(&mut stream, messages::admin::Message::OnCommand as u32, &msg) {
+            Ok((ok, output)) => {
+                print_output(ok, &output);
+                failed |= !ok;
+            },
+            Err(e) => {
+                eprintln!("Request failed: {}", e);
+                std::process::exit(1);
+            },
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,208 @@
+extern crate netutils;
+extern crate utils;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chat_bot::{Bot, BotConfig, Context, Event};
+use netutils::logger::Logger;
+use netutils::rotating_file::{RotatingFile, RotationPolicy};
+
+//example bot: dice, last seen, and a task that forgets people nobody saw for a long time
+
+const SEEN_PREFIX: &str = "seen:"; //one key per user, so a message only serializes its sender
+const OLD_SEEN_KEY: &str = "seen"; //older bots kept everybody in one map
+const PRUNE_EVERY: Duration = Duration::from_secs(60 * 60);
+const FORGET_AFTER: u64 = 90 * 24 * 60 * 60; //seconds
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Seen {
+    name: String,
+    at: u64, //unix seconds
+    what: String,
+}
+
+fn seen_key(user: &str) -> String {
+    format!("{}{}", SEEN_PREFIX, user.to_lowercase())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+//xorshift, plenty for dice
+struct Dice(u64);
+
+impl Dice {
+    fn new() -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_nanos() as u64).unwrap_or(0);
+        //xorshift never leaves zero
+        Dice((seed ^ ((std::process::id() as u64) << 32)) | 1)
+    }
+
+    fn roll(&mut self, sides: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % sides as u64) as u32 + 1
+    }
+}
+
+//"NdM", "dM" or "M"; None if out of range
+fn parse_dice(spec: &str) -> Option<(u32, u32)> {
+    let (count, sides) = match spec.to_lowercase().split_once('d') {
+        Some(("", sides)) => (1, sides.parse().ok()?),
+        Some((count, sides)) => (count.parse().ok()?, sides.parse().ok()?),
+        None => (1, spec.parse().ok()?),
+    };
+    if count == 0 || count > MAX_DICE || !(2..=MAX_SIDES).contains(&sides) {
+        return None;
+    }
+    Some((count, sides))
+}
+
+fn roll(dice: &mut Dice, context: &mut Context, args: &[&str]) {
+    let (count, sides) = match parse_dice(args.first().copied().unwrap_or("1d6")) {
+        Some(spec) => spec,
+        None => {
+            context.reply(&format!("Expected dice like 2d6, at most {}d{}", MAX_DICE, MAX_SIDES));
+            return;
+        },
+    };
+    let rolls: Vec<u32> = (0..count).map(|_| dice.roll(sides)).collect();
+    let total: u32 = rolls.iter().sum();
+    let user = context.user().unwrap_or("someone").to_string();
+    let text = match rolls.len() {
+        2..=10 => {
+            let rolls: Vec<String> = rolls.iter().map(|roll| roll.to_string()).collect();
+            format!("{} rolled {}d{}: {} = {}", user, count, sides, rolls.join(" + "), total)
+        },
+        _ => format!("{} rolled {}d{}: {}", user, count, sides, total),
+    };
+    context.reply(&text);
+}
+
+fn format_ago(seconds: u64) -> String {
+    match seconds {
+        0..=59 => String::from("just now"),
+        60..=3599 => format!("{} minutes ago", seconds / 60),
+        3600..=86399 => format!("{} hours ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+fn seen(context: &mut Context, args: &[&str]) {
+    let user = match args.first() {
+        Some(user) => *user,
+        None => {
+            context.reply("Usage: seen <user>");
+            return;
+        },
+    };
+    let text = match context.state().get::<Seen>(&seen_key(user)) {
+        Some(entry) => format!("{} was last seen {}, {}", entry.name, format_ago(now().saturating_sub(entry.at)), entry.what),
+        None => format!("I have not seen {}", user),
+    };
+    context.reply(&text);
+}
+
+fn remember(context: &mut Context, event: &Event) {
+    let (user, what) = match event {
+        Event::Message { private: true, .. } => return,
+        Event::Message { user, text, .. } => (user, format!("saying \"{}\"", text)),
+        Event::Action { user, text } => (user, format!("doing \"{} {}\"", user, text)),
+        Event::Joined(user) => (user, String::from("connecting")),
+        Event::Left(user) => (user, String::from("disconnecting")),
+        Event::RoomJoined { user, room } => (user, format!("joining #{}", room)),
+        Event::Renamed { old, new } => (old, format!("renaming to {}", new)),
+        _ => return,
+    };
+    if user.eq_ignore_ascii_case(context.name()) {
+        return;
+    }
+    //a struct of strings always serializes
+    let _ = context.state().set(&seen_key(user), &Seen { name: user.clone(), at: now(), what });
+}
+
+fn prune(context: &mut Context) {
+    let now = now();
+    let state = context.state();
+    let old: Vec<String> = state.keys()
+        .filter(|key| key.starts_with(SEEN_PREFIX))
+        .filter(|key| state.get::<Seen>(key).is_none_or(|entry| now.saturating_sub(entry.at) >= FORGET_AFTER))
+        .map(|key| key.to_string())
+        .collect();
+    for key in old {
+        state.remove(&key);
+    }
+}
+
+//moves entries out of the single map older bots saved
+fn migrate(bot: &mut Bot) {
+    let seen: HashMap<String, Seen> = match bot.state().get(OLD_SEEN_KEY) {
+        Some(seen) => seen,
+        None => return,
+    };
+    for entry in seen.into_values() {
+        let _ = bot.state().set(&seen_key(&entry.name), &entry);
+    }
+    bot.state().remove(OLD_SEEN_KEY);
+}
+
+fn build_logger(config: &BotConfig) -> Result<Logger, String> {
+    let logger = Logger::new(config.log.level);
+    if let Some(path) = &config.log.file {
+        match RotatingFile::open(path, RotationPolicy::default()) {
+            Ok(file) => logger.add_writer(file, config.log.format),
+            Err(e) => return Err(format!("Failed to open log file {}: {}", path.display(), e)),
+        }
+    }
+    if config.log.stderr {
+        logger.add_writer(std::io::stderr(), config.log.format);
+    }
+    Ok(logger)
+}
+
+fn main() {
+    let config = match utils::config::load::<BotConfig, _>(std::env::args().skip(1)) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            print!("{}", utils::config::usage::<BotConfig>("chat-bot"));
+            return;
+        },
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        },
+    };
+
+    let logger = match build_logger(&config) {
+        Ok(logger) => Arc::new(logger),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let mut bot = match Bot::new(config, logger) {
+        Ok(bot) => bot,
+        Err(e) => {
+            eprintln!("Failed to load the bot state: {}", e);
+            std::process::exit(1);
+        },
+    };
+    migrate(&mut bot);
+    let mut dice = Dice::new();
+    bot.command("roll", "[NdM]", "Roll N dice with M sides (default 1d6)", move |context, args| roll(&mut dice, context, args));
+    bot.command("seen", "<user>", "When someone was last seen and what they did", seen);
+    bot.on_event(remember);
+    bot.every(PRUNE_EVERY, prune);
+
+    if let Err(e) = bot.run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,42 @@
+use std::error::Error;
+
+use client_lib::request::RequestError;
+
+#[derive(Debug)]
+pub enum BotError {
+    Io(std::io::Error),
+    Request(RequestError),
+    Taken(String), //the name and every variant tried are in use, maybe by our own dropped connection
+    Rejected(String), //the naming policy refuses the name, retrying will not help
+}
+
+impl std::fmt::Display for BotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            BotError::Io(e) => write!(f, "{}", e),
+            BotError::Request(e) => write!(f, "{}", e),
+            BotError::Taken(name) => write!(f, "{} and the names tried after it are taken", name),
+            BotError::Rejected(reason) => write!(f, "Registration refused: {}", reason),
+        }
+    }
+}
+
+impl Error for BotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+impl From<std::io::Error> for BotError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+impl From<RequestError> for BotError {
+    fn from(e: RequestError) -> Self {
+        Self::Request(e)
+    }
+}
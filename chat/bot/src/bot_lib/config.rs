@@ -0,0 +1,123 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use netutils::logger::{self, Level};
+use netutils::message_stream::{Codec, Compression};
+use utils::config::{self, ConfigError, Settings};
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotConfig {
+    pub server: SocketAddr,
+    pub connect_timeout: u64, //seconds
+    pub reconnect_delay: u64, //seconds, longest wait between two reconnects
+    pub name: String, //'_' is appended while the name is taken
+    pub room: String, //empty stays in the lobby
+    pub prefix: String, //commands are messages starting with this
+    pub state_file: Option<PathBuf>, //None keeps the state in memory only
+    pub log: LogConfig,
+    pub compression: Vec<Compression>,
+    pub codec: Codec,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub file: Option<PathBuf>,
+    pub level: Level,
+    pub format: logger::Format,
+    pub stderr: bool,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        BotConfig {
+            server: SocketAddr::from(([127, 0, 0, 1], 7878)),
+            connect_timeout: 30,
+            reconnect_delay: 60,
+            name: String::from("bot"),
+            room: String::new(),
+            prefix: String::from("!"),
+            state_file: Some(PathBuf::from("bot_state.json")),
+            log: LogConfig::default(),
+            compression: vec![Compression::Lz4, Compression::Zstd],
+            codec: Codec::Bincode,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            file: None,
+            level: Level::Info,
+            format: logger::Format::Text,
+            stderr: true,
+        }
+    }
+}
+
+impl Settings for BotConfig {
+    const ENV_PREFIX: &'static str = "CHAT_BOT";
+    const DEFAULT_FILE: &'static str = "bot.toml";
+    const KEYS: &'static [(&'static str, &'static str, &'static str)] = &[
+        ("server", "ADDR", "Server address to connect to (default 127.0.0.1:7878)"),
+        ("connect_timeout", "SECS", "Connect timeout in seconds (default 30)"),
+        ("reconnect_delay", "SECS", "Longest wait between reconnects, doubling from 1 second (default 60)"),
+        ("name", "NAME", "Name to register, '_' is appended while it is taken (default bot)"),
+        ("room", "ROOM", "Room to join after registering, empty for the lobby"),
+        ("prefix", "TEXT", "Messages starting with this are commands (default !)"),
+        ("state_file", "PATH", "File the bot state is kept in, empty to keep it in memory (default bot_state.json)"),
+        ("log.file", "PATH", "Log file, empty to disable it"),
+        ("log.level", "LEVEL", "Log level: error, warn, info, debug or trace (default info)"),
+        ("log.format", "FORMAT", "Log line format: text or json (default text)"),
+        ("log.stderr", "BOOL", "Also write log records to stderr (default true)"),
+        ("compression", "LIST", "Comma separated compression to offer the server, preferred first: lz4, zstd, or none (default lz4,zstd)"),
+        ("codec", "CODEC", "Payload encoding to ask the server for: bincode, json or msgpack (default bincode)"),
+    ];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "server" => self.server = config::parse_value(key, value)?,
+            "connect_timeout" => self.connect_timeout = config::parse_value(key, value)?,
+            "reconnect_delay" => self.reconnect_delay = config::parse_value(key, value)?,
+            "name" => self.name = value.trim().to_string(),
+            "room" => self.room = value.trim().trim_start_matches('#').to_string(),
+            "prefix" => self.prefix = value.to_string(),
+            "state_file" => self.state_file = config::parse_opt_path(value),
+            "log.file" => self.log.file = config::parse_opt_path(value),
+            "log.level" => self.log.level = config::parse_value(key, value)?,
+            "log.format" => self.log.format = config::parse_value(key, value)?,
+            "log.stderr" => self.log.stderr = config::parse_bool(key, value)?,
+            "compression" => {
+                let mut algorithms = Vec::new();
+                for name in value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+                    match config::parse_value(key, name)? {
+                        Compression::None => {},
+                        algorithm => algorithms.push(algorithm),
+                    }
+                }
+                self.compression = algorithms;
+            },
+            "codec" => self.codec = config::parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownArg(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.connect_timeout == 0 {
+            return Err(ConfigError::Invalid { key: "connect_timeout".to_string(), reason: "must be at least 1 second".to_string() });
+        }
+        if self.reconnect_delay == 0 {
+            return Err(ConfigError::Invalid { key: "reconnect_delay".to_string(), reason: "must be at least 1 second".to_string() });
+        }
+        if self.name.trim().is_empty() {
+            return Err(ConfigError::Invalid { key: "name".to_string(), reason: "must not be empty".to_string() });
+        }
+        if self.prefix.trim().is_empty() {
+            return Err(ConfigError::Invalid { key: "prefix".to_string(), reason: "must not be empty".to_string() });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use client_lib::ClientSender;
+use client_lib::command::Command;
+use netutils::logger::{Level, Logger};
+
+use super::state::State;
+
+//the connection the handlers run on, replaced on every reconnect
+pub(crate) struct Session {
+    pub sender: ClientSender,
+    pub name: String, //what the server registered us as
+    pub room: String,
+    pub quit: bool,
+}
+
+//handed to commands, tasks and event handlers; sending never fails from the handler's point of
+//view, a broken connection shows up as Event::Disconnected and the bot reconnects
+pub struct Context<'a> {
+    pub(crate) session: &'a mut Session,
+    pub(crate) state: &'a mut State,
+    pub(crate) logger: &'a Logger,
+    pub(crate) user: Option<&'a str>,
+    pub(crate) private: bool,
+}
+
+impl<'a> Context<'a> {
+    //the bot's own name
+    pub fn name(&self) -> &str {
+        &self.session.name
+    }
+
+    pub fn room(&self) -> &str {
+        &self.session.room
+    }
+
+    //who sent the command or caused the event, None in scheduled tasks
+    pub fn user(&self) -> Option<&str> {
+        self.user
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    pub fn state(&mut self) -> &mut State {
+        self.state
+    }
+
+    //answers where the command came from, privately to private messages
+    pub fn reply(&mut self, text: &str) {
+        match self.user {
+            Some(user) if self.private => {
+                let user = user.to_string();
+                self.msg(&user, text)
+            },
+            _ => self.say(text),
+        }
+    }
+
+    //every line of `text` becomes one message
+    pub fn say(&mut self, text: &str) {
+        for line in lines(text) {
+            self.send(&Command::Say(line));
+        }
+    }
+
+    pub fn msg(&mut self, to: &str, text: &str) {
+        for line in lines(text) {
+            self.send(&Command::Msg { to, msg: line });
+        }
+    }
+
+    pub fn action(&mut self, text: &str) {
+        for line in lines(text) {
+            self.send(&Command::Me(line));
+        }
+    }
+
+    //the bot moves there once the server confirms it, and rejoins it after reconnecting
+    pub fn join(&mut self, room: &str) {
+        self.send(&Command::Join(room.trim_start_matches('#')));
+    }
+
+    //disconnects once the handler returns and makes Bot::run return
+    pub fn quit(&mut self) {
+        self.session.quit = true;
+    }
+
+    fn send(&self, command: &Command) {
+        let encoded = match command.encode(self.session.sender.codec()) {
+            Ok(Some(encoded)) => encoded,
+            Ok(None) => return,
+            Err(e) => {
+                self.logger.log(Level::Warn, module_path!(), "Failed to encode message", &[("error", &e)]);
+                return;
+            },
+        };
+        if let Err(e) = self.session.sender.send(encoded.as_slice()) {
+            self.logger.log(Level::Debug, module_path!(), "Failed to send message", &[("error", &e)]);
+        }
+    }
+}
+
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(|line| line.trim_end()).filter(|line| !line.is_empty())
+}
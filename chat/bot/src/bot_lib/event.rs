@@ -0,0 +1,79 @@
+use netutils::message_stream::{MsgError, MsgInfo};
+use netutils::messages;
+
+//what happens on the server, already decoded; names are the ones the server shows, users on
+//linked servers come as name@server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Connected { name: String }, //registered as `name`, the configured room is joined right after
+    Users(Vec<String>), //everyone on the server right after registering
+    Joined(String), //registered on the server
+    Left(String), //disconnected from the server
+    Message { user: String, text: String, private: bool },
+    Action { user: String, text: String },
+    RoomJoined { user: String, room: String },
+    RoomParted { user: String, room: String },
+    Renamed { old: String, new: String },
+    Notice(String), //server notices, shutdowns, kicks and away replies
+    Disconnected,
+}
+
+//None for messages bots have no use for, e.g. file transfers
+pub fn decode(msginfo: &MsgInfo) -> Result<Option<Event>, MsgError> {
+    let event = match msginfo.code {
+        code if code == messages::server::Message::OnRegisterUser as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgOnRegisterUser>()?;
+            Event::Joined(msg.user.to_string())
+        },
+        code if code == messages::server::Message::OnUserList as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgUserList>()?;
+            Event::Users(msg.users.iter().map(|user| user.to_string()).collect())
+        },
+        code if code == messages::server::Message::OnDisconnect as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgOnDisconnect>()?;
+            Event::Left(msg.user.to_string())
+        },
+        code if code == messages::server::Message::OnSent as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgOnSent>()?;
+            Event::Message { user: msg.user.to_string(), text: msg.msg.to_string(), private: false }
+        },
+        code if code == messages::server::Message::OnPrivateMsg as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgPrivateMsg>()?;
+            Event::Message { user: msg.from.to_string(), text: msg.msg.to_string(), private: true }
+        },
+        code if code == messages::server::Message::OnAction as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgAction>()?;
+            Event::Action { user: msg.user.to_string(), text: msg.msg.to_string() }
+        },
+        code if code == messages::server::Message::OnJoined as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgJoined>()?;
+            Event::RoomJoined { user: msg.user.to_string(), room: msg.room.to_string() }
+        },
+        code if code == messages::server::Message::OnParted as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgParted>()?;
+            Event::RoomParted { user: msg.user.to_string(), room: msg.room.to_string() }
+        },
+        code if code == messages::server::Message::OnRename as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgRename>()?;
+            Event::Renamed { old: msg.old.to_string(), new: msg.new.to_string() }
+        },
+        code if code == messages::server::Message::OnAway as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgAway>()?;
+            Event::Notice(format!("{} is away: {}", msg.user, msg.msg))
+        },
+        code if code == messages::server::Message::OnServerNotice as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgServerNotice>()?;
+            Event::Notice(msg.msg.to_string())
+        },
+        code if code == messages::server::Message::OnServerShutdown as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgServerShutdown>()?;
+            Event::Notice(format!("Server is shutting down: {}", msg.reason))
+        },
+        code if code == messages::server::Message::OnKicked as u32 => {
+            let msg = msginfo.decode_data::<messages::server::MsgKicked>()?;
+            Event::Notice(format!("Kicked from the server: {}", msg.reason))
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
@@ -0,0 +1,368 @@
+use std::{
+    net::TcpStream,
+    sync::{mpsc::{self, RecvTimeoutError}, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use client_lib::{Client, ClientHandler, ClientState, client::ClientStream, request::RequestError, server_error::ServerError};
+use netutils::{message_stream::{Codec, MsgError, MsgInfo}, logger::{Level, Logger}};
+use netutils::messages;
+
+mod bot_error;
+pub mod config;
+mod context;
+pub mod event;
+pub mod state;
+pub use bot_error::BotError;
+pub use config::BotConfig;
+pub use context::Context;
+pub use event::Event;
+pub use state::State;
+use context::Session;
+
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const NAME_ATTEMPTS: usize = 5; //'_' is appended after every taken name
+const IDLE_WAIT: Duration = Duration::from_secs(60); //how long the loop sleeps without tasks
+const SAVE_DELAY: Duration = Duration::from_secs(5); //how long changes to the state may stay unsaved
+const LOBBY: &str = "lobby"; //where the server puts new users
+
+pub type CommandFn = Box<dyn FnMut(&mut Context, &[&str])>;
+pub type TaskFn = Box<dyn FnMut(&mut Context)>;
+pub type EventFn = Box<dyn FnMut(&mut Context, &Event)>;
+
+struct BotCommand {
+    name: String,
+    usage: String, //arguments only, e.g. "<user>"
+    help: String,
+    run: CommandFn,
+}
+
+struct Task {
+    every: Duration,
+    due: Instant,
+    run: TaskFn,
+}
+
+//a client that answers commands, runs tasks on a schedule and keeps its state on disk; everything
+//runs on the thread that calls run, one handler at a time, so handlers need no locking
+pub struct Bot {
+    config: BotConfig,
+    commands: Vec<BotCommand>,
+    tasks: Vec<Task>,
+    handlers: Vec<EventFn>,
+    state: State,
+    logger: Arc<Logger>,
+}
+
+impl Bot {
+    //loads the state from config.state_file
+    pub fn new(config: BotConfig, logger: Arc<Logger>) -> Result<Self, std::io::Error> {
+        let state = State::load(config.state_file.as_deref())?;
+        Ok(Bot {
+            config,
+            commands: Vec::new(),
+            tasks: Vec::new(),
+            handlers: Vec::new(),
+            state,
+            logger,
+        })
+    }
+
+    //`run` gets the words after the command name; `usage` lists the arguments for help
+    pub fn command<F>(&mut self, name: &str, usage: &str, help: &str, run: F)
+    where
+        F: FnMut(&mut Context, &[&str]) + 'static,
+    {
+        let name = name.to_lowercase();
+        self.commands.retain(|command| command.name != name);
+        self.commands.push(BotCommand { name, usage: usage.to_string(), help: help.to_string(), run: Box::new(run) });
+    }
+
+    //runs `run` every `every` while connected, the first time one interval after start
+    pub fn every<F>(&mut self, every: Duration, run: F)
+    where
+        F: FnMut(&mut Context) + 'static,
+    {
+        self.tasks.push(Task { every, due: Instant::now() + every, run: Box::new(run) });
+    }
+
+    //sees every event, commands included, before the command runs
+    pub fn on_event<F>(&mut self, run: F)
+    where
+        F: FnMut(&mut Context, &Event) + 'static,
+    {
+        self.handlers.push(Box::new(run));
+    }
+
+    pub fn state(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    //connects and reconnects until a handler calls Context::quit; only errors retrying cannot fix
+    //are returned
+    pub fn run(&mut self) -> Result<(), BotError> {
+        let max_delay = Duration::from_secs(self.config.reconnect_delay);
+        let mut delay = Duration::from_secs(1);
+        loop {
+            let result = self.session();
+            self.save_state();
+            match result {
+                Ok(true) => return Ok(()),
+                Ok(false) => delay = Duration::from_secs(1),
+                Err(BotError::Rejected(reason)) => return Err(BotError::Rejected(reason)),
+                Err(e) => self.logger.log(Level::Warn, module_path!(), "Failed to connect", &[("server", &self.config.server), ("error", &e)]),
+            }
+
+            self.logger.log(Level::Info, module_path!(), "Reconnecting", &[("delay", &format!("{}s", delay.as_secs()))]);
+            thread::sleep(delay);
+            delay = std::cmp::min(delay * 2, max_delay);
+        }
+    }
+
+    //one connection from connect to disconnect, true if a handler asked to quit; errors are only
+    //returned before the bot is registered
+    fn session(&mut self) -> Result<bool, BotError> {
+        let stream = TcpStream::connect_timeout(&self.config.server, Duration::from_secs(self.config.connect_timeout))?;
+        let (sender, receiver) = mpsc::channel();
+        let mut client = Client::new(stream, handler_build(sender, self.logger.clone()), self.logger.clone());
+        client.start();
+
+        if !self.config.compression.is_empty() || self.config.codec != Codec::Bincode {
+            match client.state.hello(&self.config.compression, &[self.config.codec], false, REQUEST_TIMEOUT) {
+                Ok(options) => self.logger.log(Level::Debug, module_path!(), "Negotiated frame options", &[("compression", &options.compression), ("codec", &options.codec)]),
+                Err(e) => self.logger.log(Level::Warn, module_path!(), "Server refused the hello", &[("error", &e)]),
+            }
+        }
+
+        let name = match self.register(&client.state) {
+            Ok(name) => name,
+            Err(e) => {
+                self.shutdown(&mut client);
+                return Err(e);
+            },
+        };
+        self.logger.log(Level::Info, module_path!(), "Registered", &[("server", &self.config.server), ("name", &name)]);
+        let mut session = Session {
+            sender: client.state.sender(),
+            name: name.clone(),
+            room: String::from(LOBBY),
+            quit: false,
+        };
+        if !self.config.room.is_empty() && self.config.room != LOBBY {
+            let room = self.config.room.clone();
+            self.context(&mut session, None, false, |context| context.join(&room));
+        }
+        self.dispatch(&mut session, Event::Connected { name });
+
+        //changes are written in batches rather than after every event
+        let mut save_due = Instant::now() + SAVE_DELAY;
+        while !session.quit {
+            let mut due = self.tasks.iter().map(|task| task.due).min();
+            if self.state.is_dirty() {
+                due = Some(due.map_or(save_due, |due| due.min(save_due)));
+            }
+            let wait = match due {
+                Some(due) => due.saturating_duration_since(Instant::now()),
+                None => IDLE_WAIT,
+            };
+            match receiver.recv_timeout(wait) {
+                Ok(Event::Disconnected) | Err(RecvTimeoutError::Disconnected) => {
+                    self.dispatch(&mut session, Event::Disconnected);
+                    break;
+                },
+                Ok(event) => self.dispatch(&mut session, event),
+                Err(RecvTimeoutError::Timeout) => {},
+            }
+            self.run_tasks(&mut session);
+
+            let now = Instant::now();
+            if !self.state.is_dirty() {
+                save_due = now + SAVE_DELAY;
+            } else if save_due <= now {
+                self.save_state();
+                save_due = now + SAVE_DELAY;
+            }
+        }
+
+        self.shutdown(&mut client);
+        Ok(session.quit)
+    }
+
+    fn shutdown(&self, client: &mut Client) {
+        if let Err(e) = client.shutdown() {
+            self.logger.log(Level::Warn, module_path!(), "Shutdown failed", &[("error", &e)]);
+        }
+    }
+
+    fn register(&self, client_state: &ClientState) -> Result<String, BotError> {
+        let mut name = self.config.name.clone();
+        for _ in 0..NAME_ATTEMPTS {
+            let msg = messages::client::MsgOnRegisterUser {
+                user: &name,
+            };
+            let response = match client_state.request(messages::client::Message::OnRegisterUser as u32, &msg, REQUEST_TIMEOUT) {
+                Ok(response) => response,
+                Err(RequestError::Server(e)) => return Err(BotError::Rejected(e.message)),
+                Err(e) => return Err(e.into()),
+            };
+            match response.code {
+                code if code == messages::server::Message::OnRegistrationSuccess as u32 => {
+                    let msg = response.decode_data::<messages::server::MsgRegistrationSuccess>().map_err(RequestError::Msg)?;
+                    return Ok(msg.user.to_string());
+                },
+                code if code == messages::server::Message::OnAlreadyRegisteredUser as u32 => {
+                    self.logger.log(Level::Debug, module_path!(), "Name taken", &[("name", &name)]);
+                    name.push('_');
+                },
+                code if code == messages::server::Message::OnNameRejected as u32 => {
+                    let msg = response.decode_data::<messages::server::MsgNameRejected>().map_err(RequestError::Msg)?;
+                    return Err(BotError::Rejected(format!("{} cannot be used: {}", msg.user, msg.reason)));
+                },
+                code => return Err(RequestError::Msg(MsgError::LogicError { msg: format!("unexpected reply code {}", code) }).into()),
+            }
+        }
+        Err(BotError::Taken(self.config.name.clone()))
+    }
+
+    fn context<F: FnOnce(&mut Context)>(&mut self, session: &mut Session, user: Option<&str>, private: bool, run: F) {
+        let mut context = Context {
+            session,
+            state: &mut self.state,
+            logger: &self.logger,
+            user,
+            private,
+        };
+        run(&mut context);
+    }
+
+    fn dispatch(&mut self, session: &mut Session, event: Event) {
+        match &event {
+            Event::RoomJoined { user, room } if is_self(session, user) => session.room = room.clone(),
+            Event::Renamed { old, new } if is_self(session, old) => session.name = new.clone(),
+            _ => {},
+        }
+
+        let (user, private) = match &event {
+            Event::Joined(user) | Event::Left(user) => (Some(user.as_str()), false),
+            Event::Message { user, private, .. } => (Some(user.as_str()), *private),
+            Event::Action { user, .. } | Event::RoomJoined { user, .. } | Event::RoomParted { user, .. } => (Some(user.as_str()), false),
+            Event::Renamed { new, .. } => (Some(new.as_str()), false),
+            _ => (None, false),
+        };
+        let mut handlers = std::mem::take(&mut self.handlers);
+        self.context(session, user, private, |context| {
+            for handler in handlers.iter_mut() {
+                handler(context, &event);
+            }
+        });
+        self.handlers = handlers;
+
+        if let Event::Message { user, text, private } = &event {
+            if !is_self(session, user) {
+                self.on_command(session, user, text, *private);
+            }
+        }
+    }
+
+    //commands in rooms need the prefix, in private messages it is optional
+    fn on_command(&mut self, session: &mut Session, user: &str, text: &str, private: bool) {
+        let text = text.trim();
+        let body = match text.strip_prefix(self.config.prefix.as_str()) {
+            Some(body) => body,
+            None if private => text,
+            None => return,
+        };
+        let mut words = body.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name.to_lowercase(),
+            None => return,
+        };
+        let args: Vec<&str> = words.collect();
+
+        let index = match self.commands.iter().position(|command| command.name == name) {
+            Some(index) => index,
+            None => {
+                let text = match name.as_str() {
+                    "help" => Some(self.help(args.first().copied())),
+                    //other bots may share the room, only private messages are answered
+                    _ if private => Some(format!("Unknown command {}{}, try {}help", self.config.prefix, name, self.config.prefix)),
+                    _ => None,
+                };
+                if let Some(text) = text {
+                    self.context(session, Some(user), private, |context| context.reply(&text));
+                }
+                return;
+            },
+        };
+
+        self.logger.log(Level::Debug, module_path!(), "Command", &[("user", &user), ("command", &name)]);
+        let mut commands = std::mem::take(&mut self.commands);
+        self.context(session, Some(user), private, |context| (commands[index].run)(context, &args));
+        self.commands = commands;
+    }
+
+    //the built-in help, replaced by registering a command named "help"
+    fn help(&self, command: Option<&str>) -> String {
+        let prefix = &self.config.prefix;
+        let command = match command {
+            Some(command) => command.trim_start_matches(prefix.as_str()).to_lowercase(),
+            None => {
+                let mut names: Vec<String> = self.commands.iter().map(|command| format!("{}{}", prefix, command.name)).collect();
+                names.insert(0, format!("{}help", prefix));
+                return format!("Commands: {} ({}help <command> for details)", names.join(", "), prefix);
+            },
+        };
+        match self.commands.iter().find(|other| other.name == command) {
+            Some(command) if command.usage.is_empty() => format!("{}{}  {}", prefix, command.name, command.help),
+            Some(command) => format!("{}{} {}  {}", prefix, command.name, command.usage, command.help),
+            None if command == "help" => format!("{}help [command]  Show the commands or what one of them does", prefix),
+            None => format!("Unknown command {}{}", prefix, command),
+        }
+    }
+
+    fn run_tasks(&mut self, session: &mut Session) {
+        let now = Instant::now();
+        let mut tasks = std::mem::take(&mut self.tasks);
+        for task in tasks.iter_mut().filter(|task| task.due <= now) {
+            //missed runs are not made up for
+            task.due = now + task.every;
+            self.context(session, None, false, |context| (task.run)(context));
+        }
+        self.tasks = tasks;
+    }
+
+    fn save_state(&mut self) {
+        if let Err(e) = self.state.save() {
+            self.logger.log(Level::Error, module_path!(), "Failed to save state", &[("error", &e)]);
+        }
+    }
+}
+
+fn is_self(session: &Session, user: &str) -> bool {
+    user.eq_ignore_ascii_case(&session.name)
+}
+
+//decodes on the reader thread and hands the events to the thread running the bot
+fn handler_build(sender: mpsc::Sender<Event>, logger: Arc<Logger>) -> ClientHandler {
+    let sender0 = sender.clone();
+    let logger0 = logger.clone();
+    ClientHandler::new(
+        Box::new(move |_client_state: &ClientState, _stream: &ClientStream, msginfo: &MsgInfo| {
+            match event::decode(msginfo) {
+                Ok(Some(event)) => {
+                    let _ = sender0.send(event);
+                },
+                Ok(None) => {},
+                Err(e) => logger0.log(Level::Warn, module_path!(), "Failed to decode message", &[("code", &msginfo.code), ("error", &e)]),
+            }
+        }),
+        Box::new(move |_client_state: &ClientState, _stream: &ClientStream| {
+            //the bot may be gone already when the reader thread shuts down
+            let _ = sender.send(Event::Disconnected);
+        }),
+        Box::new(move |_client_state: &ClientState, _stream: &ClientStream, error: &ServerError| {
+            logger.log(Level::Debug, module_path!(), "Request refused", &[("code", &error.code), ("message", &error.message)]);
+        }),
+    )
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+use utils::io::write_atomic;
+
+//key/value store that survives restarts, kept as one JSON object; values are anything serde can
+//turn into JSON, the bot saves changes every few seconds and when it disconnects or quits
+pub struct State {
+    file: Option<PathBuf>,
+    values: Map<String, Value>,
+    dirty: bool,
+}
+
+impl State {
+    //a missing file starts an empty state, None keeps it in memory only
+    pub fn load(file: Option<&Path>) -> io::Result<Self> {
+        let mut state = State {
+            file: file.map(|file| file.to_path_buf()),
+            values: Map::new(),
+            dirty: false,
+        };
+        let file = match file {
+            Some(file) => file,
+            None => return Ok(state),
+        };
+
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(state),
+            Err(e) => return Err(e),
+        };
+        match serde_json::from_str::<Map<String, Value>>(&content) {
+            Ok(values) => state.values = values,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file.display(), e))),
+        }
+        Ok(state)
+    }
+
+    //None if `key` is not set or holds something that is not a T
+    pub fn get<T: serde::de::DeserializeOwned>(&self, key: &str) -> Option<T> {
+        serde_json::from_value(self.values.get(key)?.clone()).ok()
+    }
+
+    pub fn set<T: serde::Serialize>(&mut self, key: &str, value: &T) -> Result<(), serde_json::Error> {
+        self.values.insert(key.to_string(), serde_json::to_value(value)?);
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        let removed = self.values.remove(key).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(|key| key.as_str())
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    //writes the state if it changed since the last save
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let file = match &self.file {
+            Some(file) => file,
+            None => {
+                self.dirty = false;
+                return Ok(());
+            },
+        };
+
+        let content = match serde_json::to_string_pretty(&self.values) {
+            Ok(content) => content,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        write_atomic(file, content.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+}
@@ -1,117 +1,301 @@
 use std::{
-    sync::{mpsc::{self, Sender}, Arc},
+    path::PathBuf,
+    sync::{mpsc, Arc},
+    time::Duration,
 };
 
-use client_lib::{ClientState, ClientHandler, client::ClientStream};
-use netutils::{message_stream::{self, MsgInfo}};
+use client_lib::{ClientState, ClientHandler, client::ClientStream, command::Command, request::{PendingRequest, RequestError, Response}, server_error::ServerError};
+use netutils::{message_stream::{MsgError, MsgInfo}, logger::{Level, Logger}};
 use netutils::messages;
+use super::transfer::{self, Transfers};
 
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+//answer to a registration request, refusals carry the text to show
+pub enum Registration {
+    Registered(String),
+    Refused(String),
+}
+
+//everything the reader thread learns is handed to the ui on the main thread, nothing is printed here
 pub enum MainThreadCode {
-    NameAlreadyRegistered,
-    RegistrationSucecssful,
+    Users(Vec<String>),
+    Joined(String),
+    Left(String),
+    Message { user: String, msg: String },
+    RoomJoined { user: String, room: String },
+    RoomParted { user: String, room: String },
+    PrivateMessage { from: String, msg: String },
+    Action { user: String, msg: String },
+    Who { room: String, users: Vec<(String, Option<String>)> },
+    Away { user: String, msg: String },
+    Renamed { old: String, new: String },
+    FileOffer { id: u32, from: String, name: String, size: u64 },
+    Transfer(String), //progress of a file transfer
+    TransferFailed(String),
+    Notice(String),
+    Error(ServerError),
     Disconnected,
 }
 
+impl MainThreadCode {
+    //single line rendering shared by the plain and full screen interfaces
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            MainThreadCode::Users(_) => None,
+            MainThreadCode::Joined(user) => Some(format!("{} has joined the server!", user)),
+            MainThreadCode::Left(user) => Some(format!("{} has disconnected from the server!", user)),
+            MainThreadCode::Message { user, msg } => Some(format!("{} sent \"{}\"", user, msg)),
+            MainThreadCode::RoomJoined { user, room } => Some(format!("{} joined #{}", user, room)),
+            MainThreadCode::RoomParted { user, room } => Some(format!("{} left #{}", user, room)),
+            MainThreadCode::PrivateMessage { from, msg } => Some(format!("*{}* {}", from, msg)),
+            MainThreadCode::Action { user, msg } => Some(format!("* {} {}", user, msg)),
+            MainThreadCode::Who { room, users } => {
+                let users: Vec<String> = users.iter()
+                    .map(|(user, away)| match away {
+                        Some(away) => format!("{} (away: {})", user, away),
+                        None => user.clone(),
+                    })
+                    .collect();
+                Some(format!("Users in #{}: {}", room, users.join(", ")))
+            },
+            MainThreadCode::Away { user, msg } => Some(format!("{} is away: {}", user, msg)),
+            MainThreadCode::Renamed { old, new } => Some(format!("{} is now known as {}", old, new)),
+            MainThreadCode::FileOffer { id, from, name, size } => Some(format!("{} offers {} ({}), /accept {} to download it or /decline {}", from, name, transfer::format_size(*size), id, id)),
+            MainThreadCode::Transfer(text) | MainThreadCode::TransferFailed(text) => Some(text.clone()),
+            MainThreadCode::Notice(text) => Some(text.clone()),
+            MainThreadCode::Error(e) => Some(format!("Error: {}", e.message)),
+            MainThreadCode::Disconnected => Some(String::from("Disconnected from the server")),
+        }
+    }
+}
+
 pub struct ClientImpl {
     sender: mpsc::Sender<MainThreadCode>,
+    transfers: Arc<Transfers>,
+    logger: Arc<Logger>,
 }
 
 impl ClientImpl {
-    pub fn new(sender: mpsc::Sender<MainThreadCode>) -> Self {
+    //received files are saved to `download_dir`
+    pub fn new(sender: mpsc::Sender<MainThreadCode>, logger: Arc<Logger>, download_dir: PathBuf) -> Self {
         ClientImpl {
-            sender
+            transfers: Arc::new(Transfers::new(download_dir, sender.clone(), logger.clone())),
+            sender,
+            logger,
+        }
+    }
+
+    fn notify(&self, code: MainThreadCode) {
+        //the ui may already be gone while the reader thread drains the socket
+        let _ = self.sender.send(code);
+    }
+
+    fn decode<'a, T: serde::Deserialize<'a>>(&self, msginfo: &'a MsgInfo) -> Option<T> {
+        match msginfo.decode_data::<T>() {
+            Ok(data) => Some(data),
+            Err(e) => {
+                self.logger.log(Level::Warn, module_path!(), "Failed to decode message", &[("code", &msginfo.code), ("error", &e)]);
+                None
+            },
         }
     }
 
-    fn on_read(&self, client_state: &ClientState, stream: &ClientStream, msginfo: &MsgInfo) {
+    fn on_read(&self, client_state: &ClientState, _stream: &ClientStream, msginfo: &MsgInfo) {
         match msginfo.code {
-            x if msginfo.code == messages::server::Message::OnRegisterUser as u32 => {
-                let msg = match msginfo.decode_data::<messages::server::MsgOnRegisterUser>() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("{}", e);
-                        return;
-                    },
-                };
-    
-                println!("{} has joined the server!", msg.user);
-            },
-            x if msginfo.code == messages::server::Message::OnAlreadyRegisteredUser as u32 => {
-                let msg = match msginfo.decode_data::<messages::server::MsgAlreadyRegisteredUser>() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("{}", e);
-                        return;
-                    },
-                };
-    
-                println!("{} username already taken!", msg.user);
-                self.sender.send(MainThreadCode::NameAlreadyRegistered).expect("Failed to send msg to main thread");
-            },
-            x if msginfo.code == messages::server::Message::OnRegistrationSuccess as u32 => {
-                let msg = match msginfo.decode_data::<messages::server::MsgRegistrationSuccess>() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("{}", e);
-                        return;
-                    },
-                };
-    
-                println!("{} username registered!", msg.user);
-                self.sender.send(MainThreadCode::RegistrationSucecssful).expect("Failed to send msg to main thread");
-            },
-            x if msginfo.code == messages::server::Message::OnDisconnect as u32 =>  {
-                let msg = match msginfo.decode_data::<messages::server::MsgOnDisconnect>() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("{}", e);
-                        return;
-                    },
-                };
-    
-                println!("{} has disconnected from the server!", msg.user);
-            },
-            x if msginfo.code == messages::server::Message::OnSent as u32 => {
-                let msg = match msginfo.decode_data::<messages::server::MsgOnSent>() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("{}", e);
-                        return;
-                    },
-                };
-    
-                println!("{} sent \"{}\"", msg.user, msg.msg);
+            code if code == messages::server::Message::OnRegisterUser as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgOnRegisterUser>(msginfo) {
+                    self.notify(MainThreadCode::Joined(msg.user.to_string()));
+                }
+            },
+            code if code == messages::server::Message::OnUserList as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgUserList>(msginfo) {
+                    self.notify(MainThreadCode::Users(msg.users.iter().map(|user| user.to_string()).collect()));
+                }
+            },
+            code if code == messages::server::Message::OnDisconnect as u32 =>  {
+                if let Some(msg) = self.decode::<messages::server::MsgOnDisconnect>(msginfo) {
+                    self.notify(MainThreadCode::Left(msg.user.to_string()));
+                }
+            },
+            code if code == messages::server::Message::OnSent as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgOnSent>(msginfo) {
+                    self.notify(MainThreadCode::Message { user: msg.user.to_string(), msg: msg.msg.to_string() });
+                }
+            },
+            code if code == messages::server::Message::OnJoined as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgJoined>(msginfo) {
+                    self.notify(MainThreadCode::RoomJoined { user: msg.user.to_string(), room: msg.room.to_string() });
+                }
+            },
+            code if code == messages::server::Message::OnParted as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgParted>(msginfo) {
+                    self.notify(MainThreadCode::RoomParted { user: msg.user.to_string(), room: msg.room.to_string() });
+                }
+            },
+            code if code == messages::server::Message::OnPrivateMsg as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgPrivateMsg>(msginfo) {
+                    self.notify(MainThreadCode::PrivateMessage { from: msg.from.to_string(), msg: msg.msg.to_string() });
+                }
+            },
+            code if code == messages::server::Message::OnAction as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgAction>(msginfo) {
+                    self.notify(MainThreadCode::Action { user: msg.user.to_string(), msg: msg.msg.to_string() });
+                }
+            },
+            code if code == messages::server::Message::OnWho as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgWho>(msginfo) {
+                    let users = msg.users.iter().map(|entry| (entry.user.to_string(), entry.away.map(|away| away.to_string()))).collect();
+                    self.notify(MainThreadCode::Who { room: msg.room.to_string(), users });
+                }
+            },
+            code if code == messages::server::Message::OnAway as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgAway>(msginfo) {
+                    self.notify(MainThreadCode::Away { user: msg.user.to_string(), msg: msg.msg.to_string() });
+                }
+            },
+            code if code == messages::server::Message::OnRename as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgRename>(msginfo) {
+                    self.notify(MainThreadCode::Renamed { old: msg.old.to_string(), new: msg.new.to_string() });
+                }
+            },
+            code if code == messages::server::Message::OnServerShutdown as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgServerShutdown>(msginfo) {
+                    let text = match msg.retry_after {
+                        0 => format!("Server is shutting down: {}", msg.reason),
+                        retry_after => format!("Server is shutting down: {} (retry in {}s)", msg.reason, retry_after),
+                    };
+                    self.notify(MainThreadCode::Notice(text));
+                }
+            },
+            code if code == messages::server::Message::OnKicked as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgKicked>(msginfo) {
+                    self.notify(MainThreadCode::Notice(format!("You were kicked from the server: {}", msg.reason)));
+                }
+            },
+            code if code == messages::server::Message::OnServerNotice as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgServerNotice>(msginfo) {
+                    self.notify(MainThreadCode::Notice(format!("[server] {}", msg.msg)));
+                }
+            },
+            code if code == messages::server::Message::OnFileOffer as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgFileOffer>(msginfo) {
+                    if let Err(e) = self.transfers.offered(&client_state.sender(), msg.id, msg.from, msg.name, msg.size, msg.sha256) {
+                        self.logger.log(Level::Warn, module_path!(), "Failed to decline file", &[("id", &msg.id), ("error", &e)]);
+                    }
+                }
+            },
+            code if code == messages::server::Message::OnFileAccepted as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgFileAccepted>(msginfo) {
+                    self.transfers.accepted(client_state.sender(), msg.id, msg.user, msg.offset);
+                }
+            },
+            code if code == messages::server::Message::OnFileDeclined as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgFileDeclined>(msginfo) {
+                    self.transfers.declined(msg.id, msg.user);
+                }
+            },
+            code if code == messages::server::Message::OnFileChunk as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgFileChunk>(msginfo) {
+                    self.transfers.chunk(&client_state.sender(), msg.id, msg.offset, &msg.data);
+                }
+            },
+            code if code == messages::server::Message::OnFileComplete as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgFileComplete>(msginfo) {
+                    self.transfers.complete(msg.id);
+                }
+            },
+            code if code == messages::server::Message::OnFileCancelled as u32 => {
+                if let Some(msg) = self.decode::<messages::server::MsgFileCancelled>(msginfo) {
+                    self.transfers.cancelled(msg.id, msg.user, msg.reason);
+                }
             },
             code => {
-                println!("unhandled message code: \"{}\"", code);  
+                self.logger.log(Level::Warn, module_path!(), "Unhandled message code", &[("code", &code)]);
             },
         }
     }
-    
-    fn on_disconnect(&self, client_state: &ClientState, stream: &ClientStream) {
-        println!("Client disconnected from the server {}", stream.addr.to_string());
-        self.sender.send(MainThreadCode::Disconnected).expect("Failed to send msg to main thread");
+
+    fn on_error(&self, _client_state: &ClientState, _stream: &ClientStream, error: &ServerError) {
+        self.logger.log(Level::Debug, module_path!(), "Request refused", &[("code", &error.code), ("message", &error.message)]);
+        self.notify(MainThreadCode::Error(error.clone()));
+    }
+
+    fn on_disconnect(&self, _client_state: &ClientState, stream: &ClientStream) {
+        self.logger.log(Level::Info, module_path!(), "Disconnected from the server", &[("addr", &stream.addr)]);
+        self.notify(MainThreadCode::Disconnected);
+    }
+
+    //the server enforces its own naming policy and says why a name was rejected
+    pub fn valid_name(name: &str) -> bool {
+        !name.trim().is_empty()
     }
 
-    pub fn register_user(&self, client_state: &ClientState) -> String {
-        let name: String = utils::io::read_val::<_, _, _> (
-            "Enter name:",
-            |_input: &str| format!("Expected string of length: [0, 15]").to_string(),
-            Some(|val: &String| return val.len() <= 15 && val.len() > 0),
-        );
-    
+    pub fn register_user(&self, client_state: &ClientState, name: &str) -> Result<Registration, RequestError> {
+        Self::registration(self.register_user_async(client_state, name)?.wait(REQUEST_TIMEOUT))
+    }
+
+    pub fn register_user_async(&self, client_state: &ClientState, name: &str) -> Result<PendingRequest, RequestError> {
         let msg = messages::client::MsgOnRegisterUser {
-            user: name.as_str(),
+            user: name,
         };
-        let msg_encoded: Vec<u8> = message_stream::serialize_data(messages::client::Message::OnRegisterUser as u32, &msg).expect("Failed to serialze message");
-        client_state.send(msg_encoded.as_slice()).expect("failed to send message");
-    
-        name
+        client_state.request_async(messages::client::Message::OnRegisterUser as u32, &msg)
+    }
+
+    //turns the reply to register_user_async into a Registration
+    pub fn registration(response: Result<Response, RequestError>) -> Result<Registration, RequestError> {
+        let msginfo = match response {
+            Ok(msginfo) => msginfo,
+            Err(RequestError::Server(e)) => return Ok(Registration::Refused(e.message)),
+            Err(e) => return Err(e),
+        };
+        match msginfo.code {
+            code if code == messages::server::Message::OnRegistrationSuccess as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgRegistrationSuccess>()?;
+                Ok(Registration::Registered(msg.user.to_string()))
+            },
+            code if code == messages::server::Message::OnAlreadyRegisteredUser as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgAlreadyRegisteredUser>()?;
+                Ok(Registration::Refused(format!("{} username already taken!", msg.user)))
+            },
+            code if code == messages::server::Message::OnNameRejected as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgNameRejected>()?;
+                Ok(Registration::Refused(format!("{} cannot be used: {}", msg.user, msg.reason)))
+            },
+            code => Err(RequestError::Msg(MsgError::LogicError { msg: format!("unexpected reply code {}", code) })),
+        }
+    }
+
+    //sends the protocol message for `command`, returns false for commands the caller has to handle
+    pub fn send_command(&self, client_state: &ClientState, command: &Command) -> Result<bool, std::io::Error> {
+        match *command {
+            Command::Send { to, path } => {
+                self.transfers.send(client_state.sender(), to, path);
+                return Ok(true);
+            },
+            Command::Accept(id) => return self.transfers.accept(&client_state.sender(), id).map(|_| true),
+            Command::Decline(id) => return self.transfers.decline(&client_state.sender(), id).map(|_| true),
+            Command::Cancel(id) => return self.transfers.cancel(&client_state.sender(), id).map(|_| true),
+            _ => {},
+        }
+        match command.encode(client_state.sender().codec()) {
+            Ok(Some(msg_encoded)) => {
+                client_state.send(msg_encoded.as_slice())?;
+                Ok(true)
+            },
+            Ok(None) => Ok(false),
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())),
+        }
     }
 }
 
 pub fn client_handler_build(client_impl: Arc<ClientImpl>) -> ClientHandler {
     let client_impl0 = client_impl.clone();
     let client_impl1 = client_impl.clone();
-    ClientHandler::new(Box::new(move |client_state: &ClientState, stream: &ClientStream, msginfo: &MsgInfo| client_impl0.on_read(client_state, stream, msginfo)), Box::new(move |client_state: &ClientState, stream: &ClientStream| client_impl1.on_disconnect(client_state, stream)))
-}
\ No newline at end of file
+    let client_impl2 = client_impl.clone();
+    ClientHandler::new(
+        Box::new(move |client_state: &ClientState, stream: &ClientStream, msginfo: &MsgInfo| client_impl0.on_read(client_state, stream, msginfo)),
+        Box::new(move |client_state: &ClientState, stream: &ClientStream| client_impl1.on_disconnect(client_state, stream)),
+        Box::new(move |client_state: &ClientState, stream: &ClientStream, error: &ServerError| client_impl2.on_error(client_state, stream, error)),
+    )
+}
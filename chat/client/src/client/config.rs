@@ -0,0 +1,185 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use netutils::logger::{self, Level};
+use netutils::message_stream::{Codec, Compression};
+use netutils::rotating_file::RotateEvery;
+use utils::config::{self, ConfigError, Settings};
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub server: SocketAddr,
+    pub connect_timeout: u64, //seconds
+    pub ui: UiMode,
+    pub log: LogConfig,
+    pub tls: TlsConfig,
+    pub files: FilesConfig,
+    pub compression: Vec<Compression>, //offered to the server, most preferred first
+    pub checksum: bool, //ask for a CRC32C on every frame
+    pub codec: Codec, //payload encoding asked of the server
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UiMode {
+    Auto, //full screen when stdin and stdout are terminals
+    Tui,
+    Plain,
+}
+
+impl FromStr for UiMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(UiMode::Auto),
+            "tui" => Ok(UiMode::Tui),
+            "plain" => Ok(UiMode::Plain),
+            _ => Err(format!("unknown ui \"{}\", expected auto, tui or plain", s)),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub file: Option<PathBuf>,
+    pub level: Level,
+    pub targets: String, //per-target levels, "server_lib=debug,netutils=warn"
+    pub format: logger::Format,
+    pub stderr: bool,
+    pub max_size: u64, //bytes, 0 disables size based rotation
+    pub rotate: RotateEvery,
+    pub keep: usize,
+    pub compress: bool,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub ca_file: Option<PathBuf>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilesConfig {
+    pub dir: PathBuf, //where received files are saved, created on the first download
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            server: SocketAddr::from(([127, 0, 0, 1], 7878)),
+            connect_timeout: 30,
+            ui: UiMode::Auto,
+            log: LogConfig::default(),
+            tls: TlsConfig::default(),
+            files: FilesConfig::default(),
+            compression: vec![Compression::Lz4, Compression::Zstd],
+            checksum: false,
+            codec: Codec::Bincode,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            file: Some(PathBuf::from("client_log.txt")),
+            level: Level::Info,
+            targets: String::new(),
+            format: logger::Format::Text,
+            stderr: false,
+            max_size: 10 * 1024 * 1024,
+            rotate: RotateEvery::Daily,
+            keep: 7,
+            compress: false,
+        }
+    }
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            dir: PathBuf::from("downloads"),
+        }
+    }
+}
+
+impl Settings for ClientConfig {
+    const ENV_PREFIX: &'static str = "CHAT_CLIENT";
+    const DEFAULT_FILE: &'static str = "client.toml";
+    const KEYS: &'static [(&'static str, &'static str, &'static str)] = &[
+        ("server", "ADDR", "Server address to connect to (default 127.0.0.1:7878)"),
+        ("connect_timeout", "SECS", "Connect timeout in seconds (default 30)"),
+        ("ui", "MODE", "Interface: tui, plain or auto to pick tui on a terminal (default auto)"),
+        ("log.file", "PATH", "Log file, empty to disable logging (default client_log.txt)"),
+        ("log.level", "LEVEL", "Log level: error, warn, info, debug or trace (default info)"),
+        ("log.targets", "DIRECTIVES", "Per-target log levels, e.g. server_lib=debug,netutils=warn"),
+        ("log.format", "FORMAT", "Log line format: text or json (default text)"),
+        ("log.stderr", "BOOL", "Also write log records to stderr (default false)"),
+        ("log.max_size", "BYTES", "Rotate the log file once it exceeds this size, 0 to disable (default 10485760)"),
+        ("log.rotate", "WHEN", "Time based rotation: never, hourly or daily (default daily)"),
+        ("log.keep", "N", "Number of rotated log files to keep (default 7)"),
+        ("log.compress", "BOOL", "Gzip rotated log files (default false)"),
+        ("tls.ca_file", "PATH", "CA bundle used to verify the server certificate (PEM)"),
+        ("files.dir", "PATH", "Directory received files are saved to (default downloads)"),
+        ("compression", "LIST", "Comma separated compression to offer the server, preferred first: lz4, zstd, or none (default lz4,zstd)"),
+        ("checksum", "BOOL", "Ask the server to checksum every frame in both directions (default false)"),
+        ("codec", "CODEC", "Payload encoding to ask the server for: bincode, json or msgpack (default bincode)"),
+    ];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "server" => self.server = config::parse_value(key, value)?,
+            "connect_timeout" => self.connect_timeout = config::parse_value(key, value)?,
+            "ui" => self.ui = config::parse_value(key, value)?,
+            "log.file" => self.log.file = config::parse_opt_path(value),
+            "log.level" => self.log.level = config::parse_value(key, value)?,
+            "log.targets" => self.log.targets = value.to_string(),
+            "log.format" => self.log.format = config::parse_value(key, value)?,
+            "log.stderr" => self.log.stderr = config::parse_bool(key, value)?,
+            "log.max_size" => self.log.max_size = config::parse_value(key, value)?,
+            "log.rotate" => self.log.rotate = config::parse_value(key, value)?,
+            "log.keep" => self.log.keep = config::parse_value(key, value)?,
+            "log.compress" => self.log.compress = config::parse_bool(key, value)?,
+            "tls.ca_file" => self.tls.ca_file = config::parse_opt_path(value),
+            "files.dir" => self.files.dir = PathBuf::from(value),
+            "compression" => {
+                let mut algorithms = Vec::new();
+                for name in value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+                    match config::parse_value(key, name)? {
+                        Compression::None => {},
+                        algorithm => algorithms.push(algorithm),
+                    }
+                }
+                self.compression = algorithms;
+            },
+            "checksum" => self.checksum = config::parse_bool(key, value)?,
+            "codec" => self.codec = config::parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownArg(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Err(e) = logger::parse_filters(&self.log.targets) {
+            return Err(ConfigError::InvalidValue { key: "log.targets".to_string(), value: self.log.targets.clone(), reason: e.to_string() });
+        }
+
+        if self.connect_timeout == 0 {
+            return Err(ConfigError::Invalid { key: "connect_timeout".to_string(), reason: "must be at least 1 second".to_string() });
+        }
+        if self.files.dir.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid { key: "files.dir".to_string(), reason: "must not be empty".to_string() });
+        }
+        if self.tls.ca_file.is_some() {
+            config::check_file("tls.ca_file", &self.tls.ca_file)?;
+            //refuse to silently fall back to plaintext
+            return Err(ConfigError::Invalid { key: "tls".to_string(), reason: "TLS is not supported by this build yet".to_string() });
+        }
+        Ok(())
+    }
+}
@@ -2,71 +2,118 @@ extern crate netutils;
 extern crate utils;
 
 mod client_impl;
-use client_impl::{ClientImpl, MainThreadCode};
+mod config;
+mod plain;
+mod transfer;
+mod tui;
+use client_impl::ClientImpl;
+use config::{ClientConfig, LogConfig, UiMode};
 
-use client_lib::{Client};
-use std::net::{TcpStream, SocketAddr, Ipv4Addr};
+use client_lib::Client;
+use std::io::IsTerminal;
+use std::net::TcpStream;
 use std::time::Duration;
 use std::sync::{Arc, mpsc};
 
-use netutils::message_stream::{self};
-use netutils::messages;
-use netutils::logger::Logger;
-use std::fs::File;
+use netutils::logger::{self, Level, Logger};
+use netutils::message_stream::Codec;
+use netutils::rotating_file::{RotatingFile, RotationPolicy};
+
+fn build_logger(config: &LogConfig) -> Result<Logger, String> {
+    let logger = Logger::new(config.level);
+    match logger::parse_filters(&config.targets) {
+        Ok(filters) => logger.set_filters(&filters),
+        Err(e) => return Err(format!("Invalid log targets: {}", e)),
+    }
+
+    if let Some(path) = &config.file {
+        let policy = RotationPolicy {
+            max_size: config.max_size,
+            every: config.rotate,
+            keep: config.keep,
+            compress: config.compress,
+        };
+        match RotatingFile::open(path, policy) {
+            Ok(file) => logger.add_writer(file, config.format),
+            Err(e) => return Err(format!("Failed to open log file {}: {}", path.display(), e)),
+        }
+    }
+    if config.stderr {
+        logger.add_writer(std::io::stderr(), config.format);
+    }
+    Ok(logger)
+}
 
 fn main() {
-    let addr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7878);
-    let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(30)).expect("Failed to connect");
+    let mut config = match utils::config::load::<ClientConfig, _>(std::env::args().skip(1)) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            print!("{}", utils::config::usage::<ClientConfig>("client"));
+            return;
+        },
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        },
+    };
+
+    let stream = match TcpStream::connect_timeout(&config.server, Duration::from_secs(config.connect_timeout)) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Failed to connect to {}: {}", config.server, e);
+            std::process::exit(1);
+        },
+    };
+
+    let tui = match config.ui {
+        UiMode::Tui => true,
+        UiMode::Plain => false,
+        UiMode::Auto => std::io::stdin().is_terminal() && std::io::stdout().is_terminal(),
+    };
+    if tui && config.log.stderr {
+        eprintln!("Ignoring log.stderr, it would draw over the terminal UI");
+        config.log.stderr = false;
+    }
+
+    let logger = match build_logger(&config.log) {
+        Ok(logger) => Arc::new(logger),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
 
     let (sender, receiver) = mpsc::channel();
-    let client_impl = Arc::new(ClientImpl::new(sender));
+    let client_impl = Arc::new(ClientImpl::new(sender, logger.clone(), config.files.dir.clone()));
     let handler = client_impl::client_handler_build(client_impl.clone());
-    let log_file = File::create("client_log.txt").expect("failed to create file client_log.txt");
-    let logger = Arc::new(Logger::new(Some(Box::new(log_file))));
-    let mut client = Client::new(stream, handler, logger);
+    let mut client = Client::new(stream, handler, logger.clone());
     client.start();
 
-    let mut name = client_impl.register_user(&client.state);
-
-    loop {
-        let res = receiver.recv();
-        let code = match res {
-            Ok(code) => code,
-            Err(e) => {
-                println!("{}", e);
-                return;
-            }
-        };
-    
-        match code {
-            MainThreadCode::NameAlreadyRegistered => {
-                name = client_impl.register_user(&client.state);
-            }
-            MainThreadCode::RegistrationSucecssful => break,
-            MainThreadCode::Disconnected => {
-                println!("Main thread received disconnected code");
-                return;
-            }
+    if !config.compression.is_empty() || config.checksum || config.codec != Codec::Bincode {
+        match client.state.hello(&config.compression, &[config.codec], config.checksum, Duration::from_secs(config.connect_timeout)) {
+            Ok(options) => {
+                logger.log(Level::Info, module_path!(), "Negotiated frame options", &[("compression", &options.compression), ("checksum", &options.checksum), ("codec", &options.codec)]);
+                if config.checksum && !options.checksum {
+                    eprintln!("The server does not checksum frames, continuing without");
+                }
+                if options.codec != config.codec {
+                    eprintln!("The server does not speak {}, continuing with {}", config.codec, options.codec);
+                }
+            },
+            Err(e) => logger.log(Level::Warn, module_path!(), "Server refused the hello", &[("error", &e)]),
         }
     }
 
-    loop {
-        let msg: String = utils::io::read_val::<_, _, _> (
-            "Send (q for quit): ",
-            |_input: &str| format!("Expected string").to_string(),
-            None::<fn(&String) -> bool>,
-        );
-
-        if msg.to_lowercase() == "q".to_lowercase() {
-            break;
+    if tui {
+        if let Err(e) = tui::run(&client.state, &client_impl, receiver, config.server) {
+            eprintln!("Terminal UI failed: {}", e);
         }
-
-        let msg = messages::client::MsgOnSent {
-            msg: msg.as_str(),
-        };
-        let msg_encoded: Vec<u8> = message_stream::serialize_data(messages::client::Message::OnSent as u32, &msg).expect("Failed to serialze message");
-        client.state.send(msg_encoded.as_slice()).expect("failed to send message");
+    }
+    else {
+        plain::run(&client.state, &client_impl, receiver);
     }
 
-    client.shutdown();
+    if let Err(e) = client.shutdown() {
+        println!("{}", e);
+    }
 }
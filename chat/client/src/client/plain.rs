@@ -0,0 +1,112 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use client_lib::ClientState;
+use client_lib::command::{self, Command};
+use super::client_impl::{ClientImpl, MainThreadCode, Registration};
+
+//line based interface for pipes and dumb terminals, incoming text may interleave with the prompt
+pub fn run(client_state: &ClientState, client_impl: &ClientImpl, events: mpsc::Receiver<MainThreadCode>) {
+    let mut name = prompt_name();
+    loop {
+        let registration = client_impl.register_user(client_state, &name);
+        //anything the server said before answering, e.g. why a name is reserved
+        if print_events(&events) {
+            return;
+        }
+        match registration {
+            Ok(Registration::Registered(user)) => {
+                println!("{} username registered!", user);
+                break;
+            },
+            Ok(Registration::Refused(reason)) => {
+                println!("{}", reason);
+                name = prompt_name();
+            },
+            Err(e) => {
+                println!("Registration failed: {}", e);
+                return;
+            },
+        }
+    }
+
+    //stdin blocks, so it is read on its own thread while incoming events are printed here
+    let (line_sender, lines) = mpsc::channel();
+    thread::spawn(move || read_lines(line_sender));
+
+    loop {
+        if print_events(&events) {
+            return;
+        }
+
+        let msg = match lines.recv_timeout(Duration::from_millis(100)) {
+            Ok(msg) => msg,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        if msg.to_lowercase() == "q" {
+            return;
+        }
+        if msg.is_empty() {
+            continue;
+        }
+
+        let command = match command::parse(&msg) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            },
+        };
+        match command {
+            Command::Quit(_) => return,
+            Command::Help(topic) => println!("{}", command::help(topic)),
+            command => {
+                if let Err(e) = client_impl.send_command(client_state, &command) {
+                    println!("Failed to send message: {}", e);
+                    return;
+                }
+            },
+        }
+    }
+}
+
+//returns true once the connection is gone
+fn print_events(events: &mpsc::Receiver<MainThreadCode>) -> bool {
+    while let Ok(code) = events.try_recv() {
+        if let Some(text) = code.describe() {
+            println!("{}", text);
+        }
+        if let MainThreadCode::Disconnected = code {
+            return true;
+        }
+    }
+    false
+}
+
+fn prompt_name() -> String {
+    utils::io::read_val::<_, _, _> (
+        "Enter name:",
+        |_input: &str| String::from("Expected a name"),
+        Some(|val: &String| ClientImpl::valid_name(val)),
+    )
+}
+
+fn read_lines(sender: mpsc::Sender<String>) {
+    let stdin = io::stdin();
+    loop {
+        print!("Send (q for quit): ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {},
+        }
+        if sender.send(line.trim().to_string()).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex};
+use std::thread;
+
+use sha2::{Digest, Sha256};
+
+use client_lib::{ClientSender, request::PendingRequest};
+use netutils::{message_stream, logger::{Level, Logger}};
+use netutils::messages;
+use super::client_impl::{MainThreadCode, REQUEST_TIMEOUT};
+
+const CHUNK_SIZE: usize = 16 * 1024;
+const WINDOW: usize = 8; //chunks in flight before waiting for the server to acknowledge the oldest one
+
+struct Offer {
+    from: String,
+    name: String,
+    size: u64,
+    sha256: [u8; 32],
+}
+
+struct Download {
+    offer: Offer,
+    file: File,
+    part: PathBuf,
+    received: u64,
+}
+
+struct Upload {
+    to: String,
+    name: String,
+    path: PathBuf,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Uploads {
+    offered: HashMap<u32, Upload>,
+    //the recipient can accept before the thread that offered the file learned the id from the reply
+    accepted_early: HashMap<u32, u64>,
+}
+
+//file transfers in both directions; incoming data is written by the reader thread as it arrives,
+//every upload runs on its own thread
+pub struct Transfers {
+    dir: PathBuf,
+    events: mpsc::Sender<MainThreadCode>,
+    logger: Arc<Logger>,
+    offers: Mutex<HashMap<u32, Offer>>,
+    downloads: Mutex<HashMap<u32, Download>>,
+    uploads: Mutex<Uploads>,
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_file(path: &Path) -> io::Result<(u64, [u8; 32])> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((size, hasher.finalize().into()))
+}
+
+//the name comes from another user, it must not be able to point outside the download directory
+fn safe_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.chars().any(|c| c == '/' || c == '\\' || c.is_control())
+}
+
+//"report.txt", then "report (1).txt", "report (2).txt", ...
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let stem = Path::new(name).file_stem().and_then(|stem| stem.to_str()).unwrap_or(name);
+    let extension = Path::new(name).extension().and_then(|ext| ext.to_str()).map(|ext| format!(".{}", ext)).unwrap_or_default();
+    let mut i = 1;
+    loop {
+        let path = dir.join(format!("{} ({}){}", stem, i, extension));
+        if !path.exists() {
+            return path;
+        }
+        i += 1;
+    }
+}
+
+impl Transfers {
+    pub fn new(dir: PathBuf, events: mpsc::Sender<MainThreadCode>, logger: Arc<Logger>) -> Self {
+        Transfers {
+            dir,
+            events,
+            logger,
+            offers: Mutex::new(HashMap::new()),
+            downloads: Mutex::new(HashMap::new()),
+            uploads: Mutex::new(Uploads::default()),
+        }
+    }
+
+    fn notify(&self, code: MainThreadCode) {
+        let _ = self.events.send(code);
+    }
+
+    fn failed(&self, text: String) {
+        self.notify(MainThreadCode::TransferFailed(text));
+    }
+
+    //partial downloads are named after the file's hash so offering the same file again resumes them
+    fn part_path(&self, offer: &Offer) -> PathBuf {
+        self.dir.join(format!("{}.{}.part", offer.name, to_hex(&offer.sha256[..8])))
+    }
+
+    fn send_cancel(&self, sender: &ClientSender, id: u32, reason: &str) -> io::Result<()> {
+        let msg = messages::client::MsgFileCancel {
+            id,
+            reason,
+        };
+        match message_stream::serialize_request_with(sender.codec(), messages::client::Message::OnFileCancel as u32, 0, &msg) {
+            Ok(msg_encoded) => sender.send(msg_encoded.as_slice()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    //hashes the file and offers it on a separate thread, large files take a while
+    pub fn send(self: &Arc<Self>, sender: ClientSender, to: &str, path: &str) {
+        let transfers = self.clone();
+        let to = to.to_string();
+        let path = PathBuf::from(path);
+        thread::spawn(move || {
+            if let Err(e) = transfers.offer(&sender, &to, &path) {
+                transfers.failed(format!("Failed to offer {} to {}: {}", path.display(), to, e));
+            }
+        });
+    }
+
+    fn offer(self: &Arc<Self>, sender: &ClientSender, to: &str, path: &Path) -> Result<(), Box<dyn Error>> {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => return Err("not a file name".into()),
+        };
+        let (size, sha256) = hash_file(path)?;
+
+        let msg = messages::client::MsgFileOffer {
+            to,
+            name: name.as_str(),
+            size,
+            sha256,
+        };
+        let response = sender.request(messages::client::Message::OnFileOffer as u32, &msg, REQUEST_TIMEOUT)?;
+        let offered = response.decode_data::<messages::server::MsgFileOffered>()?;
+        let id = offered.id;
+        self.notify(MainThreadCode::Transfer(format!("Offered {} ({}) to {} as transfer {}", name, format_size(size), offered.to, id)));
+
+        let upload = Upload {
+            to: offered.to.to_string(),
+            name,
+            path: path.to_path_buf(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+        let mut uploads = self.uploads.lock().expect("Failed to lock mutex");
+        let early = uploads.accepted_early.remove(&id);
+        uploads.offered.insert(id, upload);
+        drop(uploads);
+        if let Some(offset) = early {
+            self.start_upload(sender.clone(), id, offset);
+        }
+        Ok(())
+    }
+
+    //the recipient accepted, the data is sent from `offset` on
+    pub fn accepted(self: &Arc<Self>, sender: ClientSender, id: u32, user: &str, offset: u64) {
+        let mut uploads = self.uploads.lock().expect("Failed to lock mutex");
+        if !uploads.offered.contains_key(&id) {
+            uploads.accepted_early.insert(id, offset);
+            return;
+        }
+        drop(uploads);
+        self.logger.log(Level::Debug, module_path!(), "Upload accepted", &[("id", &id), ("user", &user), ("offset", &offset)]);
+        self.start_upload(sender, id, offset);
+    }
+
+    fn start_upload(self: &Arc<Self>, sender: ClientSender, id: u32, offset: u64) {
+        let (name, to, path, cancelled) = match self.uploads.lock().expect("Failed to lock mutex").offered.get(&id) {
+            Some(upload) => (upload.name.clone(), upload.to.clone(), upload.path.clone(), upload.cancelled.clone()),
+            None => return,
+        };
+        if offset > 0 {
+            self.notify(MainThreadCode::Transfer(format!("Resuming {} for {} at {}", name, to, format_size(offset))));
+        }
+
+        let transfers = self.clone();
+        thread::spawn(move || {
+            let res = upload(&sender, id, &path, offset, &cancelled);
+            transfers.uploads.lock().expect("Failed to lock mutex").offered.remove(&id);
+            match res {
+                Ok(()) => transfers.notify(MainThreadCode::Transfer(format!("Sent {} to {}", name, to))),
+                //whoever cancelled has already been told
+                Err(_) if cancelled.load(Ordering::Relaxed) => {},
+                Err(e) => {
+                    transfers.failed(format!("Sending {} to {} failed: {}", name, to, e));
+                    if let Err(e) = transfers.send_cancel(&sender, id, &e.to_string()) {
+                        transfers.logger.log(Level::Warn, module_path!(), "Failed to cancel transfer", &[("id", &id), ("error", &e)]);
+                    }
+                },
+            }
+        });
+    }
+
+    pub fn declined(&self, id: u32, user: &str) {
+        let upload = self.uploads.lock().expect("Failed to lock mutex").offered.remove(&id);
+        if let Some(upload) = upload {
+            self.notify(MainThreadCode::Transfer(format!("{} declined {}", user, upload.name)));
+        }
+    }
+
+    //the other side cancelled or disconnected, partial downloads are kept for resuming
+    pub fn cancelled(&self, id: u32, user: &str, reason: &str) {
+        let name = if let Some(upload) = self.uploads.lock().expect("Failed to lock mutex").offered.remove(&id) {
+            upload.cancelled.store(true, Ordering::Relaxed);
+            upload.name
+        }
+        else if let Some(download) = self.downloads.lock().expect("Failed to lock mutex").remove(&id) {
+            download.offer.name
+        }
+        else if let Some(offer) = self.offers.lock().expect("Failed to lock mutex").remove(&id) {
+            offer.name
+        }
+        else {
+            return;
+        };
+        self.failed(format!("{} cancelled the transfer of {}: {}", user, name, reason));
+    }
+
+    pub fn offered(&self, sender: &ClientSender, id: u32, from: &str, name: &str, size: u64, sha256: [u8; 32]) -> io::Result<()> {
+        if !safe_name(name) {
+            self.failed(format!("{} offered a file named {:?}, refused", from, name));
+            let msg = messages::client::MsgFileDecline {
+                id,
+            };
+            return match message_stream::serialize_request_with(sender.codec(), messages::client::Message::OnFileDecline as u32, 0, &msg) {
+                Ok(msg_encoded) => sender.send(msg_encoded.as_slice()),
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            };
+        }
+
+        self.notify(MainThreadCode::FileOffer { id, from: from.to_string(), name: name.to_string(), size });
+        let offer = Offer {
+            from: from.to_string(),
+            name: name.to_string(),
+            size,
+            sha256,
+        };
+        self.offers.lock().expect("Failed to lock mutex").insert(id, offer);
+        Ok(())
+    }
+
+    pub fn accept(&self, sender: &ClientSender, id: u32) -> io::Result<()> {
+        let offer = match self.offers.lock().expect("Failed to lock mutex").remove(&id) {
+            Some(offer) => offer,
+            None => {
+                self.failed(format!("No file offer {}", id));
+                return Ok(());
+            },
+        };
+
+        let part = self.part_path(&offer);
+        let opened = fs::create_dir_all(&self.dir).and_then(|_| OpenOptions::new().create(true).append(true).open(&part));
+        let file = match opened {
+            Ok(file) => file,
+            Err(e) => {
+                self.failed(format!("Failed to create {}: {}", part.display(), e));
+                self.offers.lock().expect("Failed to lock mutex").insert(id, offer);
+                return Ok(());
+            },
+        };
+        //a leftover longer than the file cannot be the start of it
+        let mut offset = file.metadata()?.len();
+        if offset > offer.size {
+            file.set_len(0)?;
+            offset = 0;
+        }
+
+        let text = match offset {
+            0 => format!("Downloading {} from {}", offer.name, offer.from),
+            offset => format!("Resuming {} from {} at {}", offer.name, offer.from, format_size(offset)),
+        };
+        self.downloads.lock().expect("Failed to lock mutex").insert(id, Download {
+            offer,
+            file,
+            part,
+            received: offset,
+        });
+        self.notify(MainThreadCode::Transfer(text));
+
+        let msg = messages::client::MsgFileAccept {
+            id,
+            offset,
+        };
+        match message_stream::serialize_request_with(sender.codec(), messages::client::Message::OnFileAccept as u32, 0, &msg) {
+            Ok(msg_encoded) => sender.send(msg_encoded.as_slice()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    pub fn decline(&self, sender: &ClientSender, id: u32) -> io::Result<()> {
+        let offer = match self.offers.lock().expect("Failed to lock mutex").remove(&id) {
+            Some(offer) => offer,
+            None => {
+                self.failed(format!("No file offer {}", id));
+                return Ok(());
+            },
+        };
+        self.notify(MainThreadCode::Transfer(format!("Declined {} from {}", offer.name, offer.from)));
+
+        let msg = messages::client::MsgFileDecline {
+            id,
+        };
+        match message_stream::serialize_request_with(sender.codec(), messages::client::Message::OnFileDecline as u32, 0, &msg) {
+            Ok(msg_encoded) => sender.send(msg_encoded.as_slice()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    pub fn cancel(&self, sender: &ClientSender, id: u32) -> io::Result<()> {
+        if self.offers.lock().expect("Failed to lock mutex").contains_key(&id) {
+            return self.decline(sender, id);
+        }
+        let name = if let Some(upload) = self.uploads.lock().expect("Failed to lock mutex").offered.remove(&id) {
+            upload.cancelled.store(true, Ordering::Relaxed);
+            upload.name
+        }
+        else if let Some(download) = self.downloads.lock().expect("Failed to lock mutex").remove(&id) {
+            download.offer.name
+        }
+        else {
+            self.failed(format!("No transfer {}", id));
+            return Ok(());
+        };
+        self.notify(MainThreadCode::Transfer(format!("Cancelled the transfer of {}", name)));
+        self.send_cancel(sender, id, "cancelled")
+    }
+
+    pub fn chunk(&self, sender: &ClientSender, id: u32, offset: u64, data: &[u8]) {
+        let mut downloads = self.downloads.lock().expect("Failed to lock mutex");
+        let download = match downloads.get_mut(&id) {
+            Some(download) => download,
+            None => return,
+        };
+        let res = match offset == download.received && download.received + data.len() as u64 <= download.offer.size {
+            true => download.file.write_all(data).map_err(|e| e.to_string()),
+            false => Err(format!("unexpected data at offset {}", offset)),
+        };
+        match res {
+            Ok(()) => download.received += data.len() as u64,
+            Err(e) => {
+                let download = downloads.remove(&id).expect("download checked above");
+                drop(downloads);
+                self.failed(format!("Downloading {} failed: {}", download.offer.name, e));
+                if let Err(e) = self.send_cancel(sender, id, &e) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to cancel transfer", &[("id", &id), ("error", &e)]);
+                }
+            },
+        }
+    }
+
+    //checks the download against the offered hash and moves it next to the other downloads
+    pub fn complete(&self, id: u32) {
+        let download = match self.downloads.lock().expect("Failed to lock mutex").remove(&id) {
+            Some(download) => download,
+            None => return,
+        };
+        let Download { offer, file, part, received } = download;
+        drop(file);
+
+        if received != offer.size {
+            self.failed(format!("{} from {} is incomplete, {} of {}", offer.name, offer.from, format_size(received), format_size(offer.size)));
+            return;
+        }
+        match hash_file(&part) {
+            Ok((_, sha256)) if sha256 == offer.sha256 => {},
+            Ok(_) => {
+                let _ = fs::remove_file(&part);
+                self.failed(format!("{} from {} is corrupted (SHA-256 mismatch), discarded", offer.name, offer.from));
+                return;
+            },
+            Err(e) => {
+                self.failed(format!("Failed to verify {}: {}", part.display(), e));
+                return;
+            },
+        }
+
+        let path = unique_path(&self.dir, &offer.name);
+        match fs::rename(&part, &path) {
+            Ok(()) => self.notify(MainThreadCode::Transfer(format!("Saved {} from {} to {}", offer.name, offer.from, path.display()))),
+            Err(e) => self.failed(format!("Failed to save {}: {}", path.display(), e)),
+        }
+    }
+}
+
+fn upload(sender: &ClientSender, id: u32, path: &Path, offset: u64, cancelled: &AtomicBool) -> Result<(), Box<dyn Error>> {
+    let mut window: VecDeque<PendingRequest> = VecDeque::new();
+    let res = send_chunks(sender, id, path, offset, cancelled, &mut window);
+    //wait for the chunks still in flight even after a failure, their replies would show up as stray errors otherwise
+    let mut acked = Ok(());
+    for pending in window {
+        if let Err(e) = pending.wait(REQUEST_TIMEOUT) {
+            acked = acked.and(Err(e));
+        }
+    }
+    res?;
+    acked?;
+
+    let msg = messages::client::MsgFileComplete {
+        id,
+    };
+    sender.request(messages::client::Message::OnFileComplete as u32, &msg, REQUEST_TIMEOUT)?;
+    Ok(())
+}
+
+fn send_chunks(sender: &ClientSender, id: u32, path: &Path, offset: u64, cancelled: &AtomicBool, window: &mut VecDeque<PendingRequest>) -> Result<(), Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut offset = offset;
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err("cancelled".into());
+        }
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        let msg = messages::client::MsgFileChunk {
+            id,
+            offset,
+            data: Cow::Borrowed(&buffer[..read]),
+        };
+        window.push_back(sender.request_async(messages::client::Message::OnFileChunk as u32, &msg)?);
+        offset += read as u64;
+        if window.len() >= WINDOW {
+            window.pop_front().expect("window is not empty").wait(REQUEST_TIMEOUT)?;
+        }
+    }
+}
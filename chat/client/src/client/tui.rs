@@ -0,0 +1,374 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::Frame;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use client_lib::ClientState;
+use client_lib::command::{self, Command};
+use client_lib::request::{PendingRequest, RequestError};
+use super::client_impl::{self, ClientImpl, MainThreadCode, Registration};
+
+const SCROLLBACK_LINES: usize = 1000;
+const SIDEBAR_WIDTH: u16 = 20;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Chat,
+    Own,
+    Private,
+    Event,
+    Error,
+}
+
+enum Action {
+    None,
+    Submit(String),
+    Quit,
+}
+
+struct App {
+    server: SocketAddr,
+    scrollback: VecDeque<(Kind, String)>,
+    users: BTreeSet<String>,
+    input: String,
+    cursor: usize, //in chars
+    name: Option<String>, //set once registered
+    room: Option<String>,
+    connected: bool,
+    scroll: usize, //rendered lines hidden below the bottom of the scrollback pane
+}
+
+impl App {
+    fn new(server: SocketAddr) -> Self {
+        App {
+            server,
+            scrollback: VecDeque::new(),
+            users: BTreeSet::new(),
+            input: String::new(),
+            cursor: 0,
+            name: None,
+            room: None,
+            connected: true,
+            scroll: 0,
+        }
+    }
+
+    fn push(&mut self, kind: Kind, text: String) {
+        self.scrollback.push_back((kind, text));
+        while self.scrollback.len() > SCROLLBACK_LINES {
+            self.scrollback.pop_front();
+        }
+    }
+
+    fn on_code(&mut self, code: MainThreadCode) {
+        match code {
+            MainThreadCode::Message { user, msg } => self.push(Kind::Chat, format!("<{}> {}", user, msg)),
+            MainThreadCode::PrivateMessage { from, msg } => self.push(Kind::Private, format!("*{}* {}", from, msg)),
+            MainThreadCode::Action { .. } => self.push_event(&code),
+            MainThreadCode::RoomJoined { ref user, ref room } => {
+                if Some(user) == self.name.as_ref() {
+                    self.room = Some(room.clone());
+                }
+                self.push_event(&code);
+            },
+            MainThreadCode::RoomParted { .. } | MainThreadCode::Who { .. } | MainThreadCode::Away { .. } => self.push_event(&code),
+            MainThreadCode::Users(users) => self.users = users.into_iter().collect(),
+            MainThreadCode::Joined(ref user) => {
+                self.users.insert(user.clone());
+                self.push_event(&code);
+            },
+            MainThreadCode::Left(ref user) => {
+                self.users.remove(user);
+                self.push_event(&code);
+            },
+            MainThreadCode::Disconnected => {
+                self.connected = false;
+                self.users.clear();
+                self.push(Kind::Error, String::from("Disconnected from the server, press Esc to quit"));
+            },
+            MainThreadCode::Renamed { ref old, ref new } => {
+                if self.users.remove(old) {
+                    self.users.insert(new.clone());
+                }
+                if Some(old) == self.name.as_ref() {
+                    self.name = Some(new.clone());
+                }
+                self.push_event(&code);
+            },
+            MainThreadCode::FileOffer { .. } | MainThreadCode::Transfer(_) | MainThreadCode::Notice(_) => self.push_event(&code),
+            MainThreadCode::TransferFailed(text) => self.push(Kind::Error, text),
+            MainThreadCode::Error(ref e) => self.push(Kind::Error, e.message.clone()),
+        }
+    }
+
+    fn on_registration(&mut self, registration: Result<Registration, RequestError>) {
+        match registration {
+            Ok(Registration::Registered(user)) => {
+                self.push(Kind::Event, format!("{} username registered!", user));
+                self.name = Some(user.clone());
+                self.room = Some(String::from("lobby"));
+                self.users.insert(user);
+            },
+            Ok(Registration::Refused(reason)) => self.push(Kind::Error, reason),
+            Err(e) => self.push(Kind::Error, format!("Registration failed: {}", e)),
+        }
+    }
+
+    fn push_event(&mut self, code: &MainThreadCode) {
+        if let Some(text) = code.describe() {
+            self.push(Kind::Event, text);
+        }
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.input.char_indices().nth(cursor).map(|(i, _)| i).unwrap_or(self.input.len())
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::None;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => return Action::Quit,
+            KeyCode::Char('u') if ctrl => {
+                self.input.clear();
+                self.cursor = 0;
+            },
+            KeyCode::Char(c) => {
+                let index = self.byte_index(self.cursor);
+                self.input.insert(index, c);
+                self.cursor += 1;
+            },
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let index = self.byte_index(self.cursor);
+                self.input.remove(index);
+            },
+            KeyCode::Delete if self.cursor < self.input.chars().count() => {
+                let index = self.byte_index(self.cursor);
+                self.input.remove(index);
+            },
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.chars().count(),
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Enter => {
+                let line = self.input.trim().to_string();
+                self.input.clear();
+                self.cursor = 0;
+                if !line.is_empty() {
+                    return Action::Submit(line);
+                }
+            },
+            _ => {},
+        }
+        Action::None
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [main, input, status] = Layout::vertical([Constraint::Min(3), Constraint::Length(3), Constraint::Length(1)]).areas(frame.area());
+        let [chat, sidebar] = Layout::horizontal([Constraint::Min(10), Constraint::Length(SIDEBAR_WIDTH)]).areas(main);
+
+        self.render_scrollback(frame, chat);
+
+        let users: Vec<ListItem> = self.users.iter()
+            .map(|user| {
+                let style = if Some(user) == self.name.as_ref() { Style::default().add_modifier(Modifier::BOLD) } else { Style::default() };
+                ListItem::new(user.as_str()).style(style)
+            })
+            .collect();
+        frame.render_widget(List::new(users).block(Block::default().borders(Borders::ALL).title(format!("Users ({})", self.users.len()))), sidebar);
+
+        self.render_input(frame, input);
+
+        let (state, color) = match (self.connected, &self.name) {
+            (false, _) => (String::from("disconnected"), Color::Red),
+            (true, Some(name)) => (format!("connected to {} as {} in #{}", self.server, name, self.room.as_deref().unwrap_or("lobby")), Color::Green),
+            (true, None) => (format!("connected to {}, choose a name", self.server), Color::Yellow),
+        };
+        let mut spans = vec![Span::styled(format!(" {} ", state), Style::default().fg(Color::Black).bg(color))];
+        if self.scroll > 0 {
+            spans.push(Span::raw(format!(" scrolled up {} lines ", self.scroll)));
+        }
+        spans.push(Span::raw(" PgUp/PgDn scroll, /help commands, Esc quit"));
+        frame.render_widget(Paragraph::new(Line::from(spans)), status);
+    }
+
+    fn render_scrollback(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::ALL).title("Chat");
+        let inner = block.inner(area);
+        let width = inner.width.max(1) as usize;
+        let height = inner.height as usize;
+
+        let mut lines: Vec<Line> = Vec::new();
+        for (kind, text) in self.scrollback.iter() {
+            let style = match kind {
+                Kind::Chat => Style::default(),
+                Kind::Own => Style::default().fg(Color::Cyan),
+                Kind::Private => Style::default().fg(Color::Magenta),
+                Kind::Event => Style::default().fg(Color::DarkGray),
+                Kind::Error => Style::default().fg(Color::Red),
+            };
+            for part in wrap(text, width) {
+                lines.push(Line::styled(part, style));
+            }
+        }
+
+        //keep the view still while scrolled up, but never past the first line
+        self.scroll = self.scroll.min(lines.len().saturating_sub(height));
+        let end = lines.len() - self.scroll;
+        let start = end.saturating_sub(height);
+        let visible: Vec<Line> = lines.drain(start..end).collect();
+        frame.render_widget(Paragraph::new(visible).block(block), area);
+    }
+
+    fn render_input(&self, frame: &mut Frame, area: Rect) {
+        let title = match self.name {
+            Some(_) => "Message",
+            None => "Name",
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let inner = block.inner(area);
+
+        //scroll the input horizontally so the cursor stays visible
+        let before: String = self.input.chars().take(self.cursor).collect();
+        let width = inner.width.saturating_sub(1) as usize;
+        let mut skip = 0;
+        while before.chars().skip(skip).collect::<String>().width() > width {
+            skip += 1;
+        }
+        let shown: String = self.input.chars().skip(skip).collect();
+        let cursor_x = before.chars().skip(skip).collect::<String>().width() as u16;
+
+        frame.render_widget(Paragraph::new(shown).block(block), area);
+        frame.set_cursor_position((inner.x + cursor_x, inner.y));
+    }
+}
+
+//hard wraps on display width, long words are split
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0;
+    for c in text.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if line_width + c_width > width && !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+        }
+        line.push(c);
+        line_width += c_width;
+    }
+    lines.push(line);
+    lines
+}
+
+pub fn run(client_state: &ClientState, client_impl: &ClientImpl, events: mpsc::Receiver<MainThreadCode>, server: SocketAddr) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let res = event_loop(&mut terminal, client_state, client_impl, events, server);
+    ratatui::restore();
+    res
+}
+
+fn event_loop(terminal: &mut ratatui::DefaultTerminal, client_state: &ClientState, client_impl: &ClientImpl, events: mpsc::Receiver<MainThreadCode>, server: SocketAddr) -> io::Result<()> {
+    let mut app = App::new(server);
+    app.push(Kind::Event, format!("Connected to {}, enter a name to join", server));
+
+    //the ui keeps running while the server answers
+    let mut registering: Option<(PendingRequest, Instant)> = None;
+    loop {
+        while let Ok(code) = events.try_recv() {
+            app.on_code(code);
+        }
+        if let Some((pending, started)) = registering.as_mut() {
+            if let Some(response) = pending.try_take() {
+                registering = None;
+                app.on_registration(ClientImpl::registration(response));
+            }
+            else if started.elapsed() > client_impl::REQUEST_TIMEOUT {
+                registering = None;
+                app.on_registration(Err(RequestError::Timeout));
+            }
+        }
+        terminal.draw(|frame| app.render(frame))?;
+
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let line = match event::read()? {
+            Event::Key(key) => match app.on_key(key) {
+                Action::Quit => return Ok(()),
+                Action::Submit(line) => line,
+                Action::None => continue,
+            },
+            _ => continue,
+        };
+
+        if !app.connected {
+            app.push(Kind::Error, String::from("Not connected"));
+            continue;
+        }
+        let res = match app.name {
+            None if !ClientImpl::valid_name(&line) => {
+                app.push(Kind::Error, String::from("Expected a name"));
+                continue;
+            },
+            None if registering.is_some() => {
+                app.push(Kind::Error, String::from("Still waiting for the server to accept the name"));
+                continue;
+            },
+            None => {
+                match client_impl.register_user_async(client_state, &line) {
+                    Ok(pending) => registering = Some((pending, Instant::now())),
+                    Err(e) => app.on_registration(Err(e)),
+                }
+                continue;
+            },
+            Some(ref name) => {
+                let command = match command::parse(&line) {
+                    Ok(command) => command,
+                    Err(e) => {
+                        app.push(Kind::Error, e.to_string());
+                        continue;
+                    },
+                };
+                //the server does not echo our own messages back
+                let echo = match command {
+                    Command::Quit(_) => return Ok(()),
+                    Command::Help(topic) => {
+                        for text in command::help(topic).lines() {
+                            app.push(Kind::Event, text.to_string());
+                        }
+                        continue;
+                    },
+                    Command::Say(msg) => Some((Kind::Own, format!("<{}> {}", name, msg))),
+                    Command::Me(msg) => Some((Kind::Own, format!("* {} {}", name, msg))),
+                    Command::Msg { to, msg } => Some((Kind::Private, format!("-> *{}* {}", to, msg))),
+                    _ => None,
+                };
+                let res = client_impl.send_command(client_state, &command);
+                if let (Ok(_), Some((kind, text))) = (&res, echo) {
+                    app.push(kind, text);
+                    app.scroll = 0;
+                }
+                res.map(|_| ())
+            },
+        };
+        if let Err(e) = res {
+            app.push(Kind::Error, format!("Failed to send message: {}", e));
+        }
+    }
+}
@@ -5,32 +5,51 @@ use std::{
 };
 use core::cell::RefCell;
 
+use netutils::message_stream::{FrameBuffer, FrameOptions, Streams};
+
 pub struct ClientStream {
     pub stream_write: Mutex<Option<TcpStream>>,
     pub addr: SocketAddr,
-    pub(crate) stream_read: RefCell<Option<TcpStream>>,
+    pub(crate) stream_read: RefCell<Option<TcpStream>>, //only touched by the reader thread
+    frame_options: Mutex<FrameOptions>, //what the server agreed to in its hello
 }
 
+unsafe impl Sync for ClientStream {}
+unsafe impl Send for ClientStream {}
+
 impl ClientStream {
     pub(crate) fn new(stream: TcpStream) -> Self {
         let stream_read = stream.try_clone().expect("Failed to clone TcpStream");
         let addr = stream.peer_addr().expect("Unable to get peer_addr");
         ClientStream {
             stream_write:Mutex::new(Some(stream)),
-            addr:addr,
+            addr,
             stream_read:RefCell::new(Some(stream_read)),
+            frame_options: Mutex::new(FrameOptions::default()),
         }
     }
+
+    pub fn frame_options(&self) -> FrameOptions {
+        *self.frame_options.lock().expect("Failed to lock mutex")
+    }
+
+    pub(crate) fn set_frame_options(&self, options: FrameOptions) {
+        *self.frame_options.lock().expect("Failed to lock mutex") = options;
+    }
 }
 
 pub(crate) struct ClientData {
-   pub(crate) msg: Vec::<u8>,
+   pub(crate) buffer: FrameBuffer, //received bytes not parsed into a frame yet
+   pub(crate) streams: Streams, //messages the server sends in several frames
+   pub(crate) checksums: bool, //set by the first checksummed frame, every later one needs one too
 }
 
 impl ClientData {
     pub(crate) fn new() -> Self {
         ClientData {
-            msg:Vec::new(),
+            buffer: FrameBuffer::default(),
+            streams: Streams::default(),
+            checksums: false,
         }
     }
 }
\ No newline at end of file
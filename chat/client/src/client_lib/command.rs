@@ -0,0 +1,174 @@
+use std::error::Error;
+
+use netutils::message_stream::{self, Codec, MsgError};
+use netutils::messages;
+
+//input typed by a user, lines starting with a single '/' are commands,
+//"//text" sends "/text" as a plain message
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    Say(&'a str),
+    Nick { name: &'a str, password: Option<&'a str> },
+    Reserve(&'a str),
+    Unreserve,
+    Join(&'a str),
+    Part(Option<&'a str>),
+    Msg { to: &'a str, msg: &'a str },
+    Me(&'a str),
+    Who(Option<&'a str>),
+    Away(Option<&'a str>),
+    Send { to: &'a str, path: &'a str },
+    Accept(u32),
+    Decline(u32),
+    Cancel(u32),
+    Quit(Option<&'a str>),
+    Help(Option<&'a str>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Unknown(String),
+    Usage(&'static str),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            ParseError::Unknown(command) => write!(f, "Unknown command /{} (try /help)", command),
+            ParseError::Usage(usage) => write!(f, "Usage: {}", usage),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+//(name, usage, help)
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+    ("nick", "/nick <name> [password]", "Change your name, reserved names need their password"),
+    ("reserve", "/reserve <password>", "Reserve your current name so only you can use it"),
+    ("unreserve", "/unreserve", "Release the reservation of your current name"),
+    ("join", "/join <room>", "Switch to a room, it is created if needed"),
+    ("part", "/part [room]", "Leave the current room and return to the lobby"),
+    ("msg", "/msg <user> <text>", "Send a private message"),
+    ("me", "/me <action>", "Describe what you are doing"),
+    ("who", "/who [room]", "List the users in a room"),
+    ("away", "/away [reason]", "Mark yourself away, without a reason marks you back"),
+    ("send", "/send <user> <file>", "Offer a file to a user"),
+    ("accept", "/accept <id>", "Download a file you were offered, resuming an interrupted download"),
+    ("decline", "/decline <id>", "Refuse a file you were offered"),
+    ("cancel", "/cancel <id>", "Stop a file transfer"),
+    ("quit", "/quit [reason]", "Disconnect and exit"),
+    ("help", "/help [command]", "Show the available commands"),
+];
+
+fn usage(name: &str) -> &'static str {
+    COMMANDS.iter().find(|(n, _, _)| *n == name).map(|(_, usage, _)| *usage).unwrap_or("")
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+fn transfer_id(name: &str, text: &str) -> Result<u32, ParseError> {
+    match split_word(text).0.trim_start_matches('#').parse::<u32>() {
+        Ok(id) => Ok(id),
+        Err(_) => Err(ParseError::Usage(usage(name))),
+    }
+}
+
+fn optional(text: &str) -> Option<&str> {
+    if text.is_empty() { None } else { Some(text) }
+}
+
+pub fn parse(line: &str) -> Result<Command<'_>, ParseError> {
+    let line = line.trim();
+    let input = match line.strip_prefix('/') {
+        Some(escaped) if escaped.starts_with('/') => return Ok(Command::Say(escaped)),
+        Some(input) => input,
+        None => return Ok(Command::Say(line)),
+    };
+
+    let (name, rest) = split_word(input);
+    match name.to_lowercase().as_str() {
+        "nick" => match split_word(rest) {
+            ("", _) => Err(ParseError::Usage(usage("nick"))),
+            (name, password) => Ok(Command::Nick { name, password: optional(password) }),
+        },
+        "reserve" => match rest {
+            "" => Err(ParseError::Usage(usage("reserve"))),
+            password => Ok(Command::Reserve(password)),
+        },
+        "unreserve" => Ok(Command::Unreserve),
+        "join" | "j" => match split_word(rest) {
+            ("", _) => Err(ParseError::Usage(usage("join"))),
+            (room, _) => Ok(Command::Join(room)),
+        },
+        "part" | "leave" => Ok(Command::Part(optional(split_word(rest).0))),
+        "msg" | "query" => match split_word(rest) {
+            ("", _) | (_, "") => Err(ParseError::Usage(usage("msg"))),
+            (to, msg) => Ok(Command::Msg { to, msg }),
+        },
+        "me" => match rest {
+            "" => Err(ParseError::Usage(usage("me"))),
+            action => Ok(Command::Me(action)),
+        },
+        "who" | "names" => Ok(Command::Who(optional(split_word(rest).0))),
+        "away" => Ok(Command::Away(optional(rest))),
+        "send" => match split_word(rest) {
+            ("", _) | (_, "") => Err(ParseError::Usage(usage("send"))),
+            (to, path) => Ok(Command::Send { to, path }),
+        },
+        "accept" => Ok(Command::Accept(transfer_id("accept", rest)?)),
+        "decline" => Ok(Command::Decline(transfer_id("decline", rest)?)),
+        "cancel" => Ok(Command::Cancel(transfer_id("cancel", rest)?)),
+        "quit" | "exit" => Ok(Command::Quit(optional(rest))),
+        "help" | "?" => Ok(Command::Help(optional(split_word(rest).0.trim_start_matches('/')))),
+        _ => Err(ParseError::Unknown(name.to_string())),
+    }
+}
+
+pub fn help(command: Option<&str>) -> String {
+    if let Some(command) = command {
+        return match COMMANDS.iter().find(|(name, _, _)| *name == command) {
+            Some((_, usage, help)) => format!("{}  {}", usage, help),
+            None => format!("Unknown command /{}", command),
+        };
+    }
+
+    let width = COMMANDS.iter().map(|(_, usage, _)| usage.len()).max().unwrap_or(0);
+    let lines: Vec<String> = COMMANDS.iter().map(|(_, usage, help)| format!("{:width$}  {}", usage, help, width = width)).collect();
+    lines.join("\n")
+}
+
+impl<'a> Command<'a> {
+    //frame to send to the server with the connection's codec, None for commands handled locally (quit, help) and file
+    //transfers, which need local state
+    pub fn encode(&self, codec: Codec) -> Result<Option<Vec<u8>>, MsgError> {
+        let encoded = match *self {
+            Command::Say(msg) => message_stream::serialize_request_with(codec, messages::client::Message::OnSent as u32, 0, &messages::client::MsgOnSent { msg })?,
+            Command::Join(room) => message_stream::serialize_request_with(codec, messages::client::Message::OnJoin as u32, 0, &messages::client::MsgJoin { room })?,
+            Command::Part(room) => message_stream::serialize_request_with(codec, messages::client::Message::OnPart as u32, 0, &messages::client::MsgPart { room: room.unwrap_or("") })?,
+            Command::Msg { to, msg } => message_stream::serialize_request_with(codec, messages::client::Message::OnPrivateMsg as u32, 0, &messages::client::MsgPrivateMsg { to, msg })?,
+            Command::Me(msg) => message_stream::serialize_request_with(codec, messages::client::Message::OnAction as u32, 0, &messages::client::MsgAction { msg })?,
+            Command::Who(room) => message_stream::serialize_request_with(codec, messages::client::Message::OnWho as u32, 0, &messages::client::MsgWho { room: room.unwrap_or("") })?,
+            Command::Away(msg) => message_stream::serialize_request_with(codec, messages::client::Message::OnAway as u32, 0, &messages::client::MsgAway { msg })?,
+            Command::Nick { name, password } => message_stream::serialize_request_with(codec, messages::client::Message::OnNick as u32, 0, &messages::client::MsgNick { user: name, password })?,
+            Command::Reserve(password) => message_stream::serialize_request_with(codec, messages::client::Message::OnReserve as u32, 0, &messages::client::MsgReserve { password: Some(password) })?,
+            Command::Unreserve => message_stream::serialize_request_with(codec, messages::client::Message::OnReserve as u32, 0, &messages::client::MsgReserve { password: None })?,
+            Command::Send { .. } | Command::Accept(_) | Command::Decline(_) | Command::Cancel(_) => return Ok(None),
+            Command::Quit(_) | Command::Help(_) => return Ok(None),
+        };
+        Ok(Some(encoded))
+    }
+}
@@ -1,17 +1,27 @@
 use std::{
     net::{TcpStream},
     io::{ErrorKind, Read, Write},
-    sync::{Arc},
+    sync::{atomic::{AtomicU32, Ordering}, Arc},
+    thread,
+    time::{Duration, Instant},
 };
 use core::cell::RefCell;
 
-use netutils::{thread_helper::{self, ThreadHelper}, message_stream::{self, MsgInfo}, logger::Logger};
+use netutils::{thread_helper::{self, ThreadHelper}, message_stream::{self, Codec, Compression, FrameOptions, MsgError, MsgInfo}, logger::{Level, Logger}};
+use netutils::messages;
 
 mod client_error;
 pub mod client;
+pub mod command;
+pub mod request;
+pub mod server_error;
 use client_error::ClientError;
+use request::{PendingRequest, RequestError, Requests, Response};
+use server_error::ServerError;
 use client::{ClientData, ClientStream};
 
+const SEND_TIMEOUT: Duration = Duration::from_secs(30); //how long a frame may wait for room in the send buffer
+
 struct ClientThreads {
     read_thread: ThreadHelper,
 }
@@ -29,6 +39,7 @@ impl ClientThreads {
         self.read_thread.start(String::from("Reader"), move || client_clone0.read_thread());
     }
 
+    #[allow(dead_code)]
     pub fn shutdown(&mut self) -> Result<(), thread_helper::ThreadError> {
         self.read_thread.shutdown()
     }
@@ -38,11 +49,81 @@ impl ClientThreads {
     }
 }
 
+//sending half of a client, cheap to clone and safe to move to other threads
+#[derive(Clone)]
+pub struct ClientSender {
+    stream: Arc<ClientStream>,
+    requests: Arc<Requests>,
+    next_stream_id: Arc<AtomicU32>,
+}
+
+impl ClientSender {
+    //what every payload sent on this connection has to be serialized with
+    pub fn codec(&self) -> Codec {
+        self.stream.frame_options().codec
+    }
+
+    //the socket is nonblocking for the reader, so a full send buffer is waited out here; the lock
+    //is held until the whole frame is written so frames from other threads never interleave
+    pub fn send(&self, buffer: &[u8]) -> Result<(), std::io::Error> {
+        let buffer = message_stream::encode_frame(buffer, &self.stream.frame_options());
+        let guard = self.stream.stream_write.lock().expect("failed to lock mutex");
+        let mut stream = match guard.as_ref() {
+            Some(stream) => stream,
+            None => return Err(std::io::Error::new(ErrorKind::NotConnected, "Not connected to the server")),
+        };
+
+        let deadline = Instant::now() + SEND_TIMEOUT;
+        let mut written = 0;
+        while written < buffer.len() {
+            match stream.write(&buffer[written..]) {
+                Ok(0) => return Err(std::io::Error::new(ErrorKind::WriteZero, "The server stopped taking data")),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(std::io::Error::new(ErrorKind::TimedOut, "The server stopped reading"));
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    //sends `msg` tagged with a fresh request id, the reply is delivered to the returned handle
+    //instead of the handler; an OnError reply becomes RequestError::Server
+    pub fn request_async<T: serde::Serialize>(&self, code: u32, msg: &T) -> Result<PendingRequest, RequestError> {
+        let pending = self.requests.register();
+        let msg_encoded = message_stream::serialize_request_with(self.codec(), code, pending.id(), msg)?;
+        self.send(msg_encoded.as_slice())?;
+        Ok(pending)
+    }
+
+    pub fn request<T: serde::Serialize>(&self, code: u32, msg: &T, timeout: Duration) -> Result<Response, RequestError> {
+        self.request_async(code, msg)?.wait(timeout)
+    }
+
+    //sends what `reader` yields as a stream of `code` chunks of chunk_size, which the server handles as
+    //they arrive; other threads can send between chunks so a large body does not hold up the chat
+    pub fn send_stream<R: Read>(&self, code: u32, request_id: u32, reader: R, chunk_size: usize) -> Result<(), MsgError> {
+        let stream_id = self.next_stream_id.fetch_add(1, Ordering::Relaxed);
+        for frame in message_stream::StreamWriter::new(reader, self.codec(), code, request_id, stream_id, chunk_size) {
+            if let Err(e) = self.send(frame?.as_slice()) {
+                return Err(MsgError::Write(e));
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct ClientState {
     thread_state: Arc<thread_helper::ThreadState>,
     handler: ClientHandler,
     pub stream: Arc<ClientStream>,
     data: RefCell<ClientData>,
+    sender: ClientSender,
     logger: Arc<Logger>,
 }
 
@@ -52,18 +133,66 @@ unsafe impl Send for ClientState {}
 impl ClientState {
     pub fn new(thread_state: Arc<thread_helper::ThreadState>, stream: TcpStream, handler: ClientHandler, logger: Arc<Logger>) -> Self {
         stream.set_nonblocking(true).expect("Failed to put socket in nonblocking mode");
+        let stream = Arc::new(ClientStream::new(stream));
         ClientState {
             thread_state,
             handler,
-            stream:Arc::new(ClientStream::new(stream)),
+            sender: ClientSender {
+                stream: stream.clone(),
+                requests: Arc::new(Requests::default()),
+                next_stream_id: Arc::new(AtomicU32::new(0)),
+            },
+            stream,
             data:RefCell::new(ClientData::new()),
             logger,
         }
     }
 
+    //for threads that send on their own, e.g. file uploads
+    pub fn sender(&self) -> ClientSender {
+        self.sender.clone()
+    }
+
     pub fn send(&self, buffer: &[u8]) -> Result<(), std::io::Error> {
-        self.stream.stream_write.lock().expect("failed to lock mutex").as_ref().expect("Invalid socket").write_all(buffer)?;
-        Ok(())
+        self.sender.send(buffer)
+    }
+
+    pub fn request_async<T: serde::Serialize>(&self, code: u32, msg: &T) -> Result<PendingRequest, RequestError> {
+        self.sender.request_async(code, msg)
+    }
+
+    pub fn request<T: serde::Serialize>(&self, code: u32, msg: &T, timeout: Duration) -> Result<Response, RequestError> {
+        self.sender.request(code, msg, timeout)
+    }
+
+    //offers `algorithms` and `codecs` to the server, most preferred first, and asks for checksums on
+    //every frame; what is sent from then on follows the server's answer, which is returned; servers
+    //that do not know the hello refuse it and frames stay as they are
+    pub fn hello(&self, algorithms: &[Compression], codecs: &[Codec], checksum: bool, timeout: Duration) -> Result<FrameOptions, RequestError> {
+        let msg = messages::client::MsgHello {
+            compression: algorithms.iter().map(|algorithm| *algorithm as u8).collect(),
+            checksum,
+            codecs: codecs.iter().map(|codec| *codec as u8).collect(),
+        };
+        let response = self.request(messages::client::Message::OnHello as u32, &msg, timeout)?;
+        let reply = response.decode_data::<messages::server::MsgHello>()?;
+        let compression = match Compression::from_u8(reply.compression) {
+            Some(compression) => compression,
+            None => return Err(MsgError::Compression { msg: format!("server picked unknown algorithm {}", reply.compression) }.into()),
+        };
+        let codec = match Codec::from_u8(reply.codec) {
+            Some(codec) => codec,
+            None => return Err(MsgError::Codec { msg: format!("server picked unknown codec {}", reply.codec) }.into()),
+        };
+        let options = FrameOptions {
+            compression,
+            threshold: reply.threshold as usize,
+            level: reply.level,
+            checksum: reply.checksum,
+            codec,
+        };
+        self.stream.set_frame_options(options);
+        Ok(options)
     }
 
     pub fn shutdown(&self) -> Result<(), std::io::Error> {
@@ -71,10 +200,10 @@ impl ClientState {
         match res {
             Ok(_) => {},
             Err(thread_helper::ThreadError::AlreadyShuttingDown) => {
-                self.logger.log("Already shutting down...".as_bytes()).unwrap();
+                self.logger.debug(module_path!(), "Already shutting down...");
                 return Ok(())
             },
-            Err(e) => {self.logger.log(format!("{}", e).as_bytes()).unwrap();},
+            Err(e) => {self.logger.log(Level::Error, module_path!(), "Shutdown failed", &[("error", &e)]);},
         }
         
         self.disconnect()
@@ -86,7 +215,8 @@ impl ClientState {
         if let Some(stream) = self.stream.stream_write.lock().expect("Failed to lock mutex").take() {
             drop(stream);
         }
-        (self.handler.on_disconnect)(&self, self.stream.as_ref());
+        self.sender.requests.fail_all();
+        (self.handler.on_disconnect)(self, self.stream.as_ref());
         Ok(())
     }
 
@@ -94,46 +224,42 @@ impl ClientState {
         if let Some(stream) = self.stream.stream_read.take() {
             drop(stream);
         }
-        self.data.borrow_mut().msg = Vec::new();
+        self.data.borrow_mut().buffer.clear();
     }
 
     fn read_thread(&self) {
-        self.logger.log("read_thread start".as_bytes()).unwrap();
+        self.logger.info(module_path!(), "read_thread start");
         loop {
             if self.thread_state.is_shuttingdown() {
-                self.logger.log("Threads are shutting down!!!!".as_bytes()).unwrap();
+                self.logger.debug(module_path!(), "Threads are shutting down!!!!");
                 break;
             }
             
             let res = self.read_server();
             if let Err(e) = res {
-                self.logger.log(format!("{}", e).as_bytes()).unwrap();
+                self.logger.log(Level::Error, module_path!(), "Reading from server failed", &[("error", &e)]);
             }
         }
 
         self.read_thread_cleanup();
-        self.logger.log("read_thread done".as_bytes()).unwrap();
+        self.logger.info(module_path!(), "read_thread done");
     }
 
     fn read_server(&self) -> Result<(), ClientError>  {
-        const BUFF_SZ: usize = 4096;
-        let mut buffer: [u8; BUFF_SZ] = [0; BUFF_SZ];
-
-        match(self.read_helper(&mut buffer)) {
+        match self.read_helper() {
             Ok(_) => {},
             Err(ClientError::IoError(e)) => {
                 let error_kind = e.kind();
                 if error_kind != ErrorKind::WouldBlock && error_kind != ErrorKind::Interrupted {
-                    self.logger.log(format!("Read error: {}, kind {}", e, error_kind).as_bytes()).unwrap();
+                    self.logger.log(Level::Warn, module_path!(), "Read error", &[("error", &e), ("kind", &error_kind)]);
                     self.shutdown()?;
                 }
                 return Ok(())
             },
+            //frames that can be skipped never get here, the rest of the stream is unreadable
             Err(ClientError::MsgError(e)) => {
-                self.logger.log(format!("{}", e).as_bytes()).unwrap();
-                if !self.thread_state.is_shuttingdown() {
-                    self.data.borrow_mut().msg = Vec::new();
-                }
+                self.logger.log(Level::Error, module_path!(), "Corrupt stream from the server", &[("error", &e)]);
+                self.shutdown()?;
                 return Ok(())
             },
             Err(ClientError::ThreadError(e)) => {
@@ -144,30 +270,75 @@ impl ClientState {
         Ok(())
     }
 
-    fn read_helper(&self, buffer: &mut [u8]) -> Result<(), ClientError> {
-        let read = self.stream.stream_read.borrow_mut().as_ref().expect("Invalid socket").read(buffer)?;
+    //replies go to whoever waits for them, errors are handed over already decoded,
+    //everything else goes to on_read as is
+    fn dispatch(&self, msginfo: MsgInfo<'_>) {
+        let result = if msginfo.code != messages::server::Message::OnError as u32 {
+            Ok(msginfo)
+        }
+        else {
+            match msginfo.decode_data::<messages::server::MsgError>() {
+                Ok(msg) => Err(ServerError::from(msg)),
+                Err(e) => {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to decode error reply", &[("error", &e)]);
+                    return;
+                },
+            }
+        };
+
+        let request_id = match &result {
+            Ok(msginfo) => msginfo.request_id,
+            Err(e) => e.request_id,
+        };
+        let result = match request_id {
+            0 => result,
+            //the waiting thread outlives the read buffer, only replies are copied out of it
+            request_id => match self.sender.requests.complete(request_id, result.map(MsgInfo::into_owned).map_err(RequestError::Server)) {
+                None => return,
+                Some(Ok(msginfo)) => Ok(msginfo),
+                Some(Err(RequestError::Server(e))) => Err(e),
+                Some(Err(_)) => return,
+            },
+        };
 
-        let msg = &mut self.data.borrow_mut().msg;
-        msg.extend_from_slice(&buffer[..read]);
-        
-        let mut msg_buffer = msg.as_slice();
+        //nobody waits for it, e.g. the request timed out
+        match result {
+            Ok(msginfo) => (self.handler.on_read)(self, self.stream.as_ref(), &msginfo),
+            Err(e) => (self.handler.on_error)(self, self.stream.as_ref(), &e),
+        }
+    }
+
+    fn read_helper(&self) -> Result<(), ClientError> {
+        let mut data = self.data.borrow_mut();
+        let data = &mut *data;
+        let read = data.buffer.read_from(self.stream.stream_read.borrow_mut().as_mut().expect("Invalid socket"))?;
+        if read == 0 {
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Server closed the connection").into());
+        }
 
         loop {
-            let msgstream = match message_stream::parse_msgstream(msg_buffer) {
-                Ok(Some(msginfo)) => msginfo,
-                Ok(_) => {
-                    let mut msg_rem = Vec::new();
-                    msg_rem.extend_from_slice(msg_buffer);
-                    *msg = msg_rem;
-                    break;
+            let frame = match data.buffer.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(MsgError::Frame { error, .. }) => {
+                    self.logger.log(Level::Warn, module_path!(), "Dropped frame", &[("error", &error)]);
+                    continue;
                 },
                 Err(e) => { 
                     return Err(e.into()) 
                 },
             };
     
-            (self.handler.on_read)(&self, self.stream.as_ref(), &msgstream.msginfo);
-            msg_buffer = msgstream.buffer_rem;
+            frame.require_checksum(&mut data.checksums)?;
+            if !frame.msginfo.is_stream() {
+                self.dispatch(frame.msginfo);
+                continue;
+            }
+            //chunks go to the handler as they arrive, request ids included, nothing waits for a whole stream
+            match data.streams.push(&frame.msginfo) {
+                Ok(_) => (self.handler.on_read)(self, self.stream.as_ref(), &frame.msginfo),
+                Err(e) => self.logger.log(Level::Warn, module_path!(), "Dropped stream", &[("error", &e)]),
+            }
         }  
 
         Ok(())
@@ -175,7 +346,6 @@ impl ClientState {
 }
 
 pub struct Client {
-    thread_state: Arc<thread_helper::ThreadState>,
     threads: ClientThreads,
     pub state: Arc<ClientState>,
 }
@@ -184,7 +354,6 @@ impl Client {
     pub fn new(stream: TcpStream, handler: ClientHandler, logger: Arc<Logger>) -> Self {
         let thread_state = Arc::new(thread_helper::ThreadState::new());
         Client {
-            thread_state:thread_state.clone(),
             threads:ClientThreads::new(thread_state.clone(), logger.clone()),
             state:Arc::new(ClientState::new(thread_state.clone(), stream, handler, logger.clone()))
         }
@@ -211,16 +380,22 @@ impl Drop for Client {
     }
 }
 
+pub type ReadFn = Box<dyn Fn(&ClientState, &ClientStream, &MsgInfo)>;
+pub type DisconnectFn = Box<dyn Fn(&ClientState, &ClientStream)>;
+pub type ErrorFn = Box<dyn Fn(&ClientState, &ClientStream, &ServerError)>;
+
 pub struct ClientHandler {
-    on_read: Box<dyn Fn(&ClientState, &ClientStream, &MsgInfo)>,
-    on_disconnect: Box<dyn Fn(&ClientState, &ClientStream)>,
+    on_read: ReadFn,
+    on_disconnect: DisconnectFn,
+    on_error: ErrorFn,
 }
 
 impl ClientHandler {
-    pub fn new(on_read: Box<dyn Fn(&ClientState, &ClientStream, &MsgInfo)>, on_disconnect: Box<dyn Fn(&ClientState, &ClientStream)>) -> Self {
+    pub fn new(on_read: ReadFn, on_disconnect: DisconnectFn, on_error: ErrorFn) -> Self {
         ClientHandler {
             on_read,
-            on_disconnect
+            on_disconnect,
+            on_error,
         }
     }
 }
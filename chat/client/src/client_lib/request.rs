@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{atomic::{AtomicU32, Ordering}, Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use netutils::message_stream::{MsgError, MsgInfo};
+use super::server_error::ServerError;
+
+//the frame the server answered a request with, decode it with decode_data
+pub type Response = MsgInfo<'static>;
+
+#[derive(Debug)]
+pub enum RequestError {
+    Io(std::io::Error),
+    Msg(MsgError),
+    Server(ServerError),
+    Timeout,
+    Disconnected,
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            RequestError::Io(e) => write!(f, "{}", e),
+            RequestError::Msg(e) => write!(f, "{}", e),
+            RequestError::Server(e) => write!(f, "{}", e),
+            RequestError::Timeout => write!(f, "No reply from the server"),
+            RequestError::Disconnected => write!(f, "Disconnected from the server"),
+        }
+    }
+}
+
+impl Error for RequestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+impl From<std::io::Error> for RequestError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+impl From<MsgError> for RequestError {
+    fn from(e: MsgError) -> Self {
+        Self::Msg(e)
+    }
+}
+
+struct Slot {
+    result: Option<Result<Response, RequestError>>,
+    waker: Option<Waker>,
+}
+
+struct Pending {
+    slot: Mutex<Slot>,
+    ready: Condvar,
+}
+
+impl Pending {
+    fn fulfil(&self, result: Result<Response, RequestError>) {
+        let mut slot = self.slot.lock().expect("Failed to lock mutex");
+        slot.result = Some(result);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+//requests waiting for a reply, keyed by the id carried in their frame
+#[derive(Default)]
+pub(crate) struct Requests {
+    next_id: AtomicU32,
+    pending: Mutex<HashMap<u32, Arc<Pending>>>,
+}
+
+impl Requests {
+    pub(crate) fn register(self: &Arc<Self>) -> PendingRequest {
+        //0 marks frames that are not part of a request
+        let mut id = self.next_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        if id == 0 {
+            id = self.next_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        }
+        let pending = Arc::new(Pending {
+            slot: Mutex::new(Slot { result: None, waker: None }),
+            ready: Condvar::new(),
+        });
+        self.pending.lock().expect("Failed to lock mutex").insert(id, pending.clone());
+        PendingRequest {
+            id,
+            pending,
+            requests: self.clone(),
+        }
+    }
+
+    //hands `result` to whoever waits for `request_id`, gives it back if nobody does
+    pub(crate) fn complete(&self, request_id: u32, result: Result<Response, RequestError>) -> Option<Result<Response, RequestError>> {
+        match self.pending.lock().expect("Failed to lock mutex").remove(&request_id) {
+            Some(pending) => {
+                pending.fulfil(result);
+                None
+            },
+            None => Some(result),
+        }
+    }
+
+    pub(crate) fn fail_all(&self) {
+        let pending: Vec<Arc<Pending>> = self.pending.lock().expect("Failed to lock mutex").drain().map(|(_, pending)| pending).collect();
+        for pending in pending {
+            pending.fulfil(Err(RequestError::Disconnected));
+        }
+    }
+}
+
+//a request that was sent and waits for its reply, either block on it with wait,
+//poll it with try_take or await it; dropping it stops waiting
+pub struct PendingRequest {
+    id: u32,
+    pending: Arc<Pending>,
+    requests: Arc<Requests>,
+}
+
+impl PendingRequest {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn wait(self, timeout: Duration) -> Result<Response, RequestError> {
+        let deadline = Instant::now() + timeout;
+        let mut slot = self.pending.slot.lock().expect("Failed to lock mutex");
+        loop {
+            if let Some(result) = slot.result.take() {
+                return result;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RequestError::Timeout);
+            }
+            slot = self.pending.ready.wait_timeout(slot, deadline - now).expect("Failed to lock mutex").0;
+        }
+    }
+
+    pub fn try_take(&mut self) -> Option<Result<Response, RequestError>> {
+        self.pending.slot.lock().expect("Failed to lock mutex").result.take()
+    }
+}
+
+//there is no timer here, callers that await wrap it in their runtime's timeout
+impl Future for PendingRequest {
+    type Output = Result<Response, RequestError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.pending.slot.lock().expect("Failed to lock mutex");
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        self.requests.pending.lock().expect("Failed to lock mutex").remove(&self.id);
+    }
+}
@@ -0,0 +1,36 @@
+use std::error::Error;
+
+use netutils::messages::server::{ErrorCode, MsgError};
+
+//a request the server refused, decoded from an OnError message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerError {
+    pub code: ErrorCode,
+    pub request_id: u32, //0 when the request carried none
+    pub message: String,
+}
+
+impl From<MsgError<'_>> for ServerError {
+    fn from(msg: MsgError) -> Self {
+        ServerError {
+            code: ErrorCode::from_u32(msg.code),
+            request_id: msg.request_id,
+            message: msg.message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
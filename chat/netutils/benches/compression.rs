@@ -0,0 +1,122 @@
+use std::borrow::Cow;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use netutils::message_stream::{self, Compression, FrameOptions};
+use netutils::messages::server;
+
+//what packing a frame costs and saves: chat lines are tiny and stay under the default threshold,
+//replayed history and pasted text shrink a lot, file chunks of already compressed data hardly at all;
+//checksums are measured on their own
+
+fn chat_text(len: usize) -> String {
+    let lines = [
+        "alice: did anyone look at the build failure on the release branch?",
+        "bob: yes, the linker ran out of memory again, I bumped the runner",
+        "carol: thanks! I'll rebase my branch once it's green",
+        "dave: lunch in 10 minutes, who's in?",
+    ];
+    let mut text = String::new();
+    let mut i = 0;
+    while text.len() < len {
+        text.push_str(lines[i % lines.len()]);
+        text.push_str(&format!(" ({})\n", i));
+        i += 1;
+    }
+    text.truncate(len);
+    text
+}
+
+//xorshift, stands in for compressed or encrypted files
+fn noise(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+    }).collect()
+}
+
+fn frames() -> Vec<(&'static str, Vec<u8>)> {
+    let sent = |msg: &str| message_stream::serialize_data(server::Message::OnSent as u32, &server::MsgOnSent { user: "alice", msg }).expect("Failed to serialze message");
+    let chunk = |data: &[u8]| message_stream::serialize_data(server::Message::OnFileChunk as u32, &server::MsgFileChunk { id: 1, offset: 0, data: Cow::Borrowed(data) }).expect("Failed to serialze message");
+    vec![
+        ("chat line", sent("see you all tomorrow")),
+        ("4 KiB text", sent(&chat_text(4 * 1024))),
+        ("64 KiB text", chunk(chat_text(64 * 1024).as_bytes())),
+        ("64 KiB noise", chunk(&noise(64 * 1024))),
+    ]
+}
+
+fn options(compression: Compression) -> FrameOptions {
+    FrameOptions {
+        compression,
+        threshold: 0,
+        ..FrameOptions::default()
+    }
+}
+
+const ALGORITHMS: [Compression; 3] = [Compression::None, Compression::Lz4, Compression::Zstd];
+
+fn report_sizes() {
+    println!("frame sizes (bytes on the wire):");
+    for (name, frame) in frames() {
+        let sizes: Vec<String> = ALGORITHMS.iter()
+            .map(|algorithm| format!("{} {}", algorithm, message_stream::encode_frame(&frame, &options(*algorithm)).len()))
+            .collect();
+        println!("  {:<13} {}", name, sizes.join(", "));
+    }
+}
+
+fn compress(c: &mut Criterion) {
+    report_sizes();
+    for (name, frame) in frames() {
+        let mut group = c.benchmark_group(format!("compress/{}", name));
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        for algorithm in ALGORITHMS {
+            let options = options(algorithm);
+            group.bench_with_input(BenchmarkId::from_parameter(algorithm), &frame, |b, frame| {
+                b.iter(|| message_stream::encode_frame(black_box(frame), &options).len())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, frame) in frames() {
+        let mut group = c.benchmark_group(format!("parse/{}", name));
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        for algorithm in ALGORITHMS {
+            let packed = message_stream::encode_frame(&frame, &options(algorithm)).into_owned();
+            group.bench_with_input(BenchmarkId::from_parameter(algorithm), &packed, |b, packed| {
+                b.iter(|| message_stream::parse_msgstream(black_box(packed)).expect("Failed to parse frame").is_some())
+            });
+        }
+        group.finish();
+    }
+}
+
+//what a CRC32C on every frame adds to sending and parsing it
+fn checksum(c: &mut Criterion) {
+    let options = FrameOptions {
+        checksum: true,
+        ..FrameOptions::default()
+    };
+    for (name, frame) in frames() {
+        let mut group = c.benchmark_group(format!("checksum/{}", name));
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input("encode", &frame, |b, frame| {
+            b.iter(|| message_stream::encode_frame(black_box(frame), &options).len())
+        });
+        let checked = message_stream::encode_frame(&frame, &options).into_owned();
+        group.bench_with_input("parse", &checked, |b, checked| {
+            b.iter(|| message_stream::parse_msgstream(black_box(checked)).expect("Failed to parse frame").is_some())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, compress, parse, checksum);
+criterion_main!(benches);
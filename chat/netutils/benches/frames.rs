@@ -0,0 +1,88 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::io::{Cursor, Read};
+
+use netutils::message_stream::{self, FrameBuffer, MsgInfo};
+use netutils::messages::server;
+
+//the common case on a busy channel: lots of short chat lines arriving back to back, read off the
+//socket 4 KiB at a time; "copying" is how frames were parsed before FrameBuffer, every frame got a
+//payload of its own and the leftover bytes were moved to a fresh Vec after each read
+
+const FRAMES: usize = 1000;
+const READ_SIZE: usize = 4096;
+
+fn chat_line(i: usize) -> Vec<u8> {
+    let msg = format!("did anyone look at the build failure on the release branch? ({})", i);
+    message_stream::serialize_data(server::Message::OnSent as u32, &server::MsgOnSent { user: "alice", msg: &msg }).expect("Failed to serialze message")
+}
+
+fn received() -> Vec<u8> {
+    (0..FRAMES).flat_map(chat_line).collect()
+}
+
+fn handle(msginfo: &MsgInfo) -> usize {
+    let msg = msginfo.decode_data::<server::MsgOnSent>().expect("Failed to decode message");
+    msg.user.len() + msg.msg.len()
+}
+
+fn read_buffered(bytes: &[u8], buffer: &mut FrameBuffer) -> usize {
+    let mut reader = Cursor::new(bytes);
+    let mut total = 0;
+    while buffer.read_from(&mut reader).expect("Failed to read") > 0 {
+        while let Some(frame) = buffer.next_frame().expect("Failed to parse frame") {
+            total += handle(&frame.msginfo);
+        }
+    }
+    total
+}
+
+fn read_copying(bytes: &[u8]) -> usize {
+    let mut reader = Cursor::new(bytes);
+    let mut read_buffer = [0u8; READ_SIZE];
+    let mut msg: Vec<u8> = Vec::new();
+    let mut total = 0;
+    loop {
+        let read = reader.read(&mut read_buffer).expect("Failed to read");
+        if read == 0 {
+            break;
+        }
+        msg.extend_from_slice(&read_buffer[..read]);
+        let mut msg_buffer = msg.as_slice();
+        while let Some(msgstream) = message_stream::parse_msgstream(msg_buffer).expect("Failed to parse frame") {
+            msg_buffer = msgstream.buffer_rem;
+            total += handle(&msgstream.msginfo.into_owned());
+        }
+        msg = msg_buffer.to_vec();
+    }
+    total
+}
+
+fn serialize(c: &mut Criterion) {
+    let msg = "did anyone look at the build failure on the release branch?";
+    let mut group = c.benchmark_group("serialize/chat line");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("data", |b| {
+        b.iter(|| message_stream::serialize_data(server::Message::OnSent as u32, black_box(&server::MsgOnSent { user: "alice", msg })).expect("Failed to serialze message").len())
+    });
+    group.bench_function("request", |b| {
+        b.iter(|| message_stream::serialize_request(server::Message::OnSent as u32, 7, black_box(&server::MsgOnSent { user: "alice", msg })).expect("Failed to serialze message").len())
+    });
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let bytes = received();
+    let mut group = c.benchmark_group(format!("parse/{} chat lines", FRAMES));
+    group.throughput(Throughput::Elements(FRAMES as u64));
+    let mut buffer = FrameBuffer::new(READ_SIZE);
+    group.bench_with_input(BenchmarkId::from_parameter("frame buffer"), &bytes, |b, bytes| {
+        b.iter(|| read_buffered(black_box(bytes), &mut buffer))
+    });
+    group.bench_with_input(BenchmarkId::from_parameter("copying"), &bytes, |b, bytes| {
+        b.iter(|| read_copying(black_box(bytes)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, serialize, parse);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+use std::io::{self, ErrorKind, Read, Write};
+
+//just enough HTTP/1.1 for the endpoints the server answers itself: metrics, webhooks and the
+//WebSocket upgrade; one request per connection, bodies only with a Content-Length
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    //without the query string
+    pub fn route(&self) -> &str {
+        self.path.split('?').next().unwrap_or("")
+    }
+}
+
+//the outer error is the connection failing, the inner one a request to refuse with (status, text);
+//the head is read a byte at a time so nothing past it is consumed, a POST body up to `max_body`
+//bytes is read after it
+pub fn read_request<R: Read>(reader: &mut R, max_head: usize, max_body: usize) -> io::Result<Result<Request, (u16, String)>> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= max_head {
+            return Ok(Err((431, String::from("request headers too large"))));
+        }
+        match reader.read(&mut byte) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "closed before the end of the headers")),
+            Ok(_) => head.push(byte[0]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.split("\r\n");
+    let mut parts = lines.next().unwrap_or("").split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err((400, String::from("malformed request line")))),
+    };
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+    if request.method != "POST" {
+        return Ok(Ok(request));
+    }
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Ok(Err((411, String::from("send the body with a Content-Length"))));
+    }
+    let len = match request.header("Content-Length").map(|len| len.parse::<usize>()) {
+        Some(Ok(len)) => len,
+        Some(Err(_)) => return Ok(Err((400, String::from("invalid Content-Length")))),
+        None => return Ok(Err((411, String::from("Content-Length is required")))),
+    };
+    if len > max_body {
+        return Ok(Err((413, format!("bodies are limited to {} bytes", max_body))));
+    }
+    request.body = vec![0u8; len];
+    reader.read_exact(&mut request.body)?;
+    Ok(Ok(request))
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+//answers and asks the client to close, `headers` go out before Content-Length
+pub fn respond<W: Write>(writer: &mut W, status: u16, headers: &[(&str, &str)], body: &str) -> io::Result<()> {
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body));
+    writer.write_all(response.as_bytes())?;
+    writer.flush()
+}
@@ -1,7 +1,11 @@
 pub mod thread_helper;
 pub mod message_stream;
+pub mod outbound;
+pub mod websocket;
+pub mod http;
 pub mod messages;
 pub mod logger;
+pub mod rotating_file;
 
 extern crate byteorder;
 extern crate bincode;
@@ -1,54 +1,384 @@
-use std::fs::File;
-use std::ops::DerefMut;
-use std::path::Path;
-use std::io::Result;
-use std::sync::Mutex;
-use std::io::Write;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn from_u8(val: u8) -> Option<Level> {
+        match val {
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseLevelError(String);
+
+impl std::fmt::Display for ParseLevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown log level \"{}\", expected one of error, warn, info, debug, trace", self.0)
+    }
+}
+
+impl std::error::Error for ParseLevelError {}
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(ParseLevelError(s.to_string())),
+        }
+    }
+}
+
+//parses per-target directives such as "server_lib=debug,netutils::thread_helper=warn"
+pub fn parse_filters(directives: &str) -> Result<Vec<(String, Level)>, ParseLevelError> {
+    let mut filters = Vec::new();
+    for directive in directives.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+        match directive.split_once('=') {
+            Some((target, level)) => filters.push((target.trim().to_string(), level.parse::<Level>()?)),
+            None => return Err(ParseLevelError(directive.to_string())),
+        }
+    }
+    Ok(filters)
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown log format \"{}\", expected text or json", s)),
+        }
+    }
+}
+
+pub struct Record<'a> {
+    pub level: Level,
+    pub target: &'a str,
+    pub message: &'a str,
+    pub fields: &'a [(&'a str, &'a dyn Display)],
+    pub time: SystemTime,
+    pub thread: Option<&'a str>,
+}
+
+pub trait Sink: Send {
+    fn write(&mut self, record: &Record) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+//"2023-08-01T12:00:00.123Z INFO [Reader] server_lib: message key=value"
+pub struct TextSink<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        TextSink {
+            writer,
+        }
+    }
+}
+
+impl<W: Write + Send> Sink for TextSink<W> {
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = format!("{} {:5} [{}] {}: {}",
+            format_timestamp(record.time),
+            record.level.as_str().to_uppercase(),
+            record.thread.unwrap_or("-"),
+            record.target,
+            record.message);
+        for (key, value) in record.fields {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+//one JSON object per line, fields are flattened next to the fixed keys
+pub struct JsonSink<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonSink {
+            writer,
+        }
+    }
+}
+
+impl<W: Write + Send> Sink for JsonSink<W> {
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = String::from("{");
+        push_json_pair(&mut line, "ts", &format_timestamp(record.time));
+        line.push(',');
+        push_json_pair(&mut line, "level", record.level.as_str());
+        line.push(',');
+        push_json_pair(&mut line, "target", record.target);
+        if let Some(thread) = record.thread {
+            line.push(',');
+            push_json_pair(&mut line, "thread", thread);
+        }
+        line.push(',');
+        push_json_pair(&mut line, "msg", record.message);
+        for (key, value) in record.fields {
+            line.push(',');
+            push_json_pair(&mut line, key, &value.to_string());
+        }
+        line.push_str("}\n");
+        self.writer.write_all(line.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn push_json_pair(out: &mut String, key: &str, value: &str) {
+    push_json_str(out, key);
+    out.push(':');
+    push_json_str(out, value);
+}
+
+fn push_json_str(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+//forwards records to the `log` facade so embedding applications can capture them
+#[cfg(feature = "log")]
+pub struct FacadeSink;
+
+#[cfg(feature = "log")]
+impl Sink for FacadeSink {
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let level = match record.level {
+            Level::Error => log::Level::Error,
+            Level::Warn => log::Level::Warn,
+            Level::Info => log::Level::Info,
+            Level::Debug => log::Level::Debug,
+            Level::Trace => log::Level::Trace,
+        };
+        let mut message = record.message.to_string();
+        for (key, value) in record.fields {
+            message.push_str(&format!(" {}={}", key, value));
+        }
+        log::logger().log(&log::Record::builder()
+            .level(level)
+            .target(record.target)
+            .args(format_args!("{}", message))
+            .build());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        log::logger().flush();
+        Ok(())
+    }
+}
 
 pub struct Logger {
-    // file: Mutex<File>,
-    writer: Mutex<Option<Box<dyn Write>>>,
+    sinks: Mutex<Vec<Box<dyn Sink>>>,
+    level: AtomicU8,
+    targets: RwLock<Vec<(String, Level)>>,
+    failed_writes: AtomicUsize,
+    reported_failure: AtomicBool,
 }
 
 impl Logger {
-    pub fn new(writer: Option<Box<dyn Write>>) -> Self {
+    pub fn new(level: Level) -> Self {
         Logger {
-            writer: Mutex::new(writer),
+            sinks: Mutex::new(Vec::new()),
+            level: AtomicU8::new(level as u8),
+            targets: RwLock::new(Vec::new()),
+            failed_writes: AtomicUsize::new(0),
+            reported_failure: AtomicBool::new(false),
         }
     }
 
-    // pub fn apply(&self, f: impl FnOnce(&mut Box<dyn Write>)) {
-    //     let mut inner = self.writer.lock().expect("Failed to lock mutex");
-    //     f(&mut *inner);
-    // }
-    // pub fn create<P: AsRef<Path>>(path: P) -> Result<Logger> {
-    //     let file = File::create(path);
-    //     let file = match file {
-    //         Ok(file) => file,
-    //         Err(e) => return Err(e),
-    //     };
+    //logger that drops everything, for callers that do not care about logs
+    pub fn disabled() -> Self {
+        Logger::new(Level::Error)
+    }
 
-    //     let logger = Logger {
-    //         file: Mutex::new(file)
-    //     };
-    //     Ok(logger)
-    // }
+    pub fn add_sink(&self, sink: Box<dyn Sink>) {
+        self.sinks.lock().unwrap_or_else(|e| e.into_inner()).push(sink);
+    }
 
-    pub fn log(&self, buffer: &[u8]) -> Result<usize> {
-        let mut lock_guard = self.writer.lock().expect("Failed to lock mutex");
-        if lock_guard.is_some() {
-            let log = lock_guard.as_mut().unwrap();
-            let res = [log.write(buffer), log.write("\n".as_bytes())];
+    pub fn add_writer<W: Write + Send + 'static>(&self, writer: W, format: Format) {
+        match format {
+            Format::Text => self.add_sink(Box::new(TextSink::new(writer))),
+            Format::Json => self.add_sink(Box::new(JsonSink::new(writer))),
+        }
+    }
 
-            return res.into_iter().sum();
+    pub fn is_set(&self) -> bool {
+        !self.sinks.lock().unwrap_or_else(|e| e.into_inner()).is_empty()
+    }
+
+    pub fn level(&self) -> Level {
+        Level::from_u8(self.level.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+    }
+
+    pub fn set_level(&self, level: Level) {
+        self.level.store(level as u8, Ordering::Relaxed);
+    }
+
+    //the longest matching target prefix wins over the global level
+    pub fn set_target_level(&self, target: &str, level: Level) {
+        let mut targets = self.targets.write().unwrap_or_else(|e| e.into_inner());
+        targets.retain(|(t, _)| t != target);
+        targets.push((target.to_string(), level));
+        targets.sort_by_key(|(t, _)| std::cmp::Reverse(t.len()));
+    }
+
+    pub fn set_filters(&self, filters: &[(String, Level)]) {
+        for (target, level) in filters {
+            self.set_target_level(target, *level);
         }
+    }
 
-        Ok(0)
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        let targets = self.targets.read().unwrap_or_else(|e| e.into_inner());
+        let max = targets.iter()
+            .find(|(t, _)| target == t || (target.starts_with(t.as_str()) && target[t.len()..].starts_with("::")))
+            .map(|(_, l)| *l)
+            .unwrap_or_else(|| self.level());
+        level <= max
     }
-    pub fn is_set(&self) -> bool {
-        self.writer.lock().expect("Failed to lock mutex").is_some()
+
+    pub fn failed_writes(&self) -> usize {
+        self.failed_writes.load(Ordering::Relaxed)
+    }
+
+    //never fails: a broken sink is counted and reported once on stderr
+    pub fn log(&self, level: Level, target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+        if !self.enabled(level, target) {
+            return;
+        }
+
+        let thread = std::thread::current();
+        let record = Record {
+            level,
+            target,
+            message,
+            fields,
+            time: SystemTime::now(),
+            thread: thread.name(),
+        };
+
+        let mut sinks = self.sinks.lock().unwrap_or_else(|e| e.into_inner());
+        for sink in sinks.iter_mut() {
+            let res = sink.write(&record).and_then(|_| sink.flush());
+            if let Err(e) = res {
+                self.failed_writes.fetch_add(1, Ordering::Relaxed);
+                if !self.reported_failure.swap(true, Ordering::Relaxed) {
+                    let _ = writeln!(io::stderr(), "Failed to write log record: {}", e);
+                }
+            }
+        }
+    }
+
+    pub fn error(&self, target: &str, message: &str) {
+        self.log(Level::Error, target, message, &[]);
+    }
+    pub fn warn(&self, target: &str, message: &str) {
+        self.log(Level::Warn, target, message, &[]);
+    }
+    pub fn info(&self, target: &str, message: &str) {
+        self.log(Level::Info, target, message, &[]);
+    }
+    pub fn debug(&self, target: &str, message: &str) {
+        self.log(Level::Debug, target, message, &[]);
+    }
+    pub fn trace(&self, target: &str, message: &str) {
+        self.log(Level::Trace, target, message, &[]);
     }
 }
 
-unsafe impl Sync for Logger{}
-unsafe impl Send for Logger{}
\ No newline at end of file
+//RFC 3339 in UTC with millisecond precision
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    //civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, rem / 3600, (rem % 3600) / 60, rem % 60, since_epoch.subsec_millis())
+}
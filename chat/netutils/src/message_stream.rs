@@ -1,10 +1,13 @@
-use bincode::{self, options};
+use bincode;
 pub use serde;
-use std::ptr;
 use std::mem::size_of;
-use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
-use std::io::Cursor;
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
 use std::error::Error;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub enum MsgError {
@@ -14,6 +17,11 @@ pub enum MsgError {
     Serialize(bincode::Error),
     Deserialize(bincode::Error),
     LogicError { msg: String },
+    Stream { stream_id: u32, msg: String },
+    Compression { msg: String },
+    Codec { msg: String }, //a JSON or MessagePack payload that could not be written or read
+    Corrupt { msg: String }, //the framing itself broke, nothing after it can be trusted
+    Frame { len: usize, error: Box<MsgError> }, //a frame that passed its checksum but could not be decoded, skip len bytes
 }
 
 impl std::fmt::Display for MsgError {
@@ -37,6 +45,21 @@ impl std::fmt::Display for MsgError {
             MsgError::LogicError{msg} => {
                 write!(f, "Logic error: {}", msg)
             },
+            MsgError::Stream{stream_id, msg} => {
+                write!(f, "Stream {}: {}", stream_id, msg)
+            },
+            MsgError::Compression{msg} => {
+                write!(f, "Compressed frame: {}", msg)
+            },
+            MsgError::Codec{msg} => {
+                write!(f, "Payload codec: {}", msg)
+            },
+            MsgError::Corrupt{msg} => {
+                write!(f, "Corrupt frame: {}", msg)
+            },
+            MsgError::Frame{len, error} => {
+                write!(f, "Undecodable frame of {} bytes: {}", len, error)
+            },
         }
     }
 }
@@ -59,35 +82,102 @@ impl Error for MsgError {
 //     unsafe { ptr::read_unaligned(ptr) }
 // }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
-pub struct MsgInfo {
+//parsed frames borrow their payload from the buffer they were read into, into_owned detaches them
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct MsgInfo<'a> {
     // len: u32, need to write len to buffer first (not in serialized struct otherwise you cant know when the message is ready to be deserialized and parsed yet...)
     pub code: u32,
-    data: Vec<u8>,
+    pub request_id: u32, //0 for events, otherwise echoed by the reply to this request
+    #[serde(borrow)]
+    data: Cow<'a, [u8]>,
+    #[serde(skip)]
+    codec: Codec, //taken from the frame prefix, the envelope itself is the same for every codec
+    #[serde(skip)]
+    transcoded: OnceCell<Vec<u8>>, //JSON payloads as MessagePack, made by the first decode_data
 }
 
-impl MsgInfo {
+impl MsgInfo<'_> {
+    pub fn into_owned(self) -> MsgInfo<'static> {
+        MsgInfo {
+            code: self.code,
+            request_id: self.request_id,
+            data: Cow::Owned(self.data.into_owned()),
+            codec: self.codec,
+            transcoded: self.transcoded,
+        }
+    }
+
+    //what the payload was written with
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    //true for frames carrying a piece of a stream, see StreamWriter
+    pub fn is_stream(&self) -> bool {
+        self.code == STREAM_CODE
+    }
+
+    pub fn stream_chunk(&self) -> Result<StreamChunk<'_>, MsgError> {
+        if !self.is_stream() {
+            return Err(MsgError::LogicError { msg: format!("message {} is not part of a stream", self.code) });
+        }
+        self.decode_data::<StreamChunk>()
+    }
+
     pub fn decode_data<'a, T>(&'a self) -> Result<T, MsgError> 
     where
-        T: ?Sized + serde::Deserialize<'a>
+        T: serde::Deserialize<'a>
     {
-        let res = bincode::deserialize::<'a, T>(self.data.as_slice());
-        match res {
-            Ok(v) => return Ok(v),
-            Err(e) => Err(MsgError::Deserialize(e)),
+        match self.codec {
+            Codec::Bincode => {
+                let res = bincode::deserialize::<'a, T>(&self.data);
+                match res {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(MsgError::Deserialize(e)),
+                }
+            },
+            Codec::MessagePack => decode_msgpack(&self.data),
+            //serde_json can only lend out strings that have no escapes in them, which would fail every
+            //&str field holding a quote or, from most JSON writers, any non-ASCII text; MessagePack
+            //keeps strings as they are, so the payload is transcoded once and read from there
+            Codec::Json => {
+                if self.transcoded.get().is_none() {
+                    let _ = self.transcoded.set(json_to_msgpack(&self.data)?);
+                }
+                decode_msgpack(self.transcoded.get().expect("Payload was just transcoded"))
+            },
+        }
+    }
+}
+
+pub struct Frame<'a> {
+    pub msginfo: MsgInfo<'a>,
+    pub checksum: bool, //the frame carried a checksum that matched
+}
+
+impl Frame<'_> {
+    //once a connection sent one checksummed frame every later one needs a checksum too, so a flipped
+    //flag can not turn it off unnoticed; `checksums` is what the connection has seen so far
+    pub fn require_checksum(&self, checksums: &mut bool) -> Result<(), MsgError> {
+        if *checksums && !self.checksum {
+            return Err(MsgError::Corrupt { msg: "frame without a checksum".to_string() });
         }
+        *checksums |= self.checksum;
+        Ok(())
     }
 }
 
 pub struct MsgStream<'a> {
-    pub msginfo: MsgInfo,
+    pub msginfo: MsgInfo<'a>,
+    pub checksum: bool,
     pub buffer_rem: &'a [u8],
 }
 
 impl<'a> MsgStream<'a> {
-    fn new(msginfo: MsgInfo, buffer_rem: &'a [u8]) -> Self {
+    fn new(frame: Frame<'a>, buffer_rem: &'a [u8]) -> Self {
         MsgStream {
-            msginfo,
+            msginfo: frame.msginfo,
+            checksum: frame.checksum,
             buffer_rem
         }
     }
@@ -97,32 +187,50 @@ pub fn serialize_data<T>(code: u32, data: &T) -> Result<Vec<u8>, MsgError>
 where
     T: ?Sized + serde::Serialize,
 {
-    let data_encode = match bincode::serialize(&data) {
-        Ok(v) => v,
-        Err(e) => return Err(MsgError::Serialize(e)),
-    };
+    serialize_request(code, 0, data)
+}
 
-    let msginfo = MsgInfo {
-        code: code,
-        data: data_encode,
-    };
+pub fn serialize_request<T>(code: u32, request_id: u32, data: &T) -> Result<Vec<u8>, MsgError> 
+where
+    T: ?Sized + serde::Serialize,
+{
+    serialize_request_with(Codec::Bincode, code, request_id, data)
+}
 
-    let mut msg_data = match bincode::serialize(&msginfo) {
-        Ok(v) => v,
-        Err(e) => return Err(MsgError::Serialize(e)),
+pub fn serialize_request_with<T>(codec: Codec, code: u32, request_id: u32, data: &T) -> Result<Vec<u8>, MsgError> 
+where
+    T: ?Sized + serde::Serialize,
+{
+    //the frame is written the way bincode lays out a MsgInfo (code, request_id, u64 length, payload)
+    //straight into one buffer, instead of encoding the payload first and then the envelope around it;
+    //bincode knows the payload size up front, for the others the lengths are filled in afterwards
+    const ENVELOPE_LEN: usize = 2 * size_of::<u32>() + size_of::<u64>();
+    const HEADER_LEN: usize = PREFIX_LEN + ENVELOPE_LEN;
+    let too_large = |msg_data_len: usize| MsgError::LogicError { msg: format!("message of {} bytes does not fit in a frame, send it as a stream", msg_data_len) };
+    let size_hint = match codec {
+        Codec::Bincode => match bincode::serialized_size(data) {
+            Ok(len) => len as usize,
+            Err(e) => return Err(MsgError::Serialize(e)),
+        },
+        _ => 0,
     };
-    let msg_data_len = msg_data.len();
-
-    let mut buffer = Vec::new();
-    let sz = (msg_data_len) as u32;
-    let res = buffer.write_u32::<LittleEndian>(sz);
-    match res {
-        Ok(_) => {},
-        Err(e) => return Err(MsgError::Write(e)),
+    if ENVELOPE_LEN + size_hint > MAX_FRAME_LEN {
+        return Err(too_large(ENVELOPE_LEN + size_hint));
     }
 
-    buffer.append(&mut msg_data);
+    let mut buffer = Vec::with_capacity(HEADER_LEN + size_hint);
+    buffer.resize(HEADER_LEN, 0);
+    codec.encode_into(&mut buffer, data)?;
+    let data_len = buffer.len() - HEADER_LEN;
+    let msg_data_len = ENVELOPE_LEN + data_len;
+    if msg_data_len > MAX_FRAME_LEN {
+        return Err(too_large(msg_data_len));
+    }
 
+    buffer[..4].copy_from_slice(&(msg_data_len as u32 | codec.prefix_bits()).to_le_bytes());
+    buffer[4..8].copy_from_slice(&code.to_le_bytes());
+    buffer[8..12].copy_from_slice(&request_id.to_le_bytes());
+    buffer[12..HEADER_LEN].copy_from_slice(&(data_len as u64).to_le_bytes());
     Ok(buffer)
 }
 
@@ -139,36 +247,842 @@ fn msg_size(bytes: &[u8]) -> Result<u32, MsgError> {
     }
 }
 
-fn decode_msginfo(bytes: &[u8]) -> Result<MsgInfo, MsgError> {
-    let res = bincode::deserialize(&bytes);
+fn decode_msginfo(bytes: &[u8]) -> Result<MsgInfo<'_>, MsgError> {
+    let res = bincode::deserialize(bytes);
     match res {
-        Ok(v) => return Ok(v),
+        Ok(v) => Ok(v),
         Err(e) => Err(MsgError::Deserialize(e)),
     }
 }
 
-pub fn parse_msgstream(buffer: &[u8]) -> Result<Option<MsgStream>, MsgError> {
-    let sz = match msg_size(&buffer) {
+fn decode_msgpack<'a, T: serde::Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, MsgError> {
+    let res = rmp_serde::from_slice::<'a, T>(bytes);
+    match res {
+        Ok(v) => Ok(v),
+        Err(e) => Err(MsgError::Codec { msg: e.to_string() }),
+    }
+}
+
+//structs stay maps keyed by field name, which is what decoding them from MessagePack expects too
+fn json_to_msgpack(json: &[u8]) -> Result<Vec<u8>, MsgError> {
+    let fail = |msg: String| MsgError::Codec { msg };
+    let mut deserializer = serde_json::Deserializer::from_slice(json);
+    let mut buffer = Vec::with_capacity(json.len());
+    let mut serializer = rmp_serde::Serializer::new(&mut buffer).with_struct_map();
+    serde_transcode::transcode(&mut deserializer, &mut serializer).map_err(|e| fail(e.to_string()))?;
+    deserializer.end().map_err(|e| fail(e.to_string()))?;
+    Ok(buffer)
+}
+
+//every frame starts with a u32 prefix, the low 28 bits are the length of the body and the high
+//bits say what else is in there:
+//  COMPRESSED_FLAG: the body is packed, see compress_frame
+//  CHECKSUM_FLAG: a CRC32C of the prefix and the body sits between the two
+//  CODEC_MASK: what the payload of the message was written with, see Codec; 0 is bincode
+//parsing handles all of them whatever was negotiated, senders only use them once the peer agreed to it
+pub const COMPRESSED_FLAG: u32 = 1 << 31;
+pub const CHECKSUM_FLAG: u32 = 1 << 30;
+const CODEC_SHIFT: u32 = 28;
+pub const CODEC_MASK: u32 = 0b11 << CODEC_SHIFT;
+const LEN_MASK: u32 = (1 << CODEC_SHIFT) - 1;
+const PREFIX_LEN: usize = size_of::<u32>();
+const CHECKSUM_LEN: usize = size_of::<u32>();
+//larger lengths can only come from a broken stream, big payloads travel as streams
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+pub const MAX_UNPACKED_LEN: usize = MAX_FRAME_LEN; //whatever a packed body claims
+
+struct FrameHeader {
+    sz: u32, //the prefix as sent, the checksum covers it
+    len: usize, //of the body
+    compressed: bool,
+    checksum: bool,
+    codec: Codec,
+}
+
+impl FrameHeader {
+    fn new(sz: u32) -> Result<Self, MsgError> {
+        let len = (sz & LEN_MASK) as usize;
+        if len == 0 || len > MAX_FRAME_LEN {
+            return Err(MsgError::Corrupt { msg: format!("frame of {} bytes, expected 1 to {}", len, MAX_FRAME_LEN) });
+        }
+        let codec = match Codec::from_u8(((sz & CODEC_MASK) >> CODEC_SHIFT) as u8) {
+            Some(codec) => codec,
+            None => return Err(MsgError::Corrupt { msg: format!("unknown codec in prefix {:08x}", sz) }),
+        };
+        Ok(FrameHeader {
+            sz,
+            len,
+            compressed: sz & COMPRESSED_FLAG != 0,
+            checksum: sz & CHECKSUM_FLAG != 0,
+            codec,
+        })
+    }
+
+    //everything that follows the prefix
+    fn rest_len(&self) -> usize {
+        match self.checksum {
+            true => CHECKSUM_LEN + self.len,
+            false => self.len,
+        }
+    }
+
+    //checks the checksum if there is one and decodes the body, once the checksum matched the frame
+    //boundaries are known to be right and a bad body only costs this one frame
+    //packed bodies are unpacked into a buffer of their own, only those frames own their payload
+    fn decode<'a>(&self, rest: &'a [u8]) -> Result<MsgInfo<'a>, MsgError> {
+        let body = match self.checksum {
+            true => {
+                let expected = msg_size(rest)?;
+                let body = &rest[CHECKSUM_LEN..];
+                let actual = crc32c::crc32c_append(crc32c::crc32c(&self.sz.to_le_bytes()), body);
+                if actual != expected {
+                    return Err(MsgError::Corrupt { msg: format!("checksum {:08x} does not match {:08x}", actual, expected) });
+                }
+                body
+            },
+            false => rest,
+        };
+
+        let res = match self.compressed {
+            true => decompress_body(body).and_then(|unpacked| decode_msginfo(&unpacked).map(MsgInfo::into_owned)),
+            false => decode_msginfo(body),
+        };
+        match res {
+            Ok(msginfo) => Ok(MsgInfo { codec: self.codec, ..msginfo }),
+            Err(e) if self.checksum => Err(MsgError::Frame { len: PREFIX_LEN + self.rest_len(), error: Box::new(e) }),
+            //without a checksum there is no telling a bad body from a bad length
+            Err(e) => Err(MsgError::Corrupt { msg: e.to_string() }),
+        }
+    }
+}
+
+//errors other than MsgError::Frame mean the stream can not be read any further
+//the first frame in `buffer` and the bytes it took up, None until all of it is there
+fn parse_frame(buffer: &[u8]) -> Result<Option<(Frame<'_>, usize)>, MsgError> {
+    let sz = match msg_size(buffer) {
         Ok(sz) => sz,
-        Err(MsgError::DataSizeTooSmall { min_size }) => return Ok(None),
+        Err(MsgError::DataSizeTooSmall { .. }) => return Ok(None),
         Err(e) => return Err(e),
-    } + size_of::<u32>() as u32; //for size
+    };
+    let header = FrameHeader::new(sz)?;
 
-    if sz == 0 {
-        return Err(MsgError::LogicError {msg: "0 sized msg detected".to_string()});
+    let newmsg_start = PREFIX_LEN + header.rest_len();
+    if buffer.len() < newmsg_start {
+        return Ok(None)
     }
 
-    if (buffer.len() as u32) < sz {
-        return Ok(None)
+    let msginfo = header.decode(&buffer[PREFIX_LEN..newmsg_start])?;
+    Ok(Some((Frame { msginfo, checksum: header.checksum }, newmsg_start)))
+}
+
+//errors other than MsgError::Frame mean the stream can not be read any further
+pub fn parse_msgstream(buffer: &[u8]) -> Result<Option<MsgStream<'_>>, MsgError> {
+    match parse_frame(buffer)? {
+        Some((frame, len)) => Ok(Some(MsgStream::new(frame, &buffer[len..]))),
+        None => Ok(None),
+    }
+}
+//blocking counterpart of parse_msgstream for request/response style connections,
+//frames announcing more than max_len bytes are refused before anything is allocated
+pub fn read_msginfo<R: Read>(reader: &mut R, max_len: usize) -> Result<MsgInfo<'static>, MsgError> {
+    let sz = match reader.read_u32::<LittleEndian>() {
+        Ok(sz) => sz,
+        Err(e) => return Err(MsgError::Read(e)),
+    };
+    let header = FrameHeader::new(sz)?;
+    if header.len > max_len {
+        return Err(MsgError::LogicError { msg: format!("frame of {} bytes, expected 1 to {}", header.len, max_len) });
     }
 
-    let newmsg_start = sz as usize;
+    let mut buffer = vec![0u8; header.rest_len()];
+    if let Err(e) = reader.read_exact(&mut buffer) {
+        return Err(MsgError::Read(e));
+    }
+    header.decode(&buffer).map(MsgInfo::into_owned)
+}
 
-    let msginfo = match decode_msginfo(&buffer[size_of::<u32>()..newmsg_start]) {
-        Ok(msginfo) => msginfo,
-        Err(e) => return Err(e),
+//bytes received on a nonblocking connection, read straight into the spare room at the end and parsed
+//from the front; parsed frames borrow from it, and the room they took is reused by later reads once
+//whatever is left over was moved to the front, so a busy connection does not allocate per read
+pub struct FrameBuffer {
+    buffer: Vec<u8>,
+    start: usize, //first byte not parsed yet
+    end: usize, //one past the last byte received
+    read_size: usize,
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        FrameBuffer::new(4096)
+    }
+}
+
+impl FrameBuffer {
+    //a buffer that grew past this for a large frame is given back once it is empty again
+    const KEEP_CAPACITY: usize = 64 * 1024;
+
+    //read_size is the most read_from asks the reader for at once
+    pub fn new(read_size: usize) -> Self {
+        FrameBuffer {
+            buffer: Vec::new(),
+            start: 0,
+            end: 0,
+            read_size: read_size.max(1),
+        }
+    }
+
+    //bytes received that are not part of a parsed frame yet
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.end = 0;
+        if self.buffer.len() > Self::KEEP_CAPACITY {
+            self.buffer = Vec::new();
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        if self.is_empty() {
+            self.clear();
+        }
+        if self.buffer.len() - self.end >= additional {
+            return;
+        }
+        self.buffer.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+        if self.buffer.len() - self.end < additional {
+            self.buffer.resize(self.end + additional, 0);
+        }
+    }
+
+    //one read of at most read_size bytes, 0 means the other side closed the connection
+    pub fn read_from<R: Read>(&mut self, reader: &mut R) -> std::io::Result<usize> {
+        self.reserve(self.read_size);
+        let read = reader.read(&mut self.buffer[self.end..self.end + self.read_size])?;
+        self.end += read;
+        Ok(read)
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        self.reserve(data.len());
+        self.buffer[self.end..self.end + data.len()].copy_from_slice(data);
+        self.end += data.len();
+    }
+
+    //the next complete frame, None until more bytes arrive; a MsgError::Frame was already skipped,
+    //the caller reports it and carries on, any other error leaves the buffer as it was
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'_>>, MsgError> {
+        match parse_frame(&self.buffer[self.start..self.end]) {
+            Ok(Some((frame, len))) => {
+                self.start += len;
+                Ok(Some(frame))
+            },
+            Ok(None) => Ok(None),
+            Err(MsgError::Frame { len, error }) => {
+                self.start += len;
+                Err(MsgError::Frame { len, error })
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Lz4, //cheap, good enough for chat traffic
+    Zstd, //smaller frames for more cpu time
+}
+
+impl Compression {
+    pub fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            id if id == Compression::None as u8 => Some(Compression::None),
+            id if id == Compression::Lz4 as u8 => Some(Compression::Lz4),
+            id if id == Compression::Zstd as u8 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression \"{}\", expected none, lz4 or zstd", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//how the payload of a message is written, the envelope around it is the same for all of them;
+//bincode is what the Rust peers use, JSON and MessagePack are for tooling in other languages
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    Bincode,
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack, //structs are maps keyed by field name
+}
+
+impl Codec {
+    pub fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            id if id == Codec::Bincode as u8 => Some(Codec::Bincode),
+            id if id == Codec::Json as u8 => Some(Codec::Json),
+            id if id == Codec::MessagePack as u8 => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+
+    fn prefix_bits(&self) -> u32 {
+        (*self as u32) << CODEC_SHIFT
+    }
+
+    fn encode_into<T: ?Sized + serde::Serialize>(&self, buffer: &mut Vec<u8>, data: &T) -> Result<(), MsgError> {
+        let fail = |msg: String| MsgError::Codec { msg };
+        match self {
+            Codec::Bincode => bincode::serialize_into(buffer, data).map_err(MsgError::Serialize),
+            Codec::Json => serde_json::to_writer(buffer, data).map_err(|e| fail(e.to_string())),
+            Codec::MessagePack => rmp_serde::encode::write_named(buffer, data).map_err(|e| fail(e.to_string())),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "bincode" => Ok(Codec::Bincode),
+            "json" => Ok(Codec::Json),
+            "msgpack" | "messagepack" => Ok(Codec::MessagePack),
+            _ => Err(format!("unknown codec \"{}\", expected bincode, json or msgpack", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Codec::Bincode => "bincode",
+            Codec::Json => "json",
+            Codec::MessagePack => "msgpack",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//what a connection agreed to do with the frames it sends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameOptions {
+    pub compression: Compression,
+    pub threshold: usize, //bodies shorter than this are not compressed
+    pub level: i32, //zstd only
+    pub checksum: bool,
+    pub codec: Codec, //used by whoever serializes for the connection, encode_frame does not touch payloads
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        FrameOptions {
+            compression: Compression::None,
+            threshold: 1024,
+            level: 3,
+            checksum: false,
+            codec: Codec::Bincode,
+        }
+    }
+}
+
+//applies `options` to one frame made by serialize_data/serialize_request, returns it untouched if
+//there is nothing to do; frames already carrying flags are left alone
+pub fn encode_frame<'a>(frame: &'a [u8], options: &FrameOptions) -> Cow<'a, [u8]> {
+    let frame = compress_frame(frame, options);
+    if !options.checksum {
+        return frame;
+    }
+    match add_checksum(&frame) {
+        Some(buffer) => Cow::Owned(buffer),
+        None => frame,
+    }
+}
+
+//the body of a frame without flags, None for anything else
+fn plain_body(frame: &[u8]) -> Option<&[u8]> {
+    let sz = msg_size(frame).ok()?;
+    if sz & (COMPRESSED_FLAG | CHECKSUM_FLAG) != 0 || frame.len() != PREFIX_LEN + (sz & LEN_MASK) as usize {
+        return None;
+    }
+    Some(&frame[PREFIX_LEN..])
+}
+
+//a packed body is the algorithm (u8), the unpacked length (u32) and the compressed bytes; bodies
+//under the threshold, those that would not shrink and those the compressor fails on stay as they are
+fn compress_frame<'a>(frame: &'a [u8], options: &FrameOptions) -> Cow<'a, [u8]> {
+    let body = match plain_body(frame) {
+        Some(body) if body.len() >= options.threshold => body,
+        _ => return Cow::Borrowed(frame),
+    };
+    let packed = match options.compression {
+        Compression::None => return Cow::Borrowed(frame),
+        Compression::Lz4 => lz4_flex::block::compress(body),
+        Compression::Zstd => match zstd::bulk::compress(body, options.level) {
+            Ok(packed) => packed,
+            Err(_) => return Cow::Borrowed(frame),
+        },
+    };
+    let packed_len = 1 + size_of::<u32>() + packed.len();
+    if packed_len >= body.len() {
+        return Cow::Borrowed(frame);
+    }
+
+    let mut buffer = Vec::with_capacity(PREFIX_LEN + packed_len);
+    let codec = msg_size(frame).map(|sz| sz & CODEC_MASK).unwrap_or(0);
+    buffer.extend_from_slice(&(packed_len as u32 | COMPRESSED_FLAG | codec).to_le_bytes());
+    buffer.push(options.compression as u8);
+    buffer.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&packed);
+    Cow::Owned(buffer)
+}
+
+fn add_checksum(frame: &[u8]) -> Option<Vec<u8>> {
+    let sz = msg_size(frame).ok()?;
+    if sz & CHECKSUM_FLAG != 0 || frame.len() != PREFIX_LEN + (sz & LEN_MASK) as usize {
+        return None;
+    }
+    let prefix = (sz | CHECKSUM_FLAG).to_le_bytes();
+    let body = &frame[PREFIX_LEN..];
+    let checksum = crc32c::crc32c_append(crc32c::crc32c(&prefix), body);
+
+    let mut buffer = Vec::with_capacity(frame.len() + CHECKSUM_LEN);
+    buffer.extend_from_slice(&prefix);
+    buffer.extend_from_slice(&checksum.to_le_bytes());
+    buffer.extend_from_slice(body);
+    Some(buffer)
+}
+
+fn decompress_body(body: &[u8]) -> Result<Vec<u8>, MsgError> {
+    const HEADER: usize = 1 + size_of::<u32>();
+    let fail = |msg: String| MsgError::Compression { msg };
+    if body.len() < HEADER {
+        return Err(fail(format!("{} bytes is too short for a packed body", body.len())));
+    }
+    let len = msg_size(&body[1..])? as usize;
+    if len > MAX_UNPACKED_LEN {
+        return Err(fail(format!("unpacks to {} bytes, at most {} are allowed", len, MAX_UNPACKED_LEN)));
+    }
+
+    let packed = &body[HEADER..];
+    let unpacked = match Compression::from_u8(body[0]) {
+        Some(Compression::Lz4) => lz4_flex::block::decompress(packed, len).map_err(|e| fail(e.to_string()))?,
+        Some(Compression::Zstd) => zstd::bulk::decompress(packed, len).map_err(|e| fail(e.to_string()))?,
+        _ => return Err(fail(format!("unknown algorithm {}", body[0]))),
     };
+    if unpacked.len() != len {
+        return Err(fail(format!("unpacked to {} bytes instead of {}", unpacked.len(), len)));
+    }
+    Ok(unpacked)
+}
+
+//large payloads are cut into chunks that each travel in a frame of their own, so a big body never holds
+//up the messages queued behind it for longer than one chunk; the chunks of every stream are numbered
+//from 0 and the last one carries `end`; receivers handle every chunk as it arrives, so neither side
+//ever holds the whole payload, `code` says what the data is
+pub const STREAM_CODE: u32 = u32::MAX;
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct StreamChunk<'a> {
+    pub stream_id: u32, //picked by the sender, unique among its open streams
+    pub seq: u32,
+    pub end: bool,
+    pub code: u32, //of the message the stream carries
+    #[serde(with = "serde_bytes")]
+    pub data: &'a [u8],
+}
+
+//turns whatever `reader` yields into stream frames, one chunk at a time; callers send each frame
+//as it is produced and are free to send other frames in between
+pub struct StreamWriter<R> {
+    reader: R,
+    codec: Codec, //the one the connection negotiated
+    code: u32,
+    request_id: u32,
+    stream_id: u32,
+    seq: u32,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> StreamWriter<R> {
+    pub fn new(reader: R, codec: Codec, code: u32, request_id: u32, stream_id: u32, chunk_size: usize) -> Self {
+        StreamWriter {
+            reader,
+            codec,
+            code,
+            request_id,
+            stream_id,
+            seq: 0,
+            buffer: vec![0u8; chunk_size.max(1)],
+            done: false,
+        }
+    }
+
+    //the next frame to send, None once the end marker was produced
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, MsgError> {
+        if self.done {
+            return Ok(None);
+        }
+        let read = loop {
+            match self.reader.read(&mut self.buffer) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(MsgError::Read(e)),
+            }
+        };
+        //the reader cannot tell whether a full chunk was the last one, so the end marker may come without data
+        let end = read == 0;
+        let chunk = StreamChunk {
+            stream_id: self.stream_id,
+            seq: self.seq,
+            end,
+            code: self.code,
+            data: &self.buffer[..read],
+        };
+        let frame = serialize_request_with(self.codec, STREAM_CODE, self.request_id, &chunk)?;
+
+        self.done = end;
+        self.seq = match self.seq.checked_add(1) {
+            Some(seq) => seq,
+            None => return Err(MsgError::Stream { stream_id: self.stream_id, msg: "too many chunks".to_string() }),
+        };
+        Ok(Some(frame))
+    }
+}
+
+impl<R: Read> Iterator for StreamWriter<R> {
+    type Item = Result<Vec<u8>, MsgError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+struct OpenStream {
+    code: u32,
+    next_seq: u32,
+    len: usize, //data received so far, only counted
+}
+
+//checks the streams of one connection as their chunks come in, nothing is buffered: each chunk is
+//passed on to be handled as soon as it arrived in order; a stream that breaks the rules (chunks out
+//of order, a changed code, too much data) is dropped and reported, the others carry on
+pub struct Streams {
+    open: HashMap<u32, OpenStream>,
+    max_len: usize, //per stream
+    max_open: usize,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Streams::new(16 * 1024 * 1024, 16)
+    }
+}
+
+impl Streams {
+    pub fn new(max_len: usize, max_open: usize) -> Self {
+        Streams {
+            open: HashMap::new(),
+            max_len,
+            max_open,
+        }
+    }
+
+    //checks a stream frame, returns its chunk if it is the next one of its stream; the stream is
+    //closed once the chunk with `end` went through
+    pub fn push<'a>(&mut self, msginfo: &'a MsgInfo) -> Result<StreamChunk<'a>, MsgError> {
+        let chunk = msginfo.stream_chunk()?;
+        let fail = |msg: String| MsgError::Stream { stream_id: chunk.stream_id, msg };
+
+        if !self.open.contains_key(&chunk.stream_id) {
+            if chunk.seq != 0 {
+                return Err(fail(format!("chunk {} of a stream that was never opened", chunk.seq)));
+            }
+            if self.open.len() >= self.max_open {
+                return Err(fail(format!("more than {} streams open at once", self.max_open)));
+            }
+            self.open.insert(chunk.stream_id, OpenStream {
+                code: chunk.code,
+                next_seq: 0,
+                len: 0,
+            });
+        }
+        let stream = self.open.get_mut(&chunk.stream_id).expect("stream opened above");
+
+        let res = if chunk.seq != stream.next_seq {
+            Err(fail(format!("expected chunk {}, got {}", stream.next_seq, chunk.seq)))
+        }
+        else if chunk.code != stream.code {
+            Err(fail(format!("code changed from {} to {}", stream.code, chunk.code)))
+        }
+        else if stream.len + chunk.data.len() > self.max_len {
+            Err(fail(format!("longer than {} bytes", self.max_len)))
+        }
+        else {
+            //StreamWriter refuses to number a chunk past u32::MAX, so no sender goes there either
+            stream.next_seq.checked_add(1).ok_or_else(|| fail("too many chunks".to_string()))
+        };
+        let next_seq = match res {
+            Ok(next_seq) => next_seq,
+            Err(e) => {
+                self.open.remove(&chunk.stream_id);
+                return Err(e);
+            },
+        };
+
+        stream.len += chunk.data.len();
+        stream.next_seq = next_seq;
+        if chunk.end {
+            self.open.remove(&chunk.stream_id);
+        }
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_frame(seq: u32, end: bool) -> MsgInfo<'static> {
+        let chunk = StreamChunk { stream_id: 1, seq, end, code: 7, data: b"chunk" };
+        let frame = serialize_request_with(Codec::Bincode, STREAM_CODE, 0, &chunk).unwrap();
+        read_msginfo(&mut frame.as_slice(), MAX_FRAME_LEN).unwrap()
+    }
+
+    #[test]
+    fn stream_chunks_arrive_in_order() {
+        let mut streams = Streams::default();
+        assert_eq!(streams.push(&chunk_frame(0, false)).unwrap().seq, 0);
+        assert!(matches!(streams.push(&chunk_frame(2, false)), Err(MsgError::Stream { stream_id: 1, .. })));
+        //the stream was dropped with the bad chunk
+        assert!(streams.open.is_empty());
+    }
+
+    #[test]
+    fn stream_sequence_does_not_wrap() {
+        let mut streams = Streams::default();
+        streams.push(&chunk_frame(0, false)).unwrap();
+        streams.open.get_mut(&1).unwrap().next_seq = u32::MAX;
+        assert!(matches!(streams.push(&chunk_frame(u32::MAX, false)), Err(MsgError::Stream { stream_id: 1, .. })));
+        assert!(streams.open.is_empty());
+    }
+
+    //the only frame in `buffer`
+    fn parse_one(buffer: &[u8]) -> Result<MsgInfo<'_>, MsgError> {
+        let (frame, len) = parse_frame(buffer)?.expect("Frame is incomplete");
+        assert_eq!(len, buffer.len());
+        Ok(frame.msginfo)
+    }
+
+    fn round_trip(compression: Compression) {
+        let options = FrameOptions { compression, threshold: 256, ..FrameOptions::default() };
+        for payload in ["short", &"a compressible line\n".repeat(100)] {
+            let frame = serialize_data(42, payload).unwrap();
+            let encoded = encode_frame(&frame, &options);
+            let sz = msg_size(&encoded).unwrap();
+            assert_eq!(sz & COMPRESSED_FLAG != 0, payload.len() >= options.threshold, "{} bytes", payload.len());
+
+            let msginfo = parse_one(&encoded).unwrap();
+            assert_eq!(msginfo.code, 42);
+            assert_eq!(msginfo.decode_data::<&str>().unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn lz4_frames_round_trip() {
+        round_trip(Compression::Lz4);
+    }
+
+    #[test]
+    fn zstd_frames_round_trip() {
+        round_trip(Compression::Zstd);
+    }
+
+    #[test]
+    fn packed_bodies_must_unpack_to_their_size() {
+        let payload = "a compressible line\n".repeat(100);
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let options = FrameOptions { compression, ..FrameOptions::default() };
+            let frame = serialize_data(42, &payload).unwrap();
+            let mut encoded = encode_frame(&frame, &options).into_owned();
+            let body = &mut encoded[PREFIX_LEN..];
+            assert!(decompress_body(body).is_ok());
 
-    let msgstream = MsgStream::new(msginfo, &buffer[newmsg_start..]);
-    Ok(Some(msgstream))
-}
\ No newline at end of file
+            //claims one byte more than it unpacks to
+            let len = msg_size(&body[1..]).unwrap() + 1;
+            body[1..5].copy_from_slice(&len.to_le_bytes());
+            assert!(matches!(decompress_body(body), Err(MsgError::Compression { .. })), "{}", compression);
+        }
+    }
+
+    fn checksummed(payload: &str) -> Vec<u8> {
+        let options = FrameOptions { checksum: true, ..FrameOptions::default() };
+        encode_frame(&serialize_data(42, payload).unwrap(), &options).into_owned()
+    }
+
+    #[test]
+    fn checksums_catch_a_changed_body() {
+        let mut frame = checksummed("checked");
+        assert_eq!(parse_one(&frame).unwrap().decode_data::<&str>().unwrap(), "checked");
+
+        *frame.last_mut().unwrap() ^= 1;
+        assert!(matches!(parse_one(&frame), Err(MsgError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn checksums_catch_a_changed_flag() {
+        let mut frame = checksummed("checked");
+        //the prefix is little endian, the compressed flag is the top bit of its last byte
+        frame[PREFIX_LEN - 1] ^= (COMPRESSED_FLAG >> 24) as u8;
+        assert!(matches!(parse_one(&frame), Err(MsgError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn checksums_can_not_be_dropped_halfway() {
+        let mut buffer = FrameBuffer::default();
+        buffer.extend_from_slice(&serialize_data(42, "plain").unwrap());
+        buffer.extend_from_slice(&checksummed("checked"));
+        buffer.extend_from_slice(&serialize_data(42, "plain").unwrap());
+
+        let mut checksums = false;
+        buffer.next_frame().unwrap().unwrap().require_checksum(&mut checksums).unwrap();
+        assert!(!checksums);
+        buffer.next_frame().unwrap().unwrap().require_checksum(&mut checksums).unwrap();
+        assert!(checksums);
+        let frame = buffer.next_frame().unwrap().unwrap();
+        assert!(matches!(frame.require_checksum(&mut checksums), Err(MsgError::Corrupt { .. })));
+    }
+
+    //every payload the buffer hands out until it runs dry, None for a frame it skipped
+    fn drain(buffer: &mut FrameBuffer, payloads: &mut Vec<Option<String>>) {
+        loop {
+            match buffer.next_frame() {
+                Ok(Some(frame)) => payloads.push(Some(frame.msginfo.decode_data::<String>().unwrap())),
+                Ok(None) => break,
+                Err(MsgError::Frame { .. }) => payloads.push(None),
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    //a checksummed frame whose body is not a message at all
+    fn undecodable() -> Vec<u8> {
+        let mut frame = 3u32.to_le_bytes().to_vec();
+        frame.extend_from_slice(&[0xff; 3]);
+        add_checksum(&frame).unwrap()
+    }
+
+    fn frames() -> (Vec<u8>, Vec<Option<String>>) {
+        let long = "long ".repeat(2000);
+        let mut bytes = serialize_data(1, "first").unwrap();
+        bytes.extend_from_slice(&checksummed("second"));
+        bytes.extend_from_slice(&undecodable());
+        bytes.extend_from_slice(&serialize_data(1, &long).unwrap());
+        bytes.extend_from_slice(&checksummed("last"));
+        let payloads = vec![Some("first".to_string()), Some("second".to_string()), None, Some(long), Some("last".to_string())];
+        (bytes, payloads)
+    }
+
+    #[test]
+    fn frames_arrive_byte_by_byte() {
+        let (bytes, expected) = frames();
+        let mut buffer = FrameBuffer::default();
+        let mut payloads = Vec::new();
+        for byte in &bytes {
+            buffer.extend_from_slice(std::slice::from_ref(byte));
+            drain(&mut buffer, &mut payloads);
+        }
+        assert_eq!(payloads, expected);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn frames_arrive_coalesced() {
+        let (bytes, expected) = frames();
+        //all at once, then in reads that end anywhere in a frame
+        for read_size in [bytes.len(), 7, 100, 4096] {
+            let mut buffer = FrameBuffer::new(read_size);
+            let mut reader = bytes.as_slice();
+            let mut payloads = Vec::new();
+            while buffer.read_from(&mut reader).unwrap() > 0 {
+                drain(&mut buffer, &mut payloads);
+            }
+            assert_eq!(payloads, expected, "reads of {} bytes", read_size);
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn broken_framing_stops_the_buffer() {
+        let mut bytes = serialize_data(1, "first").unwrap();
+        //a zero length can not be skipped, nothing after it is read
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&serialize_data(1, "lost").unwrap());
+        let mut buffer = FrameBuffer::default();
+        buffer.extend_from_slice(&bytes);
+        assert_eq!(buffer.next_frame().unwrap().unwrap().msginfo.decode_data::<&str>().unwrap(), "first");
+        assert!(matches!(buffer.next_frame(), Err(MsgError::Corrupt { .. })));
+        assert!(matches!(buffer.next_frame(), Err(MsgError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn payloads_round_trip_in_every_codec() {
+        use crate::messages::server::{Message, MsgOnSent, MsgWho, MsgWhoEntry};
+        //escapes and non-ASCII text are where JSON strings stop being borrowable
+        let sent = MsgOnSent { user: "zoë", msg: "she said \"hi\"\n" };
+        let who = MsgWho {
+            room: "dev",
+            users: vec![MsgWhoEntry { user: "alice", away: None }, MsgWhoEntry { user: "bob", away: Some("at \"lunch\" ☕") }],
+        };
+        for codec in [Codec::Bincode, Codec::Json, Codec::MessagePack] {
+            let frame = serialize_request_with(codec, Message::OnSent as u32, 7, &sent).unwrap();
+            let msginfo = parse_one(&frame).unwrap();
+            assert_eq!((msginfo.code, msginfo.request_id, msginfo.codec()), (Message::OnSent as u32, 7, codec));
+            let decoded = msginfo.decode_data::<MsgOnSent>().unwrap();
+            assert_eq!((decoded.user, decoded.msg), (sent.user, sent.msg), "{}", codec);
+
+            let frame = serialize_request_with(codec, Message::OnWho as u32, 0, &who).unwrap();
+            let msginfo = parse_one(&frame).unwrap();
+            let decoded = msginfo.decode_data::<MsgWho>().unwrap();
+            assert_eq!(decoded.room, who.room, "{}", codec);
+            let users: Vec<_> = decoded.users.iter().map(|entry| (entry.user, entry.away)).collect();
+            assert_eq!(users, [("alice", None), ("bob", Some("at \"lunch\" ☕"))], "{}", codec);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use serde;
+
+//messages on the admin channel, both directions, request/response in lockstep
+//the first request on a connection must be OnAuth, commands use the console syntax
+
+pub enum Message {
+    OnAuth,
+    OnCommand,
+    OnResult,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAuth<'a> {
+    pub token: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgCommand<'a> {
+    pub line: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgResult<'a> {
+    pub ok: bool,
+    pub output: &'a str,
+}
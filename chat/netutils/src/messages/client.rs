@@ -1,10 +1,26 @@
 use serde;
+use std::borrow::Cow;
 
 //messages sent from client to server
 
 pub enum Message {
     OnRegisterUser,
     OnSent,
+    OnJoin,
+    OnPart,
+    OnPrivateMsg,
+    OnAction,
+    OnWho,
+    OnAway,
+    OnNick,
+    OnReserve,
+    OnFileOffer,
+    OnFileAccept,
+    OnFileDecline,
+    OnFileChunk,
+    OnFileComplete,
+    OnFileCancel,
+    OnHello,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -15,4 +31,106 @@ pub struct MsgOnRegisterUser<'a> {
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct MsgOnSent<'a> {
     pub msg: &'a str,
-}
\ No newline at end of file
+}
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgJoin<'a> {
+    pub room: &'a str,
+}
+
+//leaving the lobby is not possible, parting any other room moves the user back there
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgPart<'a> {
+    pub room: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgPrivateMsg<'a> {
+    pub to: &'a str,
+    pub msg: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAction<'a> {
+    pub msg: &'a str,
+}
+
+//empty room means the sender's current room
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgWho<'a> {
+    pub room: &'a str,
+}
+
+//None clears the away status
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAway<'a> {
+    #[serde(borrow)]
+    pub msg: Option<&'a str>,
+}
+
+//the password is only needed when the new name is reserved
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgNick<'a> {
+    pub user: &'a str,
+    #[serde(borrow)]
+    pub password: Option<&'a str>,
+}
+
+//reserves the sender's current name, None releases it
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgReserve<'a> {
+    #[serde(borrow)]
+    pub password: Option<&'a str>,
+}
+
+//the server answers with OnFileOffered carrying the id of the transfer, the file itself is only
+//sent once the recipient accepted it
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileOffer<'a> {
+    pub to: &'a str,
+    pub name: &'a str, //file name without any directory
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+//offset is how much of the file the recipient already has from an interrupted transfer
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileAccept {
+    pub id: u32,
+    pub offset: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileDecline {
+    pub id: u32,
+}
+
+//chunks are relayed as they come, each one has to start where the previous one ended
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileChunk<'a> {
+    pub id: u32,
+    pub offset: u64,
+    #[serde(with = "serde_bytes", borrow)]
+    pub data: Cow<'a, [u8]>, //borrowed from the frame unless the codec has no raw bytes, like JSON
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileComplete {
+    pub id: u32,
+}
+
+//either side may cancel, the other one is told why
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileCancel<'a> {
+    pub id: u32,
+    pub reason: &'a str,
+}
+
+//sent right after connecting, lists the compression algorithms (message_stream::Compression as u8)
+//and payload codecs (message_stream::Codec as u8) the client is willing to use, most preferred
+//first, and whether it wants every frame checksummed; the server answers with its own OnHello
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgHello {
+    pub compression: Vec<u8>,
+    pub checksum: bool,
+    pub codecs: Vec<u8>, //bincode is assumed when empty or nothing else matches
+}
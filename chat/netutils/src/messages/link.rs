@@ -0,0 +1,118 @@
+use serde;
+
+//messages between linked servers, both directions; the server that dials sends OnHello first, the
+//other one answers with its own OnHello or with OnError, then each side sends OnUser for everyone
+//on its side of the link
+//links form a tree, every event names the server it started on and is passed on to every link but
+//the one it came from, so each server sees it exactly once
+
+pub enum Message {
+    OnHello,
+    OnError,
+    OnServers,
+    OnSquit,
+    OnUser,
+    OnQuit,
+    OnRename,
+    OnMove,
+    OnAway,
+    OnSent,
+    OnAction,
+    OnPrivateMsg,
+}
+
+//`servers` are the ones already linked to the sender, the receiver refuses the link if it knows
+//any of them, linking would close a loop
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgHello<'a> {
+    pub name: &'a str,
+    pub password: &'a str,
+    #[serde(borrow)]
+    pub servers: Vec<&'a str>,
+}
+
+//the link was refused, the connection is closed by whoever receives this
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgError<'a> {
+    pub reason: &'a str,
+}
+
+//servers reachable through the sender from now on
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgServers<'a> {
+    #[serde(borrow)]
+    pub servers: Vec<&'a str>,
+}
+
+//servers split off the network, everyone on them is gone as well
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgSquit<'a> {
+    #[serde(borrow)]
+    pub servers: Vec<&'a str>,
+    pub reason: &'a str,
+}
+
+//a user registered on `server`, or was there already when the link came up
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgUser<'a> {
+    pub server: &'a str,
+    pub user: &'a str,
+    pub room: &'a str,
+    #[serde(borrow)]
+    pub away: Option<&'a str>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgQuit<'a> {
+    pub server: &'a str,
+    pub user: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgRename<'a> {
+    pub server: &'a str,
+    pub old: &'a str,
+    pub new: &'a str,
+}
+
+//the user left their room for `room`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgMove<'a> {
+    pub server: &'a str,
+    pub user: &'a str,
+    pub room: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAway<'a> {
+    pub server: &'a str,
+    pub user: &'a str,
+    #[serde(borrow)]
+    pub msg: Option<&'a str>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgSent<'a> {
+    pub server: &'a str,
+    pub user: &'a str,
+    pub room: &'a str,
+    pub msg: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAction<'a> {
+    pub server: &'a str,
+    pub user: &'a str,
+    pub room: &'a str,
+    pub msg: &'a str,
+}
+
+//only passed towards `to_server`, not to every link
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgPrivateMsg<'a> {
+    pub server: &'a str,
+    pub from: &'a str,
+    pub to_server: &'a str,
+    pub to: &'a str,
+    pub msg: &'a str,
+}
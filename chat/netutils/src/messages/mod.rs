@@ -1,2 +1,4 @@
+pub mod admin;
 pub mod client;
+pub mod link;
 pub mod server;
\ No newline at end of file
@@ -1,4 +1,5 @@
 use serde;
+use std::borrow::Cow;
 
 //messages sent from server to client
 
@@ -9,6 +10,76 @@ pub enum Message {
     OnAlreadyRegisteredUser,
     OnRegistrationSuccess,
     OnSent,
+    OnServerShutdown,
+    OnKicked,
+    OnServerNotice,
+    OnUserList,
+    OnJoined,
+    OnParted,
+    OnPrivateMsg,
+    OnAction,
+    OnWho,
+    OnAway,
+    OnRename,
+    OnNameRejected,
+    OnError,
+    OnAck,
+    OnFileOffer,
+    OnFileOffered,
+    OnFileAccepted,
+    OnFileDeclined,
+    OnFileChunk,
+    OnFileComplete,
+    OnFileCancelled,
+    OnHello,
+}
+
+//why the server refused a request, sent as the code of MsgError
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnknownMessage, //the message code is not known to the server
+    Malformed, //the message could not be decoded
+    NotRegistered, //the request needs a registered name
+    InvalidArgument,
+    NotFound,
+    Conflict, //the request clashes with the current state, e.g. joining the room you are in
+    Forbidden,
+    TooLarge,
+    Internal,
+}
+
+impl ErrorCode {
+    //codes added by newer servers are reported as Internal
+    pub fn from_u32(code: u32) -> Self {
+        match code {
+            code if code == ErrorCode::UnknownMessage as u32 => ErrorCode::UnknownMessage,
+            code if code == ErrorCode::Malformed as u32 => ErrorCode::Malformed,
+            code if code == ErrorCode::NotRegistered as u32 => ErrorCode::NotRegistered,
+            code if code == ErrorCode::InvalidArgument as u32 => ErrorCode::InvalidArgument,
+            code if code == ErrorCode::NotFound as u32 => ErrorCode::NotFound,
+            code if code == ErrorCode::Conflict as u32 => ErrorCode::Conflict,
+            code if code == ErrorCode::Forbidden as u32 => ErrorCode::Forbidden,
+            code if code == ErrorCode::TooLarge as u32 => ErrorCode::TooLarge,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ErrorCode::UnknownMessage => "unknown message",
+            ErrorCode::Malformed => "malformed message",
+            ErrorCode::NotRegistered => "not registered",
+            ErrorCode::InvalidArgument => "invalid argument",
+            ErrorCode::NotFound => "not found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::TooLarge => "too large",
+            ErrorCode::Internal => "internal error",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -39,4 +110,167 @@ pub struct MsgOnDisconnect<'a> {
 pub struct MsgOnSent<'a, 'b> {
     pub user: &'a str,
     pub msg: &'b str,
-}
\ No newline at end of file
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgServerShutdown<'a> {
+    pub reason: &'a str,
+    pub retry_after: u32, //seconds, 0 if unknown
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgKicked<'a> {
+    pub reason: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgServerNotice<'a> {
+    pub msg: &'a str,
+}
+
+//sent to a newly registered user, everyone else learns about it through OnRegisterUser
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgUserList<'a> {
+    #[serde(borrow)]
+    pub users: Vec<&'a str>,
+}
+
+//sent to every member of the room, including the user who joined
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgJoined<'a> {
+    pub user: &'a str,
+    pub room: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgParted<'a> {
+    pub user: &'a str,
+    pub room: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgPrivateMsg<'a> {
+    pub from: &'a str,
+    pub msg: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAction<'a> {
+    pub user: &'a str,
+    pub msg: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgWhoEntry<'a> {
+    pub user: &'a str,
+    #[serde(borrow)]
+    pub away: Option<&'a str>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgWho<'a> {
+    pub room: &'a str,
+    #[serde(borrow)]
+    pub users: Vec<MsgWhoEntry<'a>>,
+}
+
+//answer to a private message sent to someone who is away
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAway<'a> {
+    pub user: &'a str,
+    pub msg: &'a str,
+}
+
+//sent to everyone, including the user who changed their name
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgRename<'a> {
+    pub old: &'a str,
+    pub new: &'a str,
+}
+
+//the name breaks the server's naming policy, the client may try another one
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgNameRejected<'a> {
+    pub user: &'a str,
+    pub reason: &'a str,
+}
+
+//reply to a request the server refused, request_id is 0 when the request carried none
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgError<'a> {
+    pub code: u32, //ErrorCode
+    pub request_id: u32,
+    pub message: &'a str,
+}
+
+//reply to a request that succeeded without anything else to say, only sent when the request carried an id
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgAck {
+}
+
+//sent to the recipient of a file, who answers with OnFileAccept or OnFileDecline
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileOffer<'a> {
+    pub id: u32,
+    pub from: &'a str,
+    pub name: &'a str,
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+//reply to the sender's offer
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileOffered<'a> {
+    pub id: u32,
+    pub to: &'a str,
+    pub name: &'a str,
+}
+
+//tells the sender to start sending chunks from offset
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileAccepted<'a> {
+    pub id: u32,
+    pub user: &'a str,
+    pub offset: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileDeclined<'a> {
+    pub id: u32,
+    pub user: &'a str,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileChunk<'a> {
+    pub id: u32,
+    pub offset: u64,
+    #[serde(with = "serde_bytes", borrow)]
+    pub data: Cow<'a, [u8]>, //borrowed from the frame unless the codec has no raw bytes, like JSON
+}
+
+//every chunk was relayed, the recipient checks the file against the offered hash
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileComplete {
+    pub id: u32,
+}
+
+//user is whoever cancelled, or the one who disconnected
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgFileCancelled<'a> {
+    pub id: u32,
+    pub user: &'a str,
+    pub reason: &'a str,
+}
+
+//the algorithm picked for this connection, Compression::None if nothing matched; frames with a body
+//of at least `threshold` bytes may be packed from now on, in both directions, and with `checksum`
+//every frame carries a CRC32C, this reply included; payloads are written with `codec` from this
+//reply on, it is one the client offered or bincode
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MsgHello {
+    pub compression: u8,
+    pub threshold: u32,
+    pub level: i32,
+    pub checksum: bool,
+    pub codec: u8,
+}
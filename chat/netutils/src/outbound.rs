@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, IoSlice, Write};
+use std::ops::Deref;
+use std::sync::Arc;
+
+//an encoded frame that can sit in many queues at once, cloning it only bumps a reference count
+#[derive(Clone, Debug)]
+pub struct SharedFrame {
+    bytes: Arc<[u8]>,
+}
+
+impl SharedFrame {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        SharedFrame {
+            bytes: Arc::from(bytes),
+        }
+    }
+}
+
+impl From<Vec<u8>> for SharedFrame {
+    fn from(bytes: Vec<u8>) -> Self {
+        SharedFrame::new(bytes)
+    }
+}
+
+impl From<&[u8]> for SharedFrame {
+    fn from(bytes: &[u8]) -> Self {
+        SharedFrame {
+            bytes: Arc::from(bytes),
+        }
+    }
+}
+
+impl Deref for SharedFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl AsRef<[u8]> for SharedFrame {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+//frames waiting to be written to one connection, in the order they were queued;
+//a nonblocking socket takes what fits and the rest waits for the next flush
+#[derive(Default)]
+pub struct OutboundQueue {
+    frames: VecDeque<SharedFrame>,
+    written: usize, //bytes of the front frame already written
+    len: usize, //bytes queued and not written yet
+    closed: bool, //the connection is gone, frames pushed from now on are dropped
+}
+
+impl OutboundQueue {
+    //frames handed to the socket in one write_vectored call at most
+    const MAX_BATCH: usize = 64;
+
+    pub fn new() -> Self {
+        OutboundQueue::default()
+    }
+
+    pub fn push(&mut self, frame: SharedFrame) {
+        if frame.is_empty() || self.closed {
+            return;
+        }
+        self.len += frame.len();
+        self.frames.push_back(frame);
+    }
+
+    //bytes still to be written
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.written = 0;
+        self.len = 0;
+    }
+
+    //drops what is queued and everything pushed later, for connections that were shut down
+    pub fn close(&mut self) {
+        self.clear();
+        self.closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    //writes until the queue is empty or the writer would block, several frames per call;
+    //returns the bytes written, a frame cut short is picked up where it stopped next time
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> std::io::Result<usize> {
+        let mut total = 0;
+        while !self.is_empty() {
+            let mut slices = Vec::with_capacity(self.frames.len().min(Self::MAX_BATCH));
+            for (i, frame) in self.frames.iter().take(Self::MAX_BATCH).enumerate() {
+                match i {
+                    0 => slices.push(IoSlice::new(&frame[self.written..])),
+                    _ => slices.push(IoSlice::new(frame)),
+                }
+            }
+
+            let written = match writer.write_vectored(&slices) {
+                Ok(0) => return Err(std::io::Error::new(ErrorKind::WriteZero, "failed to write queued frames")),
+                Ok(written) => written,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            };
+            drop(slices);
+            self.advance(written);
+            total += written;
+        }
+        Ok(total)
+    }
+
+    fn advance(&mut self, mut written: usize) {
+        self.len -= written;
+        while written > 0 {
+            let rest = self.frames[0].len() - self.written;
+            if written < rest {
+                self.written += written;
+                return;
+            }
+            written -= rest;
+            self.written = 0;
+            self.frames.pop_front();
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::{write::GzEncoder, Compression};
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotateEvery {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl RotateEvery {
+    fn period_secs(&self) -> Option<u64> {
+        match self {
+            RotateEvery::Never => None,
+            RotateEvery::Hourly => Some(3600),
+            RotateEvery::Daily => Some(86400),
+        }
+    }
+}
+
+impl FromStr for RotateEvery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "never" => Ok(RotateEvery::Never),
+            "hourly" => Ok(RotateEvery::Hourly),
+            "daily" => Ok(RotateEvery::Daily),
+            _ => Err(format!("unknown rotation interval \"{}\", expected never, hourly or daily", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    pub max_size: u64, //bytes, 0 disables size based rotation
+    pub every: RotateEvery,
+    pub keep: usize, //rotated files kept next to the active one
+    pub compress: bool,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        RotationPolicy {
+            max_size: 0,
+            every: RotateEvery::Never,
+            keep: 5,
+            compress: false,
+        }
+    }
+}
+
+//appends to `path` and moves it to path.1 (path.1.gz when compressing) once it grows past
+//max_size or crosses a UTC hour/day boundary; older files shift up and only `keep` survive;
+//compression runs on its own thread so writers never wait for it
+pub struct RotatingFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    file: Option<File>,
+    size: u64,
+    period: u64, //index of the hour/day the active file belongs to
+    compressing: Option<JoinHandle<()>>, //gzips the last rotated file into path.1.gz
+}
+
+impl RotatingFile {
+    pub fn open<P: AsRef<Path>>(path: P, policy: RotationPolicy) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut rotating = RotatingFile {
+            path,
+            policy,
+            file: None,
+            size: 0,
+            period: 0,
+            compressing: None,
+        };
+
+        rotating.finish_staged()?;
+        //an existing file from an earlier period is rotated away instead of appended to
+        if let Ok(meta) = fs::metadata(&rotating.path) {
+            let modified = meta.modified().unwrap_or_else(|_| SystemTime::now());
+            if meta.len() > 0 && rotating.period_of(modified) != rotating.period_of(SystemTime::now()) {
+                rotating.rotate()?;
+            }
+        }
+        rotating.open_active()?;
+        Ok(rotating)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn period_of(&self, time: SystemTime) -> u64 {
+        match self.policy.every.period_secs() {
+            Some(period) => time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / period,
+            None => 0,
+        }
+    }
+
+    fn open_active(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.period = self.period_of(SystemTime::now());
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        if self.policy.compress {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+
+    //where a rotated file waits to be compressed, left readable there if the process dies first
+    fn staging_path(&self) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".1");
+        PathBuf::from(name)
+    }
+
+    //the previous compression has to be done before path.1.gz is shifted up
+    fn wait_compressed(&mut self) {
+        if let Some(handle) = self.compressing.take() {
+            let _ = handle.join();
+        }
+    }
+
+    //a staged file left by a crash or a failed compression is finished before it can be overwritten;
+    //path.1.gz was shifted up before the file was staged, so anything there is a partial copy of it
+    fn finish_staged(&self) -> io::Result<()> {
+        let staging = self.staging_path();
+        if self.policy.compress && staging.exists() {
+            compress(&staging, &self.rotated_path(1))?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        self.wait_compressed();
+        self.finish_staged()?;
+
+        if self.policy.keep == 0 {
+            if self.path.exists() {
+                fs::remove_file(&self.path)?;
+            }
+            return Ok(());
+        }
+
+        let oldest = self.rotated_path(self.policy.keep);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for index in (1..self.policy.keep).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+
+        if !self.path.exists() {
+            return Ok(());
+        }
+        if self.policy.compress {
+            let staging = self.staging_path();
+            let target = self.rotated_path(1);
+            fs::rename(&self.path, &staging)?;
+            let (thread_staging, thread_target) = (staging.clone(), target.clone());
+            let res = thread::Builder::new().name(String::from("LogCompress")).spawn(move || {
+                //nowhere else to report it, the logger is what is being written
+                if let Err(e) = compress(&thread_staging, &thread_target) {
+                    let _ = writeln!(io::stderr(), "Failed to compress {}: {}", thread_staging.display(), e);
+                }
+            });
+            match res {
+                Ok(handle) => self.compressing = Some(handle),
+                Err(_) => compress(&staging, &target)?,
+            }
+        }
+        else {
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        Ok(())
+    }
+
+    fn rotate_if_needed(&mut self, incoming: usize) -> io::Result<()> {
+        let too_big = self.policy.max_size > 0 && self.size > 0 && self.size + incoming as u64 > self.policy.max_size;
+        let new_period = self.period_of(SystemTime::now()) != self.period;
+        if too_big || new_period || self.file.is_none() {
+            if self.file.is_some() {
+                self.rotate()?;
+            }
+            self.open_active()?;
+        }
+        Ok(())
+    }
+}
+
+fn compress(from: &Path, to: &Path) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(from)
+}
+
+impl Drop for RotatingFile {
+    fn drop(&mut self) {
+        self.wait_compressed();
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.rotate_if_needed(buf.len())?;
+        let written = match self.file.as_mut() {
+            Some(file) => file.write(buf)?,
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, "log file is not open")),
+        };
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rotating-file-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("log.txt")
+    }
+
+    fn policy() -> RotationPolicy {
+        RotationPolicy { max_size: 8, compress: true, ..RotationPolicy::default() }
+    }
+
+    fn gunzip(path: &Path) -> String {
+        let mut text = String::new();
+        io::Read::read_to_string(&mut flate2::read::GzDecoder::new(File::open(path).unwrap()), &mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn a_file_left_staged_is_compressed_at_startup() {
+        let path = scratch("startup");
+        let staged = path.with_extension("txt.1");
+        fs::write(&staged, "before the crash\n").unwrap();
+        //a compression that died halfway
+        fs::write(path.with_extension("txt.1.gz"), "partial").unwrap();
+
+        let file = RotatingFile::open(&path, policy()).unwrap();
+        assert!(!staged.exists());
+        assert_eq!(gunzip(&path.with_extension("txt.1.gz")), "before the crash\n");
+        drop(file);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn a_file_left_staged_survives_the_next_rotation() {
+        let path = scratch("rotation");
+        let mut file = RotatingFile::open(&path, policy()).unwrap();
+        file.write_all(b"first\n").unwrap();
+        //as if the compression of a rotation had failed
+        fs::write(path.with_extension("txt.1"), "staged\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        drop(file);
+
+        assert_eq!(gunzip(&path.with_extension("txt.1.gz")), "first\n");
+        assert_eq!(gunzip(&path.with_extension("txt.2.gz")), "staged\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
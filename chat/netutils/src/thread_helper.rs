@@ -50,7 +50,7 @@ impl ThreadHelper {
             thread_state,
             name: String::new(),
             handle: None,
-            logger: logger,
+            logger,
         }
     }
 
@@ -58,14 +58,17 @@ impl ThreadHelper {
     where
         Job: FnOnce() + Send + 'static, 
     {
-        let thread = thread::spawn(move || job());
+        let thread = thread::Builder::new()
+            .name(name.clone())
+            .spawn(job)
+            .expect("Failed to spawn thread");
 
         self.name = name;
         self.handle = Some(thread);
     }
 
     pub fn shutdown(&mut self) -> Result<(), ThreadError> {
-        self.logger.log(format!("Thread {} shutting down...", self.name).as_bytes()).expect("Failed to write to log");
+        self.logger.info(module_path!(), &format!("Thread {} shutting down...", self.name));
         // let f = |writer: &mut Box<dyn std::io::Write>| writer.write(format!("Thread {} shutting down...", self.name).as_bytes()).expect("Failed to write to log");
         // self.logger.apply(f);
 
@@ -79,7 +82,7 @@ impl ThreadHelper {
             handle.join().expect("Failed to join thread"); //join requires a move (takes self instead of &self)
         }
 
-        self.logger.log(format!("Thread {} shut down successfully", self.name).as_bytes()).expect("Failed to write to log");
+        self.logger.info(module_path!(), &format!("Thread {} shut down successfully", self.name));
     }
 }
 
@@ -94,6 +97,12 @@ impl Drop for ThreadHelper {
     }
 }
 
+impl Default for ThreadState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ThreadState {
     pub fn new() -> Self {
         ThreadState {
@@ -0,0 +1,280 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use crate::http;
+use crate::message_stream::MAX_FRAME_LEN;
+
+//RFC 6455 transport for browsers; every binary message carries frames exactly as they are sent over
+//plain TCP, prefix included, so the rest of the pipeline does not know the difference
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_REQUEST_LEN: usize = 8192;
+//a message may hold a whole chat frame plus its prefix and checksum
+const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN + 16;
+
+#[derive(Debug)]
+pub enum WsError {
+    Handshake { status: u16, msg: String }, //answered with `status` and closed
+    Protocol { msg: String }, //the peer broke the framing rules, the connection has to go
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for WsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            WsError::Handshake{status, msg} => {
+                write!(f, "WebSocket handshake failed ({}): {}", status, msg)
+            },
+            WsError::Protocol{msg} => {
+                write!(f, "WebSocket protocol error: {}", msg)
+            },
+            WsError::Io(error) => {
+                write!(f, "{}", error)
+            },
+        }
+    }
+}
+
+impl Error for WsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+impl From<std::io::Error> for WsError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation = 0,
+    Text = 1,
+    Binary = 2,
+    Close = 8,
+    Ping = 9,
+    Pong = 10,
+}
+
+impl Opcode {
+    pub fn from_u8(opcode: u8) -> Option<Opcode> {
+        match opcode {
+            code if code == Opcode::Continuation as u8 => Some(Opcode::Continuation),
+            code if code == Opcode::Text as u8 => Some(Opcode::Text),
+            code if code == Opcode::Binary as u8 => Some(Opcode::Binary),
+            code if code == Opcode::Close as u8 => Some(Opcode::Close),
+            code if code == Opcode::Ping as u8 => Some(Opcode::Ping),
+            code if code == Opcode::Pong as u8 => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    pub fn is_control(&self) -> bool {
+        (*self as u8) & 0x8 != 0
+    }
+}
+
+//close codes sent back to the peer
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_UNSUPPORTED: u16 = 1003;
+pub const CLOSE_PROTOCOL: u16 = 1002;
+
+//Sec-WebSocket-Accept for the key the client sent
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+//reads the upgrade request and answers it, blocking for at most `timeout`; the stream is left
+//blocking, nothing the client sends after the request is read; pages are only let in from the
+//server's own host or an origin listed in `origins`, "*" lets any page connect
+pub fn accept(stream: &mut TcpStream, origins: &[String], timeout: Duration) -> Result<(), WsError> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    //frames may follow the request right away, the reader stops at the blank line
+    let request = match http::read_request(stream, MAX_REQUEST_LEN, 0)? {
+        Ok(request) => request,
+        Err((status, text)) => return reject(stream, status, &text),
+    };
+    if request.method != "GET" {
+        return reject(stream, 405, "only GET can be upgraded");
+    }
+
+    let upgrade = request.header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let version = request.header("Sec-WebSocket-Version") == Some("13");
+    let origin = request.header("Origin");
+    let host = request.header("Host");
+    if !upgrade {
+        return reject(stream, 426, "expected a WebSocket upgrade");
+    }
+    if !version {
+        return reject(stream, 426, "only version 13 is supported");
+    }
+    let key = match request.header("Sec-WebSocket-Key") {
+        Some(key) => key,
+        None => return reject(stream, 400, "missing Sec-WebSocket-Key"),
+    };
+    //the key is 16 random bytes in base64
+    if base64::engine::general_purpose::STANDARD.decode(key).map_or(true, |decoded| decoded.len() != 16) {
+        return reject(stream, 400, "invalid Sec-WebSocket-Key");
+    }
+    //native clients send no origin, browsers always do
+    if let Some(origin) = origin {
+        if !origin_allowed(origin, host, origins) {
+            return reject(stream, 403, &format!("origin {} is not allowed", origin));
+        }
+    }
+
+    let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key));
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+//a page served from the host it connects to is never cross-origin
+fn origin_allowed(origin: &str, host: Option<&str>, origins: &[String]) -> bool {
+    if origins.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin)) {
+        return true;
+    }
+    let authority = origin.split_once("://").map_or(origin, |(_, authority)| authority);
+    host.is_some_and(|host| host.eq_ignore_ascii_case(authority))
+}
+
+fn reject(stream: &mut TcpStream, status: u16, msg: &str) -> Result<(), WsError> {
+    let headers = [("Sec-WebSocket-Version", "13"), ("Content-Type", "text/plain")];
+    let _ = http::respond(stream, status, &headers, &format!("{}\n", msg));
+    Err(WsError::Handshake { status, msg: msg.to_string() })
+}
+
+//a single unmasked frame the way servers send them
+pub fn frame(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode as u8);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        },
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub fn close_frame(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    frame(Opcode::Close, &payload)
+}
+
+#[derive(Debug)]
+pub struct WsFrame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>, //already unmasked
+}
+
+//turns the bytes a client sends into frames, whatever way they were split by the network
+#[derive(Default)]
+pub struct WsDecoder {
+    buffer: Vec<u8>,
+}
+
+impl WsDecoder {
+    const READ_SIZE: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        WsDecoder::default()
+    }
+
+    //bytes received and not decoded yet
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    //one read from `reader`, returns what it returned
+    pub fn read_from<R: Read>(&mut self, reader: &mut R) -> std::io::Result<usize> {
+        let start = self.buffer.len();
+        self.buffer.resize(start + Self::READ_SIZE, 0);
+        let res = reader.read(&mut self.buffer[start..]);
+        let read = *res.as_ref().unwrap_or(&0);
+        self.buffer.truncate(start + read);
+        res
+    }
+
+    //clients have to mask everything they send, servers never do
+    pub fn next_frame(&mut self) -> Result<Option<WsFrame>, WsError> {
+        if self.buffer.len() < 2 {
+            return Ok(None);
+        }
+        let fin = self.buffer[0] & 0x80 != 0;
+        if self.buffer[0] & 0x70 != 0 {
+            return Err(WsError::Protocol { msg: "reserved bits set without an extension".to_string() });
+        }
+        let opcode = match Opcode::from_u8(self.buffer[0] & 0x0f) {
+            Some(opcode) => opcode,
+            None => return Err(WsError::Protocol { msg: format!("unknown opcode {}", self.buffer[0] & 0x0f) }),
+        };
+        if self.buffer[1] & 0x80 == 0 {
+            return Err(WsError::Protocol { msg: "unmasked frame from a client".to_string() });
+        }
+
+        let (len, mut pos) = match self.buffer[1] & 0x7f {
+            126 => {
+                if self.buffer.len() < 4 {
+                    return Ok(None);
+                }
+                (u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as u64, 4)
+            },
+            127 => {
+                if self.buffer.len() < 10 {
+                    return Ok(None);
+                }
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&self.buffer[2..10]);
+                (u64::from_be_bytes(len), 10)
+            },
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (len > 125 || !fin) {
+            return Err(WsError::Protocol { msg: "control frames must be short and unfragmented".to_string() });
+        }
+        if len > MAX_PAYLOAD_LEN as u64 {
+            return Err(WsError::Protocol { msg: format!("frame of {} bytes, at most {} allowed", len, MAX_PAYLOAD_LEN) });
+        }
+        let len = len as usize;
+        if self.buffer.len() < pos + 4 + len {
+            return Ok(None);
+        }
+
+        let mut mask = [0u8; 4];
+        mask.copy_from_slice(&self.buffer[pos..pos + 4]);
+        pos += 4;
+        let mut payload: Vec<u8> = self.buffer.drain(..pos + len).skip(pos).collect();
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok(Some(WsFrame { fin, opcode, payload }))
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::Sha256;
+use utils::{io::write_atomic, secret};
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const ROUNDS: u32 = 10_000;
+
+//reserved nicks, only a client presenting the password may use one
+//nicks are identified by names::key, the file has one "key salt hash" line per nick, salt and hash hex encoded
+struct Reservation {
+    salt: [u8; SALT_LEN],
+    hash: [u8; HASH_LEN],
+}
+
+pub struct Accounts {
+    file: Option<PathBuf>, //kept in memory only when unset
+    nicks: HashMap<String, Reservation>,
+}
+
+fn hash(password: &str, salt: &[u8; SALT_LEN]) -> [u8; HASH_LEN] {
+    let mut hash = [0u8; HASH_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, ROUNDS, &mut hash);
+    hash
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+impl Accounts {
+    pub fn load(file: Option<&Path>) -> io::Result<Self> {
+        let mut accounts = Accounts {
+            file: file.map(|file| file.to_path_buf()),
+            nicks: HashMap::new(),
+        };
+        let file = match file {
+            Some(file) => file,
+            None => return Ok(accounts),
+        };
+
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(accounts),
+            Err(e) => return Err(e),
+        };
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let reservation = match fields.as_slice() {
+                [_, salt, hash] => from_hex(salt).zip(from_hex(hash)).map(|(salt, hash)| Reservation { salt, hash }),
+                _ => None,
+            };
+            match reservation {
+                Some(reservation) => accounts.nicks.insert(fields[0].to_string(), reservation),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: expected \"name salt hash\"", file.display(), i + 1))),
+            };
+        }
+        Ok(accounts)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let mut names: Vec<&String> = self.nicks.keys().collect();
+        names.sort();
+        let mut content = String::new();
+        for name in names {
+            let reservation = &self.nicks[name];
+            content.push_str(&format!("{} {} {}\n", name, to_hex(&reservation.salt), to_hex(&reservation.hash)));
+        }
+        write_atomic(file, content.as_bytes())
+    }
+
+    pub fn is_reserved(&self, name: &str) -> bool {
+        self.nicks.contains_key(name)
+    }
+
+    //true if `name` is free to use with `password`, either because nobody reserved it or the password matches
+    pub fn allows(&self, name: &str, password: Option<&str>) -> bool {
+        let reservation = match self.nicks.get(name) {
+            Some(reservation) => reservation,
+            None => return true,
+        };
+        let password = match password {
+            Some(password) => password,
+            None => return false,
+        };
+        let hash = hash(password, &reservation.salt);
+        secret::constant_time_eq(&hash, &reservation.hash)
+    }
+
+    //reserves or changes the password of `name`
+    pub fn reserve(&mut self, name: &str, password: &str) -> io::Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|e| io::Error::other(e.to_string()))?;
+        let reservation = Reservation {
+            salt,
+            hash: hash(password, &salt),
+        };
+
+        let old = self.nicks.insert(name.to_string(), reservation);
+        if let Err(e) = self.save() {
+            match old {
+                Some(old) => self.nicks.insert(name.to_string(), old),
+                None => self.nicks.remove(name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    //returns false if `name` was not reserved
+    pub fn release(&mut self, name: &str) -> io::Result<bool> {
+        let old = match self.nicks.remove(name) {
+            Some(old) => old,
+            None => return Ok(false),
+        };
+        if let Err(e) = self.save() {
+            self.nicks.insert(name.to_string(), old);
+            return Err(e);
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use netutils::logger::{Level, Logger};
+use netutils::message_stream::{self, MsgError, MsgInfo};
+use netutils::messages;
+use server_lib::service::{Peer, Service};
+use utils::secret;
+use super::console::{self, Request};
+use super::server_impl::ServerImpl;
+
+const MAX_CONNECTIONS: usize = 8;
+const MAX_FRAME_LEN: usize = 64 * 1024;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+//chat-admin connections, commands are forwarded to the main thread just like console input
+pub fn service(sender: mpsc::Sender<Request>, server_impl: Arc<ServerImpl>, logger: Arc<Logger>) -> Service {
+    Service {
+        name: String::from("Admin"),
+        max: MAX_CONNECTIONS,
+        handle: Box::new(move |stream, peer| {
+            match handle(stream, peer, &sender, &server_impl, &logger) {
+                Ok(_) => logger.log(Level::Info, module_path!(), "Admin disconnected", &[("addr", &peer.addr)]),
+                Err(e) => logger.log(Level::Info, module_path!(), "Admin connection closed", &[("addr", &peer.addr), ("error", &e)]),
+            }
+        }),
+    }
+}
+
+fn handle(mut stream: TcpStream, peer: &Peer, sender: &mpsc::Sender<Request>, server_impl: &ServerImpl, logger: &Logger) -> Result<(), MsgError> {
+    let addr = peer.addr;
+    stream.set_read_timeout(Some(IDLE_TIMEOUT)).map_err(MsgError::Read)?;
+    stream.set_write_timeout(Some(IDLE_TIMEOUT)).map_err(MsgError::Write)?;
+
+    let msginfo = message_stream::read_msginfo(&mut stream, MAX_FRAME_LEN)?;
+    if !authenticate(&msginfo, &server_impl.admin_token()) {
+        logger.log(Level::Warn, module_path!(), "Admin authentication failed", &[("addr", &addr)]);
+        peer.auth_failed();
+        return respond(&mut stream, false, "Authentication failed");
+    }
+    logger.log(Level::Info, module_path!(), "Admin authenticated", &[("addr", &addr)]);
+    respond(&mut stream, true, "")?;
+
+    loop {
+        let msginfo = match message_stream::read_msginfo(&mut stream, MAX_FRAME_LEN) {
+            Ok(msginfo) => msginfo,
+            Err(MsgError::Read(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if msginfo.code != messages::admin::Message::OnCommand as u32 {
+            return respond(&mut stream, false, "Expected a command");
+        }
+        let line = msginfo.decode_data::<messages::admin::MsgCommand>()?.line;
+        logger.log(Level::Info, module_path!(), "Admin command", &[("addr", &addr), ("command", &line)]);
+
+        let command = match console::parse(line) {
+            Ok(command) => command,
+            Err(e) => {
+                respond(&mut stream, false, &e)?;
+                continue;
+            },
+        };
+        let (reply_sender, reply) = mpsc::channel();
+        if sender.send((command, reply_sender)).is_err() {
+            return respond(&mut stream, false, "Server is shutting down");
+        }
+        match reply.recv() {
+            Ok(Ok(out)) => respond(&mut stream, true, &out)?,
+            Ok(Err(out)) => respond(&mut stream, false, &out)?,
+            Err(_) => return respond(&mut stream, false, "Server is shutting down"),
+        }
+    }
+}
+
+fn authenticate(msginfo: &MsgInfo, token: &str) -> bool {
+    if msginfo.code != messages::admin::Message::OnAuth as u32 || token.is_empty() {
+        return false;
+    }
+    match msginfo.decode_data::<messages::admin::MsgAuth>() {
+        Ok(msg) => secret::constant_time_eq(msg.token.as_bytes(), token.as_bytes()),
+        Err(_) => false,
+    }
+}
+
+fn respond(stream: &mut TcpStream, ok: bool, output: &str) -> Result<(), MsgError> {
+    let msg = messages::admin::MsgResult {
+        ok,
+        output,
+    };
+    let msg_encoded = message_stream::serialize_data(messages::admin::Message::OnResult as u32, &msg)?;
+    stream.write_all(msg_encoded.as_slice()).map_err(MsgError::Write)
+}
@@ -1,12 +1,34 @@
+use super::names;
+
+pub const LOBBY: &str = "lobby";
+
 #[derive(Debug, Clone)]
 pub struct ClientInfo {
-    pub name: String
+    pub name: String,
+    pub key: String, //see names::key
+    pub room: String,
+    pub away: Option<String>,
 }
 
 impl ClientInfo {
     pub fn new(name: String) -> Self {
         ClientInfo {
-            name
+            key: names::key(&name),
+            name,
+            room: String::from(LOBBY),
+            away: None,
         }
     }
-}
\ No newline at end of file
+}
+
+//"#Rust" and "rust" are the same room
+pub fn normalize_room(room: &str) -> Result<String, String> {
+    let room = room.trim().trim_start_matches('#').to_lowercase();
+    if room.is_empty() || room.len() > 32 {
+        return Err(String::from("Room names must be 1 to 32 characters long"));
+    }
+    if !room.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(String::from("Room names may only contain letters, digits, '-' and '_'"));
+    }
+    Ok(room)
+}
@@ -0,0 +1,623 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use netutils::logger::{self, Level};
+use netutils::message_stream::{Codec, Compression};
+use netutils::rotating_file::RotateEvery;
+use utils::config::{self, ConfigError, Settings};
+
+use super::names::{self, Charset};
+use super::webhooks;
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    pub log: LogConfig,
+    pub limits: LimitsConfig,
+    pub tls: TlsConfig,
+    pub history: HistoryConfig,
+    pub metrics: MetricsConfig,
+    pub shutdown: ShutdownConfig,
+    pub admin: AdminConfig,
+    pub accounts: AccountsConfig,
+    pub names: NamesConfig,
+    pub files: FilesConfig,
+    pub compression: CompressionConfig,
+    pub checksum: ChecksumConfig,
+    pub codec: CodecConfig,
+    pub websocket: WebSocketConfig,
+    pub irc: IrcConfig,
+    pub link: LinkConfig,
+    pub webhooks: WebhooksConfig,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub file: Option<PathBuf>,
+    pub level: Level,
+    pub targets: String, //per-target levels, "server_lib=debug,netutils=warn"
+    pub format: logger::Format,
+    pub stderr: bool,
+    pub max_size: u64, //bytes, 0 disables size based rotation
+    pub rotate: RotateEvery,
+    pub keep: usize,
+    pub compress: bool,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_clients: usize,
+    pub max_message_len: usize,
+    pub max_queued: usize, //bytes waiting to be sent to one client before it is dropped as too slow
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub size: usize,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub bind: Option<SocketAddr>, //disabled when unset
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    pub grace: u64, //seconds clients get to disconnect on their own
+    pub retry_after: u64, //seconds, hint sent to clients
+    pub reason: String,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocketConfig {
+    pub bind: Option<SocketAddr>, //disabled when unset
+    pub origins: Vec<String>, //other pages allowed to connect, e.g. "https://chat.example.org", "*" allows any
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IrcConfig {
+    pub bind: Option<SocketAddr>, //disabled when unset
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConfig {
+    pub bind: Option<SocketAddr>, //other servers connect here, disabled when unset
+    pub name: String, //unique on the network, remote users see each other as user@name
+    pub password: String, //shared by every server on the network
+    pub peers: Vec<SocketAddr>, //dialed and redialed whenever the link is down
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    pub bind: Option<SocketAddr>, //integrations post messages here, disabled when unset
+    pub token: String, //bearer token every post has to carry
+    pub user: String, //name posts appear under when they do not give one
+    pub urls: Vec<String>, //http:// endpoints chat events are posted to
+    pub events: Vec<String>, //events posted to urls, empty posts all of WEBHOOK_EVENTS
+    pub retries: u32, //further attempts after a failed post
+    pub timeout: u64, //seconds per attempt
+}
+
+//what outbound webhooks can be told about
+pub const WEBHOOK_EVENTS: &[&str] = &["connect", "disconnect", "message", "action", "join", "part", "rename"];
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub bind: Option<SocketAddr>, //disabled when unset
+    pub token: String, //shared secret chat-admin has to present
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    pub file: Option<PathBuf>, //reserved nicks, kept in memory only when unset
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamesConfig {
+    pub min_len: usize, //characters, not bytes
+    pub max_len: usize,
+    pub charset: Charset,
+    pub extra_chars: String, //allowed on top of the charset's letters and digits
+    pub reserved: Vec<String>, //compared the same way names are compared with each other
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilesConfig {
+    pub enabled: bool,
+    pub max_size: u64, //bytes
+    pub max_chunk: usize, //bytes of file data per frame
+    pub max_transfers: usize, //offered or running at once, per sender
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub algorithms: Vec<Compression>, //offered to clients that say hello, empty disables compression
+    pub threshold: usize, //bytes, smaller frames are sent as they are
+    pub level: i32, //zstd only
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChecksumConfig {
+    pub enabled: bool, //grant CRC32C checksums to clients that ask for them
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodecConfig {
+    pub allowed: Vec<Codec>, //payload encodings clients may pick besides bincode, which is always allowed
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: SocketAddr::from(([127, 0, 0, 1], 7878)),
+            log: LogConfig::default(),
+            limits: LimitsConfig::default(),
+            tls: TlsConfig::default(),
+            history: HistoryConfig::default(),
+            metrics: MetricsConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            admin: AdminConfig::default(),
+            accounts: AccountsConfig::default(),
+            names: NamesConfig::default(),
+            files: FilesConfig::default(),
+            compression: CompressionConfig::default(),
+            checksum: ChecksumConfig::default(),
+            codec: CodecConfig::default(),
+            websocket: WebSocketConfig::default(),
+            irc: IrcConfig::default(),
+            link: LinkConfig::default(),
+            webhooks: WebhooksConfig::default(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            file: Some(PathBuf::from("server_log.txt")),
+            level: Level::Info,
+            targets: String::new(),
+            format: logger::Format::Text,
+            stderr: false,
+            max_size: 10 * 1024 * 1024,
+            rotate: RotateEvery::Daily,
+            keep: 7,
+            compress: false,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_clients: 1024,
+            max_message_len: 4096,
+            max_queued: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: true,
+            size: 50,
+        }
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            grace: 5,
+            retry_after: 30,
+            reason: String::from("Server is shutting down"),
+        }
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig {
+            bind: None,
+            token: String::new(),
+            user: String::from("webhook"),
+            urls: Vec::new(),
+            events: Vec::new(),
+            retries: 3,
+            timeout: 5,
+        }
+    }
+}
+
+impl Default for NamesConfig {
+    fn default() -> Self {
+        NamesConfig {
+            min_len: 1,
+            max_len: 15,
+            charset: Charset::Unicode,
+            extra_chars: String::from("_-"),
+            reserved: vec![String::from("admin"), String::from("server"), String::from("system")],
+        }
+    }
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            enabled: true,
+            max_size: 100 * 1024 * 1024,
+            max_chunk: 64 * 1024,
+            max_transfers: 4,
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            algorithms: vec![Compression::Lz4, Compression::Zstd],
+            threshold: 1024,
+            level: 3,
+        }
+    }
+}
+
+impl Default for CodecConfig {
+    fn default() -> Self {
+        CodecConfig {
+            allowed: vec![Codec::Json, Codec::MessagePack],
+        }
+    }
+}
+
+impl Default for ChecksumConfig {
+    fn default() -> Self {
+        ChecksumConfig {
+            enabled: true,
+        }
+    }
+}
+
+//port 0 lets the system pick a free port, so those never clash
+fn clashes(addr: Option<SocketAddr>, other: Option<SocketAddr>) -> bool {
+    match addr {
+        Some(addr) if addr.port() != 0 => other == Some(addr),
+        _ => false,
+    }
+}
+
+impl Settings for ServerConfig {
+    const ENV_PREFIX: &'static str = "CHAT_SERVER";
+    const DEFAULT_FILE: &'static str = "server.toml";
+    const KEYS: &'static [(&'static str, &'static str, &'static str)] = &[
+        ("bind", "ADDR", "Address the chat listener binds to (default 127.0.0.1:7878)"),
+        ("log.file", "PATH", "Log file, empty to disable logging (default server_log.txt)"),
+        ("log.level", "LEVEL", "Log level: error, warn, info, debug or trace (default info)"),
+        ("log.targets", "DIRECTIVES", "Per-target log levels, e.g. server_lib=debug,netutils=warn"),
+        ("log.format", "FORMAT", "Log line format: text or json (default text)"),
+        ("log.stderr", "BOOL", "Also write log records to stderr (default false)"),
+        ("log.max_size", "BYTES", "Rotate the log file once it exceeds this size, 0 to disable (default 10485760)"),
+        ("log.rotate", "WHEN", "Time based rotation: never, hourly or daily (default daily)"),
+        ("log.keep", "N", "Number of rotated log files to keep (default 7)"),
+        ("log.compress", "BOOL", "Gzip rotated log files (default false)"),
+        ("limits.max_clients", "N", "Maximum number of connected clients (default 1024)"),
+        ("limits.max_message_len", "N", "Maximum length of a chat message in bytes (default 4096)"),
+        ("limits.max_queued", "BYTES", "Output queued for a client before it is dropped as too slow (default 4194304)"),
+        ("tls.cert_file", "PATH", "TLS certificate chain (PEM)"),
+        ("tls.key_file", "PATH", "TLS private key (PEM)"),
+        ("history.enabled", "BOOL", "Replay recent messages to newly registered users (default true)"),
+        ("history.size", "N", "Number of messages kept for replay (default 50)"),
+        ("metrics.bind", "ADDR", "Serve Prometheus metrics over HTTP on this address, empty to disable"),
+        ("shutdown.grace", "SECS", "Time clients get to disconnect after the shutdown notice (default 5)"),
+        ("shutdown.retry_after", "SECS", "Reconnect delay suggested to clients on shutdown (default 30)"),
+        ("shutdown.reason", "TEXT", "Reason sent to clients on shutdown"),
+        ("websocket.bind", "ADDR", "Accept browser clients over WebSocket on this address, empty to disable"),
+        ("websocket.origins", "LIST", "Comma separated origins of other pages allowed to connect over WebSocket, * allows any (default: same host only)"),
+        ("irc.bind", "ADDR", "Accept IRC clients on this address, empty to disable"),
+        ("link.bind", "ADDR", "Accept links from other servers on this address, empty to disable"),
+        ("link.name", "NAME", "Name of this server on the network, required for linking"),
+        ("link.password", "PASSWORD", "Shared secret every linked server presents, prefer the environment variable"),
+        ("link.peers", "LIST", "Comma separated addresses of servers to link to, redialed while the link is down"),
+        ("webhooks.bind", "ADDR", "Accept JSON posts from integrations over HTTP on this address, empty to disable"),
+        ("webhooks.token", "TOKEN", "Bearer token integrations have to send, prefer the environment variable"),
+        ("webhooks.user", "NAME", "Name posted messages appear under unless the post names one (default webhook)"),
+        ("webhooks.urls", "LIST", "Comma separated http:// URLs chat events are posted to"),
+        ("webhooks.events", "LIST", "Comma separated events posted to webhooks.urls: connect, disconnect, message, action, join, part, rename (default all)"),
+        ("webhooks.retries", "N", "Further attempts after a failed post, with doubling delays from 1 second (default 3)"),
+        ("webhooks.timeout", "SECS", "Timeout of one outbound post in seconds (default 5)"),
+        ("admin.bind", "ADDR", "Accept chat-admin connections on this address, empty to disable"),
+        ("admin.token", "TOKEN", "Shared secret required from chat-admin, prefer the environment variable"),
+        ("accounts.file", "PATH", "File storing reserved nicks, empty to keep them in memory only"),
+        ("names.min_len", "N", "Minimum name length in characters (default 1)"),
+        ("names.max_len", "N", "Maximum name length in characters (default 15)"),
+        ("names.charset", "CHARSET", "Letters and digits allowed in names: ascii or unicode (default unicode)"),
+        ("names.extra_chars", "CHARS", "Other characters allowed in names (default _-)"),
+        ("names.reserved", "NAMES", "Comma separated names nobody may use (default admin,server,system)"),
+        ("files.enabled", "BOOL", "Let users send each other files through the server (default true)"),
+        ("files.max_size", "BYTES", "Largest file that may be offered (default 104857600)"),
+        ("files.max_chunk", "BYTES", "Largest piece of a file relayed in one message (default 65536)"),
+        ("files.max_transfers", "N", "Transfers a user may have pending or running at once (default 4)"),
+        ("compression.algorithms", "LIST", "Comma separated compression clients may pick: lz4, zstd, or none to disable (default lz4,zstd)"),
+        ("compression.threshold", "BYTES", "Frames smaller than this are never compressed (default 1024)"),
+        ("compression.level", "N", "Zstd level, 1 is fastest and 22 smallest (default 3)"),
+        ("checksum.enabled", "BOOL", "Checksum every frame of clients that ask for it (default true)"),
+        ("codec.allowed", "LIST", "Comma separated payload encodings clients may pick besides bincode: json, msgpack (default json,msgpack)"),
+    ];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "bind" => self.bind = config::parse_value(key, value)?,
+            "log.file" => self.log.file = config::parse_opt_path(value),
+            "log.level" => self.log.level = config::parse_value(key, value)?,
+            "log.targets" => self.log.targets = value.to_string(),
+            "log.format" => self.log.format = config::parse_value(key, value)?,
+            "log.stderr" => self.log.stderr = config::parse_bool(key, value)?,
+            "log.max_size" => self.log.max_size = config::parse_value(key, value)?,
+            "log.rotate" => self.log.rotate = config::parse_value(key, value)?,
+            "log.keep" => self.log.keep = config::parse_value(key, value)?,
+            "log.compress" => self.log.compress = config::parse_bool(key, value)?,
+            "limits.max_clients" => self.limits.max_clients = config::parse_value(key, value)?,
+            "limits.max_message_len" => self.limits.max_message_len = config::parse_value(key, value)?,
+            "limits.max_queued" => self.limits.max_queued = config::parse_value(key, value)?,
+            "tls.cert_file" => self.tls.cert_file = config::parse_opt_path(value),
+            "tls.key_file" => self.tls.key_file = config::parse_opt_path(value),
+            "history.enabled" => self.history.enabled = config::parse_bool(key, value)?,
+            "history.size" => self.history.size = config::parse_value(key, value)?,
+            "shutdown.grace" => self.shutdown.grace = config::parse_value(key, value)?,
+            "shutdown.retry_after" => self.shutdown.retry_after = config::parse_value(key, value)?,
+            "shutdown.reason" => self.shutdown.reason = value.to_string(),
+            "metrics.bind" => self.metrics.bind = match value.trim() {
+                "" => None,
+                value => Some(config::parse_value(key, value)?),
+            },
+            "websocket.bind" => self.websocket.bind = match value.trim() {
+                "" => None,
+                value => Some(config::parse_value(key, value)?),
+            },
+            "websocket.origins" => self.websocket.origins = value.split(',').map(|origin| origin.trim().trim_end_matches('/').to_string()).filter(|origin| !origin.is_empty()).collect(),
+            "irc.bind" => self.irc.bind = match value.trim() {
+                "" => None,
+                value => Some(config::parse_value(key, value)?),
+            },
+            "link.bind" => self.link.bind = match value.trim() {
+                "" => None,
+                value => Some(config::parse_value(key, value)?),
+            },
+            "link.name" => self.link.name = value.trim().to_string(),
+            "link.password" => self.link.password = value.to_string(),
+            "link.peers" => {
+                let mut peers = Vec::new();
+                for peer in value.split(',').map(|peer| peer.trim()).filter(|peer| !peer.is_empty()) {
+                    peers.push(config::parse_value(key, peer)?);
+                }
+                self.link.peers = peers;
+            },
+            "webhooks.bind" => self.webhooks.bind = match value.trim() {
+                "" => None,
+                value => Some(config::parse_value(key, value)?),
+            },
+            "webhooks.token" => self.webhooks.token = value.to_string(),
+            "webhooks.user" => self.webhooks.user = value.trim().to_string(),
+            "webhooks.urls" => self.webhooks.urls = value.split(',').map(|url| url.trim().to_string()).filter(|url| !url.is_empty()).collect(),
+            "webhooks.events" => self.webhooks.events = value.split(',').map(|event| event.trim().to_lowercase()).filter(|event| !event.is_empty()).collect(),
+            "webhooks.retries" => self.webhooks.retries = config::parse_value(key, value)?,
+            "webhooks.timeout" => self.webhooks.timeout = config::parse_value(key, value)?,
+            "admin.bind" => self.admin.bind = match value.trim() {
+                "" => None,
+                value => Some(config::parse_value(key, value)?),
+            },
+            "admin.token" => self.admin.token = value.to_string(),
+            "accounts.file" => self.accounts.file = config::parse_opt_path(value),
+            "names.min_len" => self.names.min_len = config::parse_value(key, value)?,
+            "names.max_len" => self.names.max_len = config::parse_value(key, value)?,
+            "names.charset" => self.names.charset = config::parse_value(key, value)?,
+            "names.extra_chars" => self.names.extra_chars = value.to_string(),
+            "names.reserved" => self.names.reserved = value.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect(),
+            "files.enabled" => self.files.enabled = config::parse_bool(key, value)?,
+            "files.max_size" => self.files.max_size = config::parse_value(key, value)?,
+            "files.max_chunk" => self.files.max_chunk = config::parse_value(key, value)?,
+            "files.max_transfers" => self.files.max_transfers = config::parse_value(key, value)?,
+            "compression.algorithms" => {
+                let mut algorithms = Vec::new();
+                for name in value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+                    match config::parse_value(key, name)? {
+                        Compression::None => {},
+                        algorithm => algorithms.push(algorithm),
+                    }
+                }
+                self.compression.algorithms = algorithms;
+            },
+            "compression.threshold" => self.compression.threshold = config::parse_value(key, value)?,
+            "compression.level" => self.compression.level = config::parse_value(key, value)?,
+            "checksum.enabled" => self.checksum.enabled = config::parse_bool(key, value)?,
+            "codec.allowed" => {
+                let mut allowed = Vec::new();
+                for name in value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+                    match config::parse_value(key, name)? {
+                        Codec::Bincode => {},
+                        codec => allowed.push(codec),
+                    }
+                }
+                self.codec.allowed = allowed;
+            },
+            _ => return Err(ConfigError::UnknownArg(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Err(e) = logger::parse_filters(&self.log.targets) {
+            return Err(ConfigError::InvalidValue { key: "log.targets".to_string(), value: self.log.targets.clone(), reason: e.to_string() });
+        }
+
+        if self.limits.max_clients == 0 {
+            return Err(ConfigError::Invalid { key: "limits.max_clients".to_string(), reason: "must be at least 1".to_string() });
+        }
+        if self.limits.max_message_len == 0 {
+            return Err(ConfigError::Invalid { key: "limits.max_message_len".to_string(), reason: "must be at least 1".to_string() });
+        }
+        if self.limits.max_queued == 0 {
+            return Err(ConfigError::Invalid { key: "limits.max_queued".to_string(), reason: "must be at least 1".to_string() });
+        }
+        let binds = self.binds();
+        for (i, (key, addr)) in binds.iter().enumerate() {
+            if let Some((other, _)) = binds[..i].iter().find(|(_, other)| clashes(*addr, *other)) {
+                return Err(ConfigError::Invalid { key: key.to_string(), reason: format!("must differ from {}", other) });
+            }
+        }
+        if self.admin.bind.is_some() && self.admin.token.len() < 16 {
+            return Err(ConfigError::Invalid { key: "admin.token".to_string(), reason: "must be at least 16 characters when admin.bind is set".to_string() });
+        }
+        if self.link.bind.is_some() || !self.link.peers.is_empty() {
+            if self.link.name.is_empty() || !self.link.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-') {
+                return Err(ConfigError::InvalidValue { key: "link.name".to_string(), value: self.link.name.clone(), reason: "required for linking, letters, digits, '.' and '-' only".to_string() });
+            }
+            if self.link.password.len() < 16 {
+                return Err(ConfigError::Invalid { key: "link.password".to_string(), reason: "must be at least 16 characters when linking".to_string() });
+            }
+        }
+        if self.webhooks.bind.is_some() {
+            if self.webhooks.token.len() < 16 {
+                return Err(ConfigError::Invalid { key: "webhooks.token".to_string(), reason: "must be at least 16 characters when webhooks.bind is set".to_string() });
+            }
+            if let Err(e) = names::check(&self.names, &names::normalize(&self.webhooks.user)) {
+                return Err(ConfigError::InvalidValue { key: "webhooks.user".to_string(), value: self.webhooks.user.clone(), reason: e.to_string() });
+            }
+        }
+        for url in self.webhooks.urls.iter() {
+            if let Err(e) = webhooks::Url::parse(url) {
+                return Err(ConfigError::InvalidValue { key: "webhooks.urls".to_string(), value: url.clone(), reason: e });
+            }
+        }
+        if let Some(event) = self.webhooks.events.iter().find(|event| !WEBHOOK_EVENTS.contains(&event.as_str())) {
+            return Err(ConfigError::InvalidValue { key: "webhooks.events".to_string(), value: event.clone(), reason: format!("expected one of {}", WEBHOOK_EVENTS.join(", ")) });
+        }
+        if self.webhooks.timeout == 0 {
+            return Err(ConfigError::Invalid { key: "webhooks.timeout".to_string(), reason: "must be at least 1 second".to_string() });
+        }
+        if self.shutdown.retry_after > u32::MAX as u64 {
+            return Err(ConfigError::Invalid { key: "shutdown.retry_after".to_string(), reason: format!("must be at most {}", u32::MAX) });
+        }
+        if self.names.min_len == 0 || self.names.min_len > self.names.max_len {
+            return Err(ConfigError::Invalid { key: "names.min_len".to_string(), reason: "must be at least 1 and at most names.max_len".to_string() });
+        }
+        if self.names.extra_chars.chars().any(|c| c.is_control()) {
+            return Err(ConfigError::InvalidValue { key: "names.extra_chars".to_string(), value: self.names.extra_chars.clone(), reason: "control characters are never allowed".to_string() });
+        }
+        if self.files.max_chunk == 0 {
+            return Err(ConfigError::Invalid { key: "files.max_chunk".to_string(), reason: "must be at least 1".to_string() });
+        }
+        if self.files.enabled && self.files.max_transfers == 0 {
+            return Err(ConfigError::Invalid { key: "files.max_transfers".to_string(), reason: "must be at least 1 when file transfers are enabled".to_string() });
+        }
+        if self.compression.threshold > u32::MAX as usize {
+            return Err(ConfigError::Invalid { key: "compression.threshold".to_string(), reason: format!("must be at most {}", u32::MAX) });
+        }
+        if !(1..=22).contains(&self.compression.level) {
+            return Err(ConfigError::Invalid { key: "compression.level".to_string(), reason: "must be between 1 and 22".to_string() });
+        }
+        if self.history.enabled && self.history.size == 0 {
+            return Err(ConfigError::Invalid { key: "history.size".to_string(), reason: "must be at least 1 when history is enabled".to_string() });
+        }
+
+        match (&self.tls.cert_file, &self.tls.key_file) {
+            (None, None) => {},
+            (Some(_), None) => return Err(ConfigError::Invalid { key: "tls.key_file".to_string(), reason: "required when tls.cert_file is set".to_string() }),
+            (None, Some(_)) => return Err(ConfigError::Invalid { key: "tls.cert_file".to_string(), reason: "required when tls.key_file is set".to_string() }),
+            (Some(_), Some(_)) => {
+                config::check_file("tls.cert_file", &self.tls.cert_file)?;
+                config::check_file("tls.key_file", &self.tls.key_file)?;
+                //refuse to silently fall back to plaintext
+                return Err(ConfigError::Invalid { key: "tls".to_string(), reason: "TLS is not supported by this build yet".to_string() });
+            },
+        }
+        Ok(())
+    }
+}
+
+impl ServerConfig {
+    //every listener with the key that sets it, no two may share an address
+    fn binds(&self) -> Vec<(&'static str, Option<SocketAddr>)> {
+        vec![
+            ("bind", Some(self.bind)),
+            ("metrics.bind", self.metrics.bind),
+            ("admin.bind", self.admin.bind),
+            ("webhooks.bind", self.webhooks.bind),
+            ("websocket.bind", self.websocket.bind),
+            ("irc.bind", self.irc.bind),
+            ("link.bind", self.link.bind),
+        ]
+    }
+
+    //settings that only take effect on restart and differ between self and `other`
+    pub fn restart_required(&self, other: &ServerConfig) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+        if self.bind != other.bind {
+            keys.push("bind");
+        }
+        if self.metrics.bind != other.metrics.bind {
+            keys.push("metrics.bind");
+        }
+        if self.admin.bind != other.admin.bind {
+            keys.push("admin.bind");
+        }
+        if self.websocket.bind != other.websocket.bind || self.websocket.origins != other.websocket.origins {
+            keys.push("websocket");
+        }
+        if self.irc.bind != other.irc.bind {
+            keys.push("irc.bind");
+        }
+        if self.link.bind != other.link.bind {
+            keys.push("link.bind");
+        }
+        if self.link.name != other.link.name {
+            keys.push("link.name");
+        }
+        if self.webhooks.bind != other.webhooks.bind {
+            keys.push("webhooks.bind");
+        }
+        if self.webhooks.urls != other.webhooks.urls || self.webhooks.events != other.webhooks.events || self.webhooks.retries != other.webhooks.retries || self.webhooks.timeout != other.webhooks.timeout {
+            keys.push("webhooks");
+        }
+        if self.accounts.file != other.accounts.file {
+            keys.push("accounts.file");
+        }
+        if self.tls != other.tls {
+            keys.push("tls");
+        }
+        //level and targets are applied on the fly, everything else needs a new log file
+        let mut log = other.log.clone();
+        log.level = self.log.level;
+        log.targets = self.log.targets.clone();
+        if self.log != log {
+            keys.push("log");
+        }
+        keys
+    }
+}
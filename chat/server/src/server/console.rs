@@ -0,0 +1,370 @@
+use std::sync::{atomic::Ordering, mpsc, Arc};
+use std::time::Duration;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use netutils::logger::{self, Level, Logger};
+use server_lib::ServerState;
+use super::config::ServerConfig;
+use super::server_impl::ServerImpl;
+
+pub enum Command {
+    Help(Option<String>),
+    List,
+    Kick { name: String, reason: String },
+    Ban { target: String, reason: String },
+    Unban(String),
+    Bans,
+    Broadcast(String),
+    Stats,
+    Rooms,
+    Links,
+    LogLevel { level: Level, target: Option<String> },
+    Reload,
+    Shutdown { grace: Option<u64> },
+}
+
+//commands are executed on the main thread, the result is sent back to whoever issued them
+pub type Reply = Result<String, String>;
+pub type Request = (Command, mpsc::Sender<Reply>);
+
+//(name, usage, help)
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("help", "help [command]", "Show the available commands"),
+    ("list", "list", "List connected clients with their address, name and idle time"),
+    ("kick", "kick <name> [reason]", "Disconnect a user, the reason is shown to them"),
+    ("ban", "ban <name|ip> [reason]", "Kick and refuse a user name and its address, or an address"),
+    ("unban", "unban <name|ip>", "Lift a ban"),
+    ("bans", "bans", "List banned names and addresses"),
+    ("broadcast", "broadcast <text>", "Send a server notice to every client"),
+    ("stats", "stats", "Show server counters"),
+    ("rooms", "rooms", "List rooms and their member count"),
+    ("links", "links", "List the other servers on the network and the link each one is reached through"),
+    ("loglevel", "loglevel <level> [target]", "Change the log level, globally or for one target"),
+    ("reload", "reload", "Re-read the config file and environment, most settings apply immediately"),
+    ("shutdown", "shutdown [grace]", "Notify clients and stop, optionally overriding the grace period in seconds"),
+    ("q", "q", "Same as shutdown"),
+];
+
+const LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (name, rest) = match line.split_once(char::is_whitespace) {
+        Some((name, rest)) => (name, rest.trim()),
+        None => (line, ""),
+    };
+
+    match name.to_lowercase().as_str() {
+        "help" | "?" => Ok(Command::Help(if rest.is_empty() { None } else { Some(rest.to_string()) })),
+        "list" => Ok(Command::List),
+        "kick" => {
+            let (name, reason) = match rest.split_once(char::is_whitespace) {
+                Some((name, reason)) => (name, reason.trim()),
+                None => (rest, ""),
+            };
+            if name.is_empty() {
+                return Err(String::from("Usage: kick <name> [reason]"));
+            }
+            let reason = if reason.is_empty() { "Kicked by an administrator" } else { reason };
+            Ok(Command::Kick { name: name.to_string(), reason: reason.to_string() })
+        },
+        "ban" => {
+            let (target, reason) = match rest.split_once(char::is_whitespace) {
+                Some((target, reason)) => (target, reason.trim()),
+                None => (rest, ""),
+            };
+            if target.is_empty() {
+                return Err(String::from("Usage: ban <name|ip> [reason]"));
+            }
+            let reason = if reason.is_empty() { "Banned by an administrator" } else { reason };
+            Ok(Command::Ban { target: target.to_string(), reason: reason.to_string() })
+        },
+        "unban" => {
+            if rest.is_empty() {
+                return Err(String::from("Usage: unban <name|ip>"));
+            }
+            Ok(Command::Unban(rest.to_string()))
+        },
+        "bans" => Ok(Command::Bans),
+        "reload" => Ok(Command::Reload),
+        "broadcast" => {
+            if rest.is_empty() {
+                return Err(String::from("Usage: broadcast <text>"));
+            }
+            Ok(Command::Broadcast(rest.to_string()))
+        },
+        "stats" => Ok(Command::Stats),
+        "rooms" => Ok(Command::Rooms),
+        "links" => Ok(Command::Links),
+        "loglevel" => {
+            let mut args = rest.split_whitespace();
+            let level = match args.next() {
+                Some(level) => level.parse::<Level>().map_err(|e| e.to_string())?,
+                None => return Err(String::from("Usage: loglevel <level> [target]")),
+            };
+            Ok(Command::LogLevel { level, target: args.next().map(|t| t.to_string()) })
+        },
+        "shutdown" | "q" => {
+            if rest.is_empty() {
+                return Ok(Command::Shutdown { grace: None });
+            }
+            match rest.parse::<u64>() {
+                Ok(grace) => Ok(Command::Shutdown { grace: Some(grace) }),
+                Err(_) => Err(format!("Invalid grace period \"{}\", expected seconds", rest)),
+            }
+        },
+        _ => Err(format!("Unknown command \"{}\", type help for a list", name)),
+    }
+}
+
+pub fn help(command: Option<&str>) -> String {
+    if let Some(command) = command {
+        return match COMMANDS.iter().find(|(name, _, _)| *name == command) {
+            Some((_, usage, help)) => format!("{}\n  {}\n", usage, help),
+            None => format!("Unknown command \"{}\"\n", command),
+        };
+    }
+
+    let width = COMMANDS.iter().map(|(_, usage, _)| usage.len()).max().unwrap_or(0);
+    let mut out = String::from("Commands:\n");
+    for (_, usage, help) in COMMANDS {
+        out.push_str(&format!("  {:width$}  {}\n", usage, help, width = width));
+    }
+    out
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+pub struct Executor<'a> {
+    pub server_state: &'a ServerState,
+    pub server_impl: &'a ServerImpl,
+    pub logger: &'a Logger,
+    pub args: &'a [String], //command line the config is reloaded with
+}
+
+impl<'a> Executor<'a> {
+    //runs every command except shutdown, which the caller handles
+    pub fn execute(&self, command: Command) -> Reply {
+        let server_state = self.server_state;
+        let server_impl = self.server_impl;
+        match command {
+            Command::Help(command) => Ok(help(command.as_deref())),
+            Command::List => {
+                let mut clients: Vec<_> = server_state.clients_stream.read().expect("Failed to lock mutex").values().cloned().collect();
+                clients.sort_by_key(|client| client.connected_at);
+                if clients.is_empty() {
+                    return Ok(String::from("No clients connected\n"));
+                }
+                let mut out = format!("{:<22} {:<16} {:>8} {:>8}\n", "ADDRESS", "NAME", "IDLE", "ONLINE");
+                for client in clients {
+                    let name = server_impl.name_of(&client.stream.addr).unwrap_or_else(|| String::from("-"));
+                    out.push_str(&format!("{:<22} {:<16} {:>8} {:>8}\n", client.stream.addr, name, format_duration(client.idle()), format_duration(client.connected_at.elapsed())));
+                }
+                Ok(out)
+            },
+            Command::Kick { name, reason } => match server_impl.kick(server_state, &name, &reason) {
+                Ok(true) => Ok(format!("Kicked {}\n", name)),
+                Ok(false) => Err(format!("No user named {}\n", name)),
+                Err(e) => Err(format!("Failed to kick {}: {}\n", name, e)),
+            },
+            Command::Ban { target, reason } => match server_impl.ban(server_state, &target, &reason) {
+                Ok(kicked) if kicked.is_empty() => Ok(format!("Banned {}\n", target)),
+                Ok(kicked) => Ok(format!("Banned {}, kicked {}\n", target, kicked.join(", "))),
+                Err(e) => Err(format!("{}\n", e)),
+            },
+            Command::Unban(target) => match server_impl.unban(&target) {
+                true => Ok(format!("Unbanned {}\n", target)),
+                false => Err(format!("{} is not banned\n", target)),
+            },
+            Command::Bans => {
+                let (names, ips) = server_impl.bans();
+                if names.is_empty() && ips.is_empty() {
+                    return Ok(String::from("No bans\n"));
+                }
+                let mut out = String::new();
+                for name in names {
+                    out.push_str(&format!("name {}\n", name));
+                }
+                for ip in ips {
+                    out.push_str(&format!("ip   {}\n", ip));
+                }
+                Ok(out)
+            },
+            Command::Broadcast(text) => match server_impl.broadcast(server_state, &text) {
+                Ok(_) => Ok(format!("Sent to {} clients\n", server_state.clients_stream.read().expect("Failed to lock mutex").len())),
+                Err(e) => Err(format!("Broadcast failed: {}\n", e)),
+            },
+            Command::Stats => {
+                let metrics = &server_state.metrics;
+                let mut out = String::new();
+                out.push_str(&format!("uptime               {}\n", format_duration(metrics.uptime())));
+                out.push_str(&format!("connected clients    {}\n", metrics.connected_clients.load(Ordering::Relaxed)));
+                out.push_str(&format!("registered users     {}\n", server_impl.names().len()));
+                out.push_str(&format!("connections          {}\n", metrics.connections_total.load(Ordering::Relaxed)));
+                out.push_str(&format!("rejected             {}\n", metrics.connections_rejected_total.load(Ordering::Relaxed)));
+                out.push_str(&format!("disconnections       {}\n", metrics.disconnections_total.load(Ordering::Relaxed)));
+                out.push_str(&format!("messages in/out      {}/{}\n", metrics.messages_in_total.load(Ordering::Relaxed), metrics.messages_out_total.load(Ordering::Relaxed)));
+                out.push_str(&format!("bytes in/out         {}/{}\n", metrics.bytes_in_total.load(Ordering::Relaxed), metrics.bytes_out_total.load(Ordering::Relaxed)));
+                out.push_str(&format!("decode errors        {}\n", metrics.decode_errors_total.load(Ordering::Relaxed)));
+                out.push_str(&format!("send errors          {}\n", metrics.send_errors_total.load(Ordering::Relaxed)));
+                out.push_str(&format!("queued output        {}\n", metrics.write_buffer_bytes.load(Ordering::Relaxed)));
+                out.push_str(&format!("slow clients         {}\n", metrics.slow_clients_total.load(Ordering::Relaxed)));
+                Ok(out)
+            },
+            Command::Rooms => {
+                let mut out = String::new();
+                for (room, members) in server_impl.rooms() {
+                    out.push_str(&format!("{:<16} {} members\n", room, members));
+                }
+                Ok(out)
+            },
+            Command::Links => {
+                let servers = server_impl.servers();
+                if servers.is_empty() {
+                    return Ok(String::from("Not linked to any server\n"));
+                }
+                let mut out = format!("{:<20} {:<22} {:>6}\n", "SERVER", "VIA", "USERS");
+                for (server, via, users) in servers {
+                    out.push_str(&format!("{:<20} {:<22} {:>6}\n", server, via, users));
+                }
+                Ok(out)
+            },
+            Command::LogLevel { level, target } => match target {
+                Some(target) => {
+                    self.logger.set_target_level(&target, level);
+                    Ok(format!("Log level for {} set to {}\n", target, level))
+                },
+                None => {
+                    self.logger.set_level(level);
+                    Ok(format!("Log level set to {}\n", level))
+                },
+            },
+            Command::Reload => self.reload(),
+            Command::Shutdown { .. } => Ok(String::new()),
+        }
+    }
+
+    fn reload(&self) -> Reply {
+        let config = match utils::config::load::<ServerConfig, _>(self.args.iter().cloned()) {
+            Ok(Some(config)) => config,
+            Ok(None) => return Err(String::from("Nothing to reload, the server was started with --help\n")),
+            Err(e) => return Err(format!("Config not reloaded: {}\n", e)),
+        };
+
+        //validate() already checked the targets
+        self.logger.set_level(config.log.level);
+        if let Ok(filters) = logger::parse_filters(&config.log.targets) {
+            self.logger.set_filters(&filters);
+        }
+        self.server_state.set_max_queued(config.limits.max_queued);
+
+        let restart = self.server_impl.reload(config);
+        if restart.is_empty() {
+            return Ok(String::from("Config reloaded\n"));
+        }
+        Ok(format!("Config reloaded, changes to {} take effect after a restart\n", restart.join(", ")))
+    }
+}
+
+struct ConsoleHelper {
+    server_impl: Arc<ServerImpl>,
+}
+
+impl Completer for ConsoleHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let choices: Vec<String> = match words.as_slice() {
+            [] => COMMANDS.iter().map(|(name, _, _)| name.to_string()).collect(),
+            ["help"] => COMMANDS.iter().map(|(name, _, _)| name.to_string()).collect(),
+            ["kick"] | ["ban"] => self.server_impl.names(),
+            ["unban"] => {
+                let (names, ips) = self.server_impl.bans();
+                names.into_iter().chain(ips.into_iter().map(|ip| ip.to_string())).collect()
+            },
+            ["loglevel"] => LEVELS.iter().map(|level| level.to_string()).collect(),
+            _ => Vec::new(),
+        };
+
+        let pairs = choices.into_iter()
+            .filter(|choice| choice.starts_with(word))
+            .map(|choice| Pair { display: choice.clone(), replacement: format!("{} ", choice) })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ConsoleHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ConsoleHelper {}
+
+impl Validator for ConsoleHelper {}
+
+impl Helper for ConsoleHelper {}
+
+//reads commands from stdin with line editing and tab completion, invalid input is reported here
+//and never reaches the main thread; Ctrl-C at the prompt counts as a shutdown request
+pub fn run(sender: mpsc::Sender<Request>, server_impl: Arc<ServerImpl>) {
+    let mut editor = match Editor::<ConsoleHelper, _>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Failed to start the admin console: {}", e);
+            return;
+        },
+    };
+    editor.set_helper(Some(ConsoleHelper { server_impl }));
+    println!("Admin console ready, type help for a list of commands");
+
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => String::from("shutdown"),
+            Err(ReadlineError::Eof) => return, //stdin closed, keep running until signalled
+            Err(e) => {
+                eprintln!("Failed to read command: {}", e);
+                return;
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+
+        let command = match parse(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            },
+        };
+        let shutdown = matches!(command, Command::Shutdown { .. });
+        let (reply_sender, reply) = mpsc::channel();
+        if sender.send((command, reply_sender)).is_err() || shutdown {
+            return;
+        }
+        match reply.recv() {
+            Ok(Ok(out)) | Ok(Err(out)) => print!("{}", out),
+            Err(_) => return,
+        }
+    }
+}
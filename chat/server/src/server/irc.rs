@@ -0,0 +1,422 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use netutils::message_stream::{self, MsgInfo, MAX_FRAME_LEN};
+use netutils::messages::{self, server::ErrorCode};
+use server_lib::{client::ClientStream, gateway::Gateway};
+
+use super::client_info::LOBBY;
+
+//the core of RFC 1459 on top of the native protocol: IRC users register, join, talk and leave
+//through the same requests native clients send, and see native users as IRC users; rooms are
+//channels with a # in front, and like native users they are in one channel at a time
+
+const SERVER_NAME: &str = "chat";
+const MAX_LINE_LEN: usize = 8192; //512 in the RFC, message tags made real clients send more
+
+//the requests the gateway makes for IRC commands, replies are told apart by them
+const REQUEST_REGISTER: u32 = 1;
+const REQUEST_NAMES: u32 = 2;
+const REQUEST_WHO: u32 = 3;
+const REQUEST_PRIVMSG: u32 = 4;
+const REQUEST_JOIN: u32 = 5;
+const REQUEST_PART: u32 = 6;
+const REQUEST_NICK: u32 = 7;
+
+#[derive(Default)]
+struct IrcClient {
+    line: Vec<u8>, //received after the last complete line
+    nick: Option<String>, //asked for with NICK, the name the server gave once registered
+    user: bool, //USER was sent, registration waits for it
+    password: Option<String>, //PASS, sent along with NICK changes to reserved names
+    registered: bool,
+    room: String,
+}
+
+pub struct IrcGateway {
+    clients: Mutex<HashMap<SocketAddr, IrcClient>>,
+}
+
+impl IrcGateway {
+    pub fn new() -> Self {
+        IrcGateway {
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Gateway for IrcGateway {
+    fn name(&self) -> &'static str {
+        "irc"
+    }
+
+    fn inbound(&self, stream: &ClientStream, data: &[u8], frames: &mut Vec<u8>, replies: &mut Vec<u8>) -> Result<(), String> {
+        let mut lock_guard = self.clients.lock().expect("Failed to lock mutex");
+        let client = lock_guard.entry(stream.addr).or_default();
+        client.line.extend_from_slice(data);
+
+        let mut out = String::new();
+        let mut res = Ok(());
+        while let Some(end) = client.line.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = client.line.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some((command, params)) = parse(line) {
+                res = client.command(&command, &params, frames, &mut out);
+                if res.is_err() {
+                    break;
+                }
+            }
+        }
+        if res.is_ok() && client.line.len() > MAX_LINE_LEN {
+            out.push_str("ERROR :Line too long\r\n");
+            res = Err(String::from("IRC line too long"));
+        }
+        replies.extend_from_slice(out.as_bytes());
+        res
+    }
+
+    fn outbound(&self, stream: &ClientStream, frame: &[u8]) -> Vec<u8> {
+        let msginfo = match message_stream::read_msginfo(&mut &frame[..], MAX_FRAME_LEN) {
+            Ok(msginfo) => msginfo,
+            Err(_) => return Vec::new(),
+        };
+        let mut lock_guard = self.clients.lock().expect("Failed to lock mutex");
+        let client = lock_guard.entry(stream.addr).or_default();
+        let mut out = String::new();
+        //anything the server sends decodes, a failure only loses that one line
+        let _ = client.event(&msginfo, &mut out);
+        out.into_bytes()
+    }
+
+    fn disconnected(&self, stream: &ClientStream) {
+        self.clients.lock().expect("Failed to lock mutex").remove(&stream.addr);
+    }
+}
+
+//tags and the source prefix are dropped, clients have no business setting them
+fn parse(line: &str) -> Option<(String, Vec<&str>)> {
+    let mut rest = line.trim_start();
+    if rest.starts_with('@') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+    let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    if command.is_empty() {
+        return None;
+    }
+
+    let mut params = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(trailing) = rest.strip_prefix(':') {
+            params.push(trailing);
+            break;
+        }
+        match rest.split_once(' ') {
+            Some((param, next)) => {
+                params.push(param);
+                rest = next;
+            },
+            None => {
+                params.push(rest);
+                break;
+            },
+        }
+    }
+    Some((command.to_ascii_uppercase(), params))
+}
+
+fn push<T: serde::Serialize + ?Sized>(frames: &mut Vec<u8>, code: messages::client::Message, request_id: u32, data: &T) {
+    let msg_encoded = message_stream::serialize_request(code as u32, request_id, data).expect("Failed to serialze message");
+    frames.extend_from_slice(&msg_encoded);
+}
+
+fn room_of(channel: &str) -> &str {
+    channel.strip_prefix('#').unwrap_or(channel)
+}
+
+//every field written into a line goes through here, a CR, LF or NUL in a message, reason or name
+//would otherwise end the line early and let the rest be read as a command of its own
+fn clean(text: &str) -> Cow<'_, str> {
+    if !text.contains(['\r', '\n', '\0']) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.replace(['\r', '\n', '\0'], " "))
+}
+
+//multi-line text becomes one line each, a lone CR counts as a break too
+fn text_lines(text: &str) -> impl Iterator<Item = Cow<'_, str>> {
+    text.split(['\r', '\n']).filter(|line| !line.is_empty()).map(clean)
+}
+
+fn source(user: &str) -> String {
+    let user = clean(user);
+    format!("{}!{}@{}", user, user, SERVER_NAME)
+}
+
+impl IrcClient {
+    fn target(&self) -> &str {
+        self.nick.as_deref().filter(|_| self.registered).unwrap_or("*")
+    }
+
+    fn numeric(&self, out: &mut String, numeric: &str, rest: &str) {
+        let _ = write!(out, ":{} {} {} {}\r\n", SERVER_NAME, numeric, clean(self.target()), rest);
+    }
+
+    fn command(&mut self, command: &str, params: &[&str], frames: &mut Vec<u8>, out: &mut String) -> Result<(), String> {
+        match command {
+            "CAP" => match params.first().map(|sub| sub.to_ascii_uppercase()).as_deref() {
+                Some("LS") | Some("LIST") => { let _ = write!(out, ":{} CAP * LS :\r\n", SERVER_NAME); },
+                Some("REQ") => { let _ = write!(out, ":{} CAP * NAK :{}\r\n", SERVER_NAME, clean(params.get(1).unwrap_or(&""))); },
+                _ => {},
+            },
+            "PASS" => self.password = params.first().map(|password| password.to_string()),
+            "NICK" => {
+                let nick = match params.first() {
+                    Some(nick) => *nick,
+                    None => {
+                        self.numeric(out, "431", ":No nickname given");
+                        return Ok(());
+                    },
+                };
+                if self.registered {
+                    let msg = messages::client::MsgNick {
+                        user: nick,
+                        password: self.password.as_deref(),
+                    };
+                    push(frames, messages::client::Message::OnNick, REQUEST_NICK, &msg);
+                    return Ok(());
+                }
+                self.nick = Some(nick.to_string());
+                self.register(frames);
+            },
+            "USER" => {
+                if self.registered {
+                    self.numeric(out, "462", ":You may not reregister");
+                    return Ok(());
+                }
+                self.user = true;
+                self.register(frames);
+            },
+            "PING" => { let _ = write!(out, ":{} PONG {} :{}\r\n", SERVER_NAME, SERVER_NAME, clean(params.first().unwrap_or(&""))); },
+            "PONG" => {},
+            "QUIT" => {
+                let _ = write!(out, "ERROR :Closing link ({})\r\n", clean(params.first().unwrap_or(&"Quit")));
+                return Err(String::from("IRC client quit"));
+            },
+            _ if !self.registered => self.numeric(out, "451", ":You have not registered"),
+            "JOIN" => {
+                let channels = match params.first() {
+                    Some(channels) => *channels,
+                    None => {
+                        self.numeric(out, "461", "JOIN :Not enough parameters");
+                        return Ok(());
+                    },
+                };
+                //one room at a time, joining several ends up in the last one
+                for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+                    if channel == "0" {
+                        let msg = messages::client::MsgPart { room: self.room.as_str() };
+                        push(frames, messages::client::Message::OnPart, REQUEST_PART, &msg);
+                        push(frames, messages::client::Message::OnWho, REQUEST_NAMES, &messages::client::MsgWho { room: "" });
+                        continue;
+                    }
+                    let room = room_of(channel);
+                    push(frames, messages::client::Message::OnJoin, REQUEST_JOIN, &messages::client::MsgJoin { room });
+                    push(frames, messages::client::Message::OnWho, REQUEST_NAMES, &messages::client::MsgWho { room });
+                }
+            },
+            "PART" => {
+                let channels = params.first().copied().unwrap_or("");
+                for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+                    let msg = messages::client::MsgPart { room: room_of(channel) };
+                    push(frames, messages::client::Message::OnPart, REQUEST_PART, &msg);
+                    //parting lands the user in the lobby
+                    push(frames, messages::client::Message::OnWho, REQUEST_NAMES, &messages::client::MsgWho { room: "" });
+                }
+            },
+            "PRIVMSG" | "NOTICE" => {
+                let (target, text) = match (params.first(), params.get(1)) {
+                    (Some(target), Some(text)) => (*target, *text),
+                    _ => {
+                        self.numeric(out, "412", ":No text to send");
+                        return Ok(());
+                    },
+                };
+                let action = text.strip_prefix("\u{1}ACTION ").map(|action| action.trim_end_matches('\u{1}'));
+                //other CTCP requests have nothing to map to
+                if action.is_none() && text.starts_with('\u{1}') {
+                    return Ok(());
+                }
+                if !target.starts_with('#') {
+                    let msg = messages::client::MsgPrivateMsg { to: target, msg: action.unwrap_or(text) };
+                    push(frames, messages::client::Message::OnPrivateMsg, REQUEST_PRIVMSG, &msg);
+                }
+                else if room_of(target) != self.room {
+                    if command == "PRIVMSG" {
+                        self.numeric(out, "404", &format!("{} :Cannot send to channel", clean(target)));
+                    }
+                }
+                else if let Some(action) = action {
+                    push(frames, messages::client::Message::OnAction, 0, &messages::client::MsgAction { msg: action });
+                }
+                else {
+                    push(frames, messages::client::Message::OnSent, 0, &messages::client::MsgOnSent { msg: text });
+                }
+            },
+            "WHO" | "NAMES" => {
+                let room = params.first().map(|channel| room_of(channel)).unwrap_or("");
+                let request_id = if command == "WHO" { REQUEST_WHO } else { REQUEST_NAMES };
+                push(frames, messages::client::Message::OnWho, request_id, &messages::client::MsgWho { room });
+            },
+            "MODE" => match params.first() {
+                Some(target) if target.starts_with('#') => self.numeric(out, "324", &format!("{} +", clean(target))),
+                _ => self.numeric(out, "221", "+"),
+            },
+            command => self.numeric(out, "421", &format!("{} :Unknown command", clean(command))),
+        }
+        Ok(())
+    }
+
+    //once both NICK and USER arrived, again with every NICK until the server takes one
+    fn register(&mut self, frames: &mut Vec<u8>) {
+        let nick = match self.nick.as_deref() {
+            Some(nick) if self.user => nick,
+            _ => return,
+        };
+        push(frames, messages::client::Message::OnRegisterUser, REQUEST_REGISTER, &messages::client::MsgOnRegisterUser { user: nick });
+        //refused while unregistered, so this only answers a registration that went through
+        push(frames, messages::client::Message::OnWho, REQUEST_NAMES, &messages::client::MsgWho { room: "" });
+    }
+
+    fn event(&mut self, msginfo: &MsgInfo, out: &mut String) -> Result<(), message_stream::MsgError> {
+        let channel = format!("#{}", clean(&self.room));
+        match msginfo.code {
+            code if code == messages::server::Message::OnRegistrationSuccess as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgRegistrationSuccess>()?;
+                self.nick = Some(msg.user.to_string());
+                self.registered = true;
+                self.room = String::from(LOBBY);
+                self.numeric(out, "001", &format!(":Welcome to the chat, {}", clean(msg.user)));
+                self.numeric(out, "002", &format!(":Your host is {}", SERVER_NAME));
+                self.numeric(out, "004", &format!("{} chat o o", SERVER_NAME));
+                self.numeric(out, "422", ":MOTD File is missing");
+                let _ = write!(out, ":{} JOIN #{}\r\n", source(msg.user), LOBBY);
+            },
+            code if code == messages::server::Message::OnAlreadyRegisteredUser as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgAlreadyRegisteredUser>()?;
+                self.numeric(out, "433", &format!("{} :Nickname is already in use", clean(msg.user)));
+            },
+            code if code == messages::server::Message::OnNameRejected as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgNameRejected>()?;
+                self.numeric(out, "432", &format!("{} :{}", clean(msg.user), clean(msg.reason)));
+            },
+            code if code == messages::server::Message::OnRegisterUser as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgOnRegisterUser>()?;
+                //new users start in the lobby
+                if self.registered && self.room == LOBBY {
+                    let _ = write!(out, ":{} JOIN #{}\r\n", source(msg.user), LOBBY);
+                }
+            },
+            code if code == messages::server::Message::OnDisconnect as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgOnDisconnect>()?;
+                let _ = write!(out, ":{} QUIT :Disconnected\r\n", source(msg.user));
+            },
+            code if code == messages::server::Message::OnSent as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgOnSent>()?;
+                for line in text_lines(msg.msg) {
+                    let _ = write!(out, ":{} PRIVMSG {} :{}\r\n", source(msg.user), channel, line);
+                }
+            },
+            code if code == messages::server::Message::OnAction as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgAction>()?;
+                let _ = write!(out, ":{} PRIVMSG {} :\u{1}ACTION {}\u{1}\r\n", source(msg.user), channel, clean(msg.msg));
+            },
+            code if code == messages::server::Message::OnPrivateMsg as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgPrivateMsg>()?;
+                for line in text_lines(msg.msg) {
+                    let _ = write!(out, ":{} PRIVMSG {} :{}\r\n", source(msg.from), self.target(), line);
+                }
+            },
+            code if code == messages::server::Message::OnAway as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgAway>()?;
+                self.numeric(out, "301", &format!("{} :{}", clean(msg.user), clean(msg.msg)));
+            },
+            code if code == messages::server::Message::OnJoined as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgJoined>()?;
+                if self.nick.as_deref() == Some(msg.user) {
+                    self.room = msg.room.to_string();
+                }
+                let _ = write!(out, ":{} JOIN #{}\r\n", source(msg.user), clean(msg.room));
+            },
+            code if code == messages::server::Message::OnParted as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgParted>()?;
+                let _ = write!(out, ":{} PART #{}\r\n", source(msg.user), clean(msg.room));
+            },
+            code if code == messages::server::Message::OnWho as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgWho>()?;
+                if msginfo.request_id == REQUEST_NAMES {
+                    let names: Vec<Cow<str>> = msg.users.iter().map(|entry| clean(entry.user)).collect();
+                    self.numeric(out, "353", &format!("= #{} :{}", clean(msg.room), names.join(" ")));
+                    self.numeric(out, "366", &format!("#{} :End of /NAMES list.", clean(msg.room)));
+                    return Ok(());
+                }
+                for entry in msg.users.iter() {
+                    let here = if entry.away.is_some() { "G" } else { "H" };
+                    let user = clean(entry.user);
+                    self.numeric(out, "352", &format!("#{} {} {} {} {} {} :0 {}", clean(msg.room), user, SERVER_NAME, SERVER_NAME, user, here, user));
+                }
+                self.numeric(out, "315", &format!("#{} :End of /WHO list.", clean(msg.room)));
+            },
+            code if code == messages::server::Message::OnRename as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgRename>()?;
+                if self.nick.as_deref() == Some(msg.old) {
+                    self.nick = Some(msg.new.to_string());
+                }
+                let _ = write!(out, ":{} NICK :{}\r\n", source(msg.old), clean(msg.new));
+            },
+            code if code == messages::server::Message::OnServerNotice as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgServerNotice>()?;
+                for line in text_lines(msg.msg) {
+                    let _ = write!(out, ":{} NOTICE {} :{}\r\n", SERVER_NAME, self.target(), line);
+                }
+            },
+            code if code == messages::server::Message::OnKicked as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgKicked>()?;
+                let _ = write!(out, "ERROR :Closing link (Kicked: {})\r\n", clean(msg.reason));
+            },
+            code if code == messages::server::Message::OnServerShutdown as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgServerShutdown>()?;
+                let _ = write!(out, "ERROR :Server shutting down ({})\r\n", clean(msg.reason));
+            },
+            code if code == messages::server::Message::OnFileOffer as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgFileOffer>()?;
+                let _ = write!(out, ":{} NOTICE {} :{} offers {}, files can not be received over IRC\r\n", SERVER_NAME, self.target(), clean(msg.from), clean(msg.name));
+            },
+            code if code == messages::server::Message::OnError as u32 => {
+                let msg = msginfo.decode_data::<messages::server::MsgError>()?;
+                match (msg.request_id, ErrorCode::from_u32(msg.code)) {
+                    //asked for on the user's behalf, the command itself reports what went wrong
+                    (REQUEST_NAMES, _) | (REQUEST_JOIN, ErrorCode::Conflict) => {},
+                    (_, ErrorCode::NotRegistered) => self.numeric(out, "451", ":You have not registered"),
+                    (REQUEST_PRIVMSG, ErrorCode::NotFound) => self.numeric(out, "401", &format!("* :{}", clean(msg.message))),
+                    (REQUEST_JOIN, _) | (REQUEST_PART, _) => self.numeric(out, "403", &format!("{} :{}", channel, clean(msg.message))),
+                    (REQUEST_NICK, _) => self.numeric(out, "432", &format!("* :{}", clean(msg.message))),
+                    _ => { let _ = write!(out, ":{} NOTICE {} :{}\r\n", SERVER_NAME, self.target(), clean(msg.message)); },
+                }
+            },
+            //user lists, acks, file transfers and frame options mean nothing to an IRC client
+            _ => {},
+        }
+        Ok(())
+    }
+}
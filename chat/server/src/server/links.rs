@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+
+use super::names::{self, SERVER_SEPARATOR};
+
+//a user registered on another server of the network
+#[derive(Debug, Clone)]
+pub struct RemoteUser {
+    pub name: String,
+    pub server: String,
+    pub room: String,
+    pub away: Option<String>,
+}
+
+impl RemoteUser {
+    //what local users see and address them by
+    pub fn qualified(&self) -> String {
+        qualify(&self.name, &self.server)
+    }
+}
+
+pub fn qualify(name: &str, server: &str) -> String {
+    format!("{}{}{}", name, SERVER_SEPARATOR, server)
+}
+
+//what this server knows about the rest of the network; links form a tree, so every other server
+//is reached through exactly one link, and only the bookkeeping lives here
+#[derive(Default)]
+pub struct Links {
+    dialed: HashSet<SocketAddr>, //link connections this server opened, linked or still saying hello
+    peers: HashMap<SocketAddr, String>, //links that said hello -> the server at the other end
+    servers: HashMap<String, SocketAddr>, //every other server on the network -> the link leading to it
+    users: HashMap<(String, String), RemoteUser>, //(server, names::key)
+}
+
+impl Links {
+    //false if `addr` is dialed already
+    pub fn dial(&mut self, addr: SocketAddr) -> bool {
+        self.dialed.insert(addr)
+    }
+
+    pub fn undial(&mut self, addr: SocketAddr) {
+        self.dialed.remove(&addr);
+    }
+
+    pub fn is_dialed(&self, addr: &SocketAddr) -> bool {
+        self.dialed.contains(addr)
+    }
+
+    //the server at the other end of the link, None until it said hello
+    pub fn peer(&self, addr: &SocketAddr) -> Option<&str> {
+        self.peers.get(addr).map(|name| name.as_str())
+    }
+
+    //every link that said hello, but `except`
+    pub fn links(&self, except: Option<SocketAddr>) -> Vec<SocketAddr> {
+        self.peers.keys().filter(|addr| Some(**addr) != except).cloned().collect()
+    }
+
+    //the link `server` is reached through
+    pub fn route(&self, server: &str) -> Option<SocketAddr> {
+        self.servers.get(server).cloned()
+    }
+
+    //events are only taken from the link that leads to the server they started on, anything else
+    //went around a loop or comes from a server that split off already
+    pub fn routes(&self, addr: SocketAddr, server: &str) -> bool {
+        self.servers.get(server) == Some(&addr)
+    }
+
+    //(server, link it is reached through), sorted by name
+    pub fn servers(&self) -> Vec<(String, SocketAddr)> {
+        let mut servers: Vec<(String, SocketAddr)> = self.servers.iter().map(|(name, addr)| (name.clone(), *addr)).collect();
+        servers.sort();
+        servers
+    }
+
+    //refuses a link to `name` and `servers` behind it when any of them, this server included, is
+    //already on the network
+    pub fn check(&self, own: &str, name: &str, servers: &[&str]) -> Result<(), String> {
+        if name.is_empty() {
+            return Err(String::from("servers need a name to link"));
+        }
+        for server in std::iter::once(&name).chain(servers.iter()) {
+            if *server == own || self.servers.contains_key(*server) {
+                return Err(format!("{} is already on the network, linking would close a loop", server));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_link(&mut self, addr: SocketAddr, name: &str, servers: &[&str]) {
+        self.peers.insert(addr, name.to_string());
+        for server in std::iter::once(&name).chain(servers.iter()) {
+            self.servers.insert(server.to_string(), addr);
+        }
+    }
+
+    //returns the servers that were not known yet, the others are ignored
+    pub fn add_servers(&mut self, addr: SocketAddr, own: &str, servers: &[&str]) -> Vec<String> {
+        let mut added = Vec::new();
+        for server in servers {
+            if *server == own || self.servers.contains_key(*server) {
+                continue;
+            }
+            self.servers.insert(server.to_string(), addr);
+            added.push(server.to_string());
+        }
+        added
+    }
+
+    //drops the link with everything behind it, returns the server at its end, the servers and the
+    //users that split off; None if it never said hello
+    pub fn remove_link(&mut self, addr: SocketAddr) -> Option<(String, Vec<String>, Vec<RemoteUser>)> {
+        self.dialed.remove(&addr);
+        let name = self.peers.remove(&addr)?;
+        let servers: Vec<String> = self.servers.iter().filter(|(_, via)| **via == addr).map(|(name, _)| name.clone()).collect();
+        let (servers, users) = self.remove_servers(addr, &servers);
+        Some((name, servers, users))
+    }
+
+    //only the servers reached through `addr` are removed, returns them and their users
+    pub fn remove_servers<S: AsRef<str>>(&mut self, addr: SocketAddr, servers: &[S]) -> (Vec<String>, Vec<RemoteUser>) {
+        let mut removed = Vec::new();
+        for server in servers.iter().map(|server| server.as_ref()) {
+            if self.routes(addr, server) {
+                self.servers.remove(server);
+                removed.push(server.to_string());
+            }
+        }
+        let keys: Vec<(String, String)> = self.users.keys().filter(|(server, _)| removed.contains(server)).cloned().collect();
+        let mut users: Vec<RemoteUser> = keys.iter().filter_map(|key| self.users.remove(key)).collect();
+        users.sort_by_key(|user| user.qualified());
+        (removed, users)
+    }
+
+    //users on the servers that are not reached through `except`
+    pub fn users(&self, except: Option<SocketAddr>) -> Vec<RemoteUser> {
+        self.users.values()
+            .filter(|user| except.is_none() || self.servers.get(&user.server) != except.as_ref())
+            .cloned()
+            .collect()
+    }
+
+    pub fn add_user(&mut self, user: RemoteUser) {
+        self.users.insert((user.server.clone(), names::key(&user.name)), user);
+    }
+
+    pub fn remove_user(&mut self, server: &str, name: &str) -> Option<RemoteUser> {
+        self.users.remove(&(server.to_string(), names::key(name)))
+    }
+
+    pub fn rename_user(&mut self, server: &str, old: &str, new: &str) -> bool {
+        match self.remove_user(server, old) {
+            Some(mut user) => {
+                user.name = new.to_string();
+                self.add_user(user);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn user_mut(&mut self, server: &str, name: &str) -> Option<&mut RemoteUser> {
+        self.users.get_mut(&(server.to_string(), names::key(name)))
+    }
+
+    //`qualified` is name@server, see qualify
+    pub fn find(&self, qualified: &str) -> Option<&RemoteUser> {
+        let (name, server) = qualified.rsplit_once(SERVER_SEPARATOR)?;
+        self.users.get(&(server.to_string(), names::key(name)))
+    }
+}
@@ -1,37 +1,226 @@
 extern crate netutils;
 extern crate utils;
 
+mod accounts;
+mod admin;
 mod client_info;
+mod config;
+mod irc;
+mod links;
+mod console;
+mod names;
 mod server_impl;
+mod transfers;
+mod webhooks;
 
-use server_impl::{ServerImpl};
+use config::{ServerConfig, LogConfig};
+use accounts::Accounts;
+use server_impl::ServerImpl;
+use irc::IrcGateway;
+use webhooks::Webhooks;
 
-use server_lib::{Server};
-use std::fs::File;
-use std::net::{TcpListener};
-use std::sync::{Arc};
+use server_lib::Server;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use netutils::logger::Logger;
+use netutils::logger::{self, Logger};
+use netutils::rotating_file::{RotatingFile, RotationPolicy};
+
+//how often peers without a link are dialed
+const LINK_RETRY: Duration = Duration::from_secs(5);
+
+fn build_logger(config: &LogConfig) -> Result<Logger, String> {
+    let logger = Logger::new(config.level);
+    match logger::parse_filters(&config.targets) {
+        Ok(filters) => logger.set_filters(&filters),
+        Err(e) => return Err(format!("Invalid log targets: {}", e)),
+    }
+
+    if let Some(path) = &config.file {
+        let policy = RotationPolicy {
+            max_size: config.max_size,
+            every: config.rotate,
+            keep: config.keep,
+            compress: config.compress,
+        };
+        match RotatingFile::open(path, policy) {
+            Ok(file) => logger.add_writer(file, config.format),
+            Err(e) => return Err(format!("Failed to open log file {}: {}", path.display(), e)),
+        }
+    }
+    if config.stderr {
+        logger.add_writer(std::io::stderr(), config.format);
+    }
+    Ok(logger)
+}
+
+//None when `addr` is unset, exits if it cannot be bound
+fn bind_optional(addr: Option<SocketAddr>, what: &str) -> Option<TcpListener> {
+    let addr = addr?;
+    match TcpListener::bind(addr) {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            eprintln!("Failed to bind {} {}: {}", what, addr, e);
+            std::process::exit(1);
+        },
+    }
+}
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").expect("Failed to call bind");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match utils::config::load::<ServerConfig, _>(args.iter().cloned()) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            print!("{}", utils::config::usage::<ServerConfig>("server"));
+            return;
+        },
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        },
+    };
+
+    let listener = match TcpListener::bind(config.bind) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind {}: {}", config.bind, e);
+            std::process::exit(1);
+        },
+    };
+    println!("Listening on {}", listener.local_addr().unwrap_or(config.bind));
+
+    let logger = match build_logger(&config.log) {
+        Ok(logger) => Arc::new(logger),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let metrics_listener = bind_optional(config.metrics.bind, "metrics endpoint");
+    if let (Some(listener), Some(addr)) = (&metrics_listener, config.metrics.bind) {
+        println!("Serving metrics on http://{}/metrics", listener.local_addr().unwrap_or(addr));
+    }
+
+    let websocket_listener = bind_optional(config.websocket.bind, "WebSocket endpoint");
+    if let (Some(listener), Some(addr)) = (&websocket_listener, config.websocket.bind) {
+        println!("Accepting WebSocket clients on ws://{}", listener.local_addr().unwrap_or(addr));
+    }
+    let websocket_origins = config.websocket.origins.clone();
+
+    let irc_listener = bind_optional(config.irc.bind, "IRC endpoint");
+    if let (Some(listener), Some(addr)) = (&irc_listener, config.irc.bind) {
+        println!("Accepting IRC clients on {}", listener.local_addr().unwrap_or(addr));
+    }
+
+    let link_listener = bind_optional(config.link.bind, "link endpoint");
+    if let (Some(listener), Some(addr)) = (&link_listener, config.link.bind) {
+        println!("Accepting links from other servers on {} as {}", listener.local_addr().unwrap_or(addr), config.link.name);
+    }
+    let linking = !config.link.name.is_empty();
+
+    let admin_listener = bind_optional(config.admin.bind, "admin endpoint");
+    if let (Some(listener), Some(addr)) = (&admin_listener, config.admin.bind) {
+        println!("Accepting chat-admin connections on {}", listener.local_addr().unwrap_or(addr));
+    }
+
+    let webhooks_listener = bind_optional(config.webhooks.bind, "webhook endpoint");
+    if let (Some(listener), Some(addr)) = (&webhooks_listener, config.webhooks.bind) {
+        println!("Accepting webhook posts on http://{}/messages", listener.local_addr().unwrap_or(addr));
+    }
 
-    let server_impl = Arc::new(ServerImpl::new());
+    let accounts = match Accounts::load(config.accounts.file.as_deref()) {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            eprintln!("Failed to load reserved nicks: {}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let max_queued = config.limits.max_queued;
+    let webhooks = Webhooks::start(&config.webhooks, logger.clone());
+    let server_impl = Arc::new(ServerImpl::new(config, accounts, webhooks, logger.clone()));
     let handler = server_impl::server_handler_build(server_impl.clone());
-    let log_file = File::create("server_log.txt").expect("failed to create file server_log.txt");
-    let logger = Arc::new(Logger::new(Some(Box::new(log_file))));
-    let mut server = Server::new(listener, handler, logger);
+    let mut server = Server::new(listener, handler, logger.clone());
+    server.state.set_max_queued(max_queued);
     server.start();
+    if let Some(listener) = metrics_listener {
+        server.start_metrics(listener);
+    }
+    if let Some(listener) = websocket_listener {
+        server.start_websocket(listener, websocket_origins);
+    }
+    if let Some(listener) = irc_listener {
+        server.start_gateway(listener, Arc::new(IrcGateway::new()));
+    }
+    if let Some(listener) = link_listener {
+        server.start_link(listener);
+    }
+    //peers may be added by a reload, so this keeps running until the server stops accepting
+    if linking {
+        let link_impl = server_impl.clone();
+        let link_state = server.state.clone();
+        thread::spawn(move || while link_state.is_accepting() {
+            link_impl.connect_links(&link_state);
+            //in short steps so a shutdown does not wait out a whole retry period
+            let retry_at = Instant::now() + LINK_RETRY;
+            while Instant::now() < retry_at && link_state.is_accepting() {
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+    }
+
+    let signalled = Arc::new(AtomicBool::new(false));
+    let signalled_handler = signalled.clone();
+    if let Err(e) = ctrlc::set_handler(move || signalled_handler.store(true, Ordering::SeqCst)) {
+        eprintln!("Failed to install signal handler: {}", e);
+    }
+
+    //the console reads stdin on its own thread so signals are noticed while waiting for a command
+    let (command_sender, commands) = mpsc::channel();
+    let console_impl = server_impl.clone();
+    if let Some(listener) = admin_listener {
+        server.start_service(listener, admin::service(command_sender.clone(), server_impl.clone(), logger.clone()));
+    }
+    thread::spawn(move || console::run(command_sender, console_impl));
+    if let Some(listener) = webhooks_listener {
+        server.start_service(listener, webhooks::service(server.state.clone(), server_impl.clone(), logger.clone()));
+    }
 
+    let executor = console::Executor {
+        server_state: &server.state,
+        server_impl: &server_impl,
+        logger: &logger,
+        args: &args,
+    };
+    let mut grace = None;
     loop {
-        let command: String = utils::io::read_val::<_, _, _> (
-            "Enter command(q for quit):",
-            |_input: &str| format!("Expected string of length: [0, 15]").to_string(),
-            Some(|val: &String| return val.len() <= 15 && val.len() > 0),
-        );
-    
-        if command.to_lowercase() == "q".to_lowercase() {
+        if signalled.load(Ordering::SeqCst) {
+            println!("Signal received");
             break;
         }
+
+        match commands.recv_timeout(Duration::from_millis(100)) {
+            Ok((console::Command::Shutdown { grace: requested }, reply)) => {
+                grace = requested;
+                let _ = reply.send(Ok(String::from("Shutting down\n")));
+                break;
+            },
+            Ok((command, reply)) => {
+                let _ = reply.send(executor.execute(command));
+            },
+            Err(_) => {},
+        }
+    }
+
+    //settings may have been reloaded since startup
+    let shutdown = server_impl.shutdown_config();
+    let grace = grace.unwrap_or(shutdown.grace);
+    println!("Shutting down, waiting up to {}s for clients to disconnect...", grace);
+    let res = server.shutdown_graceful(&shutdown.reason, Duration::from_secs(shutdown.retry_after), Duration::from_secs(grace));
+    if let Err(e) = res {
+        eprintln!("Shutdown failed: {}", e);
     }
 }
@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::str::FromStr;
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::MixedScript;
+
+use super::config::NamesConfig;
+
+//users on linked servers are shown as name@server, so no local name may contain it
+pub const SERVER_SEPARATOR: char = '@';
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Charset {
+    Ascii, //a-z, A-Z, 0-9
+    Unicode, //letters and digits of any single script
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "ascii" => Ok(Charset::Ascii),
+            "unicode" => Ok(Charset::Unicode),
+            _ => Err(format!("unknown charset \"{}\", expected ascii or unicode", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NameError {
+    Length { min: usize, max: usize },
+    InvalidChar(char),
+    MixedScript,
+    Reserved,
+    Confusable(String), //name of the user it could be mistaken for
+}
+
+impl std::fmt::Display for NameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            NameError::Length { min, max } => write!(f, "names must be {} to {} characters long", min, max),
+            NameError::InvalidChar(c) if c.is_control() || c.is_whitespace() => write!(f, "{:?} is not allowed in names", c),
+            NameError::InvalidChar(c) => write!(f, "'{}' is not allowed in names", c),
+            NameError::MixedScript => write!(f, "names may not mix letters from different scripts"),
+            NameError::Reserved => write!(f, "this name is reserved by the server"),
+            NameError::Confusable(other) => write!(f, "too similar to {}", other),
+        }
+    }
+}
+
+impl Error for NameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+//names are stored in NFC so the same name typed on different systems compares equal
+pub fn normalize(name: &str) -> String {
+    name.nfc().collect()
+}
+
+//uniqueness key: "Alice", "ALICE" and "Аlice" (cyrillic А) all map to the same key
+pub fn key(name: &str) -> String {
+    let folded: String = name.nfkc().collect::<String>().to_lowercase();
+    unicode_security::skeleton(&folded).collect::<String>().to_lowercase()
+}
+
+//checks `name` (already normalized) against the policy, uniqueness is up to the caller
+pub fn check(config: &NamesConfig, name: &str) -> Result<(), NameError> {
+    let len = name.chars().count();
+    if len < config.min_len || len > config.max_len {
+        return Err(NameError::Length { min: config.min_len, max: config.max_len });
+    }
+
+    let allowed = |c: char| match config.charset {
+        Charset::Ascii => c.is_ascii_alphanumeric(),
+        Charset::Unicode => c.is_alphanumeric(),
+    };
+    if let Some(c) = name.chars().find(|c| *c == SERVER_SEPARATOR || (!allowed(*c) && !config.extra_chars.contains(*c))) {
+        return Err(NameError::InvalidChar(c));
+    }
+    if !name.is_single_script() {
+        return Err(NameError::MixedScript);
+    }
+
+    let name_key = key(name);
+    if config.reserved.iter().any(|reserved| key(reserved) == name_key) {
+        return Err(NameError::Reserved);
+    }
+    Ok(())
+}
@@ -1,97 +1,1158 @@
-use std::{
-    sync::{mpsc::{self, Sender}, Arc, Mutex},
-};
-use std::net::{SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::net::{IpAddr, SocketAddr};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
 
-use super::client_info;
-use server_lib::{ServerHandler, ServerState, client::ClientStream};
-use netutils::{message_stream::{self, MsgInfo}};
-use netutils::messages;
-use std::collections::HashMap;
+use super::accounts::Accounts;
+use super::client_info::{self, LOBBY};
+use super::links::{self, Links, RemoteUser};
+use super::names;
+use super::transfers::Transfers;
+use super::webhooks::Webhooks;
+use super::config::{ServerConfig, ShutdownConfig};
+use server_lib::{ServerHandler, ServerState, client::{ClientStream, Transport}};
+use netutils::message_stream::{self, Codec, Compression, FrameOptions, MsgInfo};
+use netutils::messages::{self, server::ErrorCode};
+use netutils::logger::{Level, Logger};
+use utils::secret;
+
+//how long dialing another server may take
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct ServerImpl {
-    clients: Mutex<HashMap<SocketAddr, client_info::ClientInfo>>
+    config: RwLock<ServerConfig>,
+    clients: Mutex<HashMap<SocketAddr, client_info::ClientInfo>>,
+    history: Mutex<HashMap<String, VecDeque<(String, String)>>>, //room -> (user, msg)
+    bans: Mutex<Bans>,
+    accounts: Mutex<Accounts>, //always locked before clients when both are needed
+    transfers: Mutex<Transfers>, //never held while sending, a failed send ends up in on_disconnect
+    links: Mutex<Links>, //never held while sending either
+    webhooks: Webhooks,
+    logger: Arc<Logger>,
+}
+
+//kept in memory only, a restart lifts every ban
+#[derive(Default)]
+struct Bans {
+    names: HashSet<String>, //see names::key
+    ips: HashSet<IpAddr>,
 }
 
 impl ServerImpl {
-    pub fn new() -> Self {
+    pub fn new(config: ServerConfig, accounts: Accounts, webhooks: Webhooks, logger: Arc<Logger>) -> Self {
         ServerImpl {
+            config: RwLock::new(config),
             clients: Mutex::new(HashMap::new()),
+            history: Mutex::new(HashMap::new()),
+            bans: Mutex::new(Bans::default()),
+            accounts: Mutex::new(accounts),
+            transfers: Mutex::new(Transfers::default()),
+            links: Mutex::new(Links::default()),
+            webhooks,
+            logger,
+        }
+    }
+
+    pub fn admin_token(&self) -> String {
+        self.config.read().expect("Failed to lock mutex").admin.token.clone()
+    }
+
+    pub fn webhook_token(&self) -> String {
+        self.config.read().expect("Failed to lock mutex").webhooks.token.clone()
+    }
+
+    pub fn shutdown_config(&self) -> ShutdownConfig {
+        self.config.read().expect("Failed to lock mutex").shutdown.clone()
+    }
+
+    //swaps in a freshly loaded config, returns the changed settings that still need a restart
+    pub fn reload(&self, config: ServerConfig) -> Vec<&'static str> {
+        let mut lock_guard = self.config.write().expect("Failed to lock mutex");
+        let restart = lock_guard.restart_required(&config);
+        *lock_guard = config;
+        drop(lock_guard);
+
+        let size = self.config.read().expect("Failed to lock mutex").history.size;
+        for history in self.history.lock().expect("Failed to lock mutex").values_mut() {
+            while history.len() > size {
+                history.pop_front();
+            }
+        }
+        restart
+    }
+
+    //users on linked servers included, as name@server
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.clients.lock().expect("Failed to lock mutex").values().map(|c| c.name.clone()).collect();
+        names.extend(self.links.lock().expect("Failed to lock mutex").users(None).iter().map(|user| user.qualified()));
+        names.sort();
+        names
+    }
+
+    pub fn name_of(&self, addr: &SocketAddr) -> Option<String> {
+        self.clients.lock().expect("Failed to lock mutex").get(addr).map(|c| c.name.clone())
+    }
+
+    pub fn find(&self, name: &str) -> Option<SocketAddr> {
+        let key = names::key(name);
+        self.clients.lock().expect("Failed to lock mutex").iter().find(|(_, c)| c.key == key).map(|(addr, _)| *addr)
+    }
+
+    //(room, registered users), sorted by name; the lobby is always listed
+    pub fn rooms(&self) -> Vec<(String, usize)> {
+        let mut rooms: HashMap<String, usize> = HashMap::new();
+        rooms.insert(String::from(LOBBY), 0);
+        for client in self.clients.lock().expect("Failed to lock mutex").values() {
+            *rooms.entry(client.room.clone()).or_insert(0) += 1;
+        }
+        for user in self.links.lock().expect("Failed to lock mutex").users(None) {
+            *rooms.entry(user.room).or_insert(0) += 1;
         }
+        let mut rooms: Vec<(String, usize)> = rooms.into_iter().collect();
+        rooms.sort();
+        rooms
+    }
+
+    //(server, link it is reached through, users on it), sorted by name
+    pub fn servers(&self) -> Vec<(String, SocketAddr, usize)> {
+        let links = self.links.lock().expect("Failed to lock mutex");
+        let users = links.users(None);
+        links.servers().into_iter()
+            .map(|(server, addr)| {
+                let count = users.iter().filter(|user| user.server == server).count();
+                (server, addr, count)
+            })
+            .collect()
+    }
+
+    fn server_name(&self) -> String {
+        self.config.read().expect("Failed to lock mutex").link.name.clone()
+    }
+
+    //local members only, the other servers deliver to theirs
+    fn room_members(&self, room: &str) -> Vec<SocketAddr> {
+        self.clients.lock().expect("Failed to lock mutex").iter()
+            .filter(|(_, c)| c.room == room)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    fn send_room<T: ?Sized + serde::Serialize>(&self, server_state: &ServerState, room: &str, code: u32, data: &T, except: Option<SocketAddr>) {
+        let members: Vec<SocketAddr> = self.room_members(room).into_iter().filter(|addr| Some(*addr) != except).collect();
+        if let Err(e) = server_state.send_to(code, data, &members) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to send to room", &[("room", &room), ("error", &e)]);
+        }
+    }
+
+    fn notice(&self, server_state: &ServerState, stream: &ClientStream, request_id: u32, text: &str) {
+        let msg = messages::server::MsgServerNotice {
+            msg: text,
+        };
+        self.send(server_state, stream, messages::server::Message::OnServerNotice as u32, request_id, &msg);
+    }
+
+    //a failed send means the client is going away, on_disconnect cleans up after it
+    fn send<T: ?Sized + serde::Serialize>(&self, server_state: &ServerState, stream: &ClientStream, code: u32, request_id: u32, data: &T) {
+        if let Err(e) = server_state.send(stream, code, request_id, data) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to send", &[("addr", &stream.addr), ("code", &code), ("error", &e)]);
+        }
+    }
+
+    //requests without any other reply are acknowledged, but only if the client asked for a reply
+    fn ack(&self, server_state: &ServerState, stream: &ClientStream, request_id: u32) {
+        if request_id == 0 {
+            return;
+        }
+        if let Err(e) = server_state.send(stream, messages::server::Message::OnAck as u32, request_id, &messages::server::MsgAck {}) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to acknowledge request", &[("addr", &stream.addr), ("error", &e)]);
+        }
+    }
+
+    fn error(&self, server_state: &ServerState, stream: &ClientStream, request_id: u32, code: ErrorCode, message: &str) {
+        let msg = messages::server::MsgError {
+            code: code as u32,
+            request_id,
+            message,
+        };
+        if let Err(e) = server_state.send(stream, messages::server::Message::OnError as u32, request_id, &msg) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to send error", &[("addr", &stream.addr), ("error", &e)]);
+        }
+    }
+
+    fn decode<'a, T: serde::Deserialize<'a>>(&self, server_state: &ServerState, stream: &ClientStream, msginfo: &'a MsgInfo) -> Option<T> {
+        match msginfo.decode_data::<T>() {
+            Ok(data) => Some(data),
+            Err(e) => {
+                self.logger.log(Level::Warn, module_path!(), "Failed to decode message", &[("addr", &stream.addr), ("code", &msginfo.code), ("error", &e)]);
+                self.error(server_state, stream, msginfo.request_id, ErrorCode::Malformed, &format!("Message {} could not be decoded", msginfo.code));
+                None
+            },
+        }
+    }
+
+    fn registered(&self, addr: &SocketAddr) -> Option<client_info::ClientInfo> {
+        self.clients.lock().expect("Failed to lock mutex").get(addr).cloned()
+    }
+
+    //the registered sender of a request, everyone else is told to register first
+    fn sender(&self, server_state: &ServerState, stream: &ClientStream, request_id: u32) -> Option<client_info::ClientInfo> {
+        let cdata = self.registered(&stream.addr);
+        if cdata.is_none() {
+            self.error(server_state, stream, request_id, ErrorCode::NotRegistered, "Register a name first");
+        }
+        cdata
+    }
+
+    fn message_too_long(&self, server_state: &ServerState, stream: &ClientStream, request_id: u32, msg: &str) -> bool {
+        let max_message_len = self.config.read().expect("Failed to lock mutex").limits.max_message_len;
+        if msg.len() > max_message_len {
+            self.logger.log(Level::Info, module_path!(), "Message too long, dropped", &[("addr", &stream.addr), ("len", &msg.len()), ("max", &max_message_len)]);
+            self.error(server_state, stream, request_id, ErrorCode::TooLarge, &format!("Messages are limited to {} bytes", max_message_len));
+            return true;
+        }
+        false
+    }
+
+    //moves a registered user to `room`, members of both rooms are told, the user gets the room's history
+    fn move_to_room(&self, server_state: &ServerState, stream: &ClientStream, room: &str) {
+        let (name, old_room) = {
+            let mut lock_guard = self.clients.lock().expect("Failed to lock mutex");
+            let cdata = match lock_guard.get_mut(&stream.addr) {
+                Some(cdata) => cdata,
+                None => return,
+            };
+            let old_room = std::mem::replace(&mut cdata.room, room.to_string());
+            (cdata.name.clone(), old_room)
+        };
+
+        let parted = messages::server::MsgParted {
+            user: name.as_str(),
+            room: old_room.as_str(),
+        };
+        self.send_room(server_state, &old_room, messages::server::Message::OnParted as u32, &parted, None);
+        self.send(server_state, stream, messages::server::Message::OnParted as u32, 0, &parted);
+
+        let joined = messages::server::MsgJoined {
+            user: name.as_str(),
+            room,
+        };
+        self.send_room(server_state, room, messages::server::Message::OnJoined as u32, &joined, None);
+        self.logger.log(Level::Info, module_path!(), "User changed rooms", &[("user", &name), ("from", &old_room), ("to", &room)]);
+        self.webhooks.post("part", serde_json::json!({ "user": name, "room": old_room }));
+        self.webhooks.post("join", serde_json::json!({ "user": name, "room": room }));
+
+        let moved = messages::link::MsgMove {
+            server: &self.server_name(),
+            user: name.as_str(),
+            room,
+        };
+        self.send_links(server_state, messages::link::Message::OnMove as u32, &moved, None);
+
+        self.replay_history(server_state, stream, room);
+    }
+
+    fn reject_name(&self, server_state: &ServerState, stream: &ClientStream, request_id: u32, name: &str, reason: &names::NameError) {
+        self.logger.log(Level::Info, module_path!(), "Rejected name", &[("addr", &stream.addr), ("name", &name), ("reason", reason)]);
+        let msg = messages::server::MsgNameRejected {
+            user: name,
+            reason: &reason.to_string(),
+        };
+        self.send(server_state, stream, messages::server::Message::OnNameRejected as u32, request_id, &msg);
+    }
+
+    //the reservation and uniqueness checks and the change itself happen under the same locks as
+    //registration, so two clients can never end up with the same name
+    fn rename(&self, server_state: &ServerState, stream: &ClientStream, new: &str, password: Option<&str>) -> Result<(), (ErrorCode, String)> {
+        let new = names::normalize(new);
+        let policy = self.config.read().expect("Failed to lock mutex").names.clone();
+        if let Err(e) = names::check(&policy, &new) {
+            return Err((ErrorCode::InvalidArgument, format!("{} cannot be used: {}", new, e)));
+        }
+        let key = names::key(&new);
+        if self.bans.lock().expect("Failed to lock mutex").names.contains(&key) {
+            return Err((ErrorCode::Forbidden, format!("{} is banned", new)));
+        }
+
+        let accounts = self.accounts.lock().expect("Failed to lock mutex");
+        if !accounts.allows(&key, password) {
+            return Err(match password {
+                Some(_) => (ErrorCode::Forbidden, format!("Wrong password for {}", new)),
+                None => (ErrorCode::Forbidden, format!("{} is reserved, use /nick {} <password>", new, new)),
+            });
+        }
+        let mut lock_guard = self.clients.lock().expect("Failed to lock mutex");
+        match lock_guard.iter().find(|(addr, c)| c.key == key && **addr != stream.addr) {
+            Some((_, other)) if other.name == new => return Err((ErrorCode::Conflict, format!("{} is already taken", new))),
+            Some((_, other)) => return Err((ErrorCode::Conflict, format!("{} cannot be used: {}", new, names::NameError::Confusable(other.name.clone())))),
+            None => {},
+        }
+        let cdata = match lock_guard.get_mut(&stream.addr) {
+            Some(cdata) => cdata,
+            None => return Ok(()),
+        };
+        if cdata.name == new {
+            return Err((ErrorCode::Conflict, format!("You are already called {}", new)));
+        }
+        cdata.key = key;
+        let old = std::mem::replace(&mut cdata.name, new.clone());
+        drop(lock_guard);
+        drop(accounts);
+
+        self.logger.log(Level::Info, module_path!(), "User renamed", &[("old", &old), ("new", &new)]);
+        let msg = messages::server::MsgRename {
+            old: old.as_str(),
+            new: new.as_str(),
+        };
+        if let Err(e) = server_state.send_all(messages::server::Message::OnRename as u32, &msg) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to announce rename", &[("user", &old), ("error", &e)]);
+        }
+        let renamed = messages::link::MsgRename {
+            server: &self.server_name(),
+            old: old.as_str(),
+            new: new.as_str(),
+        };
+        self.send_links(server_state, messages::link::Message::OnRename as u32, &renamed, None);
+        self.webhooks.post("rename", serde_json::json!({ "old": old, "new": new }));
+        Ok(())
+    }
+
+    //tells the user why before dropping the connection, returns false if no such user is registered
+    pub fn kick(&self, server_state: &ServerState, name: &str, reason: &str) -> Result<bool, std::io::Error> {
+        match self.find(name) {
+            Some(addr) => self.kick_addr(server_state, addr, reason),
+            None => Ok(false),
+        }
+    }
+
+    fn kick_addr(&self, server_state: &ServerState, addr: SocketAddr, reason: &str) -> Result<bool, std::io::Error> {
+        let client = match server_state.clients_stream.read().expect("Failed to lock mutex").get(&addr) {
+            Some(client) => client.clone(),
+            None => return Ok(false),
+        };
+
+        let msg = messages::server::MsgKicked {
+            reason,
+        };
+        server_state.send(client.stream.as_ref(), messages::server::Message::OnKicked as u32, 0, &msg)?;
+        server_state.disconnect_client(addr)
+    }
+
+    //`target` is either an ip address or a registered name, banning a name also bans the address
+    //it is connected from; everyone matching the ban is kicked
+    pub fn ban(&self, server_state: &ServerState, target: &str, reason: &str) -> Result<Vec<String>, String> {
+        let ip = match target.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => match self.find(target) {
+                Some(addr) => {
+                    self.bans.lock().expect("Failed to lock mutex").names.insert(names::key(target));
+                    addr.ip()
+                },
+                None => return Err(format!("No user named {}", target)),
+            },
+        };
+        self.bans.lock().expect("Failed to lock mutex").ips.insert(ip);
+
+        let addrs: Vec<SocketAddr> = server_state.clients_stream.read().expect("Failed to lock mutex").keys().filter(|addr| addr.ip() == ip).cloned().collect();
+        let mut kicked = Vec::new();
+        for addr in addrs {
+            let name = self.name_of(&addr).unwrap_or_else(|| addr.to_string());
+            match self.kick_addr(server_state, addr, reason) {
+                Ok(true) => kicked.push(name),
+                Ok(false) => {},
+                Err(e) => return Err(format!("Failed to kick {}: {}", name, e)),
+            }
+        }
+        Ok(kicked)
+    }
+
+    pub fn unban(&self, target: &str) -> bool {
+        let mut bans = self.bans.lock().expect("Failed to lock mutex");
+        match target.parse::<IpAddr>() {
+            Ok(ip) => bans.ips.remove(&ip),
+            Err(_) => bans.names.remove(&names::key(target)),
+        }
+    }
+
+    pub fn bans(&self) -> (Vec<String>, Vec<IpAddr>) {
+        let bans = self.bans.lock().expect("Failed to lock mutex");
+        let mut names: Vec<String> = bans.names.iter().cloned().collect();
+        let mut ips: Vec<IpAddr> = bans.ips.iter().cloned().collect();
+        names.sort();
+        ips.sort();
+        (names, ips)
+    }
+
+    pub fn broadcast(&self, server_state: &ServerState, text: &str) -> Result<(), std::io::Error> {
+        let msg = messages::server::MsgServerNotice {
+            msg: text,
+        };
+        server_state.send_all(messages::server::Message::OnServerNotice as u32, &msg)
+    }
+
+    //a message posted by an integration, shown in `room` as coming from `user`; integrations are
+    //not registered, so the name only has to be free of connected users; not posted to outbound
+    //webhooks, which would echo it back to where it came from
+    pub fn inject(&self, server_state: &ServerState, room: Option<&str>, user: Option<&str>, text: &str) -> Result<(), (ErrorCode, String)> {
+        let (default_user, policy, max_message_len) = {
+            let config = self.config.read().expect("Failed to lock mutex");
+            (config.webhooks.user.clone(), config.names.clone(), config.limits.max_message_len)
+        };
+        let room = match room {
+            None | Some("") => String::from(LOBBY),
+            Some(room) => client_info::normalize_room(room).map_err(|e| (ErrorCode::InvalidArgument, e))?,
+        };
+        let user = names::normalize(user.unwrap_or(&default_user));
+        if let Err(e) = names::check(&policy, &user) {
+            return Err((ErrorCode::InvalidArgument, format!("{} cannot be used: {}", user, e)));
+        }
+        if text.trim().is_empty() {
+            return Err((ErrorCode::InvalidArgument, String::from("text must not be empty")));
+        }
+        if text.len() > max_message_len {
+            return Err((ErrorCode::TooLarge, format!("Messages are limited to {} bytes", max_message_len)));
+        }
+        let key = names::key(&user);
+        //the same names registration refuses, whether or not their owner is online
+        if self.bans.lock().expect("Failed to lock mutex").names.contains(&key) {
+            return Err((ErrorCode::Forbidden, format!("{} is banned", user)));
+        }
+        if self.accounts.lock().expect("Failed to lock mutex").is_reserved(&key) {
+            return Err((ErrorCode::Forbidden, format!("{} is reserved, integrations cannot post as it", user)));
+        }
+        if let Some(other) = self.clients.lock().expect("Failed to lock mutex").values().find(|c| c.key == key) {
+            return Err((ErrorCode::Conflict, format!("{} is connected, integrations cannot post as users", other.name)));
+        }
+
+        let msg = messages::server::MsgOnSent {
+            user: user.as_str(),
+            msg: text,
+        };
+        self.send_room(server_state, &room, messages::server::Message::OnSent as u32, &msg, None);
+        self.push_history(&room, &user, text);
+        let sent = messages::link::MsgSent {
+            server: &self.server_name(),
+            user: user.as_str(),
+            room: room.as_str(),
+            msg: text,
+        };
+        self.send_links(server_state, messages::link::Message::OnSent as u32, &sent, None);
+        self.logger.log(Level::Debug, module_path!(), "Webhook message posted", &[("user", &user), ("room", &room), ("text", &text)]);
+        Ok(())
     }
 
     fn allow_connect(&self, server_state: &ServerState, stream: &ClientStream) -> bool {
+        if self.bans.lock().expect("Failed to lock mutex").ips.contains(&stream.addr.ip()) {
+            self.logger.log(Level::Info, module_path!(), "Client rejected, address is banned", &[("addr", &stream.addr)]);
+            return false;
+        }
+
+        let connected = server_state.clients_stream.read().expect("Failed to lock mutex").len();
+        if connected >= self.config.read().expect("Failed to lock mutex").limits.max_clients {
+            self.logger.log(Level::Warn, module_path!(), "Client rejected, server is full", &[("addr", &stream.addr), ("clients", &connected)]);
+            return false;
+        }
         true
     }
     
-    fn on_connect(&self, server_state: &ServerState, stream: &ClientStream) {
-        println!("Client {} connected to the server", stream.addr);
+    fn on_connect(&self, _server_state: &ServerState, stream: &ClientStream) {
+        self.logger.log(Level::Info, module_path!(), "Client connected", &[("addr", &stream.addr), ("transport", &stream.transport)]);
     }
     
     fn on_disconnect(&self, server_state: &ServerState, stream: &ClientStream) {
+        if stream.transport == Transport::Link {
+            self.link_lost(server_state, stream.addr);
+            return;
+        }
         let cdata = match self.clients.lock().expect("Failed to lock mutex").remove(&stream.addr) {
             Some(data) => data,
             None => {
-                println!("Error: client {} not currently in registered clients", stream.addr);
+                self.logger.log(Level::Info, module_path!(), "Client disconnected before registering", &[("addr", &stream.addr)]);
                 return;
             }
         };
-        println!("Client name: {}, addr: {} disconnected from the server", cdata.name, stream.addr.to_string());
+        self.logger.log(Level::Info, module_path!(), "Client disconnected", &[("addr", &stream.addr), ("user", &cdata.name)]);
     
         let msg = messages::server::MsgOnDisconnect {
             user: cdata.name.as_str(),
         };
-        let msg_encoded = message_stream::serialize_data(messages::server::Message::OnDisconnect as u32, &msg).expect("Failed to serialze message");
-        server_state.send_all_except_s(msg_encoded.as_slice(), stream.addr).expect("failed to send message");
+        if let Err(e) = server_state.send_all_except_s(messages::server::Message::OnDisconnect as u32, &msg, stream.addr) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to send to every client", &[("error", &e)]);
+        }
+        let quit = messages::link::MsgQuit {
+            server: &self.server_name(),
+            user: cdata.name.as_str(),
+        };
+        self.send_links(server_state, messages::link::Message::OnQuit as u32, &quit, None);
+        self.webhooks.post("disconnect", serde_json::json!({ "user": cdata.name, "room": cdata.room }));
+
+        let cancelled = self.transfers.lock().expect("Failed to lock mutex").remove_client(stream.addr);
+        for (id, transfer) in cancelled {
+            let other = if transfer.from == stream.addr { transfer.to } else { transfer.from };
+            self.file_cancelled(server_state, other, id, &cdata.name, "disconnected");
+        }
+    }
+
+    fn file_cancelled(&self, server_state: &ServerState, to: SocketAddr, id: u32, user: &str, reason: &str) {
+        let msg = messages::server::MsgFileCancelled {
+            id,
+            user,
+            reason,
+        };
+        if let Err(e) = server_state.send_to(messages::server::Message::OnFileCancelled as u32, &msg, &[to]) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to cancel transfer", &[("id", &id), ("error", &e)]);
+        }
+    }
+
+    fn replay_history(&self, server_state: &ServerState, stream: &ClientStream, room: &str) {
+        if !self.config.read().expect("Failed to lock mutex").history.enabled {
+            return;
+        }
+
+        let history: Vec<(String, String)> = match self.history.lock().expect("Failed to lock mutex").get(room) {
+            Some(history) => history.iter().cloned().collect(),
+            None => return,
+        };
+        for (user, msg) in history.iter() {
+            let msg = messages::server::MsgOnSent {
+                user: user.as_str(),
+                msg: msg.as_str(),
+            };
+            self.send(server_state, stream, messages::server::Message::OnSent as u32, 0, &msg);
+        }
+    }
+
+    fn push_history(&self, room: &str, user: &str, msg: &str) {
+        let config = self.config.read().expect("Failed to lock mutex").history.clone();
+        if !config.enabled {
+            return;
+        }
+        let size = config.size;
+
+        let mut lock_guard = self.history.lock().expect("Failed to lock mutex");
+        let history = lock_guard.entry(room.to_string()).or_default();
+        history.push_back((user.to_string(), msg.to_string()));
+        while history.len() > size {
+            history.pop_front();
+        }
     }
     
-    fn on_read(&self, server_state: &ServerState, stream: &ClientStream, msginfo: &MsgInfo) {
+    //passes a link message on to every linked server but `except`, the one it came from
+    fn send_links<T: ?Sized + serde::Serialize>(&self, server_state: &ServerState, code: u32, data: &T, except: Option<SocketAddr>) {
+        let links = self.links.lock().expect("Failed to lock mutex").links(except);
+        if links.is_empty() {
+            return;
+        }
+        if let Err(e) = server_state.send_to(code, data, &links) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to send to linked servers", &[("error", &e)]);
+        }
+    }
+
+    fn link_hello_msg<'a>(&self, name: &'a str, password: &'a str, servers: &'a [String]) -> messages::link::MsgHello<'a> {
+        messages::link::MsgHello {
+            name,
+            password,
+            servers: servers.iter().map(|server| server.as_str()).collect(),
+        }
+    }
+
+    //dials every configured peer without a link, the handshake goes on in on_read; called over and
+    //over, so a link that went down comes back once the peer is reachable again
+    pub fn connect_links(&self, server_state: &ServerState) {
+        let config = self.config.read().expect("Failed to lock mutex").link.clone();
+        for peer in config.peers {
+            if !self.links.lock().expect("Failed to lock mutex").dial(peer) {
+                continue;
+            }
+            match server_state.connect_link(peer, LINK_TIMEOUT) {
+                Ok(true) => {},
+                Ok(false) => {
+                    self.links.lock().expect("Failed to lock mutex").undial(peer);
+                    self.logger.log(Level::Warn, module_path!(), "Link refused by this server", &[("peer", &peer)]);
+                    continue;
+                },
+                Err(e) => {
+                    self.links.lock().expect("Failed to lock mutex").undial(peer);
+                    self.logger.log(Level::Warn, module_path!(), "Failed to link", &[("peer", &peer), ("error", &e)]);
+                    continue;
+                },
+            }
+
+            let client = server_state.clients_stream.read().expect("Failed to lock mutex").get(&peer).cloned();
+            let client = match client {
+                Some(client) => client,
+                None => continue,
+            };
+            let servers: Vec<String> = self.links.lock().expect("Failed to lock mutex").servers().into_iter().map(|(server, _)| server).collect();
+            let hello = self.link_hello_msg(&config.name, &config.password, &servers);
+            self.logger.log(Level::Info, module_path!(), "Linking", &[("peer", &peer)]);
+            if let Err(e) = server_state.send(client.stream.as_ref(), messages::link::Message::OnHello as u32, 0, &hello) {
+                self.logger.log(Level::Warn, module_path!(), "Failed to say hello", &[("peer", &peer), ("error", &e)]);
+            }
+        }
+    }
+
+    fn decode_link<'a, T: serde::Deserialize<'a>>(&self, stream: &ClientStream, msginfo: &'a MsgInfo) -> Option<T> {
+        match msginfo.decode_data::<T>() {
+            Ok(data) => Some(data),
+            Err(e) => {
+                self.logger.log(Level::Warn, module_path!(), "Failed to decode link message", &[("addr", &stream.addr), ("code", &msginfo.code), ("error", &e)]);
+                None
+            },
+        }
+    }
+
+    //events are only taken from the link towards the server they started on
+    fn routed(&self, stream: &ClientStream, server: &str) -> bool {
+        if self.links.lock().expect("Failed to lock mutex").routes(stream.addr, server) {
+            return true;
+        }
+        self.logger.log(Level::Warn, module_path!(), "Dropped event that did not come from its direction", &[("addr", &stream.addr), ("server", &server)]);
+        false
+    }
+
+    fn link_hello(&self, server_state: &ServerState, stream: &ClientStream, msginfo: &MsgInfo) {
+        let msg = match self.decode_link::<messages::link::MsgHello>(stream, msginfo) {
+            Some(data) => data,
+            None => return,
+        };
+        let config = self.config.read().expect("Failed to lock mutex").link.clone();
+
+        let mut lock_guard = self.links.lock().expect("Failed to lock mutex");
+        let checked = match lock_guard.peer(&stream.addr) {
+            Some(peer) => Err(format!("already linked with {}", peer)),
+            None if config.password.is_empty() || !secret::constant_time_eq(msg.password.as_bytes(), config.password.as_bytes()) => Err(String::from("wrong password")),
+            None => lock_guard.check(&config.name, msg.name, &msg.servers),
+        };
+        let dialed = lock_guard.is_dialed(&stream.addr);
+        if let Err(reason) = checked {
+            drop(lock_guard);
+            self.logger.log(Level::Warn, module_path!(), "Refused link", &[("addr", &stream.addr), ("server", &msg.name), ("reason", &reason)]);
+            let error = messages::link::MsgError {
+                reason: &reason,
+            };
+            if let Err(e) = server_state.send(stream, messages::link::Message::OnError as u32, 0, &error) {
+                self.logger.log(Level::Warn, module_path!(), "Failed to refuse link", &[("addr", &stream.addr), ("error", &e)]);
+            }
+            //whoever dialed hangs up, so the other side gets to read why
+            if dialed {
+                if let Err(e) = server_state.disconnect_client(stream.addr) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to close link", &[("addr", &stream.addr), ("error", &e)]);
+                }
+            }
+            return;
+        }
+        let known: Vec<String> = lock_guard.servers().into_iter().map(|(server, _)| server).collect();
+        lock_guard.add_link(stream.addr, msg.name, &msg.servers);
+        let users = lock_guard.users(Some(stream.addr));
+        drop(lock_guard);
+        self.logger.log(Level::Info, module_path!(), "Linked", &[("addr", &stream.addr), ("server", &msg.name), ("behind", &msg.servers.len())]);
+
+        if !dialed {
+            let hello = self.link_hello_msg(&config.name, &config.password, &known);
+            self.send(server_state, stream, messages::link::Message::OnHello as u32, 0, &hello);
+        }
+        //our hello went out when the link was dialed, servers that joined since would be missing
+        //on the other side and their users dropped there
+        else if !known.is_empty() {
+            let servers = messages::link::MsgServers {
+                servers: known.iter().map(|server| server.as_str()).collect(),
+            };
+            self.send(server_state, stream, messages::link::Message::OnServers as u32, 0, &servers);
+        }
+
+        //everyone on this side of the link, the other side does the same
+        let locals: Vec<client_info::ClientInfo> = self.clients.lock().expect("Failed to lock mutex").values().cloned().collect();
+        for cdata in locals.iter() {
+            let user = messages::link::MsgUser {
+                server: &config.name,
+                user: cdata.name.as_str(),
+                room: cdata.room.as_str(),
+                away: cdata.away.as_deref(),
+            };
+            self.send(server_state, stream, messages::link::Message::OnUser as u32, 0, &user);
+        }
+        for remote in users.iter() {
+            let user = messages::link::MsgUser {
+                server: remote.server.as_str(),
+                user: remote.name.as_str(),
+                room: remote.room.as_str(),
+                away: remote.away.as_deref(),
+            };
+            self.send(server_state, stream, messages::link::Message::OnUser as u32, 0, &user);
+        }
+
+        let mut joined = vec![msg.name];
+        joined.extend(msg.servers.iter());
+        let servers = messages::link::MsgServers {
+            servers: joined,
+        };
+        self.send_links(server_state, messages::link::Message::OnServers as u32, &servers, Some(stream.addr));
+        self.network_notice(server_state, &format!("{} joined the network", servers.servers.join(", ")));
+    }
+
+    //announces `servers` and `users` on them to the other end of `stream` once more
+    fn reintroduce(&self, server_state: &ServerState, stream: &ClientStream, servers: &[String], users: &[RemoteUser]) {
+        let announced = messages::link::MsgServers {
+            servers: servers.iter().map(|server| server.as_str()).collect(),
+        };
+        self.send(server_state, stream, messages::link::Message::OnServers as u32, 0, &announced);
+        for remote in users.iter() {
+            let user = messages::link::MsgUser {
+                server: remote.server.as_str(),
+                user: remote.name.as_str(),
+                room: remote.room.as_str(),
+                away: remote.away.as_deref(),
+            };
+            self.send(server_state, stream, messages::link::Message::OnUser as u32, 0, &user);
+        }
+    }
+
+    //the link went down, so did everything behind it
+    fn link_lost(&self, server_state: &ServerState, addr: SocketAddr) {
+        let lost = self.links.lock().expect("Failed to lock mutex").remove_link(addr);
+        let (peer, servers, users) = match lost {
+            Some(lost) => lost,
+            None => {
+                self.logger.log(Level::Info, module_path!(), "Link closed before saying hello", &[("addr", &addr)]);
+                return;
+            },
+        };
+        self.logger.log(Level::Warn, module_path!(), "Link lost", &[("addr", &addr), ("server", &peer), ("users", &users.len())]);
+
+        let reason = format!("{} lost its link with {}", self.server_name(), peer);
+        self.split(server_state, &servers, users, &reason);
+        let squit = messages::link::MsgSquit {
+            servers: servers.iter().map(|server| server.as_str()).collect(),
+            reason: &reason,
+        };
+        self.send_links(server_state, messages::link::Message::OnSquit as u32, &squit, None);
+    }
+
+    //tells local users that `servers` and everyone on them are gone
+    fn split(&self, server_state: &ServerState, servers: &[String], users: Vec<RemoteUser>, reason: &str) {
+        if servers.is_empty() {
+            return;
+        }
+        for user in users.iter() {
+            let msg = messages::server::MsgOnDisconnect {
+                user: &user.qualified(),
+            };
+            if let Err(e) = server_state.send_all(messages::server::Message::OnDisconnect as u32, &msg) {
+                self.logger.log(Level::Warn, module_path!(), "Failed to announce split", &[("user", &msg.user), ("error", &e)]);
+            }
+        }
+        self.network_notice(server_state, &format!("Netsplit, {} left the network: {}", servers.join(", "), reason));
+    }
+
+    fn network_notice(&self, server_state: &ServerState, text: &str) {
+        self.logger.log(Level::Info, module_path!(), text, &[]);
+        if let Err(e) = self.broadcast(server_state, text) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to send notice", &[("error", &e)]);
+        }
+    }
+
+    //a private message to name@server, false if there is no such user
+    fn private_remote(&self, server_state: &ServerState, stream: &ClientStream, request_id: u32, from: &str, to: &str, text: &str) -> bool {
+        let lock_guard = self.links.lock().expect("Failed to lock mutex");
+        let (user, route) = match lock_guard.find(to).and_then(|user| lock_guard.route(&user.server).map(|route| (user.clone(), route))) {
+            Some(target) => target,
+            None => return false,
+        };
+        drop(lock_guard);
+
+        let private = messages::link::MsgPrivateMsg {
+            server: &self.server_name(),
+            from,
+            to_server: user.server.as_str(),
+            to: user.name.as_str(),
+            msg: text,
+        };
+        if let Err(e) = server_state.send_to(messages::link::Message::OnPrivateMsg as u32, &private, &[route]) {
+            self.logger.log(Level::Warn, module_path!(), "Failed to send private message", &[("to", &user.qualified()), ("error", &e)]);
+        }
+        if let Some(away) = user.away.as_deref() {
+            let away = messages::server::MsgAway {
+                user: &user.qualified(),
+                msg: away,
+            };
+            self.send(server_state, stream, messages::server::Message::OnAway as u32, 0, &away);
+        }
+        self.ack(server_state, stream, request_id);
+        true
+    }
+
+    fn on_link_read(&self, server_state: &ServerState, stream: &ClientStream, msginfo: &MsgInfo) {
+        match msginfo.code {
+            code if code == messages::link::Message::OnHello as u32 => {
+                self.link_hello(server_state, stream, msginfo);
+                return;
+            },
+            code if code == messages::link::Message::OnError as u32 => {
+                if let Some(msg) = self.decode_link::<messages::link::MsgError>(stream, msginfo) {
+                    self.logger.log(Level::Warn, module_path!(), "Link refused", &[("addr", &stream.addr), ("reason", &msg.reason)]);
+                }
+                if let Err(e) = server_state.disconnect_client(stream.addr) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to close link", &[("addr", &stream.addr), ("error", &e)]);
+                }
+                return;
+            },
+            _ => {},
+        }
+        if self.links.lock().expect("Failed to lock mutex").peer(&stream.addr).is_none() {
+            self.logger.log(Level::Warn, module_path!(), "Link sent a message before saying hello, closing it", &[("addr", &stream.addr), ("code", &msginfo.code)]);
+            if let Err(e) = server_state.disconnect_client(stream.addr) {
+                self.logger.log(Level::Warn, module_path!(), "Failed to close link", &[("addr", &stream.addr), ("error", &e)]);
+            }
+            return;
+        }
+
         match msginfo.code {
-            x if msginfo.code == messages::client::Message::OnRegisterUser as u32 => {
-                let msg = match msginfo.decode_data::<messages::client::MsgOnRegisterUser>() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("{}", e);
+            code if code == messages::link::Message::OnServers as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgServers>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let added = self.links.lock().expect("Failed to lock mutex").add_servers(stream.addr, &self.server_name(), &msg.servers);
+                if added.len() < msg.servers.len() {
+                    self.logger.log(Level::Warn, module_path!(), "Ignored servers that are already on the network", &[("addr", &stream.addr)]);
+                }
+                if added.is_empty() {
+                    return;
+                }
+                let servers = messages::link::MsgServers {
+                    servers: added.iter().map(|server| server.as_str()).collect(),
+                };
+                self.send_links(server_state, messages::link::Message::OnServers as u32, &servers, Some(stream.addr));
+                self.network_notice(server_state, &format!("{} joined the network", added.join(", ")));
+            },
+            code if code == messages::link::Message::OnSquit as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgSquit>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let mut lock_guard = self.links.lock().expect("Failed to lock mutex");
+                let (servers, users) = lock_guard.remove_servers(stream.addr, &msg.servers);
+                //reached through another link here, the sender lost them to a link that was refused
+                //for closing a loop after its side had taken it, and needs to hear of them again
+                let kept: Vec<String> = msg.servers.iter().filter(|server| lock_guard.route(server).is_some()).map(|server| server.to_string()).collect();
+                let kept_users: Vec<RemoteUser> = lock_guard.users(None).into_iter().filter(|user| kept.contains(&user.server)).collect();
+                drop(lock_guard);
+                if !kept.is_empty() {
+                    self.reintroduce(server_state, stream, &kept, &kept_users);
+                }
+                if servers.is_empty() {
+                    return;
+                }
+                self.split(server_state, &servers, users, msg.reason);
+                let squit = messages::link::MsgSquit {
+                    servers: servers.iter().map(|server| server.as_str()).collect(),
+                    reason: msg.reason,
+                };
+                self.send_links(server_state, messages::link::Message::OnSquit as u32, &squit, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnUser as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgUser>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                let user = RemoteUser {
+                    name: msg.user.to_string(),
+                    server: msg.server.to_string(),
+                    room: msg.room.to_string(),
+                    away: msg.away.map(|away| away.to_string()),
+                };
+                let qualified = user.qualified();
+                self.links.lock().expect("Failed to lock mutex").add_user(user);
+
+                let registered = messages::server::MsgOnRegisterUser {
+                    user: qualified.as_str(),
+                };
+                if let Err(e) = server_state.send_all(messages::server::Message::OnRegisterUser as u32, &registered) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to send to every client", &[("error", &e)]);
+                }
+                if msg.room != LOBBY {
+                    let joined = messages::server::MsgJoined {
+                        user: qualified.as_str(),
+                        room: msg.room,
+                    };
+                    self.send_room(server_state, msg.room, messages::server::Message::OnJoined as u32, &joined, None);
+                }
+                self.send_links(server_state, messages::link::Message::OnUser as u32, &msg, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnQuit as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgQuit>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                let user = match self.links.lock().expect("Failed to lock mutex").remove_user(msg.server, msg.user) {
+                    Some(user) => user,
+                    None => return,
+                };
+                let quit = messages::server::MsgOnDisconnect {
+                    user: &user.qualified(),
+                };
+                if let Err(e) = server_state.send_all(messages::server::Message::OnDisconnect as u32, &quit) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to send to every client", &[("error", &e)]);
+                }
+                self.send_links(server_state, messages::link::Message::OnQuit as u32, &msg, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnRename as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgRename>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                if !self.links.lock().expect("Failed to lock mutex").rename_user(msg.server, msg.old, msg.new) {
+                    return;
+                }
+                let old = links::qualify(msg.old, msg.server);
+                let new = links::qualify(msg.new, msg.server);
+                let renamed = messages::server::MsgRename {
+                    old: old.as_str(),
+                    new: new.as_str(),
+                };
+                if let Err(e) = server_state.send_all(messages::server::Message::OnRename as u32, &renamed) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to send to every client", &[("error", &e)]);
+                }
+                self.send_links(server_state, messages::link::Message::OnRename as u32, &msg, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnMove as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgMove>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                let old_room = match self.links.lock().expect("Failed to lock mutex").user_mut(msg.server, msg.user) {
+                    Some(user) => std::mem::replace(&mut user.room, msg.room.to_string()),
+                    None => return,
+                };
+                let qualified = links::qualify(msg.user, msg.server);
+                let parted = messages::server::MsgParted {
+                    user: qualified.as_str(),
+                    room: old_room.as_str(),
+                };
+                self.send_room(server_state, &old_room, messages::server::Message::OnParted as u32, &parted, None);
+                let joined = messages::server::MsgJoined {
+                    user: qualified.as_str(),
+                    room: msg.room,
+                };
+                self.send_room(server_state, msg.room, messages::server::Message::OnJoined as u32, &joined, None);
+                self.send_links(server_state, messages::link::Message::OnMove as u32, &msg, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnAway as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgAway>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                match self.links.lock().expect("Failed to lock mutex").user_mut(msg.server, msg.user) {
+                    Some(user) => user.away = msg.msg.map(|away| away.to_string()),
+                    None => return,
+                }
+                self.send_links(server_state, messages::link::Message::OnAway as u32, &msg, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnSent as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgSent>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                let qualified = links::qualify(msg.user, msg.server);
+                let sent = messages::server::MsgOnSent {
+                    user: qualified.as_str(),
+                    msg: msg.msg,
+                };
+                self.send_room(server_state, msg.room, messages::server::Message::OnSent as u32, &sent, None);
+                self.push_history(msg.room, &qualified, msg.msg);
+                self.send_links(server_state, messages::link::Message::OnSent as u32, &msg, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnAction as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgAction>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                let qualified = links::qualify(msg.user, msg.server);
+                let action = messages::server::MsgAction {
+                    user: qualified.as_str(),
+                    msg: msg.msg,
+                };
+                self.send_room(server_state, msg.room, messages::server::Message::OnAction as u32, &action, None);
+                self.send_links(server_state, messages::link::Message::OnAction as u32, &msg, Some(stream.addr));
+            },
+            code if code == messages::link::Message::OnPrivateMsg as u32 => {
+                let msg = match self.decode_link::<messages::link::MsgPrivateMsg>(stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if !self.routed(stream, msg.server) {
+                    return;
+                }
+                //not for this server, passed on towards the one it is for
+                if msg.to_server != self.server_name() {
+                    match self.links.lock().expect("Failed to lock mutex").route(msg.to_server) {
+                        Some(route) if route != stream.addr => {
+                            if let Err(e) = server_state.send_to(messages::link::Message::OnPrivateMsg as u32, &msg, &[route]) {
+                                self.logger.log(Level::Warn, module_path!(), "Failed to pass on private message", &[("server", &msg.to_server), ("error", &e)]);
+                            }
+                        },
+                        _ => self.logger.log(Level::Info, module_path!(), "Dropped private message for an unreachable server", &[("server", &msg.to_server)]),
+                    }
+                    return;
+                }
+                let to = match self.find(msg.to) {
+                    Some(to) => to,
+                    None => {
+                        self.logger.log(Level::Info, module_path!(), "Dropped private message for a user who left", &[("server", &msg.server), ("to", &msg.to)]);
                         return;
                     },
                 };
+                let from = links::qualify(msg.from, msg.server);
+                let private = messages::server::MsgPrivateMsg {
+                    from: from.as_str(),
+                    msg: msg.msg,
+                };
+                if let Err(e) = server_state.send_to(messages::server::Message::OnPrivateMsg as u32, &private, &[to]) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to send private message", &[("to", &msg.to), ("error", &e)]);
+                }
+            },
+            code => {
+                self.logger.log(Level::Warn, module_path!(), "Unknown link message", &[("addr", &stream.addr), ("code", &code)]);
+            },
+        }
+    }
+
+    fn on_read(&self, server_state: &ServerState, stream: &ClientStream, msginfo: &MsgInfo) {
+        if stream.transport == Transport::Link {
+            self.on_link_read(server_state, stream, msginfo);
+            return;
+        }
+        let request_id = msginfo.request_id;
+        match msginfo.code {
+            code if code == messages::client::Message::OnRegisterUser as u32 => {
+                let msg = match self.decode::<messages::client::MsgOnRegisterUser>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                //a second registration would replace the user behind everyone's back, renames go through rename
+                if self.registered(&stream.addr).is_some() {
+                    self.error(server_state, stream, request_id, ErrorCode::Conflict, "You are already registered, use /nick to change your name");
+                    return;
+                }
+
+                let name = names::normalize(msg.user);
+                let policy = self.config.read().expect("Failed to lock mutex").names.clone();
+                if let Err(e) = names::check(&policy, &name) {
+                    self.reject_name(server_state, stream, request_id, &name, &e);
+                    return;
+                }
+                let key = names::key(&name);
     
-                let mut lock_guard = self.clients.lock().expect("Failed to lock mutex");
-                if lock_guard.iter().any(|(k, v)| v.name == msg.user) {
-                    drop(lock_guard);
-    
-                    println!("{} is already registered!", msg.user);
-    
+                if self.bans.lock().expect("Failed to lock mutex").names.contains(&key) {
+                    self.logger.log(Level::Info, module_path!(), "Name is banned, closing client", &[("addr", &stream.addr), ("name", &name)]);
+                    if let Err(e) = self.kick_addr(server_state, stream.addr, "This name is banned") {
+                        self.logger.log(Level::Warn, module_path!(), "Failed to disconnect", &[("addr", &stream.addr), ("error", &e)]);
+                    }
+                    return;
+                }
+
+                //held until the name is taken so a reservation cannot slip in between
+                let accounts = self.accounts.lock().expect("Failed to lock mutex");
+                if accounts.is_reserved(&key) {
+                    drop(accounts);
+
+                    self.logger.log(Level::Info, module_path!(), "Name is reserved, registration rejected", &[("addr", &stream.addr), ("name", &name)]);
+                    self.notice(server_state, stream, 0, &format!("{} is reserved, register under another name and use /nick {} <password>", name, name));
+
                     let msg = messages::server::MsgAlreadyRegisteredUser {
-                        user: msg.user
+                        user: name.as_str()
                     };
-                    let msg_encoded = message_stream::serialize_data(messages::server::Message::OnAlreadyRegisteredUser as u32, &msg).expect("Failed to serialze message");
-                    server_state.send(&stream, msg_encoded.as_slice()).expect("failed to send message");
+                    self.send(server_state, stream, messages::server::Message::OnAlreadyRegisteredUser as u32, request_id, &msg);
+                    return;
                 }
-                else {
-                    lock_guard.insert(stream.addr, client_info::ClientInfo::new(msg.user.to_string()));
-                    drop(lock_guard);
+
+                let mut lock_guard = self.clients.lock().expect("Failed to lock mutex");
+                match lock_guard.values().find(|v| v.key == key).map(|v| v.name.clone()) {
+                    Some(existing) if existing == name => {
+                        drop(lock_guard);
+                        drop(accounts);
     
-                    println!("{} has registered!", msg.user);
+                        self.logger.log(Level::Info, module_path!(), "Name is already registered", &[("addr", &stream.addr), ("name", &name)]);
     
-                    let msg = messages::server::MsgRegistrationSuccess {
-                        user: msg.user
-                    };
-                    let msg_encoded = message_stream::serialize_data(messages::server::Message::OnRegistrationSuccess as u32, &msg).expect("Failed to serialze message");
-                    server_state.send(&stream, msg_encoded.as_slice()).expect("failed to send message");
+                        let msg = messages::server::MsgAlreadyRegisteredUser {
+                            user: name.as_str()
+                        };
+                        self.send(server_state, stream, messages::server::Message::OnAlreadyRegisteredUser as u32, request_id, &msg);
+                    },
+                    Some(existing) => {
+                        drop(lock_guard);
+                        drop(accounts);
+                        self.reject_name(server_state, stream, request_id, &name, &names::NameError::Confusable(existing));
+                    },
+                    None => {
+                        lock_guard.insert(stream.addr, client_info::ClientInfo::new(name.clone()));
+                        drop(lock_guard);
+                        drop(accounts);
+    
+                        self.logger.log(Level::Info, module_path!(), "User registered", &[("addr", &stream.addr), ("user", &name)]);
+    
+                        let msg = messages::server::MsgRegistrationSuccess {
+                            user: name.as_str()
+                        };
+                        self.send(server_state, stream, messages::server::Message::OnRegistrationSuccess as u32, request_id, &msg);
+
+                        let names = self.names();
+                        let users = messages::server::MsgUserList {
+                            users: names.iter().map(|name| name.as_str()).collect(),
+                        };
+                        self.send(server_state, stream, messages::server::Message::OnUserList as u32, 0, &users);
+
+                        let joined = messages::server::MsgOnRegisterUser {
+                            user: name.as_str()
+                        };
+                        if let Err(e) = server_state.send_all_except_s(messages::server::Message::OnRegisterUser as u32, &joined, stream.addr) {
+                            self.logger.log(Level::Warn, module_path!(), "Failed to send to every client", &[("error", &e)]);
+                        }
+                        let user = messages::link::MsgUser {
+                            server: &self.server_name(),
+                            user: name.as_str(),
+                            room: LOBBY,
+                            away: None,
+                        };
+                        self.send_links(server_state, messages::link::Message::OnUser as u32, &user, None);
+                        self.webhooks.post("connect", serde_json::json!({ "user": name, "room": LOBBY }));
+
+                        self.replay_history(server_state, stream, LOBBY);
+                    },
                 }
             },
-            x if msginfo.code == messages::client::Message::OnSent as u32 => {
-                let msg = match msginfo.decode_data::<messages::client::MsgOnSent>() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("{}", e);
-                        return;
-                    },
+            code if code == messages::client::Message::OnSent as u32 => {
+                let msg = match self.decode::<messages::client::MsgOnSent>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
                 };
-    
-                let cdata = match self.clients.lock().expect("Failed to lock mutex").get(&stream.addr) {
-                    Some(data) => data.clone(),
+
+                if self.message_too_long(server_state, stream, request_id, msg.msg) {
+                    return;
+                }
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
                     None => return,
                 };
     
@@ -99,13 +1160,491 @@ impl ServerImpl {
                     user: cdata.name.as_str(),
                     msg: msg.msg,
                 };
-                let msg_encoded: Vec<u8> = message_stream::serialize_data(messages::server::Message::OnSent as u32, &msg).expect("Failed to serialze message");
-                server_state.send_all_except_s(msg_encoded.as_slice(), stream.addr).expect("failed to send message");
+                self.send_room(server_state, &cdata.room, messages::server::Message::OnSent as u32, &msg, Some(stream.addr));
+                self.push_history(&cdata.room, msg.user, msg.msg);
+                let sent = messages::link::MsgSent {
+                    server: &self.server_name(),
+                    user: msg.user,
+                    room: cdata.room.as_str(),
+                    msg: msg.msg,
+                };
+                self.send_links(server_state, messages::link::Message::OnSent as u32, &sent, None);
     
-                println!("{} sent \"{}\"", msg.user, msg.msg);
+                self.logger.log(Level::Debug, module_path!(), "Message sent", &[("user", &msg.user), ("room", &cdata.room), ("text", &msg.msg)]);
+                self.webhooks.post("message", serde_json::json!({ "user": msg.user, "room": cdata.room, "text": msg.msg }));
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnJoin as u32 => {
+                let msg = match self.decode::<messages::client::MsgJoin>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                match client_info::normalize_room(msg.room) {
+                    Ok(room) if room == cdata.room => self.error(server_state, stream, request_id, ErrorCode::Conflict, &format!("You are already in #{}", room)),
+                    Ok(room) => {
+                        self.move_to_room(server_state, stream, &room);
+                        self.ack(server_state, stream, request_id);
+                    },
+                    Err(e) => self.error(server_state, stream, request_id, ErrorCode::InvalidArgument, &e),
+                }
+            },
+            code if code == messages::client::Message::OnPart as u32 => {
+                let msg = match self.decode::<messages::client::MsgPart>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let room = match msg.room {
+                    "" => Ok(cdata.room.clone()),
+                    room => client_info::normalize_room(room),
+                };
+                match room {
+                    Ok(room) if room != cdata.room => self.error(server_state, stream, request_id, ErrorCode::Conflict, &format!("You are not in #{}", room)),
+                    Ok(room) if room == LOBBY => self.error(server_state, stream, request_id, ErrorCode::Forbidden, "The lobby cannot be left, use /quit to disconnect"),
+                    Ok(_) => {
+                        self.move_to_room(server_state, stream, LOBBY);
+                        self.ack(server_state, stream, request_id);
+                    },
+                    Err(e) => self.error(server_state, stream, request_id, ErrorCode::InvalidArgument, &e),
+                }
+            },
+            code if code == messages::client::Message::OnPrivateMsg as u32 => {
+                let msg = match self.decode::<messages::client::MsgPrivateMsg>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if self.message_too_long(server_state, stream, request_id, msg.msg) {
+                    return;
+                }
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let (to_addr, to) = match self.find(msg.to).and_then(|addr| self.registered(&addr).map(|c| (addr, c))) {
+                    Some(target) => target,
+                    None => {
+                        if !self.private_remote(server_state, stream, request_id, &cdata.name, msg.to, msg.msg) {
+                            self.error(server_state, stream, request_id, ErrorCode::NotFound, &format!("No user named {}", msg.to));
+                        }
+                        return;
+                    },
+                };
+                let private = messages::server::MsgPrivateMsg {
+                    from: cdata.name.as_str(),
+                    msg: msg.msg,
+                };
+                if let Err(e) = server_state.send_to(messages::server::Message::OnPrivateMsg as u32, &private, &[to_addr]) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to send private message", &[("to", &to.name), ("error", &e)]);
+                }
+
+                if let Some(away) = to.away {
+                    let away = messages::server::MsgAway {
+                        user: to.name.as_str(),
+                        msg: away.as_str(),
+                    };
+                    self.send(server_state, stream, messages::server::Message::OnAway as u32, 0, &away);
+                }
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnAction as u32 => {
+                let msg = match self.decode::<messages::client::MsgAction>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if self.message_too_long(server_state, stream, request_id, msg.msg) {
+                    return;
+                }
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let action = messages::server::MsgAction {
+                    user: cdata.name.as_str(),
+                    msg: msg.msg,
+                };
+                self.send_room(server_state, &cdata.room, messages::server::Message::OnAction as u32, &action, Some(stream.addr));
+                let action = messages::link::MsgAction {
+                    server: &self.server_name(),
+                    user: action.user,
+                    room: cdata.room.as_str(),
+                    msg: action.msg,
+                };
+                self.send_links(server_state, messages::link::Message::OnAction as u32, &action, None);
+                self.webhooks.post("action", serde_json::json!({ "user": action.user, "room": action.room, "text": action.msg }));
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnWho as u32 => {
+                let msg = match self.decode::<messages::client::MsgWho>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let room = match msg.room {
+                    "" => cdata.room.clone(),
+                    room => match client_info::normalize_room(room) {
+                        Ok(room) => room,
+                        Err(e) => {
+                            self.error(server_state, stream, request_id, ErrorCode::InvalidArgument, &e);
+                            return;
+                        },
+                    },
+                };
+                let mut members: Vec<(String, Option<String>)> = self.clients.lock().expect("Failed to lock mutex").values()
+                    .filter(|c| c.room == room)
+                    .map(|c| (c.name.clone(), c.away.clone()))
+                    .collect();
+                members.extend(self.links.lock().expect("Failed to lock mutex").users(None).into_iter()
+                    .filter(|user| user.room == room)
+                    .map(|user| (user.qualified(), user.away)));
+                members.sort();
+
+                let who = messages::server::MsgWho {
+                    room: room.as_str(),
+                    users: members.iter().map(|(user, away)| messages::server::MsgWhoEntry { user: user.as_str(), away: away.as_deref() }).collect(),
+                };
+                self.send(server_state, stream, messages::server::Message::OnWho as u32, request_id, &who);
+            },
+            code if code == messages::client::Message::OnAway as u32 => {
+                let msg = match self.decode::<messages::client::MsgAway>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if self.sender(server_state, stream, request_id).is_none() {
+                    return;
+                }
+                if msg.msg.is_some_and(|away| self.message_too_long(server_state, stream, request_id, away)) {
+                    return;
+                }
+
+                let away = msg.msg.map(|away| away.to_string());
+                let name = match self.clients.lock().expect("Failed to lock mutex").get_mut(&stream.addr) {
+                    Some(cdata) => {
+                        cdata.away = away.clone();
+                        cdata.name.clone()
+                    },
+                    None => return,
+                };
+                let link_away = messages::link::MsgAway {
+                    server: &self.server_name(),
+                    user: name.as_str(),
+                    msg: msg.msg,
+                };
+                self.send_links(server_state, messages::link::Message::OnAway as u32, &link_away, None);
+                match away {
+                    Some(_) => self.notice(server_state, stream, request_id, "You are marked as away"),
+                    None => self.notice(server_state, stream, request_id, "You are no longer marked as away"),
+                }
+            },
+            code if code == messages::client::Message::OnNick as u32 => {
+                let msg = match self.decode::<messages::client::MsgNick>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if self.sender(server_state, stream, request_id).is_none() {
+                    return;
+                }
+                match self.rename(server_state, stream, msg.user, msg.password) {
+                    Ok(()) => self.ack(server_state, stream, request_id),
+                    Err((code, e)) => self.error(server_state, stream, request_id, code, &e),
+                }
+            },
+            code if code == messages::client::Message::OnReserve as u32 => {
+                let msg = match self.decode::<messages::client::MsgReserve>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if self.sender(server_state, stream, request_id).is_none() {
+                    return;
+                }
+                if msg.password.is_some_and(|password| self.message_too_long(server_state, stream, request_id, password)) {
+                    return;
+                }
+
+                //holding the name proves the password was given if it was already reserved
+                let mut accounts = self.accounts.lock().expect("Failed to lock mutex");
+                let (name, key) = match self.clients.lock().expect("Failed to lock mutex").get(&stream.addr) {
+                    Some(cdata) => (cdata.name.clone(), cdata.key.clone()),
+                    None => return,
+                };
+                let res = match msg.password {
+                    Some("") => Err((ErrorCode::InvalidArgument, String::from("The password must not be empty"))),
+                    Some(password) => match accounts.reserve(&key, password) {
+                        Ok(()) => {
+                            self.logger.log(Level::Info, module_path!(), "Name reserved", &[("user", &name)]);
+                            Ok(format!("{} is now reserved, use /nick {} <password> to claim it later", name, name))
+                        },
+                        Err(e) => {
+                            self.logger.log(Level::Error, module_path!(), "Failed to save reservation", &[("user", &name), ("error", &e)]);
+                            Err((ErrorCode::Internal, format!("Failed to reserve {}", name)))
+                        },
+                    },
+                    None => match accounts.release(&key) {
+                        Ok(true) => {
+                            self.logger.log(Level::Info, module_path!(), "Name released", &[("user", &name)]);
+                            Ok(format!("{} is no longer reserved", name))
+                        },
+                        Ok(false) => Err((ErrorCode::NotFound, format!("{} is not reserved", name))),
+                        Err(e) => {
+                            self.logger.log(Level::Error, module_path!(), "Failed to save release", &[("user", &name), ("error", &e)]);
+                            Err((ErrorCode::Internal, format!("Failed to release {}", name)))
+                        },
+                    },
+                };
+                drop(accounts);
+
+                match res {
+                    Ok(text) => self.notice(server_state, stream, request_id, &text),
+                    Err((code, e)) => self.error(server_state, stream, request_id, code, &e),
+                }
+            },
+            code if code == messages::client::Message::OnFileOffer as u32 => {
+                let msg = match self.decode::<messages::client::MsgFileOffer>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let (to_addr, to) = match self.find(msg.to).and_then(|addr| self.registered(&addr).map(|c| (addr, c))) {
+                    Some(target) => target,
+                    None if self.links.lock().expect("Failed to lock mutex").find(msg.to).is_some() => {
+                        self.error(server_state, stream, request_id, ErrorCode::Forbidden, "Files cannot be sent to users on other servers");
+                        return;
+                    },
+                    None => {
+                        self.error(server_state, stream, request_id, ErrorCode::NotFound, &format!("No user named {}", msg.to));
+                        return;
+                    },
+                };
+
+                let config = self.config.read().expect("Failed to lock mutex").files.clone();
+                let res = self.transfers.lock().expect("Failed to lock mutex").offer(&config, stream.addr, to_addr, msg.name, msg.size);
+                let id = match res {
+                    Ok(id) => id,
+                    Err((code, e)) => {
+                        self.error(server_state, stream, request_id, code, &e);
+                        return;
+                    },
+                };
+                self.logger.log(Level::Info, module_path!(), "File offered", &[("id", &id), ("from", &cdata.name), ("to", &to.name), ("file", &msg.name), ("size", &msg.size)]);
+
+                let offered = messages::server::MsgFileOffered {
+                    id,
+                    to: to.name.as_str(),
+                    name: msg.name,
+                };
+                self.send(server_state, stream, messages::server::Message::OnFileOffered as u32, request_id, &offered);
+
+                let offer = messages::server::MsgFileOffer {
+                    id,
+                    from: cdata.name.as_str(),
+                    name: msg.name,
+                    size: msg.size,
+                    sha256: msg.sha256,
+                };
+                if let Err(e) = server_state.send_to(messages::server::Message::OnFileOffer as u32, &offer, &[to_addr]) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to offer file", &[("to", &to.name), ("error", &e)]);
+                }
+            },
+            code if code == messages::client::Message::OnFileAccept as u32 => {
+                let msg = match self.decode::<messages::client::MsgFileAccept>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let res = self.transfers.lock().expect("Failed to lock mutex").accept(msg.id, stream.addr, msg.offset);
+                let from = match res {
+                    Ok(from) => from,
+                    Err((code, e)) => {
+                        self.error(server_state, stream, request_id, code, &e);
+                        return;
+                    },
+                };
+                let accepted = messages::server::MsgFileAccepted {
+                    id: msg.id,
+                    user: cdata.name.as_str(),
+                    offset: msg.offset,
+                };
+                if let Err(e) = server_state.send_to(messages::server::Message::OnFileAccepted as u32, &accepted, &[from]) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to start transfer", &[("id", &msg.id), ("error", &e)]);
+                }
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnFileDecline as u32 => {
+                let msg = match self.decode::<messages::client::MsgFileDecline>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let res = self.transfers.lock().expect("Failed to lock mutex").decline(msg.id, stream.addr);
+                let transfer = match res {
+                    Ok(transfer) => transfer,
+                    Err((code, e)) => {
+                        self.error(server_state, stream, request_id, code, &e);
+                        return;
+                    },
+                };
+                let declined = messages::server::MsgFileDeclined {
+                    id: msg.id,
+                    user: cdata.name.as_str(),
+                };
+                if let Err(e) = server_state.send_to(messages::server::Message::OnFileDeclined as u32, &declined, &[transfer.from]) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to decline transfer", &[("id", &msg.id), ("error", &e)]);
+                }
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnFileChunk as u32 => {
+                let msg = match self.decode::<messages::client::MsgFileChunk>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                //only a registered sender can have a transfer, so the transfer itself is the check
+                let config = self.config.read().expect("Failed to lock mutex").files.clone();
+                let res = self.transfers.lock().expect("Failed to lock mutex").chunk(&config, msg.id, stream.addr, msg.offset, msg.data.len());
+                let to = match res {
+                    Ok(to) => to,
+                    Err((code, e)) => {
+                        self.error(server_state, stream, request_id, code, &e);
+                        return;
+                    },
+                };
+                let chunk = messages::server::MsgFileChunk {
+                    id: msg.id,
+                    offset: msg.offset,
+                    data: msg.data,
+                };
+                if let Err(e) = server_state.send_to(messages::server::Message::OnFileChunk as u32, &chunk, &[to]) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to relay transfer", &[("id", &msg.id), ("error", &e)]);
+                }
+                //the sender waits for this before sending more, so a slow recipient slows the sender down
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnFileComplete as u32 => {
+                let msg = match self.decode::<messages::client::MsgFileComplete>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let res = self.transfers.lock().expect("Failed to lock mutex").complete(msg.id, stream.addr);
+                let transfer = match res {
+                    Ok(transfer) => transfer,
+                    Err((code, e)) => {
+                        self.error(server_state, stream, request_id, code, &e);
+                        return;
+                    },
+                };
+                self.logger.log(Level::Info, module_path!(), "Transfer complete", &[("id", &msg.id), ("file", &transfer.name), ("size", &transfer.size)]);
+                let complete = messages::server::MsgFileComplete {
+                    id: msg.id,
+                };
+                if let Err(e) = server_state.send_to(messages::server::Message::OnFileComplete as u32, &complete, &[transfer.to]) {
+                    self.logger.log(Level::Warn, module_path!(), "Failed to complete transfer", &[("id", &msg.id), ("error", &e)]);
+                }
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnFileCancel as u32 => {
+                let msg = match self.decode::<messages::client::MsgFileCancel>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+                if self.message_too_long(server_state, stream, request_id, msg.reason) {
+                    return;
+                }
+                let cdata = match self.sender(server_state, stream, request_id) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let res = self.transfers.lock().expect("Failed to lock mutex").cancel(msg.id, stream.addr);
+                let transfer = match res {
+                    Ok(transfer) => transfer,
+                    Err((code, e)) => {
+                        self.error(server_state, stream, request_id, code, &e);
+                        return;
+                    },
+                };
+                self.logger.log(Level::Info, module_path!(), "Transfer cancelled", &[("id", &msg.id), ("user", &cdata.name), ("file", &transfer.name)]);
+                let other = if transfer.from == stream.addr { transfer.to } else { transfer.from };
+                self.file_cancelled(server_state, other, msg.id, &cdata.name, msg.reason);
+                self.ack(server_state, stream, request_id);
+            },
+            code if code == messages::client::Message::OnHello as u32 => {
+                let msg = match self.decode::<messages::client::MsgHello>(server_state, stream, msginfo) {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                //the client's preference wins among what the server allows
+                let lock_guard = self.config.read().expect("Failed to lock mutex");
+                let config = lock_guard.compression.clone();
+                let checksum = msg.checksum && lock_guard.checksum.enabled;
+                let codecs = lock_guard.codec.allowed.clone();
+                drop(lock_guard);
+                let compression = msg.compression.iter()
+                    .filter_map(|id| Compression::from_u8(*id))
+                    .find(|algorithm| config.algorithms.contains(algorithm))
+                    .unwrap_or(Compression::None);
+                //bincode is what everyone speaks, so it is the answer when nothing offered is allowed
+                let codec = msg.codecs.iter()
+                    .filter_map(|id| Codec::from_u8(*id))
+                    .find(|codec| codecs.contains(codec))
+                    .unwrap_or(Codec::Bincode);
+                let options = FrameOptions {
+                    compression,
+                    threshold: config.threshold,
+                    level: config.level,
+                    checksum,
+                    codec,
+                };
+
+                //switched before replying, so nothing sent from another thread after the reply goes out the old way
+                stream.set_frame_options(options);
+                let reply = messages::server::MsgHello {
+                    compression: compression as u8,
+                    threshold: config.threshold as u32,
+                    level: config.level,
+                    checksum,
+                    codec: codec as u8,
+                };
+                self.send(server_state, stream, messages::server::Message::OnHello as u32, request_id, &reply);
+                self.logger.log(Level::Info, module_path!(), "Frame options negotiated", &[("addr", &stream.addr), ("compression", &compression), ("checksum", &checksum), ("codec", &codec)]);
+            },
+            //no request is sent as a stream yet, the sender is told once per stream rather than per chunk
+            code if code == message_stream::STREAM_CODE => {
+                if let Ok(chunk) = msginfo.stream_chunk() {
+                    if chunk.seq == 0 {
+                        self.error(server_state, stream, request_id, ErrorCode::UnknownMessage, &format!("Message {} is not accepted as a stream", chunk.code));
+                    }
+                }
             },
             code => {
-                println!("Unknown or nhandled message code: \"{}\"", code);  
+                self.logger.log(Level::Warn, module_path!(), "Unknown message", &[("addr", &stream.addr), ("code", &code)]);
+                self.error(server_state, stream, request_id, ErrorCode::UnknownMessage, &format!("Unknown message code {}", code));
             },
         }
     }
@@ -123,4 +1662,4 @@ pub fn server_handler_build(server_impl: Arc<ServerImpl>) -> ServerHandler {
         Box::new(move |server_state: &ServerState, stream: &ClientStream| server_impl2.on_disconnect(server_state, stream)),
         Box::new(move |server_state: &ServerState, stream: &ClientStream, msginfo: &MsgInfo| server_impl3.on_read(server_state, stream, msginfo)), 
     )
-}
\ No newline at end of file
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use netutils::messages::server::ErrorCode;
+
+use super::config::FilesConfig;
+
+//files offered between users, chunks are relayed as they arrive so only the bookkeeping lives here
+pub struct Transfer {
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    pub name: String,
+    pub size: u64,
+    pub offset: Option<u64>, //where the next chunk has to start, None until accepted
+}
+
+#[derive(Default)]
+pub struct Transfers {
+    next_id: u32,
+    transfers: HashMap<u32, Transfer>,
+}
+
+type TransferError = (ErrorCode, String);
+
+fn not_found(id: u32) -> TransferError {
+    (ErrorCode::NotFound, format!("No transfer {}", id))
+}
+
+//names are shown to the recipient and used to save the file, anything that looks like a path is refused
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 255 || name == "." || name == ".." {
+        return Err(String::from("File names must be 1 to 255 bytes long"));
+    }
+    if name.chars().any(|c| c == '/' || c == '\\' || c.is_control()) {
+        return Err(String::from("File names may not contain path separators or control characters"));
+    }
+    Ok(())
+}
+
+impl Transfers {
+    pub fn offer(&mut self, config: &FilesConfig, from: SocketAddr, to: SocketAddr, name: &str, size: u64) -> Result<u32, TransferError> {
+        if !config.enabled {
+            return Err((ErrorCode::Forbidden, String::from("File transfers are disabled on this server")));
+        }
+        if from == to {
+            return Err((ErrorCode::InvalidArgument, String::from("You cannot send files to yourself")));
+        }
+        if let Err(e) = check_name(name) {
+            return Err((ErrorCode::InvalidArgument, e));
+        }
+        if size > config.max_size {
+            return Err((ErrorCode::TooLarge, format!("Files are limited to {} bytes", config.max_size)));
+        }
+        if self.transfers.values().filter(|t| t.from == from).count() >= config.max_transfers {
+            return Err((ErrorCode::Conflict, format!("At most {} transfers may be pending at once", config.max_transfers)));
+        }
+
+        //0 is never handed out so it can mean "no transfer" on the wire
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        while self.transfers.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+        }
+        self.transfers.insert(self.next_id, Transfer {
+            from,
+            to,
+            name: name.to_string(),
+            size,
+            offset: None,
+        });
+        Ok(self.next_id)
+    }
+
+    //only the recipient may accept, resuming at `offset`; returns the sender
+    pub fn accept(&mut self, id: u32, by: SocketAddr, offset: u64) -> Result<SocketAddr, TransferError> {
+        let transfer = match self.transfers.get_mut(&id) {
+            Some(transfer) if transfer.to == by => transfer,
+            _ => return Err(not_found(id)),
+        };
+        if transfer.offset.is_some() {
+            return Err((ErrorCode::Conflict, format!("Transfer {} was already accepted", id)));
+        }
+        if offset > transfer.size {
+            return Err((ErrorCode::InvalidArgument, format!("Offset {} is past the end of {}", offset, transfer.name)));
+        }
+        transfer.offset = Some(offset);
+        Ok(transfer.from)
+    }
+
+    pub fn decline(&mut self, id: u32, by: SocketAddr) -> Result<Transfer, TransferError> {
+        match self.transfers.get(&id) {
+            Some(transfer) if transfer.to == by && transfer.offset.is_none() => {},
+            Some(transfer) if transfer.to == by => return Err((ErrorCode::Conflict, format!("Transfer {} was already accepted, cancel it instead", id))),
+            _ => return Err(not_found(id)),
+        }
+        Ok(self.transfers.remove(&id).expect("transfer checked above"))
+    }
+
+    //checks a chunk from the sender and moves the transfer past it, returns the recipient
+    pub fn chunk(&mut self, config: &FilesConfig, id: u32, by: SocketAddr, offset: u64, len: usize) -> Result<SocketAddr, TransferError> {
+        let transfer = match self.transfers.get_mut(&id) {
+            Some(transfer) if transfer.from == by => transfer,
+            _ => return Err(not_found(id)),
+        };
+        let expected = match transfer.offset {
+            Some(expected) => expected,
+            None => return Err((ErrorCode::Conflict, format!("Transfer {} was not accepted yet", id))),
+        };
+        if len > config.max_chunk {
+            return Err((ErrorCode::TooLarge, format!("Chunks are limited to {} bytes", config.max_chunk)));
+        }
+        if offset != expected {
+            return Err((ErrorCode::InvalidArgument, format!("Expected a chunk at offset {}, got {}", expected, offset)));
+        }
+        if offset + len as u64 > transfer.size {
+            return Err((ErrorCode::InvalidArgument, format!("Chunk runs past the end of {}", transfer.name)));
+        }
+        transfer.offset = Some(offset + len as u64);
+        Ok(transfer.to)
+    }
+
+    pub fn complete(&mut self, id: u32, by: SocketAddr) -> Result<Transfer, TransferError> {
+        match self.transfers.get(&id) {
+            Some(transfer) if transfer.from == by && transfer.offset == Some(transfer.size) => {},
+            Some(transfer) if transfer.from == by => return Err((ErrorCode::Conflict, format!("Transfer {} is missing data", id))),
+            _ => return Err(not_found(id)),
+        }
+        Ok(self.transfers.remove(&id).expect("transfer checked above"))
+    }
+
+    //either side may cancel
+    pub fn cancel(&mut self, id: u32, by: SocketAddr) -> Result<Transfer, TransferError> {
+        match self.transfers.get(&id) {
+            Some(transfer) if transfer.from == by || transfer.to == by => {},
+            _ => return Err(not_found(id)),
+        }
+        Ok(self.transfers.remove(&id).expect("transfer checked above"))
+    }
+
+    //drops every transfer `addr` takes part in
+    pub fn remove_client(&mut self, addr: SocketAddr) -> Vec<(u32, Transfer)> {
+        let ids: Vec<u32> = self.transfers.iter().filter(|(_, t)| t.from == addr || t.to == addr).map(|(id, _)| *id).collect();
+        ids.into_iter().filter_map(|id| self.transfers.remove(&id).map(|t| (id, t))).collect()
+    }
+}
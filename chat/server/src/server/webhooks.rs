@@ -0,0 +1,299 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use netutils::http;
+use netutils::logger::{Level, Logger};
+use netutils::messages::server::ErrorCode;
+use server_lib::ServerState;
+use server_lib::service::{Peer, Service};
+use utils::secret;
+use super::config::WebhooksConfig;
+use super::server_impl::ServerImpl;
+
+//inbound: integrations POST {"text": ..., "room": ..., "user": ...} to /messages with
+//"Authorization: Bearer <webhooks.token>", room defaults to the lobby and user to webhooks.user
+//outbound: every chat event this server sees first hand is POSTed as JSON to each of webhooks.urls,
+//events relayed from linked servers are left to the server they happened on
+
+const MAX_CONNECTIONS: usize = 8;
+const MAX_HEADER_LEN: usize = 8 * 1024;
+const MAX_BODY_LEN: usize = 64 * 1024;
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+const QUEUE_LEN: usize = 1024; //events waiting for one url, newer ones are dropped beyond that
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Post {
+    text: String,
+    #[serde(default)]
+    room: Option<String>,
+    #[serde(default)]
+    user: Option<String>,
+}
+
+//integration posts, every connection gets its own thread like chat-admin ones
+pub fn service(server_state: Arc<ServerState>, server_impl: Arc<ServerImpl>, logger: Arc<Logger>) -> Service {
+    Service {
+        name: String::from("Webhooks"),
+        max: MAX_CONNECTIONS,
+        handle: Box::new(move |stream, peer| {
+            if let Err(e) = handle(stream, peer, &server_state, &server_impl, &logger) {
+                logger.log(Level::Info, module_path!(), "Webhook connection closed", &[("addr", &peer.addr), ("error", &e)]);
+            }
+        }),
+    }
+}
+
+fn handle(mut stream: TcpStream, peer: &Peer, server_state: &ServerState, server_impl: &ServerImpl, logger: &Logger) -> Result<(), io::Error> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let request = match http::read_request(&mut stream, MAX_HEADER_LEN, MAX_BODY_LEN)? {
+        Ok(request) => request,
+        Err((status, text)) => return respond(&mut stream, status, &text),
+    };
+    if request.route() != "/messages" {
+        return respond(&mut stream, 404, "not found");
+    }
+    if request.method != "POST" {
+        return respond(&mut stream, 405, "only POST is allowed");
+    }
+
+    let token = server_impl.webhook_token();
+    let authorized = match request.header("Authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(given) => !token.is_empty() && secret::constant_time_eq(given.trim().as_bytes(), token.as_bytes()),
+        None => false,
+    };
+    if !authorized {
+        logger.log(Level::Warn, module_path!(), "Webhook authentication failed", &[("addr", &peer.addr)]);
+        peer.auth_failed();
+        return respond(&mut stream, 401, "missing or wrong bearer token");
+    }
+
+    let post = match serde_json::from_slice::<Post>(&request.body) {
+        Ok(post) => post,
+        Err(e) => return respond(&mut stream, 400, &format!("expected {{\"text\": ..., \"room\": ..., \"user\": ...}}: {}", e)),
+    };
+    match server_impl.inject(server_state, post.room.as_deref(), post.user.as_deref(), &post.text) {
+        Ok(_) => respond(&mut stream, 204, ""),
+        Err((code, text)) => {
+            let status = match code {
+                ErrorCode::InvalidArgument => 400,
+                ErrorCode::Forbidden => 403,
+                ErrorCode::Conflict => 409,
+                ErrorCode::TooLarge => 413,
+                _ => 500,
+            };
+            respond(&mut stream, status, &text)
+        },
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: u16, text: &str) -> Result<(), io::Error> {
+    let body = match text {
+        "" => String::new(),
+        text => format!("{}\n", text),
+    };
+    let mut headers = vec![("Content-Type", "text/plain; charset=utf-8")];
+    match status {
+        401 => headers.push(("WWW-Authenticate", "Bearer")),
+        405 => headers.push(("Allow", "POST")),
+        _ => {},
+    }
+    http::respond(stream, status, &headers, &body)
+}
+
+//where outbound events are posted, only plain http is supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String, //without brackets around IPv6 addresses
+    pub authority: String, //host and port as given, sent as the Host header
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, String> {
+        let rest = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some((scheme, _)) if scheme.eq_ignore_ascii_case("https") => return Err(String::from("https is not supported by this build yet")),
+            _ => return Err(String::from("expected an http:// URL")),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        if authority.contains('@') {
+            return Err(String::from("credentials in URLs are not supported"));
+        }
+
+        //"[::1]:8080", "example.org:8080" or "example.org"
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').ok_or_else(|| String::from("invalid port"))?)),
+                None => return Err(String::from("unclosed '[' in host")),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(String::from("missing host"));
+        }
+        let port = match port {
+            Some(port) => port.parse::<u16>().map_err(|_| format!("invalid port \"{}\"", port))?,
+            None => 80,
+        };
+        Ok(Url {
+            host: host.to_string(),
+            authority: authority.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "http://{}{}", self.authority, self.path)
+    }
+}
+
+struct Delivery {
+    event: &'static str,
+    body: Arc<str>,
+}
+
+enum Outcome {
+    Delivered,
+    Retry(String),
+    Refused(u16), //the endpoint does not want it, trying again will not change that
+}
+
+//outbound webhooks, every url gets its own queue and thread so a slow endpoint only delays itself
+pub struct Webhooks {
+    queues: Vec<(Url, mpsc::SyncSender<Delivery>)>,
+    events: Vec<String>, //empty posts everything
+    logger: Arc<Logger>,
+}
+
+impl Webhooks {
+    pub fn start(config: &WebhooksConfig, logger: Arc<Logger>) -> Self {
+        let mut queues = Vec::new();
+        //validated with the config
+        for url in config.urls.iter().filter_map(|url| Url::parse(url).ok()) {
+            let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+            let thread_url = url.clone();
+            let thread_logger = logger.clone();
+            let retries = config.retries;
+            let timeout = Duration::from_secs(config.timeout);
+            let res = thread::Builder::new().name(String::from("Webhook")).spawn(move || deliver(thread_url, receiver, retries, timeout, thread_logger));
+            match res {
+                Ok(_) => queues.push((url, sender)),
+                Err(e) => logger.log(Level::Error, module_path!(), "Failed to spawn webhook thread", &[("url", &url), ("error", &e)]),
+            }
+        }
+        Webhooks {
+            queues,
+            events: config.events.clone(),
+            logger,
+        }
+    }
+
+    fn wants(&self, event: &str) -> bool {
+        !self.queues.is_empty() && (self.events.is_empty() || self.events.iter().any(|wanted| wanted == event))
+    }
+
+    //`fields` is a JSON object, "event" and "time" are added to it
+    pub fn post(&self, event: &'static str, mut fields: serde_json::Value) {
+        if !self.wants(event) {
+            return;
+        }
+        if let Some(object) = fields.as_object_mut() {
+            let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+            object.insert(String::from("event"), serde_json::Value::from(event));
+            object.insert(String::from("time"), serde_json::Value::from(time));
+        }
+        let body: Arc<str> = Arc::from(fields.to_string());
+        for (url, queue) in self.queues.iter() {
+            match queue.try_send(Delivery { event, body: body.clone() }) {
+                Ok(_) => {},
+                Err(mpsc::TrySendError::Full(_)) => self.logger.log(Level::Warn, module_path!(), "Webhook queue full, dropped event", &[("url", url), ("event", &event)]),
+                Err(mpsc::TrySendError::Disconnected(_)) => {},
+            }
+        }
+    }
+}
+
+fn deliver(url: Url, receiver: mpsc::Receiver<Delivery>, retries: u32, timeout: Duration, logger: Arc<Logger>) {
+    for delivery in receiver.iter() {
+        let mut delay = FIRST_RETRY;
+        for attempt in 0..=retries {
+            match post(&url, &delivery, timeout) {
+                Outcome::Delivered => break,
+                Outcome::Refused(status) => {
+                    logger.log(Level::Warn, module_path!(), "Webhook refused event", &[("url", &url), ("event", &delivery.event), ("status", &status)]);
+                    break;
+                },
+                Outcome::Retry(error) if attempt == retries => {
+                    logger.log(Level::Warn, module_path!(), "Giving up on webhook event", &[("url", &url), ("event", &delivery.event), ("attempts", &(attempt + 1)), ("error", &error)]);
+                },
+                Outcome::Retry(error) => {
+                    logger.log(Level::Debug, module_path!(), "Webhook post failed, retrying", &[("url", &url), ("event", &delivery.event), ("delay", &format!("{}s", delay.as_secs())), ("error", &error)]);
+                    thread::sleep(delay);
+                    delay *= 2;
+                },
+            }
+        }
+    }
+}
+
+fn post(url: &Url, delivery: &Delivery, timeout: Duration) -> Outcome {
+    match try_post(url, delivery, timeout) {
+        Ok(status) if (200..300).contains(&status) => Outcome::Delivered,
+        Ok(status) if status == 408 || status == 429 || status >= 500 => Outcome::Retry(format!("status {}", status)),
+        Ok(status) => Outcome::Refused(status),
+        Err(e) => Outcome::Retry(e.to_string()),
+    }
+}
+
+//returns the status the endpoint answered with, the rest of the response is ignored
+fn try_post(url: &Url, delivery: &Delivery, timeout: Duration) -> Result<u16, io::Error> {
+    let addr = match (url.host.as_str(), url.port).to_socket_addrs()?.next() {
+        Some(addr) => addr,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", url.host))),
+    };
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: chat-server\r\nContent-Type: application/json\r\nContent-Length: {}\r\nX-Chat-Event: {}\r\nConnection: close\r\n\r\n",
+        url.path, url.authority, delivery.body.len(), delivery.event,
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(delivery.body.as_bytes())?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !response.windows(2).any(|w| w == b"\r\n") && response.len() < 1024 {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..read]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let mut parts = response.lines().next().unwrap_or("").split_whitespace();
+    match (parts.next(), parts.next().map(|status| status.parse::<u16>())) {
+        (Some(version), Some(Ok(status))) if version.starts_with("HTTP/") => Ok(status),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "not an HTTP response")),
+    }
+}
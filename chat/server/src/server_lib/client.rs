@@ -1,19 +1,55 @@
 
 use std::{
     net::{TcpStream, SocketAddr},
-    sync::{Arc, Mutex,},
+    sync::{atomic::AtomicUsize, Arc, Condvar, Mutex,},
     hash::{Hash, Hasher},
+    time::{Duration, Instant},
 };
 use core::cell::RefCell;
 
+use netutils::message_stream::{FrameBuffer, FrameOptions, Streams};
+use netutils::outbound::OutboundQueue;
+use netutils::websocket::WsDecoder;
+
+use super::gateway::Gateway;
+
+//how frames travel to and from a client, the messages in them are the same either way
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    WebSocket, //every frame is wrapped in a binary WebSocket message
+    Gateway(&'static str), //translated by the named gateway, see ClientStream::gateway
+    Link, //another server, only gets what is sent to it directly, never a broadcast
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::WebSocket => write!(f, "websocket"),
+            Transport::Gateway(name) => write!(f, "{}", name),
+            Transport::Link => write!(f, "link"),
+        }
+    }
+}
+
 pub(crate) struct ClientData {
-    pub(crate) msg: Vec::<u8>,
+    pub(crate) buffer: FrameBuffer, //received bytes not parsed into a frame yet
+    pub(crate) streams: Streams, //messages the client sends in several frames
+    pub(crate) checksums: bool, //set by the first checksummed frame, every later one needs one too
+    pub(crate) websocket: Option<WsDecoder>, //WebSocket messages not unwrapped yet, None for plain TCP
 }
 
 impl ClientData {
-    fn new() -> Self {
+    fn new(transport: Transport) -> Self {
         ClientData {
-            msg: Vec::new(),
+            buffer: FrameBuffer::default(),
+            streams: Streams::default(),
+            checksums: false,
+            websocket: match transport {
+                Transport::WebSocket => Some(WsDecoder::new()),
+                _ => None,
+            },
         }
     }
 }
@@ -22,6 +58,9 @@ impl ClientData {
 pub struct Client {
     pub stream: Arc<ClientStream>,
     pub(crate) data: RefCell<ClientData>,
+    pub(crate) pending_bytes: AtomicUsize, //mirrors the bytes buffered in data for threads other than the reader
+    pub connected_at: Instant,
+    pub(crate) last_active: Mutex<Instant>, //last time any bytes were received
 }
 
 unsafe impl Sync for Client {}
@@ -44,26 +83,66 @@ impl Hash for Client {
 pub struct ClientStream {
     pub stream_write: Mutex<TcpStream>,
     pub addr: SocketAddr,
-    pub(crate) stream_read: RefCell<TcpStream>,
+    pub transport: Transport,
+    pub(crate) gateway: Option<Arc<dyn Gateway>>, //set for Transport::Gateway
+    pub(crate) stream_read: Mutex<TcpStream>, //only locked by the reader thread, never contended
+    frame_options: Mutex<FrameOptions>, //applied to every frame sent to this client
+    pub(crate) outbound: Mutex<OutboundQueue>, //frames waiting for the writer thread, lock before stream_write
+    pub(crate) drained: Condvar, //on outbound, signalled when the writer thread took frames or the queue was closed
 }
 
 impl ClientStream {
-    fn new(stream: TcpStream, addr: SocketAddr) -> Self {
+    fn new(stream: TcpStream, addr: SocketAddr, transport: Transport, gateway: Option<Arc<dyn Gateway>>) -> Self {
         stream.set_nonblocking(true).expect("Failed to put socket in nonblocking mode");
         let stream_read = stream.try_clone().expect("Failed to clone TcpStream");
         ClientStream {
             stream_write:Mutex::new(stream),
-            stream_read:RefCell::new(stream_read),
-            addr
+            stream_read:Mutex::new(stream_read),
+            addr,
+            transport,
+            gateway,
+            frame_options: Mutex::new(FrameOptions::default()),
+            outbound: Mutex::new(OutboundQueue::new()),
+            drained: Condvar::new(),
         }
     }
+
+    pub fn frame_options(&self) -> FrameOptions {
+        *self.frame_options.lock().expect("Failed to lock mutex")
+    }
+
+    //only once the client agreed to it, frames already sent stay as they were
+    pub fn set_frame_options(&self, options: FrameOptions) {
+        *self.frame_options.lock().expect("Failed to lock mutex") = options;
+    }
+
+    //bytes queued for this client that the writer thread has not got out yet
+    pub fn queued(&self) -> usize {
+        self.outbound.lock().expect("Failed to lock mutex").len()
+    }
 }
 
 impl Client {
-    pub fn new(stream: TcpStream, addr: SocketAddr) -> Self  {
+    pub fn new(stream: TcpStream, addr: SocketAddr, transport: Transport) -> Self  {
+        Self::build(ClientStream::new(stream, addr, transport, None))
+    }
+
+    pub fn with_gateway(stream: TcpStream, addr: SocketAddr, gateway: Arc<dyn Gateway>) -> Self {
+        Self::build(ClientStream::new(stream, addr, Transport::Gateway(gateway.name()), Some(gateway)))
+    }
+
+    fn build(stream: ClientStream) -> Self {
+        let transport = stream.transport;
         Client {
-            stream: Arc::new(ClientStream::new(stream, addr)),
-            data: RefCell::new(ClientData::new()),
+            stream: Arc::new(stream),
+            data: RefCell::new(ClientData::new(transport)),
+            pending_bytes: AtomicUsize::new(0),
+            connected_at: Instant::now(),
+            last_active: Mutex::new(Instant::now()),
         }
     }
+
+    pub fn idle(&self) -> Duration {
+        self.last_active.lock().expect("Failed to lock mutex").elapsed()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+use super::client::ClientStream;
+
+//speaks some other protocol to clients accepted with Server::start_gateway and translates it, so
+//they are handled and broadcast to like everyone else; called from several threads at once
+pub trait Gateway: Send + Sync {
+    //shown where the transport of a client is
+    fn name(&self) -> &'static str;
+
+    //`data` is what the client sent, in whatever pieces the network delivered it; frames as made by
+    //message_stream::serialize_request go to `frames` and are handled as if the client sent them,
+    //`replies` is written back to the client as it is; Err drops the client, `replies` still goes
+    //out if the socket takes it right away
+    fn inbound(&self, stream: &ClientStream, data: &[u8], frames: &mut Vec<u8>, replies: &mut Vec<u8>) -> Result<(), String>;
+
+    //a frame on its way to the client, returns what to write instead, empty to drop it
+    fn outbound(&self, stream: &ClientStream, frame: &[u8]) -> Vec<u8>;
+
+    //the client is gone, after the handler's on_disconnect
+    fn disconnected(&self, _stream: &ClientStream) {}
+}
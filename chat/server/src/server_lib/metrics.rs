@@ -0,0 +1,135 @@
+use std::{
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::{self, Duration, Instant},
+    thread,
+};
+
+use netutils::http;
+
+//counters only ever grow, gauges go up and down
+pub struct Metrics {
+    started: Instant,
+    pub connections_total: AtomicU64,
+    pub connections_rejected_total: AtomicU64,
+    pub disconnections_total: AtomicU64,
+    pub connected_clients: AtomicI64,
+    pub messages_in_total: AtomicU64,
+    pub messages_out_total: AtomicU64,
+    pub bytes_in_total: AtomicU64,
+    pub bytes_out_total: AtomicU64,
+    pub decode_errors_total: AtomicU64,
+    pub corrupt_streams_total: AtomicU64, //clients dropped because their frames could not be told apart anymore
+    pub send_errors_total: AtomicU64,
+    pub slow_clients_total: AtomicU64,
+    pub read_buffer_bytes: AtomicI64, //bytes received but not yet parsed into a full frame
+    pub write_buffer_bytes: AtomicI64, //bytes queued for clients but not written yet
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            connections_total: AtomicU64::new(0),
+            connections_rejected_total: AtomicU64::new(0),
+            disconnections_total: AtomicU64::new(0),
+            connected_clients: AtomicI64::new(0),
+            messages_in_total: AtomicU64::new(0),
+            messages_out_total: AtomicU64::new(0),
+            bytes_in_total: AtomicU64::new(0),
+            bytes_out_total: AtomicU64::new(0),
+            decode_errors_total: AtomicU64::new(0),
+            corrupt_streams_total: AtomicU64::new(0),
+            send_errors_total: AtomicU64::new(0),
+            slow_clients_total: AtomicU64::new(0),
+            read_buffer_bytes: AtomicI64::new(0),
+            write_buffer_bytes: AtomicI64::new(0),
+        }
+    }
+
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(counter: &AtomicU64, val: u64) {
+        counter.fetch_add(val, Ordering::Relaxed);
+    }
+
+    pub fn adjust(gauge: &AtomicI64, delta: i64) {
+        gauge.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    //Prometheus text exposition format 0.0.4
+    pub fn render(&self) -> String {
+        let counters = [
+            ("chat_connections_total", "Accepted client connections", &self.connections_total),
+            ("chat_connections_rejected_total", "Client connections refused by the handler", &self.connections_rejected_total),
+            ("chat_disconnections_total", "Client connections closed", &self.disconnections_total),
+            ("chat_messages_in_total", "Frames received from clients", &self.messages_in_total),
+            ("chat_messages_out_total", "Frames sent to clients", &self.messages_out_total),
+            ("chat_bytes_in_total", "Bytes received from clients", &self.bytes_in_total),
+            ("chat_bytes_out_total", "Bytes sent to clients", &self.bytes_out_total),
+            ("chat_decode_errors_total", "Frames that failed to decode", &self.decode_errors_total),
+            ("chat_corrupt_streams_total", "Clients dropped because their framing broke", &self.corrupt_streams_total),
+            ("chat_send_errors_total", "Failed writes to client sockets", &self.send_errors_total),
+            ("chat_slow_clients_total", "Clients dropped because too much output was queued for them", &self.slow_clients_total),
+        ];
+        let gauges = [
+            ("chat_connected_clients", "Currently connected clients", &self.connected_clients),
+            ("chat_read_buffer_bytes", "Received bytes waiting for the rest of their frame", &self.read_buffer_bytes),
+            ("chat_write_buffer_bytes", "Bytes queued for clients but not written yet", &self.write_buffer_bytes),
+        ];
+
+        let mut out = String::new();
+        for (name, help, counter) in counters {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n{} {}\n", name, help, name, name, counter.load(Ordering::Relaxed)));
+        }
+        for (name, help, gauge) in gauges {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n{} {}\n", name, help, name, name, gauge.load(Ordering::Relaxed)));
+        }
+        out.push_str(&format!("# HELP chat_uptime_seconds Seconds since the server started\n# TYPE chat_uptime_seconds gauge\nchat_uptime_seconds {:.3}\n", self.uptime().as_secs_f64()));
+        out
+    }
+}
+
+//only GET /metrics is served
+pub(crate) fn serve_metrics(listener: &TcpListener, metrics: &Metrics) -> Result<(), std::io::Error> {
+    match listener.accept() {
+        Ok((stream, _)) => {
+            respond(stream, metrics)?;
+        },
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+            thread::sleep(time::Duration::from_millis(50));
+        },
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> Result<(), std::io::Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+
+    let request = match http::read_request(&mut stream, 8192, 0)? {
+        Ok(request) => request,
+        Err((status, text)) => return http::respond(&mut stream, status, &[], &format!("{}\n", text)),
+    };
+    let (status, body) = match (request.method.as_str(), request.route()) {
+        ("GET", "/metrics") => (200, metrics.render()),
+        ("GET", _) => (404, String::from("not found\n")),
+        _ => (405, String::from("method not allowed\n")),
+    };
+    http::respond(&mut stream, status, &[("Content-Type", "text/plain; version=0.0.4")], &body)
+}
@@ -1,23 +1,39 @@
 use std::{
-    net::{TcpListener, Shutdown, SocketAddr},
+    net::{TcpListener, TcpStream, Shutdown, SocketAddr},
     io::{ErrorKind, Read, Write},
-    sync::{Arc, RwLock},
+    borrow::Cow,
+    sync::{atomic::{AtomicU32, AtomicUsize, Ordering}, Arc, Condvar, Mutex, RwLock},
     collections::{HashSet, HashMap},
-    time,
+    time::{self, Duration, Instant},
     error::Error,
     thread,
 };
 
-use netutils::{thread_helper::{self, ThreadHelper}, message_stream::{self, MsgInfo, MsgError}, logger::Logger};
+use netutils::{thread_helper::{self, ThreadHelper}, message_stream::{self, FrameBuffer, FrameOptions, MsgError, MsgInfo}, outbound::SharedFrame, messages, logger::{Level, Logger}};
+use netutils::websocket::{self, Opcode, WsDecoder};
 
 pub mod client;
+pub mod gateway;
+pub mod metrics;
+pub mod service;
 mod server_error;
 use server_error::ServerError;
-use client::{Client, ClientStream};
+use client::{Client, ClientStream, Transport};
+use gateway::Gateway;
+use metrics::Metrics;
+use service::{Service, Acceptor};
 
 struct ServerThreads {
+    thread_state: Arc<thread_helper::ThreadState>,
+    logger: Arc<Logger>,
     listener_thread: ThreadHelper,
     read_thread: ThreadHelper,
+    write_thread: ThreadHelper,
+    metrics_thread: Option<ThreadHelper>,
+    websocket_thread: Option<ThreadHelper>,
+    gateway_threads: Vec<ThreadHelper>,
+    link_thread: Option<ThreadHelper>,
+    service_threads: Vec<ThreadHelper>,
 }
 
 impl ServerThreads {
@@ -25,6 +41,14 @@ impl ServerThreads {
         ServerThreads {
             listener_thread:ThreadHelper::new(thread_state.clone(), logger.clone()),
             read_thread:ThreadHelper::new(thread_state.clone(), logger.clone()),
+            write_thread:ThreadHelper::new(thread_state.clone(), logger.clone()),
+            metrics_thread: None,
+            websocket_thread: None,
+            gateway_threads: Vec::new(),
+            link_thread: None,
+            service_threads: Vec::new(),
+            thread_state,
+            logger,
         }
     }
 
@@ -32,27 +56,86 @@ impl ServerThreads {
         //can have multiple immutable references
         let server_clone0 = server.clone();
         let server_clone1 = server.clone();
+        let server_clone2 = server.clone();
         self.read_thread.start(String::from("Reader"), move || server_clone1.read_thread());
+        self.write_thread.start(String::from("Writer"), move || server_clone2.write_thread());
         self.listener_thread.start(String::from("Listener"), move || server_clone0.listen_thread());
     }
 
+    pub fn start_metrics(&mut self, server: Arc<ServerState>, listener: TcpListener) {
+        let mut thread = ThreadHelper::new(self.thread_state.clone(), self.logger.clone());
+        thread.start(String::from("Metrics"), move || server.metrics_thread(listener));
+        self.metrics_thread = Some(thread);
+    }
+
+    pub fn start_websocket(&mut self, server: Arc<ServerState>, origins: Vec<String>) {
+        let mut thread = ThreadHelper::new(self.thread_state.clone(), self.logger.clone());
+        thread.start(String::from("WebSocket"), move || server.websocket_thread(origins));
+        self.websocket_thread = Some(thread);
+    }
+
+    pub fn start_gateway(&mut self, server: Arc<ServerState>, gateway: Arc<dyn Gateway>) {
+        let mut thread = ThreadHelper::new(self.thread_state.clone(), self.logger.clone());
+        thread.start(format!("Gateway {}", gateway.name()), move || server.gateway_thread(gateway));
+        self.gateway_threads.push(thread);
+    }
+
+    pub fn start_link(&mut self, server: Arc<ServerState>) {
+        let mut thread = ThreadHelper::new(self.thread_state.clone(), self.logger.clone());
+        thread.start(String::from("Link"), move || server.link_thread());
+        self.link_thread = Some(thread);
+    }
+
+    pub fn start_service(&mut self, server: Arc<ServerState>, listener: TcpListener, service: Service) {
+        let mut thread = ThreadHelper::new(self.thread_state.clone(), self.logger.clone());
+        thread.start(service.name.clone(), move || server.service_thread(listener, Acceptor::new(service)));
+        self.service_threads.push(thread);
+    }
+
+    #[allow(dead_code)]
     pub fn shutdown(&mut self) -> Result<(), thread_helper::ThreadError> {
         self.listener_thread.shutdown()?;
         self.read_thread.shutdown()?;
+        self.write_thread.shutdown()?;
         Ok(())
     }
 
     pub fn wait_for_shutdown(&mut self) {
         self.listener_thread.wait_for_shutdown();
         self.read_thread.wait_for_shutdown();
+        self.write_thread.wait_for_shutdown();
+        if let Some(thread) = self.metrics_thread.as_mut() {
+            thread.wait_for_shutdown();
+        }
+        if let Some(thread) = self.websocket_thread.as_mut() {
+            thread.wait_for_shutdown();
+        }
+        for thread in self.gateway_threads.iter_mut() {
+            thread.wait_for_shutdown();
+        }
+        if let Some(thread) = self.link_thread.as_mut() {
+            thread.wait_for_shutdown();
+        }
+        for thread in self.service_threads.iter_mut() {
+            thread.wait_for_shutdown();
+        }
     }
 }
 
 pub struct ServerState {
     thread_state: Arc<thread_helper::ThreadState>,
-    listener: TcpListener,
+    listener: Mutex<Option<TcpListener>>, //taken once the server stops accepting
+    websocket_listener: Mutex<Option<TcpListener>>, //browsers connect here, None when the gateway is off
+    gateway_listeners: Mutex<HashMap<&'static str, TcpListener>>, //by gateway name
+    link_listener: Mutex<Option<TcpListener>>, //other servers connect here, None when linking is off
     handler: ServerHandler,
     pub clients_stream: RwLock<HashMap<SocketAddr, Arc<Client>>>, //mutex gets around const reference
+    pub metrics: Metrics,
+    next_stream_id: AtomicU32,
+    write_pending: Mutex<bool>, //frames were queued since the writer thread last looked
+    write_ready: Condvar,
+    max_queued: AtomicUsize, //bytes a client may have waiting before it is dropped as too slow
+    websocket_handshakes: AtomicUsize, //running on threads of their own
     logger: Arc<Logger>,
 }
 
@@ -61,15 +144,32 @@ impl ServerState {
         listener.set_nonblocking(true).expect("Failed to set TcpListener to nonblocking");
         ServerState {
             thread_state,
-            listener,
+            listener: Mutex::new(Some(listener)),
+            websocket_listener: Mutex::new(None),
+            gateway_listeners: Mutex::new(HashMap::new()),
+            link_listener: Mutex::new(None),
             clients_stream:RwLock::new(HashMap::new()),
             handler,
+            metrics: Metrics::new(),
+            next_stream_id: AtomicU32::new(0),
+            write_pending: Mutex::new(false),
+            write_ready: Condvar::new(),
+            max_queued: AtomicUsize::new(Self::DEFAULT_MAX_QUEUED),
+            websocket_handshakes: AtomicUsize::new(0),
             logger,
         }
     }
 
+    const DEFAULT_MAX_QUEUED: usize = 4 * 1024 * 1024;
+
+    //clients that stop reading are dropped once this many bytes are waiting for them,
+    //a single frame larger than that still goes out to a client that is keeping up
+    pub fn set_max_queued(&self, bytes: usize) {
+        self.max_queued.store(bytes, Ordering::Relaxed);
+    }
+
     pub fn disconnect(&self) -> Result<(), std::io::Error> {
-        let to_remove = self.clients_stream.read().expect("Failed to lock mutex").iter().map(|(_, v)| v.clone()).collect();
+        let to_remove: Vec<Arc<Client>> = self.clients_stream.read().expect("Failed to lock mutex").values().cloned().collect();
         self.handle_disconnected_clients(&to_remove)?;
         Ok(())
     }
@@ -78,40 +178,222 @@ impl ServerState {
         let res = self.thread_state.shutdown_start();
         match res {
             Ok(_) => {},
-            Err(thread_helper::ThreadError::AlreadyShuttingDown) => {self.logger.log("Already shutting down!!!".as_bytes()).unwrap();},
-            Err(e) => {self.logger.log(format!("{}", e).as_bytes()).unwrap();},
+            Err(thread_helper::ThreadError::AlreadyShuttingDown) => {self.logger.warn(module_path!(), "Already shutting down!!!");},
+            Err(e) => {self.logger.log(Level::Error, module_path!(), "Shutdown failed", &[("error", &e)]);},
         }    
 
         self.disconnect()
     }
 
-    pub fn send(&self, stream: &ClientStream, buffer: &[u8]) -> Result<(), std::io::Error> {
-        let mut lock_guard = stream.stream_write.lock().expect("Failed to lock mutex");
-        let res = lock_guard.write_all(buffer);
-        if let Err(e) = res {
-            let client = self.clients_stream.write().expect("Failed to lock mutex").remove(&stream.addr).expect("Failed to unwrap client");
-            lock_guard.shutdown(Shutdown::Both)?;
-            drop(lock_guard);
+    //drops a single client as if it had hung up, returns false if it is not connected
+    pub fn disconnect_client(&self, addr: SocketAddr) -> Result<bool, std::io::Error> {
+        let client = match self.clients_stream.read().expect("Failed to lock mutex").get(&addr) {
+            Some(client) => client.clone(),
+            None => return Ok(false),
+        };
+        self.handle_disconnected_clients(&[client])?;
+        Ok(true)
+    }
 
-            (self.handler.on_disconnect)(&self, stream);
+    //closes the listening socket, new connection attempts are refused from now on
+    pub fn stop_accepting(&self) {
+        drop(self.websocket_listener.lock().expect("Failed to lock mutex").take());
+        self.gateway_listeners.lock().expect("Failed to lock mutex").clear();
+        drop(self.link_listener.lock().expect("Failed to lock mutex").take());
+        if let Some(listener) = self.listener.lock().expect("Failed to lock mutex").take() {
+            drop(listener);
+            self.logger.info(module_path!(), "Stopped accepting new clients");
+        }
+    }
+
+    //false once stop_accepting was called, threads serving side listeners and dialing out stop on it
+    pub fn is_accepting(&self) -> bool {
+        self.listener.lock().expect("Failed to lock mutex").is_some()
+    }
+
+    //notifies every client, half-closes the sockets so already queued data is still delivered,
+    //then waits up to `grace` for the clients to hang up before closing what is left
+    pub fn shutdown_graceful(&self, reason: &str, retry_after: Duration, grace: Duration) -> Result<(), std::io::Error> {
+        if self.thread_state.is_shuttingdown() {
+            return Ok(());
+        }
+        self.stop_accepting();
+
+        let msg = messages::server::MsgServerShutdown {
+            reason,
+            retry_after: retry_after.as_secs() as u32,
+        };
+        if let Err(e) = self.send_all(messages::server::Message::OnServerShutdown as u32, &msg) {
+            self.logger.log(Level::Error, module_path!(), "Failed to send shutdown notice", &[("error", &e)]);
+        }
+
+        let clients: Vec<Arc<Client>> = self.clients_stream.read().expect("Failed to lock mutex").values().cloned().collect();
+        self.logger.log(Level::Info, module_path!(), "Draining clients", &[("clients", &clients.len()), ("grace_ms", &grace.as_millis())]);
+        //the notice is only queued, closing the write half before the writer got it out would lose it
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline && clients.iter().any(|client| client.stream.queued() > 0) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        for client in clients.iter() {
+            let _ = client.stream.stream_write.lock().expect("Failed to lock mutex").shutdown(Shutdown::Write);
+        }
+
+        //the reader thread keeps running and drops clients as they close their end
+        while Instant::now() < deadline {
+            if self.clients_stream.read().expect("Failed to lock mutex").is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let remaining = self.clients_stream.read().expect("Failed to lock mutex").len();
+        if remaining > 0 {
+            self.logger.log(Level::Warn, module_path!(), "Grace period expired, closing remaining clients", &[("clients", &remaining)]);
+        }
+        self.shutdown()
+    }
+
+    //serializes `data` in the payload codec the client picked; frames are queued and written by the
+    //writer thread, a client that can not take them fast enough is dropped here; write errors show up
+    //later as a disconnect
+    pub fn send<T>(&self, stream: &ClientStream, code: u32, request_id: u32, data: &T) -> Result<(), std::io::Error>
+    where
+        T: ?Sized + serde::Serialize,
+    {
+        let frame = Self::pack(&stream.frame_options(), stream, code, request_id, data)?;
+        self.send_shared(stream, frame)
+    }
+    //queues a message that is serialized already, in whichever codec its prefix names
+    pub fn send_frame(&self, stream: &ClientStream, buffer: &[u8]) -> Result<(), std::io::Error> {
+        let frame = Self::wrap(stream, message_stream::encode_frame(buffer, &stream.frame_options()).into_owned());
+        self.send_shared(stream, frame)
+    }
+    fn send_shared(&self, stream: &ClientStream, frame: SharedFrame) -> Result<(), std::io::Error> {
+        let queued = self.enqueue(stream, frame);
+        self.wake_writer();
+        if !queued {
+            //the reader thread may have dropped the client already
+            let client = self.clients_stream.read().expect("Failed to lock mutex").get(&stream.addr).cloned();
+            if let Some(client) = client {
+                self.drop_slow(&[client])?;
+            }
+        }
+        Ok(())
+    }
+    //sends what `reader` yields as a stream of `code` chunks of chunk_size, which the client handles as
+    //they arrive; other threads can send to the same client between chunks; stops early if the client
+    //is dropped on the way
+    pub fn send_stream<R: Read>(&self, stream: &ClientStream, code: u32, request_id: u32, reader: R, chunk_size: usize) -> Result<(), message_stream::MsgError> {
+        let stream_id = self.next_stream_id.fetch_add(1, Ordering::Relaxed);
+        for frame in message_stream::StreamWriter::new(reader, stream.frame_options().codec, code, request_id, stream_id, chunk_size) {
+            if let Err(e) = self.send_frame(stream, frame?.as_slice()) {
+                return Err(message_stream::MsgError::Write(e));
+            }
+            //a long stream must not pile up faster than the client takes it, half the allowance is left for everything else
+            if !self.wait_queued(stream, self.max_queued.load(Ordering::Relaxed) / 2) {
+                return Err(message_stream::MsgError::Write(std::io::Error::new(ErrorKind::NotConnected, "client disconnected")));
+            }
         }
         Ok(())
     }
-    pub fn send_all_it<It>(&self, buffer: &[u8], clients: It) -> Result<(), std::io::Error> 
+
+    pub fn send_all_it<T, It>(&self, code: u32, data: &T, clients: It) -> Result<(), std::io::Error> 
     where
+        T: ?Sized + serde::Serialize,
         It: IntoIterator<Item=Arc<Client>>
     {
-        let mut to_remove: Vec<Arc<Client>> = vec![];
+        let mut slow: Vec<Arc<Client>> = vec![];
+        //packed once per set of options, most clients share the same ones,
+        //every recipient's queue holds a reference to the same bytes
+        let mut packed: Vec<(FrameOptions, Transport, SharedFrame)> = vec![];
         for client in clients {
-            let res = client.as_ref().stream.stream_write.lock().expect("Failed to lock mutex").write_all(buffer);
-            if let Err(e) = res {
-                to_remove.push(client.clone());
+            let options = client.stream.frame_options();
+            let transport = client.stream.transport;
+            let cached = match transport {
+                Transport::Gateway(_) => None, //translated for each client on its own
+                _ => packed.iter().find(|(o, t, _)| *o == options && *t == transport),
+            };
+            let frame = match cached {
+                Some((_, _, frame)) => frame.clone(),
+                None => {
+                    let frame = Self::pack(&options, client.stream.as_ref(), code, 0, data)?;
+                    packed.push((options, transport, frame.clone()));
+                    frame
+                },
+            };
+            if !self.enqueue(&client.stream, frame) {
+                slow.push(client);
             }
         }
+        self.wake_writer();
 
-        self.handle_disconnected_clients(&to_remove)?;
+        self.drop_slow(&slow)?;
         Ok(())
     }
+
+    //serializes and frames a message the way `options` say, without copying when nothing is added to it
+    fn pack<T>(options: &FrameOptions, stream: &ClientStream, code: u32, request_id: u32, data: &T) -> Result<SharedFrame, std::io::Error>
+    where
+        T: ?Sized + serde::Serialize,
+    {
+        let buffer = match message_stream::serialize_request_with(options.codec, code, request_id, data) {
+            Ok(buffer) => buffer,
+            Err(e) => return Err(std::io::Error::new(ErrorKind::InvalidInput, e.to_string())),
+        };
+        let framed = match message_stream::encode_frame(&buffer, options) {
+            Cow::Owned(frame) => Some(frame),
+            Cow::Borrowed(_) => None,
+        };
+        Ok(Self::wrap(stream, framed.unwrap_or(buffer)))
+    }
+
+    fn wrap(stream: &ClientStream, frame: Vec<u8>) -> SharedFrame {
+        match (stream.transport, stream.gateway.as_ref()) {
+            (Transport::WebSocket, _) => SharedFrame::new(websocket::frame(Opcode::Binary, &frame)),
+            (Transport::Gateway(_), Some(gateway)) => SharedFrame::new(gateway.outbound(stream, &frame)),
+            _ => SharedFrame::new(frame),
+        }
+    }
+
+    //false if the client already has too much waiting, a client that is gone takes anything
+    fn enqueue(&self, stream: &ClientStream, frame: SharedFrame) -> bool {
+        let mut outbound = stream.outbound.lock().expect("Failed to lock mutex");
+        if outbound.is_closed() {
+            return true;
+        }
+        if !outbound.is_empty() && outbound.len() + frame.len() > self.max_queued.load(Ordering::Relaxed) {
+            return false;
+        }
+        Metrics::inc(&self.metrics.messages_out_total);
+        Metrics::adjust(&self.metrics.write_buffer_bytes, frame.len() as i64);
+        outbound.push(frame);
+        true
+    }
+
+    fn wake_writer(&self) {
+        *self.write_pending.lock().expect("Failed to lock mutex") = true;
+        self.write_ready.notify_one();
+    }
+
+    //blocks while more than `limit` bytes are queued for the client, false once it is disconnected
+    fn wait_queued(&self, stream: &ClientStream, limit: usize) -> bool {
+        if !self.clients_stream.read().expect("Failed to lock mutex").contains_key(&stream.addr) {
+            return false;
+        }
+        let mut outbound = stream.outbound.lock().expect("Failed to lock mutex");
+        while outbound.len() > limit && !outbound.is_closed() {
+            outbound = stream.drained.wait(outbound).expect("Failed to lock mutex");
+        }
+        !outbound.is_closed()
+    }
+
+    fn drop_slow(&self, clients: &[Arc<Client>]) -> Result<(), std::io::Error> {
+        for client in clients {
+            self.logger.log(Level::Warn, module_path!(), "Client is not keeping up, dropping it", &[("addr", &client.stream.addr), ("queued", &client.stream.queued())]);
+            Metrics::inc(&self.metrics.slow_clients_total);
+        }
+        self.handle_disconnected_clients(clients)
+    }
     // pub fn send_all_it2<It, T>(&self, buffer: &[u8], clients: It) -> Result<(), std::io::Error> 
     // where
     //     T: AsRef<Arc<Client>>,
@@ -128,58 +410,70 @@ impl ServerState {
     //     self.handle_disconnected_clients(to_remove)?;
     //     Ok(())
     // }
-    pub fn send_all(&self, buffer: &[u8]) -> Result<(), std::io::Error> {
+    //other servers are left out of every broadcast, they do not speak the client protocol
+    pub fn send_all<T: ?Sized + serde::Serialize>(&self, code: u32, data: &T) -> Result<(), std::io::Error> {
         let clients: Vec<Arc<Client>> = self.clients_stream.read().expect("Failed to lock mutex")
-            .iter()
-            .map(|(_, client)| client.clone())
+            .values()
+            .filter(|client| client.stream.transport != Transport::Link)
+            .cloned()
+            .collect();
+
+        self.send_all_it(code, data, clients)?;
+        Ok(())
+    }
+    pub fn send_to<T: ?Sized + serde::Serialize>(&self, code: u32, data: &T, addrs: &[SocketAddr]) -> Result<(), std::io::Error> {
+        let lock_guard = self.clients_stream.read().expect("Failed to lock mutex");
+        let clients: Vec<Arc<Client>> = addrs.iter()
+            .filter_map(|addr| lock_guard.get(addr).cloned())
             .collect();
+        drop(lock_guard);
 
-        self.send_all_it(&buffer, clients.into_iter())?;
+        self.send_all_it(code, data, clients)?;
         Ok(())
     }
-    pub fn send_all_except<I>(&self, buffer: &[u8], excluded: &HashSet<SocketAddr>) -> Result<(), std::io::Error> {
+    pub fn send_all_except<I, T: ?Sized + serde::Serialize>(&self, code: u32, data: &T, excluded: &HashSet<SocketAddr>) -> Result<(), std::io::Error> {
         let clients: Vec<Arc<Client>> = self.clients_stream.read().expect("Failed to lock mutex")
             .iter()
-            .filter(|(addr, _)| !excluded.contains(&addr))
+            .filter(|(addr, client)| !excluded.contains(addr) && client.stream.transport != Transport::Link)
             .map(|(_, client)| client.clone())
             .collect();
 
-        self.send_all_it(&buffer, clients.into_iter())?;
+        self.send_all_it(code, data, clients)?;
         Ok(())
     }
-    pub fn send_all_except_s(&self, buffer: &[u8], excluded: SocketAddr) -> Result<(), std::io::Error> {
+    pub fn send_all_except_s<T: ?Sized + serde::Serialize>(&self, code: u32, data: &T, excluded: SocketAddr) -> Result<(), std::io::Error> {
         let clients: Vec<Arc<Client>> = self.clients_stream.read().expect("Failed to lock mutex")
             .iter()
-            .filter(|(addr, _)| excluded != **addr)
+            .filter(|(addr, client)| excluded != **addr && client.stream.transport != Transport::Link)
             .map(|(_, client)| client.clone())
             .collect();
 
-        self.send_all_it(&buffer, clients.into_iter())?;
+        self.send_all_it(code, data, clients)?;
         Ok(())
     }
 
     fn listen_thread(&self) {
-        self.logger.log("listen_thread start".as_bytes()).unwrap();
+        self.logger.info(module_path!(), "listen_thread start");
         loop {
             if self.thread_state.is_shuttingdown() {
-                self.logger.log("Threads are shutting down!!!!".as_bytes()).unwrap();
+                self.logger.debug(module_path!(), "Threads are shutting down!!!!");
                 break;
             }
 
             let res = self.accept_clients();
             match res {
                 Ok(_) => {},
-                Err(e) => println!("{}", e),
+                Err(e) => self.logger.log(Level::Error, module_path!(), "Accept failed", &[("error", &e)]),
             }
         }
-        self.logger.log("listen_thread done".as_bytes()).unwrap();
+        self.logger.info(module_path!(), "listen_thread done");
     }
 
     fn read_thread(&self) {
-        self.logger.log("read_thread start".as_bytes()).unwrap();
+        self.logger.info(module_path!(), "read_thread start");
         loop {
             if self.thread_state.is_shuttingdown() {
-                self.logger.log("Threads are shutting down!!!!".as_bytes()).unwrap();
+                self.logger.debug(module_path!(), "Threads are shutting down!!!!");
                 break;
             }
 
@@ -187,25 +481,174 @@ impl ServerState {
             let to_remove = match self.read_clients() {
                 Ok(to_remove) => to_remove,
                 Err(e) => {
-                    println!("{}", e);
+                    self.logger.log(Level::Error, module_path!(), "Reading clients failed", &[("error", &e)]);
                     continue;
                 }
             };
             let res = self.handle_disconnected_clients(&to_remove);
             if let Err(e) = res {
-                self.logger.log(format!("{}", e).as_bytes()).unwrap();
+                self.logger.log(Level::Error, module_path!(), "Disconnecting clients failed", &[("error", &e)]);
+            }
+        }
+        self.logger.info(module_path!(), "read_thread done");
+    }
+
+    fn write_thread(&self) {
+        self.logger.info(module_path!(), "write_thread start");
+        let mut blocked = false;
+        loop {
+            if self.thread_state.is_shuttingdown() {
+                self.logger.debug(module_path!(), "Threads are shutting down!!!!");
+                break;
+            }
+
+            //sleeps until something is queued, a client whose socket was full is retried soon after
+            let timeout = match blocked {
+                true => Duration::from_millis(1),
+                false => Duration::from_millis(50),
+            };
+            let mut pending = self.write_pending.lock().expect("Failed to lock mutex");
+            if !*pending {
+                pending = self.write_ready.wait_timeout(pending, timeout).expect("Failed to lock mutex").0;
+            }
+            *pending = false;
+            drop(pending);
+
+            let to_remove;
+            (to_remove, blocked) = self.write_clients();
+            let res = self.handle_disconnected_clients(&to_remove);
+            if let Err(e) = res {
+                self.logger.log(Level::Error, module_path!(), "Disconnecting clients failed", &[("error", &e)]);
+            }
+        }
+        self.logger.info(module_path!(), "write_thread done");
+    }
+
+    //flushes every client with something queued, also returns whether any of them could not take all of it
+    fn write_clients(&self) -> (Vec<Arc<Client>>, bool) {
+        let mut to_remove = Vec::new();
+        let mut blocked = false;
+        let clients: Vec<Arc<Client>> = self.clients_stream.read().expect("Failed to lock mutex")
+            .values()
+            .cloned()
+            .collect();
+
+        for client in clients.iter() {
+            match self.flush(client.stream.as_ref()) {
+                Ok(rest) => blocked |= rest > 0,
+                Err(e) => {
+                    self.logger.log(Level::Warn, module_path!(), "Write error", &[("addr", &client.stream.addr), ("error", &e), ("kind", &e.kind())]);
+                    Metrics::inc(&self.metrics.send_errors_total);
+                    to_remove.push(client.clone());
+                },
+            }
+        }
+        (to_remove, blocked)
+    }
+
+    //writes what is queued for the client without blocking, returns the bytes still waiting
+    fn flush(&self, stream: &ClientStream) -> Result<usize, std::io::Error> {
+        let mut outbound = stream.outbound.lock().expect("Failed to lock mutex");
+        if outbound.is_empty() {
+            return Ok(0);
+        }
+        let queued = outbound.len();
+        let res = outbound.write_to(&mut *stream.stream_write.lock().expect("Failed to lock mutex"));
+        let written = queued - outbound.len();
+        if written > 0 {
+            stream.drained.notify_all();
+        }
+        Metrics::add(&self.metrics.bytes_out_total, written as u64);
+        Metrics::adjust(&self.metrics.write_buffer_bytes, -(written as i64));
+        res?;
+        Ok(outbound.len())
+    }
+
+    fn websocket_thread(self: &Arc<Self>, origins: Vec<String>) {
+        let origins = Arc::new(origins);
+        self.logger.info(module_path!(), "websocket_thread start");
+        loop {
+            if self.thread_state.is_shuttingdown() {
+                self.logger.debug(module_path!(), "Threads are shutting down!!!!");
+                break;
+            }
+
+            if let Err(e) = self.accept_websocket(&origins) {
+                self.logger.log(Level::Error, module_path!(), "WebSocket accept failed", &[("error", &e)]);
+            }
+        }
+        self.logger.info(module_path!(), "websocket_thread done");
+    }
+
+    fn gateway_thread(&self, gateway: Arc<dyn Gateway>) {
+        self.logger.log(Level::Info, module_path!(), "gateway_thread start", &[("gateway", &gateway.name())]);
+        loop {
+            if self.thread_state.is_shuttingdown() {
+                self.logger.debug(module_path!(), "Threads are shutting down!!!!");
+                break;
+            }
+
+            if let Err(e) = self.accept_gateway(&gateway) {
+                self.logger.log(Level::Error, module_path!(), "Gateway accept failed", &[("gateway", &gateway.name()), ("error", &e)]);
+            }
+        }
+        self.logger.log(Level::Info, module_path!(), "gateway_thread done", &[("gateway", &gateway.name())]);
+    }
+
+    fn link_thread(&self) {
+        self.logger.info(module_path!(), "link_thread start");
+        loop {
+            if self.thread_state.is_shuttingdown() {
+                self.logger.debug(module_path!(), "Threads are shutting down!!!!");
+                break;
+            }
+
+            if let Err(e) = self.accept_link() {
+                self.logger.log(Level::Error, module_path!(), "Link accept failed", &[("error", &e)]);
+            }
+        }
+        self.logger.info(module_path!(), "link_thread done");
+    }
+
+    //stops with stop_accepting, connections already accepted are served to the end
+    fn service_thread(&self, listener: TcpListener, acceptor: Acceptor) {
+        self.logger.log(Level::Info, module_path!(), "service_thread start", &[("service", &acceptor.name())]);
+        if let Err(e) = listener.set_nonblocking(true) {
+            self.logger.log(Level::Error, module_path!(), "Failed to set service listener to nonblocking", &[("service", &acceptor.name()), ("error", &e)]);
+            return;
+        }
+        while !self.thread_state.is_shuttingdown() && self.is_accepting() {
+            if let Err(e) = acceptor.accept(&listener, &self.logger) {
+                self.logger.log(Level::Warn, module_path!(), "Service accept failed", &[("service", &acceptor.name()), ("error", &e)]);
+                thread::sleep(Duration::from_millis(50));
             }
         }
-        self.logger.log("read_thread done".as_bytes()).unwrap();
+        self.logger.log(Level::Info, module_path!(), "service_thread done", &[("service", &acceptor.name())]);
+    }
+
+    fn metrics_thread(&self, listener: TcpListener) {
+        self.logger.info(module_path!(), "metrics_thread start");
+        if let Err(e) = listener.set_nonblocking(true) {
+            self.logger.log(Level::Error, module_path!(), "Failed to set metrics listener to nonblocking", &[("error", &e)]);
+            return;
+        }
+        loop {
+            if self.thread_state.is_shuttingdown() {
+                break;
+            }
+
+            if let Err(e) = metrics::serve_metrics(&listener, &self.metrics) {
+                self.logger.log(Level::Warn, module_path!(), "Metrics request failed", &[("error", &e)]);
+            }
+        }
+        self.logger.info(module_path!(), "metrics_thread done");
     }
 
     fn read_clients(&self) -> Result<Vec<Arc<Client>>, std::io::Error> {
-        const BUFF_SZ: usize = 4096;
-        let mut buffer: [u8; BUFF_SZ] = [0; BUFF_SZ];
         let mut to_remove = Vec::new();
         let clients: Vec<Arc<Client>> = self.clients_stream.read().expect("Failed to lock mutex")
-            .iter()
-            .map(|(_, client)| client.clone())
+            .values()
+            .cloned()
             .collect();
         
         for client in clients.iter() {   
@@ -213,27 +656,30 @@ impl ServerState {
                 break;
             }
 
-            let res = self.read_client_helper(client, &mut buffer);
+            let res = self.read_client_helper(client);
             match res {
                 Ok(_) => {},
                 Err(ServerError::IoError(e)) => {
                     let error_kind = e.kind();
                     if error_kind != ErrorKind::WouldBlock && error_kind != ErrorKind::Interrupted {
-                        self.logger.log(format!("Read error: {}, kind {}", e, error_kind).as_bytes())?;
+                        self.logger.log(Level::Warn, module_path!(), "Read error", &[("addr", &client.stream.addr), ("error", &e), ("kind", &error_kind)]);
                         to_remove.push(client.clone());
                     }
                     continue;
                 }
                 Err(ServerError::ReadError(s)) => {
-                    self.logger.log(format!("{}", s).as_bytes())?;
+                    self.logger.log(Level::Info, module_path!(), &s, &[("addr", &client.stream.addr)]);
                     to_remove.push(client.clone());
                 }
+                //frames that can be skipped never get here, the rest of the stream is unreadable
                 Err(ServerError::MsgError(e)) => {
-                    self.logger.log(format!("{}", e).as_bytes())?;
-                    client.data.borrow_mut().msg = Vec::new();
+                    self.logger.log(Level::Warn, module_path!(), "Corrupt stream, dropping client", &[("addr", &client.stream.addr), ("error", &e)]);
+                    Metrics::inc(&self.metrics.decode_errors_total);
+                    Metrics::inc(&self.metrics.corrupt_streams_total);
+                    to_remove.push(client.clone());
                 }
                 Err(e) => {
-                    self.logger.log(format!("{}", e).as_bytes())?;
+                    self.logger.log(Level::Error, module_path!(), "Client error", &[("addr", &client.stream.addr), ("error", &e)]);
                     to_remove.push(client.clone());
                 }
             }
@@ -241,51 +687,149 @@ impl ServerState {
         Ok(to_remove)
     }
 
-    fn read_client_helper(&self, client: &Arc<Client>, buffer: &mut [u8]) -> Result<(), ServerError> {
-        let read = client.stream.stream_read.borrow_mut().read(buffer)?;
+    fn read_client_helper(&self, client: &Arc<Client>) -> Result<(), ServerError> {
+        let mut client_data = client.data.borrow_mut();
+        let client_data = &mut *client_data;
+        let read = match (client_data.websocket.as_mut(), client.stream.gateway.as_ref()) {
+            (Some(decoder), _) => self.read_websocket(client.stream.as_ref(), decoder, &mut client_data.buffer)?,
+            (None, Some(gateway)) => self.read_gateway(client.stream.as_ref(), gateway.as_ref(), &mut client_data.buffer)?,
+            (None, None) => client_data.buffer.read_from(&mut *client.stream.stream_read.lock().expect("Failed to lock mutex"))?,
+        };
 
         if read == 0 {
             return Err(ServerError::ReadError(format!("Read error: {} bytes read", read)))
         }
         
-        let client_data = &mut client.data.borrow_mut();
-        let msg = &mut client_data.msg;
-        msg.extend_from_slice(&buffer[..read]);
-        
-        let mut msg_buffer = msg.as_slice();
+        Metrics::add(&self.metrics.bytes_in_total, read as u64);
+        *client.last_active.lock().expect("Failed to lock mutex") = Instant::now();
 
         loop {
-            let msgstream = match message_stream::parse_msgstream(msg_buffer) {
-                Ok(Some(msginfo)) => msginfo,
-                Ok(_) => {
-                    let mut msg_rem = Vec::new();
-                    msg_rem.extend_from_slice(msg_buffer);
-                    *msg = msg_rem;
-                    break;
+            //frames borrow from the buffer, handlers that keep a message around copy it
+            let frame = match client_data.buffer.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(MsgError::Frame { error, .. }) => {
+                    self.logger.log(Level::Warn, module_path!(), "Dropped frame", &[("addr", &client.stream.addr), ("error", &error)]);
+                    Metrics::inc(&self.metrics.decode_errors_total);
+                    continue;
                 },
                 Err(e) => { 
                     return Err(e.into()) 
                 },
             };
+            frame.require_checksum(&mut client_data.checksums)?;
     
-            (self.handler.on_read)(&self, client.stream.as_ref(), &msgstream.msginfo);
-            msg_buffer = msgstream.buffer_rem;
+            Metrics::inc(&self.metrics.messages_in_total);
+            if !frame.msginfo.is_stream() {
+                (self.handler.on_read)(self, client.stream.as_ref(), &frame.msginfo);
+                continue;
+            }
+            //chunks reach the handler one by one as they arrive, a broken stream is dropped on its own
+            //and the frames around it are fine
+            match client_data.streams.push(&frame.msginfo) {
+                Ok(_) => (self.handler.on_read)(self, client.stream.as_ref(), &frame.msginfo),
+                Err(e) => {
+                    self.logger.log(Level::Warn, module_path!(), "Dropped stream", &[("addr", &client.stream.addr), ("error", &e)]);
+                    Metrics::inc(&self.metrics.decode_errors_total);
+                },
+            }
         }  
+        let unwrapped = client_data.websocket.as_ref().map_or(0, |decoder| decoder.len());
+        self.update_pending(client, client_data.buffer.len() + unwrapped);
         Ok(())
     }
 
+    //unwraps what a WebSocket client sent into `buffer`, where it is parsed like any other frame;
+    //returns the bytes read from the socket
+    fn read_websocket(&self, stream: &ClientStream, decoder: &mut WsDecoder, buffer: &mut FrameBuffer) -> Result<usize, ServerError> {
+        let read = decoder.read_from(&mut *stream.stream_read.lock().expect("Failed to lock mutex"))?;
+        loop {
+            let frame = match decoder.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    self.close_websocket(stream, websocket::CLOSE_PROTOCOL, "protocol error");
+                    return Err(ServerError::ReadError(e.to_string()));
+                },
+            };
+            match frame.opcode {
+                Opcode::Binary | Opcode::Continuation => buffer.extend_from_slice(&frame.payload),
+                Opcode::Ping => self.send_shared(stream, SharedFrame::new(websocket::frame(Opcode::Pong, &frame.payload)))?,
+                Opcode::Pong => {},
+                Opcode::Text => {
+                    self.close_websocket(stream, websocket::CLOSE_UNSUPPORTED, "binary messages only");
+                    return Err(ServerError::ReadError(String::from("WebSocket text message")));
+                },
+                Opcode::Close => {
+                    self.close_websocket(stream, websocket::CLOSE_NORMAL, "");
+                    return Err(ServerError::ReadError(String::from("WebSocket closed by the client")));
+                },
+            }
+        }
+        Ok(read)
+    }
+
+    fn close_websocket(&self, stream: &ClientStream, code: u16, reason: &str) {
+        self.write_last(stream, &websocket::close_frame(code, reason));
+    }
+
+    //lets the gateway translate what the client sent into frames for `buffer`
+    fn read_gateway(&self, stream: &ClientStream, gateway: &dyn Gateway, buffer: &mut FrameBuffer) -> Result<usize, ServerError> {
+        let mut data = [0u8; 16 * 1024];
+        let read = stream.stream_read.lock().expect("Failed to lock mutex").read(&mut data)?;
+        if read == 0 {
+            return Ok(0);
+        }
+        let mut frames = Vec::new();
+        let mut replies = Vec::new();
+        if let Err(e) = gateway.inbound(stream, &data[..read], &mut frames, &mut replies) {
+            self.write_last(stream, &replies);
+            return Err(ServerError::ReadError(e));
+        }
+        buffer.extend_from_slice(&frames);
+        if !replies.is_empty() {
+            self.send_shared(stream, SharedFrame::new(replies))?;
+        }
+        Ok(read)
+    }
+
+    //best effort, written right away since the client is about to be dropped; skipped when a frame
+    //is half written, `bytes` would land in the middle of it
+    fn write_last(&self, stream: &ClientStream, bytes: &[u8]) {
+        let outbound = stream.outbound.lock().expect("Failed to lock mutex");
+        if outbound.is_empty() && !bytes.is_empty() {
+            let _ = stream.stream_write.lock().expect("Failed to lock mutex").write_all(bytes);
+        }
+        drop(outbound);
+    }
+
+    fn update_pending(&self, client: &Client, pending: usize) {
+        let previous = client.pending_bytes.swap(pending, Ordering::Relaxed);
+        Metrics::adjust(&self.metrics.read_buffer_bytes, pending as i64 - previous as i64);
+    }
+
+    fn count_disconnected(&self, client: &Client) {
+        Metrics::inc(&self.metrics.disconnections_total);
+        Metrics::adjust(&self.metrics.connected_clients, -1);
+        self.update_pending(client, 0);
+    }
+
     fn accept_clients(&self) -> Result<(), ServerError>  {
-        let strm_res = self.listener.accept();
+        let lock_guard = self.listener.lock().expect("Failed to lock mutex");
+        let strm_res = match lock_guard.as_ref() {
+            Some(listener) => listener.accept(),
+            None => {
+                drop(lock_guard);
+                thread::sleep(time::Duration::from_millis(50));
+                return Ok(());
+            },
+        };
+        drop(lock_guard);
 
         match strm_res {
             Ok((stream, addr)) => {
-                let client = Arc::new(Client::new(stream, addr));
-                let stream = client.stream.clone();
-                if (self.handler.allow_connect)(&self, stream.as_ref()) {
-                    self.clients_stream.write().expect("Failed to lock mutex").insert(addr, client);
-                    (self.handler.on_connect)(&self, stream.as_ref());
-                }
-            }
+                self.add_client(Arc::new(Client::new(stream, addr, Transport::Tcp)));
+            },
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                 // wait until network socket is ready, typically implemented
                 // via platform-specific APIs such as epoll or IOCP
@@ -298,21 +842,176 @@ impl ServerState {
 
         Ok(())
     }
+    //browsers upgrade over HTTP first, each on a thread of its own so a slow one holds up nobody
+    fn accept_websocket(self: &Arc<Self>, origins: &Arc<Vec<String>>) -> Result<(), ServerError> {
+        let lock_guard = self.websocket_listener.lock().expect("Failed to lock mutex");
+        let strm_res = match lock_guard.as_ref() {
+            Some(listener) => listener.accept(),
+            None => {
+                drop(lock_guard);
+                thread::sleep(time::Duration::from_millis(50));
+                return Ok(());
+            },
+        };
+        drop(lock_guard);
+
+        match strm_res {
+            Ok((stream, addr)) => {
+                if self.websocket_handshakes.fetch_add(1, Ordering::SeqCst) >= Self::MAX_WEBSOCKET_HANDSHAKES {
+                    self.websocket_handshakes.fetch_sub(1, Ordering::SeqCst);
+                    self.logger.log(Level::Warn, module_path!(), "Too many WebSocket handshakes, connection dropped", &[("addr", &addr)]);
+                    Metrics::inc(&self.metrics.connections_rejected_total);
+                    return Ok(());
+                }
+                let server = self.clone();
+                let origins = origins.clone();
+                let res = thread::Builder::new().name(String::from("WebSocketHandshake")).spawn(move || {
+                    server.handshake_websocket(stream, addr, &origins);
+                    server.websocket_handshakes.fetch_sub(1, Ordering::SeqCst);
+                });
+                if let Err(e) = res {
+                    self.websocket_handshakes.fetch_sub(1, Ordering::SeqCst);
+                    self.logger.log(Level::Error, module_path!(), "Failed to start WebSocket handshake", &[("addr", &addr), ("error", &e)]);
+                    Metrics::inc(&self.metrics.connections_rejected_total);
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(time::Duration::from_millis(5));
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handshake_websocket(&self, mut stream: TcpStream, addr: SocketAddr, origins: &[String]) {
+        if let Err(e) = websocket::accept(&mut stream, origins, Self::WEBSOCKET_HANDSHAKE_TIMEOUT) {
+            self.logger.log(Level::Info, module_path!(), "WebSocket handshake failed", &[("addr", &addr), ("error", &e)]);
+            Metrics::inc(&self.metrics.connections_rejected_total);
+            return;
+        }
+        //the server may have stopped accepting while the browser was answered
+        if self.thread_state.is_shuttingdown() || self.websocket_listener.lock().expect("Failed to lock mutex").is_none() {
+            return;
+        }
+        self.add_client(Arc::new(Client::new(stream, addr, Transport::WebSocket)));
+    }
+
+    const WEBSOCKET_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+    const MAX_WEBSOCKET_HANDSHAKES: usize = 64; //in progress at once, more are dropped right away
+
+    fn accept_gateway(&self, gateway: &Arc<dyn Gateway>) -> Result<(), ServerError> {
+        let lock_guard = self.gateway_listeners.lock().expect("Failed to lock mutex");
+        let strm_res = match lock_guard.get(gateway.name()) {
+            Some(listener) => listener.accept(),
+            None => {
+                drop(lock_guard);
+                thread::sleep(time::Duration::from_millis(50));
+                return Ok(());
+            },
+        };
+        drop(lock_guard);
+
+        match strm_res {
+            Ok((stream, addr)) => {
+                self.add_client(Arc::new(Client::with_gateway(stream, addr, gateway.clone())));
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(time::Duration::from_millis(5));
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn accept_link(&self) -> Result<(), ServerError> {
+        let lock_guard = self.link_listener.lock().expect("Failed to lock mutex");
+        let strm_res = match lock_guard.as_ref() {
+            Some(listener) => listener.accept(),
+            None => {
+                drop(lock_guard);
+                thread::sleep(time::Duration::from_millis(50));
+                return Ok(());
+            },
+        };
+        drop(lock_guard);
+
+        match strm_res {
+            Ok((stream, addr)) => {
+                self.add_client(Arc::new(Client::new(stream, addr, Transport::Link)));
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(time::Duration::from_millis(5));
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    //dials another server, the connection is handled like one accepted on the link listener from
+    //then on and is known by `addr`; false if the handler refused it
+    pub fn connect_link(&self, addr: SocketAddr, timeout: Duration) -> Result<bool, std::io::Error> {
+        if self.listener.lock().expect("Failed to lock mutex").is_none() {
+            return Err(std::io::Error::new(ErrorKind::NotConnected, "the server stopped accepting clients"));
+        }
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        Ok(self.add_client(Arc::new(Client::new(stream, addr, Transport::Link))))
+    }
+
+    fn add_client(&self, client: Arc<Client>) -> bool {
+        let stream = client.stream.clone();
+        if (self.handler.allow_connect)(self, stream.as_ref()) {
+            self.clients_stream.write().expect("Failed to lock mutex").insert(stream.addr, client);
+            Metrics::inc(&self.metrics.connections_total);
+            Metrics::adjust(&self.metrics.connected_clients, 1);
+            (self.handler.on_connect)(self, stream.as_ref());
+            true
+        }
+        else {
+            Metrics::inc(&self.metrics.connections_rejected_total);
+            false
+        }
+    }
+
     // fn handle_disconnected_clients<I>(&self, to_remove: I)
     // where
     //     I: Iterator<SocketAddr>
     // {
-    fn handle_disconnected_clients(&self, to_remove: &Vec<Arc<Client>>) -> Result<(), std::io::Error> {
-        if to_remove.len() > 0 {
+    fn handle_disconnected_clients(&self, to_remove: &[Arc<Client>]) -> Result<(), std::io::Error> {
+        if !to_remove.is_empty() {
+            //a client can be queued for removal twice (failed send and failed read), only the first one counts
             let mut lock_guard = self.clients_stream.write().expect("Failed to lock mutex");
-            for client in to_remove.iter() {
-                lock_guard.remove(&client.stream.addr).expect("Failed to unwrap client");
-            }
+            let removed: Vec<&Arc<Client>> = to_remove.iter()
+                .filter(|client| lock_guard.remove(&client.stream.addr).is_some())
+                .collect();
             drop(lock_guard);
 
-            for client in to_remove.iter() {
-                client.stream.stream_write.lock().expect("Failed to lock mutex").shutdown(Shutdown::Both)?; //cannot propogate error in closure as foreach doesnt return result
-                (self.handler.on_disconnect)(&self, client.stream.as_ref());
+            for client in removed {
+                self.count_disconnected(client);
+                let mut outbound = client.stream.outbound.lock().expect("Failed to lock mutex");
+                Metrics::adjust(&self.metrics.write_buffer_bytes, -(outbound.len() as i64));
+                outbound.close();
+                drop(outbound);
+                client.stream.drained.notify_all();
+                //a peer that already hung up (e.g. while draining) reports NotConnected, it still needs on_disconnect
+                let res = client.stream.stream_write.lock().expect("Failed to lock mutex").shutdown(Shutdown::Both);
+                if let Err(e) = res {
+                    if e.kind() != ErrorKind::NotConnected {
+                        self.logger.log(Level::Warn, module_path!(), "Failed to shut down socket", &[("addr", &client.stream.addr), ("error", &e)]);
+                    }
+                }
+                (self.handler.on_disconnect)(self, client.stream.as_ref());
+                if let Some(gateway) = client.stream.gateway.as_ref() {
+                    gateway.disconnected(client.stream.as_ref());
+                }
             }
 
             // to_remove.iter().for_each(
@@ -329,7 +1028,6 @@ impl ServerState {
 }
 
 pub struct Server {
-    thread_state: Arc<thread_helper::ThreadState>,
     threads: ServerThreads,
     pub state: Arc<ServerState>,
 }
@@ -338,7 +1036,6 @@ impl Server {
     pub fn new(listener: TcpListener, handler: ServerHandler, logger: Arc<Logger>) -> Self {
         let thread_state = Arc::new(thread_helper::ThreadState::new());
         Server {
-            thread_state:thread_state.clone(),
             threads:ServerThreads::new(thread_state.clone(), logger.clone()),
             state:Arc::new(ServerState::new(thread_state.clone(), listener, handler, logger.clone())),
         }
@@ -348,11 +1045,50 @@ impl Server {
         self.threads.start(self.state.clone());
     }
 
+    //serves state.metrics in Prometheus text format on GET /metrics
+    pub fn start_metrics(&mut self, listener: TcpListener) {
+        self.threads.start_metrics(self.state.clone(), listener);
+    }
+
+    //accepts browsers on `listener`, they share rooms and users with everyone else; an empty
+    //`origins` only lets in pages served from the server's own host, "*" lets in any
+    pub fn start_websocket(&mut self, listener: TcpListener, origins: Vec<String>) {
+        listener.set_nonblocking(true).expect("Failed to set TcpListener to nonblocking");
+        *self.state.websocket_listener.lock().expect("Failed to lock mutex") = Some(listener);
+        self.threads.start_websocket(self.state.clone(), origins);
+    }
+
+    //accepts clients speaking another protocol on `listener`, `gateway` translates for them
+    pub fn start_gateway(&mut self, listener: TcpListener, gateway: Arc<dyn Gateway>) {
+        listener.set_nonblocking(true).expect("Failed to set TcpListener to nonblocking");
+        self.state.gateway_listeners.lock().expect("Failed to lock mutex").insert(gateway.name(), listener);
+        self.threads.start_gateway(self.state.clone(), gateway);
+    }
+
+    //accepts other servers on `listener`, what they send is handed to the handler like anything else,
+    //see Transport::Link
+    pub fn start_link(&mut self, listener: TcpListener) {
+        listener.set_nonblocking(true).expect("Failed to set TcpListener to nonblocking");
+        *self.state.link_listener.lock().expect("Failed to lock mutex") = Some(listener);
+        self.threads.start_link(self.state.clone());
+    }
+
+    //serves `service` on `listener` until the server stops accepting
+    pub fn start_service(&mut self, listener: TcpListener, service: Service) {
+        self.threads.start_service(self.state.clone(), listener, service);
+    }
+
     pub fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
         self.state.shutdown()?;
         self.threads.wait_for_shutdown();
         Ok(())
     }
+
+    pub fn shutdown_graceful(&mut self, reason: &str, retry_after: Duration, grace: Duration) -> Result<(), Box<dyn Error>> {
+        self.state.shutdown_graceful(reason, retry_after, grace)?;
+        self.threads.wait_for_shutdown();
+        Ok(())
+    }
 }
 
 impl Drop for Server {
@@ -365,15 +1101,19 @@ impl Drop for Server {
 }
 
 
+pub type AllowConnectFn = Box<dyn Fn(&ServerState, &ClientStream)->bool>;
+pub type ClientEventFn = Box<dyn Fn(&ServerState, &ClientStream)>;
+pub type ReadFn = Box<dyn Fn(&ServerState, &ClientStream, &MsgInfo)>;
+
 pub struct ServerHandler {
-    allow_connect: Box<dyn Fn(&ServerState, &ClientStream)->bool>,
-    on_connect: Box<dyn Fn(&ServerState, &ClientStream)>,
-    on_disconnect: Box<dyn Fn(&ServerState, &ClientStream)>,
-    on_read: Box<dyn Fn(&ServerState, &ClientStream, &MsgInfo)>,
+    allow_connect: AllowConnectFn,
+    on_connect: ClientEventFn,
+    on_disconnect: ClientEventFn,
+    on_read: ReadFn,
 }
 
 impl ServerHandler {
-    pub fn new(allow_connect: Box<dyn Fn(&ServerState, &ClientStream)->bool>, on_connect: Box<dyn Fn(&ServerState, &ClientStream)>, on_disconnect: Box<dyn Fn(&ServerState, &ClientStream)>, on_read: Box<dyn Fn(&ServerState, &ClientStream, &MsgInfo)>) -> Self {
+    pub fn new(allow_connect: AllowConnectFn, on_connect: ClientEventFn, on_disconnect: ClientEventFn, on_read: ReadFn) -> Self {
         ServerHandler {
             allow_connect,
             on_connect,
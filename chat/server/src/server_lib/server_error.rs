@@ -2,6 +2,7 @@ use netutils::{thread_helper, message_stream::MsgError};
 use std::error::Error;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ServerError {
     IoError(std::io::Error),
     MsgError(MsgError),
@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use netutils::logger::{Level, Logger};
+
+//how long an address that failed to authenticate is refused, instead of keeping its slot busy
+pub const AUTH_FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+pub type ServiceFn = Box<dyn Fn(TcpStream, &Peer) + Send + Sync>;
+
+//a side listener that is not part of the chat, like chat-admin or webhooks: every connection gets a
+//thread of its own, at most `max` at a time
+pub struct Service {
+    pub name: String,
+    pub max: usize,
+    pub handle: ServiceFn,
+}
+
+//where a connection came from, handed to the service with it
+pub struct Peer {
+    pub addr: SocketAddr,
+    failures: Arc<Mutex<HashMap<IpAddr, Instant>>>,
+}
+
+impl Peer {
+    //refuses further connections from this address for AUTH_FAILURE_DELAY
+    pub fn auth_failed(&self) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().expect("Failed to lock mutex");
+        failures.retain(|_, at| now.duration_since(*at) < AUTH_FAILURE_DELAY);
+        failures.insert(self.addr.ip(), now);
+    }
+}
+
+fn refused(failures: &Mutex<HashMap<IpAddr, Instant>>, ip: IpAddr) -> bool {
+    failures.lock().expect("Failed to lock mutex").get(&ip).is_some_and(|at| at.elapsed() < AUTH_FAILURE_DELAY)
+}
+
+//gives back a connection slot when dropped, so a handler that panics does not keep it
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(crate) struct Acceptor {
+    active: Arc<AtomicUsize>,
+    failures: Arc<Mutex<HashMap<IpAddr, Instant>>>,
+    service: Arc<Service>,
+}
+
+impl Acceptor {
+    pub(crate) fn new(service: Service) -> Self {
+        Acceptor {
+            active: Arc::new(AtomicUsize::new(0)),
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            service: Arc::new(service),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.service.name
+    }
+
+    //takes one pending connection off the nonblocking `listener`
+    pub(crate) fn accept(&self, listener: &TcpListener, logger: &Arc<Logger>) -> Result<(), std::io::Error> {
+        let (stream, addr) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+                return Ok(());
+            },
+            Err(e) => return Err(e),
+        };
+        let name = &self.service.name;
+        if refused(&self.failures, addr.ip()) {
+            logger.log(Level::Debug, module_path!(), "Refused connection after a failed authentication", &[("service", name), ("addr", &addr)]);
+            return Ok(());
+        }
+        let taken = self.active.fetch_add(1, Ordering::SeqCst);
+        let slot = Slot(self.active.clone());
+        if taken >= self.service.max {
+            logger.log(Level::Warn, module_path!(), "Too many connections", &[("service", name), ("max", &self.service.max)]);
+            return Ok(());
+        }
+        stream.set_nonblocking(false)?;
+
+        let peer = Peer {
+            addr,
+            failures: self.failures.clone(),
+        };
+        let service = self.service.clone();
+        let res = thread::Builder::new().name(name.clone()).spawn(move || {
+            let _slot = slot;
+            (service.handle)(stream, &peer);
+        });
+        //a thread that failed to spawn dropped its slot with the closure
+        if let Err(e) = res {
+            logger.log(Level::Error, module_path!(), "Failed to spawn connection thread", &[("service", name), ("error", &e)]);
+        }
+        Ok(())
+    }
+}
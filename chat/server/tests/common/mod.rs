@@ -0,0 +1,196 @@
+//helpers shared by the integration tests, each test file only uses some of them
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use netutils::message_stream::{self, MsgInfo};
+use netutils::messages;
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+//a server binary running in a scratch directory of its own, killed when dropped
+pub struct TestServer {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>, //stdout and the log on stderr, interleaved
+    seen: Vec<String>, //lines read so far
+    pub dir: PathBuf,
+}
+
+impl TestServer {
+    //`args` come after the ones every test needs: an ephemeral chat port and logging to stderr only
+    pub fn start(args: &[&str]) -> Self {
+        let dir = std::env::temp_dir().join(format!("chat-test-{}-{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::SeqCst)));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Failed to create test directory");
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--bind", "127.0.0.1:0", "--log-file", "", "--log-stderr", "true"])
+            .args(args)
+            .current_dir(&dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to start the server");
+        let stdin = child.stdin.take().expect("Failed to get stdin");
+        let stdout = child.stdout.take().expect("Failed to get stdout");
+        let stderr = child.stderr.take().expect("Failed to get stderr");
+        let (sender, lines) = mpsc::channel();
+        forward_lines(stdout, sender.clone());
+        forward_lines(stderr, sender);
+        TestServer {
+            child,
+            stdin,
+            lines,
+            seen: vec![],
+            dir,
+        }
+    }
+
+    //the first line printed so far or within TIMEOUT that `accept` returns true for
+    pub fn wait_line<F: FnMut(&str) -> bool>(&mut self, what: &str, mut accept: F) -> String {
+        if let Some(line) = self.seen.iter().find(|line| accept(line)) {
+            return line.clone();
+        }
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(left) {
+                Ok(line) => {
+                    self.seen.push(line.clone());
+                    if accept(&line) {
+                        return line;
+                    }
+                },
+                Err(_) => panic!("The server never printed {}", what),
+            }
+        }
+    }
+
+    //whether a line containing `needle` was printed within `wait`
+    pub fn printed(&mut self, needle: &str, wait: Duration) -> bool {
+        let deadline = Instant::now() + wait;
+        while !self.seen.iter().any(|line| line.contains(needle)) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(left) {
+                Ok(line) => self.seen.push(line),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    //the address printed after `prefix` at startup, e.g. "Listening on "
+    pub fn addr(&mut self, prefix: &str) -> SocketAddr {
+        let line = self.wait_line(&format!("\"{}\"", prefix), |line| line.starts_with(prefix));
+        let addr = line[prefix.len()..].split(['/', ' ']).next().unwrap_or("");
+        addr.parse().unwrap_or_else(|_| panic!("No address in \"{}\"", line))
+    }
+
+    pub fn chat_addr(&mut self) -> SocketAddr {
+        self.addr("Listening on ")
+    }
+
+    //a line for the console, as if typed by the operator
+    pub fn console(&mut self, command: &str) {
+        writeln!(self.stdin, "{}", command).expect("Failed to write to the console");
+        self.stdin.flush().expect("Failed to write to the console");
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        //the log is the first thing to look at when a test failed
+        if thread::panicking() {
+            self.seen.extend(self.lines.try_iter());
+            eprintln!("--- output of the server in {}\n{}", self.dir.display(), self.seen.join("\n"));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn forward_lines<R: std::io::Read + Send + 'static>(reader: R, sender: mpsc::Sender<String>) {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+//a bare chat client speaking frames straight over TCP
+pub struct TestClient {
+    pub stream: TcpStream,
+}
+
+impl TestClient {
+    pub fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).expect("Failed to connect");
+        stream.set_read_timeout(Some(TIMEOUT)).expect("Failed to set read timeout");
+        TestClient { stream }
+    }
+
+    pub fn send<T: serde::Serialize>(&mut self, code: messages::client::Message, data: &T) {
+        let frame = message_stream::serialize_data(code as u32, data).expect("Failed to serialize");
+        self.stream.write_all(&frame).expect("Failed to send");
+    }
+
+    pub fn recv(&mut self) -> MsgInfo<'static> {
+        message_stream::read_msginfo(&mut self.stream, MAX_FRAME_LEN).expect("Failed to read a frame")
+    }
+
+    //skips everything until a `code` message for which `accept` returns true
+    pub fn expect<F: FnMut(&MsgInfo) -> bool>(&mut self, code: messages::server::Message, mut accept: F) -> MsgInfo<'static> {
+        let code = code as u32;
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            let msginfo = self.recv();
+            if msginfo.code == code && accept(&msginfo) {
+                return msginfo;
+            }
+        }
+        panic!("No message with code {} arrived", code);
+    }
+
+    //the users the server lists in `room`, "" for the one the client is in
+    pub fn who(&mut self, room: &str) -> Vec<String> {
+        self.send(messages::client::Message::OnWho, &messages::client::MsgWho { room });
+        let msginfo = self.expect(messages::server::Message::OnWho, |_| true);
+        let who = msginfo.decode_data::<messages::server::MsgWho>().expect("Failed to decode OnWho");
+        who.users.iter().map(|entry| entry.user.to_string()).collect()
+    }
+
+    //registers as `user` and waits for the server to confirm it
+    pub fn register(addr: SocketAddr, user: &str) -> Self {
+        let mut client = TestClient::connect(addr);
+        client.send(messages::client::Message::OnRegisterUser, &messages::client::MsgOnRegisterUser { user });
+        client.expect(messages::server::Message::OnRegistrationSuccess, |_| true);
+        client
+    }
+}
+
+//polls `check` until it holds or TIMEOUT passed
+pub fn eventually<F: FnMut() -> bool>(what: &str, mut check: F) {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if check() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("Timed out waiting for {}", what);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+use common::{TestClient, TestServer, TIMEOUT};
+use netutils::messages;
+
+//raw lines as the server wrote them, CR LF included
+struct IrcClient {
+    reader: BufReader<TcpStream>,
+}
+
+impl IrcClient {
+    fn connect(server: &mut TestServer, nick: &str) -> Self {
+        let addr = server.addr("Accepting IRC clients on ");
+        let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+        stream.set_read_timeout(Some(TIMEOUT)).expect("Failed to set read timeout");
+        write!(stream, "NICK {}\r\nUSER {} 0 * :{}\r\n", nick, nick, nick).expect("Failed to register");
+        let mut client = IrcClient { reader: BufReader::new(stream) };
+        client.expect(" 001 ");
+        client
+    }
+
+    fn send(&mut self, line: &str) {
+        write!(self.reader.get_mut(), "{}\r\n", line).expect("Failed to send");
+    }
+
+    fn line(&mut self) -> String {
+        let mut line = Vec::new();
+        self.reader.read_until(b'\n', &mut line).expect("Failed to read a line");
+        String::from_utf8_lossy(&line).to_string()
+    }
+
+    fn expect(&mut self, needle: &str) -> String {
+        loop {
+            let line = self.line();
+            assert!(!line.is_empty(), "Connection closed waiting for {:?}", needle);
+            if line.contains(needle) {
+                return line;
+            }
+        }
+    }
+}
+
+fn assert_single_line(line: &str) {
+    let body = line.strip_suffix("\r\n").expect("Lines end with CR LF");
+    assert!(!body.contains(['\r', '\n', '\0']), "{:?}", line);
+}
+
+#[test]
+fn line_breaks_in_fields_cannot_inject_commands() {
+    let mut server = TestServer::start(&["--irc-bind", "127.0.0.1:0"]);
+    let chat_addr = server.chat_addr();
+    let mut irc = IrcClient::connect(&mut server, "watcher");
+    let mut native = TestClient::register(chat_addr, "native");
+
+    native.send(messages::client::Message::OnAction, &messages::client::MsgAction { msg: "waves\rQUIT :injected\0" });
+    let line = irc.expect("ACTION");
+    assert_single_line(&line);
+    assert!(line.contains("waves QUIT :injected"), "{:?}", line);
+
+    //a lone CR splits a message into lines of its own, never into a raw command
+    native.send(messages::client::Message::OnSent, &messages::client::MsgOnSent { msg: "first\rsecond" });
+    let first = irc.expect("PRIVMSG #");
+    assert_single_line(&first);
+    assert!(first.ends_with(":first\r\n"), "{:?}", first);
+    let second = irc.line();
+    assert_single_line(&second);
+    assert!(second.ends_with(":second\r\n"), "{:?}", second);
+
+    native.send(messages::client::Message::OnAway, &messages::client::MsgAway { msg: Some("gone\nKILL watcher") });
+    native.expect(messages::server::Message::OnServerNotice, |_| true);
+    irc.send("PRIVMSG native :are you there?");
+    let away = irc.expect(" 301 ");
+    assert_single_line(&away);
+    assert!(away.contains("gone KILL watcher"), "{:?}", away);
+}
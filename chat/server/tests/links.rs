@@ -0,0 +1,102 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use common::{eventually, TestClient, TestServer};
+use netutils::messages;
+
+const PASSWORD: &str = "a shared link secret";
+
+struct Node {
+    server: TestServer,
+    chat: SocketAddr,
+    link: SocketAddr,
+}
+
+//a server named `name` accepting links and dialing `peers`
+fn start(name: &str, peers: &[&Node]) -> Node {
+    let peers: Vec<String> = peers.iter().map(|peer| peer.link.to_string()).collect();
+    let peers = peers.join(",");
+    let mut server = TestServer::start(&["--link-bind", "127.0.0.1:0", "--link-name", name, "--link-password", PASSWORD, "--link-peers", &peers]);
+    let chat = server.chat_addr();
+    let link = server.addr("Accepting links from other servers on ");
+    Node { server, chat, link }
+}
+
+//waits until `client` sees `user` in the lobby
+fn wait_for_user(client: &mut TestClient, user: &str) {
+    eventually(&format!("{} to show up", user), || client.who("").iter().any(|name| name == user));
+}
+
+#[test]
+fn users_rooms_and_messages_cross_links() {
+    let a = start("a", &[]);
+    let b = start("b", &[&a]);
+    let c = start("c", &[&b]);
+
+    let mut alice = TestClient::register(a.chat, "alice");
+    let mut carol = TestClient::register(c.chat, "carol");
+    //two hops away, through b
+    wait_for_user(&mut alice, "carol@c");
+    wait_for_user(&mut carol, "alice@a");
+
+    carol.send(messages::client::Message::OnJoin, &messages::client::MsgJoin { room: "dev" });
+    eventually("alice to see carol in dev", || alice.who("dev").iter().any(|name| name == "carol@c"));
+    alice.send(messages::client::Message::OnJoin, &messages::client::MsgJoin { room: "dev" });
+    carol.expect(messages::server::Message::OnJoined, |msginfo| {
+        let joined = msginfo.decode_data::<messages::server::MsgJoined>().unwrap();
+        joined.user == "alice@a" && joined.room == "dev"
+    });
+
+    carol.send(messages::client::Message::OnSent, &messages::client::MsgOnSent { msg: "hello from c" });
+    alice.expect(messages::server::Message::OnSent, |msginfo| {
+        let sent = msginfo.decode_data::<messages::server::MsgOnSent>().unwrap();
+        sent.user == "carol@c" && sent.msg == "hello from c"
+    });
+}
+
+#[test]
+fn links_closing_a_loop_are_refused() {
+    let a = start("a", &[]);
+    let mut b = start("b", &[&a]);
+    let mut alice = TestClient::register(a.chat, "alice");
+    let mut bob = TestClient::register(b.chat, "bob");
+    wait_for_user(&mut bob, "alice@a");
+
+    //c dials both, whichever link comes second would close a loop
+    let mut c = start("c", &[&a, &b]);
+    let mut a = a;
+    let refused = |node: &mut Node| node.server.printed("Refused link", Duration::from_millis(100));
+    eventually("a link to be refused", || refused(&mut a) || refused(&mut b) || refused(&mut c));
+
+    //every server is still reached exactly once
+    let mut carol = TestClient::register(c.chat, "carol");
+    wait_for_user(&mut alice, "carol@c");
+    let lobby = alice.who("");
+    assert_eq!(lobby.iter().filter(|name| *name == "carol@c").count(), 1, "{:?}", lobby);
+    wait_for_user(&mut carol, "alice@a");
+    wait_for_user(&mut carol, "bob@b");
+}
+
+#[test]
+fn dropping_a_link_removes_the_users_behind_it() {
+    let a = start("a", &[]);
+    let b = start("b", &[&a]);
+    let c = start("c", &[&b]);
+    let mut alice = TestClient::register(a.chat, "alice");
+    let _bob = TestClient::register(b.chat, "bob");
+    let _carol = TestClient::register(c.chat, "carol");
+    wait_for_user(&mut alice, "bob@b");
+    wait_for_user(&mut alice, "carol@c");
+
+    //carol was only reachable through b
+    drop(b);
+    for user in ["bob@b", "carol@c"] {
+        alice.expect(messages::server::Message::OnDisconnect, |msginfo| {
+            msginfo.decode_data::<messages::server::MsgOnDisconnect>().unwrap().user == user
+        });
+    }
+    let lobby = alice.who("");
+    assert_eq!(lobby, vec![String::from("alice")]);
+}
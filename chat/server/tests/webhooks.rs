@@ -0,0 +1,128 @@
+mod common;
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use common::{eventually, TestClient, TestServer, TIMEOUT};
+use netutils::http;
+use netutils::messages;
+
+const TOKEN: &str = "a webhook token for tests";
+
+fn start() -> (TestServer, SocketAddr, SocketAddr) {
+    let mut server = TestServer::start(&["--webhooks-bind", "127.0.0.1:0", "--webhooks-token", TOKEN]);
+    let chat = server.chat_addr();
+    let webhooks = server.addr("Accepting webhook posts on http://");
+    (server, chat, webhooks)
+}
+
+//posts `body` to /messages, None if the server closed the connection without an answer
+fn post(addr: SocketAddr, token: Option<&str>, body: &str) -> Option<u16> {
+    let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+    stream.set_read_timeout(Some(TIMEOUT)).expect("Failed to set read timeout");
+    let mut request = format!("POST /messages HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n", addr, body.len());
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    //a refused connection may already be closed
+    stream.write_all(request.as_bytes()).ok()?;
+
+    let mut response = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut response).ok()?;
+    let status = response.split_whitespace().nth(1)?;
+    Some(status.parse().expect("Invalid status"))
+}
+
+#[test]
+fn authenticated_posts_land_in_a_room() {
+    let (_server, chat, webhooks) = start();
+    let mut alice = TestClient::register(chat, "alice");
+    alice.send(messages::client::Message::OnJoin, &messages::client::MsgJoin { room: "dev" });
+    alice.expect(messages::server::Message::OnJoined, |msginfo| msginfo.decode_data::<messages::server::MsgJoined>().unwrap().room == "dev");
+
+    let status = post(webhooks, Some(TOKEN), r#"{"text": "build 42 passed", "room": "dev", "user": "ci"}"#);
+    assert_eq!(status, Some(204));
+    alice.expect(messages::server::Message::OnSent, |msginfo| {
+        let sent = msginfo.decode_data::<messages::server::MsgOnSent>().unwrap();
+        sent.user == "ci" && sent.msg == "build 42 passed"
+    });
+}
+
+#[test]
+fn bad_or_missing_tokens_are_refused() {
+    let (_server, _chat, webhooks) = start();
+    let body = r#"{"text": "hello"}"#;
+    assert_eq!(post(webhooks, None, body), Some(401));
+
+    //a failed attempt keeps the address out for a moment, without holding a connection slot
+    assert_eq!(post(webhooks, Some("not the token"), body), None);
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(post(webhooks, Some("not the token"), body), Some(401));
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(post(webhooks, Some(TOKEN), body), Some(204));
+}
+
+#[test]
+fn posts_cannot_use_names_of_users() {
+    let (mut server, chat, webhooks) = start();
+    let _bob = TestClient::register(chat, "bob");
+    assert_eq!(post(webhooks, Some(TOKEN), r#"{"text": "hi", "user": "Bob"}"#), Some(409));
+
+    //reserved and banned names are refused while their owners are offline too
+    let mut dave = TestClient::register(chat, "dave");
+    dave.send(messages::client::Message::OnReserve, &messages::client::MsgReserve { password: Some("dave's password") });
+    server.wait_line("the reservation", |line| line.contains("Name reserved"));
+    drop(dave);
+    assert_eq!(post(webhooks, Some(TOKEN), r#"{"text": "hi", "user": "dave"}"#), Some(403));
+
+    let _mallory = TestClient::register(chat, "mallory");
+    server.console("ban mallory");
+    server.wait_line("the ban", |line| line.contains("Banned mallory"));
+    assert_eq!(post(webhooks, Some(TOKEN), r#"{"text": "hi", "user": "mallory"}"#), Some(403));
+}
+
+#[test]
+fn outbound_posts_are_retried() {
+    //answers the first post with 503 and every later one with 204
+    let stub = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the stub");
+    let url = format!("http://{}/hook", stub.local_addr().unwrap());
+    let (sender, posts) = mpsc::channel();
+    thread::spawn(move || {
+        for (attempt, stream) in stub.incoming().enumerate() {
+            let mut stream = stream.expect("Failed to accept");
+            let request = http::read_request(&mut stream, 8192, 64 * 1024).unwrap().unwrap();
+            let status = if attempt == 0 { 503 } else { 204 };
+            http::respond(&mut stream, status, &[], "").unwrap();
+            let _ = sender.send((request.header("X-Chat-Event").map(String::from), String::from_utf8(request.body).unwrap()));
+        }
+    });
+
+    let mut server = TestServer::start(&["--webhooks-urls", &url, "--webhooks-events", "message", "--webhooks-retries", "2"]);
+    let mut alice = TestClient::register(server.chat_addr(), "alice");
+    alice.send(messages::client::Message::OnSent, &messages::client::MsgOnSent { msg: "ship it" });
+
+    let (first_event, first) = posts.recv_timeout(TIMEOUT).expect("Nothing was posted");
+    let (second_event, second) = posts.recv_timeout(TIMEOUT).expect("The post was not retried");
+    assert_eq!(first_event.as_deref(), Some("message"));
+    assert_eq!(second_event.as_deref(), Some("message"));
+    assert_eq!(first, second);
+    let event: serde_json::Value = serde_json::from_str(&second).unwrap();
+    assert_eq!(event["user"], "alice");
+    assert_eq!(event["text"], "ship it");
+    assert!(posts.recv_timeout(Duration::from_millis(1500)).is_err(), "delivered events must not be posted again");
+}
+
+#[test]
+fn the_listener_closes_when_the_server_drains() {
+    let (mut server, chat, webhooks) = start();
+    //keeps the server draining for the whole grace period
+    let _alice = TestClient::register(chat, "alice");
+    server.console("shutdown 30");
+    server.wait_line("the shutdown", |line| line.contains("Shutting down"));
+    eventually("the webhook listener to close", || TcpStream::connect(webhooks).is_err());
+}
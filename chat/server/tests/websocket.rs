@@ -0,0 +1,180 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use common::{TestServer, TIMEOUT};
+use netutils::message_stream::{self, MsgInfo};
+use netutils::messages;
+use netutils::websocket;
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+fn start() -> (TestServer, SocketAddr) {
+    let mut server = TestServer::start(&["--websocket-bind", "127.0.0.1:0"]);
+    let addr = server.addr("Accepting WebSocket clients on ws://");
+    (server, addr)
+}
+
+//sends an upgrade request, returns the status line and headers of the answer
+fn handshake(addr: SocketAddr, headers: &[(&str, &str)]) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+    stream.set_read_timeout(Some(TIMEOUT)).expect("Failed to set read timeout");
+    let mut request = format!("GET /chat HTTP/1.1\r\nHost: {}\r\n", addr);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).expect("Failed to send the request");
+
+    //a byte at a time so nothing after the headers is consumed
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => response.push(byte[0]),
+            Err(e) => panic!("Failed to read the response: {}", e),
+        }
+    }
+    (stream, String::from_utf8_lossy(&response).to_string())
+}
+
+fn upgrade(addr: SocketAddr) -> TcpStream {
+    let (stream, response) = handshake(addr, &[("Upgrade", "websocket"), ("Connection", "Upgrade"), ("Sec-WebSocket-Key", KEY), ("Sec-WebSocket-Version", "13")]);
+    assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+    let accept = format!("sec-websocket-accept: {}", websocket::accept_key(KEY));
+    assert!(response.lines().any(|line| line.eq_ignore_ascii_case(&accept)), "{}", response);
+    stream
+}
+
+fn client_frame(opcode: websocket::Opcode, payload: &[u8], masked: bool) -> Vec<u8> {
+    let mut frame = websocket::frame(opcode, payload);
+    if !masked {
+        return frame;
+    }
+    //the length bytes stay, the mask goes between them and the payload
+    let header_len = frame.len() - payload.len();
+    frame[1] |= 0x80;
+    let masked_payload: Vec<u8> = payload.iter().enumerate().map(|(i, byte)| byte ^ MASK[i % 4]).collect();
+    frame.truncate(header_len);
+    frame.extend_from_slice(&MASK);
+    frame.extend_from_slice(&masked_payload);
+    frame
+}
+
+//the next frame the server sent, (opcode, payload)
+fn server_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).expect("Failed to read a frame header");
+    assert_eq!(header[1] & 0x80, 0, "servers never mask");
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).expect("Failed to read the length");
+            u16::from_be_bytes(len) as usize
+        },
+        127 => {
+            let mut len = [0u8; 8];
+            stream.read_exact(&mut len).expect("Failed to read the length");
+            u64::from_be_bytes(len) as usize
+        },
+        len => len as usize,
+    };
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).expect("Failed to read the payload");
+    (header[0] & 0x0f, payload)
+}
+
+//the chat message carried by the next binary message
+fn recv(stream: &mut TcpStream) -> MsgInfo<'static> {
+    let (opcode, payload) = server_frame(stream);
+    assert_eq!(opcode, websocket::Opcode::Binary as u8);
+    message_stream::read_msginfo(&mut payload.as_slice(), payload.len()).expect("Failed to decode the message")
+}
+
+fn expect_close(stream: &mut TcpStream, code: u16) {
+    loop {
+        let (opcode, payload) = server_frame(stream);
+        if opcode == websocket::Opcode::Close as u8 {
+            assert_eq!(u16::from_be_bytes([payload[0], payload[1]]), code);
+            return;
+        }
+    }
+}
+
+#[test]
+fn registers_over_masked_binary_frames() {
+    let (_server, addr) = start();
+    let mut stream = upgrade(addr);
+
+    let register = message_stream::serialize_data(messages::client::Message::OnRegisterUser as u32, &messages::client::MsgOnRegisterUser { user: "browser" }).unwrap();
+    stream.write_all(&client_frame(websocket::Opcode::Binary, &register, true)).unwrap();
+    loop {
+        let msginfo = recv(&mut stream);
+        if msginfo.code == messages::server::Message::OnRegistrationSuccess as u32 {
+            let success = msginfo.decode_data::<messages::server::MsgRegistrationSuccess>().unwrap();
+            assert_eq!(success.user, "browser");
+            break;
+        }
+    }
+
+    //pings are answered while the connection is in use
+    stream.write_all(&client_frame(websocket::Opcode::Ping, b"ping", true)).unwrap();
+    loop {
+        let (opcode, payload) = server_frame(&mut stream);
+        if opcode == websocket::Opcode::Pong as u8 {
+            assert_eq!(payload, b"ping");
+            break;
+        }
+        assert_eq!(opcode, websocket::Opcode::Binary as u8);
+    }
+}
+
+#[test]
+fn closes_on_text_frames() {
+    let (_server, addr) = start();
+    let mut stream = upgrade(addr);
+    stream.write_all(&client_frame(websocket::Opcode::Text, b"hello", true)).unwrap();
+    expect_close(&mut stream, websocket::CLOSE_UNSUPPORTED);
+}
+
+#[test]
+fn closes_on_unmasked_frames() {
+    let (_server, addr) = start();
+    let mut stream = upgrade(addr);
+    let register = message_stream::serialize_data(messages::client::Message::OnRegisterUser as u32, &messages::client::MsgOnRegisterUser { user: "unmasked" }).unwrap();
+    stream.write_all(&client_frame(websocket::Opcode::Binary, &register, false)).unwrap();
+    expect_close(&mut stream, websocket::CLOSE_PROTOCOL);
+}
+
+#[test]
+fn rejects_bad_keys() {
+    let (_server, addr) = start();
+    let (_, response) = handshake(addr, &[("Upgrade", "websocket"), ("Connection", "Upgrade"), ("Sec-WebSocket-Key", "not a key"), ("Sec-WebSocket-Version", "13")]);
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+    let (_, response) = handshake(addr, &[("Upgrade", "websocket"), ("Connection", "Upgrade"), ("Sec-WebSocket-Version", "13")]);
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+}
+
+#[test]
+fn rejects_other_origins_by_default() {
+    let (_server, addr) = start();
+    let (_, response) = handshake(addr, &[("Upgrade", "websocket"), ("Connection", "Upgrade"), ("Sec-WebSocket-Key", KEY), ("Sec-WebSocket-Version", "13"), ("Origin", "https://elsewhere.example")]);
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    let (_, response) = handshake(addr, &[("Upgrade", "websocket"), ("Connection", "Upgrade"), ("Sec-WebSocket-Key", KEY), ("Sec-WebSocket-Version", "13"), ("Origin", &format!("http://{}", addr))]);
+    assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+}
+
+#[test]
+fn slow_handshakes_do_not_block_others() {
+    let (_server, addr) = start();
+    //never finishes its request
+    let mut stalled = TcpStream::connect(addr).unwrap();
+    stalled.write_all(b"GET /chat HTTP/1.1\r\n").unwrap();
+    let started = Instant::now();
+    upgrade(addr);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+//settings are resolved in order: defaults, config file, environment, command line
+//every overridable key is listed in Settings::KEYS as a dotted path ("log.level"),
+//which maps to env var PREFIX_LOG_LEVEL and command line flag --log-level
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, error: std::io::Error },
+    Parse { path: PathBuf, error: toml::de::Error },
+    UnknownArg(String),
+    MissingValue(String),
+    InvalidValue { key: String, value: String, reason: String },
+    Invalid { key: String, reason: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            ConfigError::Io { path, error } => write!(f, "Failed to read config file {}: {}", path.display(), error),
+            ConfigError::Parse { path, error } => write!(f, "Failed to parse config file {}: {}", path.display(), error),
+            ConfigError::UnknownArg(arg) => write!(f, "Unknown argument \"{}\" (see --help)", arg),
+            ConfigError::MissingValue(arg) => write!(f, "Missing value for argument \"{}\"", arg),
+            ConfigError::InvalidValue { key, value, reason } => write!(f, "Invalid value \"{}\" for {}: {}", value, key, reason),
+            ConfigError::Invalid { key, reason } => write!(f, "Invalid setting {}: {}", key, reason),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+pub trait Settings: Default + serde::de::DeserializeOwned {
+    //prefix of environment variables, e.g. "CHAT_SERVER"
+    const ENV_PREFIX: &'static str;
+    //config file looked up in the working directory when none is given explicitly
+    const DEFAULT_FILE: &'static str;
+    //(dotted key, metavar, help)
+    const KEYS: &'static [(&'static str, &'static str, &'static str)];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError>;
+    fn validate(&self) -> Result<(), ConfigError>;
+}
+
+pub fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    match value.trim().parse::<T>() {
+        Ok(v) => Ok(v),
+        Err(e) => Err(ConfigError::InvalidValue { key: key.to_string(), value: value.to_string(), reason: e.to_string() }),
+    }
+}
+
+pub fn parse_bool(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::InvalidValue { key: key.to_string(), value: value.to_string(), reason: "expected true or false".to_string() }),
+    }
+}
+
+pub fn parse_opt_path(value: &str) -> Option<PathBuf> {
+    if value.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(value))
+}
+
+pub fn check_file(key: &str, path: &Option<PathBuf>) -> Result<(), ConfigError> {
+    if let Some(path) = path {
+        if !path.is_file() {
+            return Err(ConfigError::Invalid { key: key.to_string(), reason: format!("{} is not a readable file", path.display()) });
+        }
+    }
+    Ok(())
+}
+
+fn env_name(prefix: &str, key: &str) -> String {
+    format!("{}_{}", prefix, key.replace(['.', '-'], "_").to_uppercase())
+}
+
+fn flag_name(key: &str) -> String {
+    format!("--{}", key.replace(['.', '_'], "-"))
+}
+
+pub fn load_file<S: Settings>(path: &Path) -> Result<S, ConfigError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => return Err(ConfigError::Io { path: path.to_path_buf(), error }),
+    };
+    match toml::from_str::<S>(&text) {
+        Ok(settings) => Ok(settings),
+        Err(error) => Err(ConfigError::Parse { path: path.to_path_buf(), error }),
+    }
+}
+
+pub fn usage<S: Settings>(program: &str) -> String {
+    let mut out = format!("Usage: {} [OPTIONS]\n\nOptions:\n", program);
+    let mut lines = vec![
+        ("--config <PATH>".to_string(), format!("Config file (default: {} if present)", S::DEFAULT_FILE)),
+    ];
+    for (key, metavar, help) in S::KEYS {
+        lines.push((format!("{} <{}>", flag_name(key), metavar), help.to_string()));
+    }
+    lines.push(("--help".to_string(), "Print this help".to_string()));
+
+    let width = lines.iter().map(|(flag, _)| flag.len()).max().unwrap_or(0);
+    for (flag, help) in lines {
+        out.push_str(&format!("  {:width$}  {}\n", flag, help, width = width));
+    }
+    out.push_str(&format!("\nEvery option can also be set with an environment variable, e.g. {}\n", env_name(S::ENV_PREFIX, S::KEYS.first().map(|k| k.0).unwrap_or("config"))));
+    out
+}
+
+//returns Ok(None) when --help was requested
+pub fn load<S, I>(args: I) -> Result<Option<S>, ConfigError>
+where
+    S: Settings,
+    I: IntoIterator<Item = String>,
+{
+    match load_with_operands::<S, I>(args)? {
+        Some((settings, operands)) => match operands.into_iter().next() {
+            Some(arg) => Err(ConfigError::UnknownArg(arg)),
+            None => Ok(Some(settings)),
+        },
+        None => Ok(None),
+    }
+}
+
+//like load, but option parsing stops at the first argument that is not a flag,
+//that argument and everything after it is returned untouched
+pub fn load_with_operands<S, I>(args: I) -> Result<Option<(S, Vec<String>)>, ConfigError>
+where
+    S: Settings,
+    I: IntoIterator<Item = String>,
+{
+    let mut operands = Vec::new();
+    let mut config_path: Option<PathBuf> = None;
+    let mut overrides: HashMap<&'static str, String> = HashMap::new();
+    let flags: Vec<(String, &'static str)> = S::KEYS.iter().map(|(key, _, _)| (flag_name(key), *key)).collect();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Ok(None);
+        }
+        if !arg.starts_with('-') {
+            operands.push(arg);
+            operands.extend(args);
+            break;
+        }
+
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || match inline_value.clone().or_else(|| args.next()) {
+            Some(value) => Ok(value),
+            None => Err(ConfigError::MissingValue(flag.clone())),
+        };
+
+        if flag == "--config" {
+            config_path = Some(PathBuf::from(value()?));
+            continue;
+        }
+        match flags.iter().find(|(name, _)| *name == flag) {
+            Some((_, key)) => {
+                let value = value()?;
+                overrides.insert(key, value);
+            },
+            None => return Err(ConfigError::UnknownArg(arg)),
+        }
+    }
+
+    if config_path.is_none() {
+        config_path = std::env::var(env_name(S::ENV_PREFIX, "config")).ok().map(PathBuf::from);
+    }
+
+    let mut settings = match config_path {
+        Some(path) => load_file::<S>(&path)?,
+        None if Path::new(S::DEFAULT_FILE).is_file() => load_file::<S>(Path::new(S::DEFAULT_FILE))?,
+        None => S::default(),
+    };
+
+    for (key, _, _) in S::KEYS {
+        if let Ok(value) = std::env::var(env_name(S::ENV_PREFIX, key)) {
+            settings.set(key, &value)?;
+        }
+    }
+    for (key, _, _) in S::KEYS {
+        if let Some(value) = overrides.get(key) {
+            settings.set(key, value)?;
+        }
+    }
+
+    settings.validate()?;
+    Ok(Some((settings, operands)))
+}
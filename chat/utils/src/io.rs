@@ -1,4 +1,6 @@
+use std::fs;
 use std::io::{self, Write};
+use std::path::Path;
 use std::str::FromStr;
 
 pub fn read_val<ErrFmt, ValPredicate, T>(prompt: &str, error_fmt: ErrFmt, pred_opt: Option<ValPredicate>) -> T
@@ -29,4 +31,13 @@ pub fn read_val<ErrFmt, ValPredicate, T>(prompt: &str, error_fmt: ErrFmt, pred_o
             Err(_) => print_err(),
         };
     }
-}
\ No newline at end of file
+}
+
+//writes a sibling file and renames it over `file` so a crash never leaves half a file
+pub fn write_atomic(file: &Path, content: &[u8]) -> io::Result<()> {
+    let tmp = file.with_extension("tmp");
+    let mut out = fs::File::create(&tmp)?;
+    out.write_all(content)?;
+    out.sync_all()?;
+    fs::rename(&tmp, file)
+}
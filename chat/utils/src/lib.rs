@@ -1 +1,3 @@
-pub mod io;
\ No newline at end of file
+pub mod io;
+pub mod config;
+pub mod secret;